@@ -4,7 +4,9 @@ fn generate_copra_internal_message() {
     protoc_rust_copra::run(protoc_rust_copra::Args {
         out_dir: "copra/src/message",
         input: &[
+            "copra/src/message/health.proto",
             "copra/src/message/meta.proto",
+            "copra/src/message/reflection.proto",
             "copra/src/message/test.proto",
         ],
         includes: &[],
@@ -10,39 +10,18 @@ use copra::codec::ProtobufCodec;
 use copra::channel::Channel;
 use copra::stub::StubFuture;
 use copra::protocol::http::HttpStatus;
-use copra_examples::protos::benchmark::{Empty, PressureRequest, StringMessage};
+use copra_examples::pressure::Pressure;
+use copra_examples::protos::benchmark::{Empty, StringMessage};
 use copra_examples::protos::benchmark_copra::{MetricRegistrant, MetricService, PressureRegistrant,
-                                              PressureService, PressureStub};
+                                              PressureStub};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::{task, Async, Poll, Stream};
 use futures::future::{self, Future, FutureResult};
 use std::thread;
-use std::time::Duration;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio_core::reactor::Core;
 
-#[derive(Clone)]
-struct Pressure;
-
-impl PressureService for Pressure {
-    type EchoFuture = FutureResult<(StringMessage, Controller), MethodError>;
-
-    type ProcessFuture = FutureResult<(Empty, Controller), MethodError>;
-
-    fn echo(&self, msg: (StringMessage, Controller)) -> Self::EchoFuture {
-        let (msg, controller) = msg;
-        let string = msg.msg;
-        let mut resp = StringMessage::new();
-        resp.msg = string;
-        future::ok((resp, controller))
-    }
-
-    fn process(&self, _msg: (PressureRequest, Controller)) -> Self::ProcessFuture {
-        unimplemented!()
-    }
-}
-
 #[derive(Clone)]
 struct Metric {
     throughput: Arc<AtomicUsize>,
@@ -118,27 +97,23 @@ fn main() {
     let registrant = MetricRegistrant::new(Metric::new(throughtput.clone()));
     registry.register_service(registrant);
 
-    let server = ServerBuilder::new(addr, registry)
+    let _server = ServerBuilder::new(addr, registry)
         .threads(1)
         .throughput(throughtput, core.remote())
         .build()
-        .unwrap();
-
-    thread::spawn(move || {
-        server.start();
-    });
-    thread::sleep(Duration::from_millis(100));
+        .unwrap()
+        .start_background();
 
     let _threads: Vec<_> = (0..client_thread_num)
         .map(|_| {
             thread::spawn(move || {
                 let mut core = Core::new().unwrap();
                 let handle = core.handle();
-                let channel = core.run(
-                    ChannelBuilder::single_server(addr, handle)
-                        .max_concurrency(1000)
-                        .build(),
-                ).unwrap();
+                let channel = core.run(ChannelBuilder::single_server_spawned(
+                    addr,
+                    &handle,
+                    |builder| builder.max_concurrency(1000),
+                )).unwrap();
 
                 let sender = Sender::new(channel);
                 core.run(sender).unwrap();
@@ -0,0 +1,105 @@
+//! Throughput of a channel with one connection to its server, and with four
+//!
+//! Usage: `connection-pool [seconds per run]`, default to 5 seconds.
+
+extern crate copra;
+extern crate copra_examples;
+extern crate env_logger;
+extern crate futures;
+extern crate tokio_core;
+
+use copra::{ChannelBuilder, ServerBuilder, ServiceRegistry};
+use copra::channel::Channel;
+use copra::codec::ProtobufCodec;
+use copra::stub::StubFuture;
+use copra_examples::pressure::Pressure;
+use copra_examples::protos::benchmark::StringMessage;
+use copra_examples::protos::benchmark_copra::{PressureRegistrant, PressureStub};
+use futures::stream::futures_unordered::FuturesUnordered;
+use futures::{task, Async, Poll, Stream};
+use futures::future::Future;
+use std::env;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Core;
+
+/// Keeps the channel busy until `deadline`, counting the answered calls
+struct Sender {
+    channel: Channel,
+    in_flight: FuturesUnordered<StubFuture<ProtobufCodec<StringMessage, StringMessage>>>,
+    deadline: Instant,
+    answered: usize,
+}
+
+impl Future for Sender {
+    type Item = usize;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let running = Instant::now() < self.deadline;
+        while running && !self.channel.congested() {
+            let stub = PressureStub::new(&self.channel);
+            let mut req = StringMessage::new();
+            req.set_msg("ABCDE_ABCDE_ABCDE_ABCDE_ABCDE_ABCDE_ABCDE_ABCDE".to_string());
+            self.in_flight.push(stub.echo(req));
+        }
+        loop {
+            match self.in_flight.poll() {
+                Ok(Async::Ready(Some(_))) => self.answered += 1,
+                Ok(Async::Ready(None)) if !running => return Ok(Async::Ready(self.answered)),
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(_) => return Err(()),
+            }
+        }
+        if running {
+            task::current().notify();
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+fn run(addr: &str, connections: usize, duration: Duration) -> f64 {
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server_spawned(addr, &core.handle(), |builder| {
+        builder
+            .connections_per_backend(connections)
+            .max_concurrency(1000)
+    });
+    let channel = core.run(channel).unwrap();
+
+    let start = Instant::now();
+    let sender = Sender {
+        channel,
+        in_flight: FuturesUnordered::new(),
+        deadline: start + duration,
+        answered: 0,
+    };
+    let answered = core.run(sender).unwrap();
+    let elapsed = start.elapsed();
+    answered as f64 / (elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9)
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let seconds = env::args()
+        .nth(1)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(5);
+    let duration = Duration::from_secs(seconds);
+
+    let mut registry = ServiceRegistry::new();
+    registry.register_service(PressureRegistrant::new(Pressure));
+    let server = ServerBuilder::new("127.0.0.1:0", registry)
+        .threads(4)
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    for &connections in &[1, 4] {
+        let throughput = run(&addr, connections, duration);
+        println!("{} connection(s): {:.0} calls/s", connections, throughput);
+    }
+
+    server.stop().unwrap();
+}
@@ -13,7 +13,6 @@ use futures::future::{self, Future, FutureResult};
 use futures_cpupool::CpuPool;
 use primal::is_prime;
 use rand::Rng;
-use std::thread;
 use std::time::Duration;
 use std::sync::Arc;
 use tokio_core::reactor::Core;
@@ -97,14 +96,13 @@ fn main() {
     let registrant = DemoRegistrant::new(Demo::new(Arc::new(pool)));
     registry.register_service(registrant);
 
-    let server = ServerBuilder::new(addr, registry).build().unwrap();
-    thread::spawn(move || {
-        server.start();
-    });
-    thread::sleep(Duration::from_millis(100));
+    let _server = ServerBuilder::new(addr, registry)
+        .build()
+        .unwrap()
+        .start_background();
 
     //setup client
-    let channel = core.run(ChannelBuilder::single_server(addr, handle).build())
+    let channel = core.run(ChannelBuilder::single_server_spawned(addr, &handle, |b| b))
         .unwrap();
 
     //create a stub for DemoService
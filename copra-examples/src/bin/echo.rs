@@ -6,7 +6,9 @@ extern crate tokio_core;
 
 use copra::{ChannelBuilder, Controller, MethodError, ServerBuilder, ServiceRegistry};
 use futures::{Future, IntoFuture};
+use std::env;
 use std::thread;
+use std::time::Duration;
 use tokio_core::reactor::Core;
 
 use copra_examples::protos::echo::{EchoRequest, EchoResponse};
@@ -22,6 +24,8 @@ impl EchoService for Echo {
 
     type RevEchoFuture = Box<Future<Item = (EchoResponse, Controller), Error = MethodError>>;
 
+    type SlowEchoFuture = Box<Future<Item = (EchoResponse, Controller), Error = MethodError>>;
+
     fn echo(&self, msg: (EchoRequest, Controller)) -> Self::EchoFuture {
         let (msg, controller) = msg;
         let string = msg.msg;
@@ -46,11 +50,31 @@ impl EchoService for Echo {
 
         Box::new(future)
     }
+
+    fn slow_echo(&self, msg: (EchoRequest, Controller)) -> Self::SlowEchoFuture {
+        let (msg, controller) = msg;
+        // sleeping on the event loop would stall every other request, so the
+        // blocking work is moved to the blocking pool
+        let future = controller
+            .spawn_blocking(move || {
+                thread::sleep(Duration::from_secs(1));
+                let mut response = EchoResponse::new();
+                response.msg = msg.msg;
+                Ok(response)
+            })
+            .map(move |resp| (resp, controller));
+
+        Box::new(future)
+    }
 }
 
 fn main() {
     env_logger::init().unwrap();
 
+    // with `--single-core`, the server and the client share one event loop
+    // and no thread is spawned
+    let single_core = env::args().any(|arg| arg == "--single-core");
+
     let addr = "127.0.0.1:8989";
     let mut core = Core::new().unwrap();
     let handle = core.handle();
@@ -58,14 +82,22 @@ fn main() {
     let mut registry = ServiceRegistry::new();
     registry.register_service(registrant);
 
-    thread::spawn(move || {
-        let server = ServerBuilder::new(addr, registry).build().unwrap();
-        server.start();
-    });
-
-    //thread::sleep(Duration::from_millis(100));
-
-    let channel = core.run(ChannelBuilder::single_server(addr, handle).build())
+    let server = ServerBuilder::new(addr, registry)
+        .blocking_pool(2)
+        .build()
+        .unwrap();
+    let shutdown = server.shutdown_handle();
+    let background = if single_core {
+        let serve = server
+            .serve_on(&handle)
+            .map_err(|e| println!("Server failed with {:?}", e));
+        handle.spawn(serve);
+        None
+    } else {
+        Some(server.start_background())
+    };
+
+    let channel = core.run(ChannelBuilder::single_server_spawned(addr, &handle, |b| b))
         .unwrap();
 
     let echo = EchoStub::new(&channel);
@@ -75,7 +107,7 @@ fn main() {
         request.set_msg(format!("hello from the other side, time {}", i));
 
         let fut = echo.echo(request.clone())
-            .map_err(move |e| println!("Request {} failed with {:?}", i, e))
+            .map_err(move |e| println!("Request {} failed with {}", i, e))
             .and_then(|(msg, _)| {
                 println!("Client received: {}", msg.get_msg());
                 Ok(())
@@ -83,11 +115,31 @@ fn main() {
         core.run(fut).unwrap();
 
         let fut = echo.rev_echo(request)
-            .map_err(move |e| println!("Request {} failed with {:?}", i, e))
+            .map_err(move |e| println!("Request {} failed with {}", i, e))
             .and_then(|(msg, _)| {
                 println!("Client received: {}", msg.get_msg());
                 Ok(())
             });
         core.run(fut).unwrap();
     }
+
+    // the fast request is answered while the slow one is still sleeping
+    let mut request = EchoRequest::new();
+    request.set_msg("slow".to_string());
+    let slow = echo.slow_echo(request).map(|(msg, _)| {
+        println!("Client received: {}", msg.get_msg());
+    });
+    let mut request = EchoRequest::new();
+    request.set_msg("fast".to_string());
+    let fast = echo.echo(request).map(|(msg, _)| {
+        println!("Client received: {}", msg.get_msg());
+    });
+    core.run(slow.join(fast))
+        .map_err(|e| println!("Request failed with {}", e))
+        .unwrap();
+
+    shutdown.shutdown();
+    if let Some(server) = background {
+        server.join().unwrap();
+    }
 }
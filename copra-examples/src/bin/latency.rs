@@ -0,0 +1,95 @@
+//! Latency of small calls over loopback, with `TCP_NODELAY` on the client
+//! connection and without
+//!
+//! A few callers issue calls one after another, each as soon as its last
+//! one is answered. Their requests are written apart, while the others are
+//! not acknowledged yet, which is when Nagle's algorithm holds them back.
+//!
+//! Usage: `latency [seconds per run]`, default to 5 seconds.
+
+extern crate copra;
+extern crate copra_examples;
+extern crate env_logger;
+extern crate futures;
+extern crate tokio_core;
+
+use copra::{ChannelBuilder, ServerBuilder, ServiceRegistry};
+use copra_examples::pressure::Pressure;
+use copra_examples::protos::benchmark::StringMessage;
+use copra_examples::protos::benchmark_copra::{PressureRegistrant, PressureStub};
+use futures::future::{self, Future, Loop};
+use std::env;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Core;
+
+fn micros(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1e6 + f64::from(d.subsec_nanos()) / 1e3
+}
+
+/// Issue calls from `callers` callers for `duration`, returning the latencies
+/// of the calls
+fn run(addr: &str, nodelay: bool, callers: usize, duration: Duration) -> Vec<Duration> {
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server_spawned(addr, &core.handle(), |builder| {
+        builder.tcp_nodelay(nodelay)
+    });
+    let channel = core.run(channel).unwrap();
+    let mut req = StringMessage::new();
+    req.set_msg("ABCDE".to_string());
+
+    let deadline = Instant::now() + duration;
+    let callers = (0..callers).map(|_| {
+        let stub = PressureStub::new(&channel);
+        let req = req.clone();
+        future::loop_fn(Vec::new(), move |mut latencies| {
+            let start = Instant::now();
+            stub.echo(req.clone()).map(move |_| {
+                latencies.push(start.elapsed());
+                if Instant::now() < deadline {
+                    Loop::Continue(latencies)
+                } else {
+                    Loop::Break(latencies)
+                }
+            })
+        })
+    });
+    let latencies = core.run(future::join_all(callers)).unwrap();
+    latencies.into_iter().flatten().collect()
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let seconds = env::args()
+        .nth(1)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(5);
+    let duration = Duration::from_secs(seconds);
+
+    let mut registry = ServiceRegistry::new();
+    registry.register_service(PressureRegistrant::new(Pressure));
+    // the responses are not held back either
+    let server = ServerBuilder::new("127.0.0.1:0", registry)
+        .tcp_nodelay(true)
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    for &nodelay in &[true, false] {
+        let mut latencies = run(&addr, nodelay, 4, duration);
+        latencies.sort();
+        let n = latencies.len();
+        let total = latencies.iter().fold(0.0, |total, &l| total + micros(l));
+        println!(
+            "nodelay {}: {} calls, mean {:.0}us, p50 {:.0}us, p99 {:.0}us",
+            nodelay,
+            n,
+            total / n as f64,
+            micros(latencies[n / 2]),
+            micros(latencies[n * 99 / 100]),
+        );
+    }
+
+    server.stop().unwrap();
+}
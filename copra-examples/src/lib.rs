@@ -2,4 +2,5 @@ extern crate copra;
 extern crate futures;
 extern crate protobuf;
 
+pub mod pressure;
 pub mod protos;
\ No newline at end of file
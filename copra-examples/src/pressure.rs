@@ -0,0 +1,25 @@
+//! An echo service for the benchmarks
+
+use copra::{Controller, MethodError};
+use futures::future::{self, FutureResult};
+
+use protos::benchmark::{Empty, PressureRequest, StringMessage};
+use protos::benchmark_copra::PressureService;
+
+/// Answer `echo` with the request, `process` is not served
+#[derive(Clone, Debug)]
+pub struct Pressure;
+
+impl PressureService for Pressure {
+    type EchoFuture = FutureResult<(StringMessage, Controller), MethodError>;
+
+    type ProcessFuture = FutureResult<(Empty, Controller), MethodError>;
+
+    fn echo(&self, msg: (StringMessage, Controller)) -> Self::EchoFuture {
+        future::ok(msg)
+    }
+
+    fn process(&self, _msg: (PressureRequest, Controller)) -> Self::ProcessFuture {
+        future::err(MethodError::MethodNotFound)
+    }
+}
@@ -63,6 +63,10 @@ where
         
         entries
     }
+
+    fn file_descriptor(&self) -> Option<Vec<u8>> {
+        ::protobuf::Message::write_to_bytes(super::benchmark::file_descriptor_proto()).ok()
+    }
 }
 
 impl<S> ::copra::dispatcher::NamedRegistrant for MetricRegistrant<S> 
@@ -75,13 +79,13 @@ where
 }
 
 #[derive(Clone)]
-pub struct MetricStub<'a> {
-    metric_wrapper: ::copra::stub::RpcWrapper<'a,
+pub struct MetricStub {
+    metric_wrapper: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<super::benchmark::Empty, super::benchmark::Empty>>,
 }
 
-impl<'a> MetricStub<'a> {
-    pub fn new(channel: &'a ::copra::channel::Channel) -> Self {
+impl MetricStub {
+    pub fn new(channel: &::copra::channel::Channel) -> Self {
         MetricStub {
             metric_wrapper: ::copra::stub::RpcWrapper::new(
                 ::copra::codec::ProtobufCodec::new(), channel
@@ -89,9 +93,26 @@ impl<'a> MetricStub<'a> {
         }
     }
 
+    pub fn new_sync(channel: &::copra::sync::SyncChannel) -> Self {
+        MetricStub::new(channel.channel())
+    }
+
     pub fn metric(
-        &'a self, 
+        &self,
+        msg: super::benchmark::Empty,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::benchmark::Empty,
+            super::benchmark::Empty,
+        >,
+    > {
+        self.metric_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn metric_opts(
+        &self,
         msg: super::benchmark::Empty,
+        opts: ::copra::stub::CallOptions,
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             super::benchmark::Empty,
@@ -99,7 +120,7 @@ impl<'a> MetricStub<'a> {
         >,
     > {
         self.metric_wrapper
-            .call((msg, "Metric".to_string(), "metric".to_string()))
+            .call_with_options((msg, "Metric".to_string(), "metric".to_string()), opts)
     }
 }
 
@@ -197,6 +218,10 @@ where
         
         entries
     }
+
+    fn file_descriptor(&self) -> Option<Vec<u8>> {
+        ::protobuf::Message::write_to_bytes(super::benchmark::file_descriptor_proto()).ok()
+    }
 }
 
 impl<S> ::copra::dispatcher::NamedRegistrant for PressureRegistrant<S> 
@@ -209,16 +234,16 @@ where
 }
 
 #[derive(Clone)]
-pub struct PressureStub<'a> {
-    echo_wrapper: ::copra::stub::RpcWrapper<'a,
+pub struct PressureStub {
+    echo_wrapper: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<super::benchmark::StringMessage, super::benchmark::StringMessage>>,
 
-    process_wrapper: ::copra::stub::RpcWrapper<'a,
+    process_wrapper: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<super::benchmark::Empty, super::benchmark::PressureRequest>>,
 }
 
-impl<'a> PressureStub<'a> {
-    pub fn new(channel: &'a ::copra::channel::Channel) -> Self {
+impl PressureStub {
+    pub fn new(channel: &::copra::channel::Channel) -> Self {
         PressureStub {
             echo_wrapper: ::copra::stub::RpcWrapper::new(
                 ::copra::codec::ProtobufCodec::new(), channel
@@ -230,22 +255,52 @@ impl<'a> PressureStub<'a> {
         }
     }
 
+    pub fn new_sync(channel: &::copra::sync::SyncChannel) -> Self {
+        PressureStub::new(channel.channel())
+    }
+
     pub fn echo(
-        &'a self, 
+        &self,
         msg: super::benchmark::StringMessage,
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             super::benchmark::StringMessage,
             super::benchmark::StringMessage,
         >,
+    > {
+        self.echo_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn echo_opts(
+        &self,
+        msg: super::benchmark::StringMessage,
+        opts: ::copra::stub::CallOptions,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::benchmark::StringMessage,
+            super::benchmark::StringMessage,
+        >,
     > {
         self.echo_wrapper
-            .call((msg, "Pressure".to_string(), "echo".to_string()))
+            .call_with_options((msg, "Pressure".to_string(), "echo".to_string()), opts)
     }
 
     pub fn process(
-        &'a self, 
+        &self,
+        msg: super::benchmark::PressureRequest,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::benchmark::Empty,
+            super::benchmark::PressureRequest,
+        >,
+    > {
+        self.process_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn process_opts(
+        &self,
         msg: super::benchmark::PressureRequest,
+        opts: ::copra::stub::CallOptions,
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             super::benchmark::Empty,
@@ -253,6 +308,6 @@ impl<'a> PressureStub<'a> {
         >,
     > {
         self.process_wrapper
-            .call((msg, "Pressure".to_string(), "process".to_string()))
+            .call_with_options((msg, "Pressure".to_string(), "process".to_string()), opts)
     }
 }
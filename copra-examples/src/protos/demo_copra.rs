@@ -99,6 +99,10 @@ where
         
         entries
     }
+
+    fn file_descriptor(&self) -> Option<Vec<u8>> {
+        ::protobuf::Message::write_to_bytes(super::demo::file_descriptor_proto()).ok()
+    }
 }
 
 impl<S> ::copra::dispatcher::NamedRegistrant for DemoRegistrant<S> 
@@ -111,16 +115,16 @@ where
 }
 
 #[derive(Clone)]
-pub struct DemoStub<'a> {
-    greet_to_wrapper: ::copra::stub::RpcWrapper<'a,
+pub struct DemoStub {
+    greet_to_wrapper: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<super::demo::GreetMessage, super::demo::GreetMessage>>,
 
-    is_prime_wrapper: ::copra::stub::RpcWrapper<'a,
+    is_prime_wrapper: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<super::demo::PrimeResponse, super::demo::PrimeRequest>>,
 }
 
-impl<'a> DemoStub<'a> {
-    pub fn new(channel: &'a ::copra::channel::Channel) -> Self {
+impl DemoStub {
+    pub fn new(channel: &::copra::channel::Channel) -> Self {
         DemoStub {
             greet_to_wrapper: ::copra::stub::RpcWrapper::new(
                 ::copra::codec::ProtobufCodec::new(), channel
@@ -132,9 +136,26 @@ impl<'a> DemoStub<'a> {
         }
     }
 
+    pub fn new_sync(channel: &::copra::sync::SyncChannel) -> Self {
+        DemoStub::new(channel.channel())
+    }
+
     pub fn greet_to(
-        &'a self, 
+        &self,
+        msg: super::demo::GreetMessage,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::demo::GreetMessage,
+            super::demo::GreetMessage,
+        >,
+    > {
+        self.greet_to_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn greet_to_opts(
+        &self,
         msg: super::demo::GreetMessage,
+        opts: ::copra::stub::CallOptions,
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             super::demo::GreetMessage,
@@ -142,12 +163,25 @@ impl<'a> DemoStub<'a> {
         >,
     > {
         self.greet_to_wrapper
-            .call((msg, "Demo".to_string(), "greet_to".to_string()))
+            .call_with_options((msg, "Demo".to_string(), "greet_to".to_string()), opts)
     }
 
     pub fn is_prime(
-        &'a self, 
+        &self,
+        msg: super::demo::PrimeRequest,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::demo::PrimeResponse,
+            super::demo::PrimeRequest,
+        >,
+    > {
+        self.is_prime_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn is_prime_opts(
+        &self,
         msg: super::demo::PrimeRequest,
+        opts: ::copra::stub::CallOptions,
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             super::demo::PrimeResponse,
@@ -155,6 +189,6 @@ impl<'a> DemoStub<'a> {
         >,
     > {
         self.is_prime_wrapper
-            .call((msg, "Demo".to_string(), "is_prime".to_string()))
+            .call_with_options((msg, "Demo".to_string(), "is_prime".to_string()), opts)
     }
 }
@@ -16,9 +16,16 @@ pub trait EchoService {
         Error = ::copra::service::MethodError,
     > + 'static;
 
+    type SlowEchoFuture: ::futures::Future<
+        Item = (super::echo::EchoResponse, ::copra::controller::Controller), 
+        Error = ::copra::service::MethodError,
+    > + 'static;
+
     fn echo(&self, msg: (super::echo::EchoRequest, ::copra::controller::Controller)) -> Self::EchoFuture;
 
     fn rev_echo(&self, msg: (super::echo::EchoRequest, ::copra::controller::Controller)) -> Self::RevEchoFuture;
+
+    fn slow_echo(&self, msg: (super::echo::EchoRequest, ::copra::controller::Controller)) -> Self::SlowEchoFuture;
 }
 
 pub struct EchoRegistrant<S> {
@@ -97,8 +104,41 @@ where
             ));
         }
         
+        {
+            #[derive(Clone)]
+            struct Wrapper<S: Clone>(S);
+
+            impl<S> ::copra::service::Service for Wrapper<S>
+            where
+                S: EchoService + Clone,
+            {
+                type Request = (super::echo::EchoRequest, ::copra::controller::Controller);
+                type Response = (super::echo::EchoResponse, ::copra::controller::Controller);
+                type Error = ::copra::service::MethodError;
+                type Future = <S as EchoService>::SlowEchoFuture;
+
+                fn call(&self, req: Self::Request) -> Self::Future {
+                    self.0.slow_echo(req)
+                }
+            }
+
+            let wrap = Wrapper(provider.clone());
+            let method = ::copra::service::EncapsulatedMethod::new(
+                ::copra::codec::ProtobufCodec::new(), wrap
+            );
+            let new_method = ::copra::service::NewEncapsulatedMethod::new(method);
+            entries.push((
+                "slow_echo".to_string(), 
+                Box::new(new_method) as ::copra::service::NewEncapService,
+            ));
+        }
+        
         entries
     }
+
+    fn file_descriptor(&self) -> Option<Vec<u8>> {
+        ::protobuf::Message::write_to_bytes(super::echo::file_descriptor_proto()).ok()
+    }
 }
 
 impl<S> ::copra::dispatcher::NamedRegistrant for EchoRegistrant<S> 
@@ -111,16 +151,19 @@ where
 }
 
 #[derive(Clone)]
-pub struct EchoStub<'a> {
-    echo_wrapper: ::copra::stub::RpcWrapper<'a,
+pub struct EchoStub {
+    echo_wrapper: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<super::echo::EchoResponse, super::echo::EchoRequest>>,
 
-    rev_echo_wrapper: ::copra::stub::RpcWrapper<'a,
+    rev_echo_wrapper: ::copra::stub::RpcWrapper<
+        ::copra::codec::ProtobufCodec<super::echo::EchoResponse, super::echo::EchoRequest>>,
+
+    slow_echo_wrapper: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<super::echo::EchoResponse, super::echo::EchoRequest>>,
 }
 
-impl<'a> EchoStub<'a> {
-    pub fn new(channel: &'a ::copra::channel::Channel) -> Self {
+impl EchoStub {
+    pub fn new(channel: &::copra::channel::Channel) -> Self {
         EchoStub {
             echo_wrapper: ::copra::stub::RpcWrapper::new(
                 ::copra::codec::ProtobufCodec::new(), channel
@@ -129,25 +172,59 @@ impl<'a> EchoStub<'a> {
             rev_echo_wrapper: ::copra::stub::RpcWrapper::new(
                 ::copra::codec::ProtobufCodec::new(), channel
             ),
+
+            slow_echo_wrapper: ::copra::stub::RpcWrapper::new(
+                ::copra::codec::ProtobufCodec::new(), channel
+            ),
         }
     }
 
+    pub fn new_sync(channel: &::copra::sync::SyncChannel) -> Self {
+        EchoStub::new(channel.channel())
+    }
+
     pub fn echo(
-        &'a self, 
+        &self,
         msg: super::echo::EchoRequest,
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             super::echo::EchoResponse,
             super::echo::EchoRequest,
         >,
+    > {
+        self.echo_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn echo_opts(
+        &self,
+        msg: super::echo::EchoRequest,
+        opts: ::copra::stub::CallOptions,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::echo::EchoResponse,
+            super::echo::EchoRequest,
+        >,
     > {
         self.echo_wrapper
-            .call((msg, "Echo".to_string(), "echo".to_string()))
+            .call_with_options((msg, "Echo".to_string(), "echo".to_string()), opts)
     }
 
     pub fn rev_echo(
-        &'a self, 
+        &self,
+        msg: super::echo::EchoRequest,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::echo::EchoResponse,
+            super::echo::EchoRequest,
+        >,
+    > {
+        self.rev_echo_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn rev_echo_opts(
+        &self,
         msg: super::echo::EchoRequest,
+        opts: ::copra::stub::CallOptions,
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             super::echo::EchoResponse,
@@ -155,6 +232,32 @@ impl<'a> EchoStub<'a> {
         >,
     > {
         self.rev_echo_wrapper
-            .call((msg, "Echo".to_string(), "rev_echo".to_string()))
+            .call_with_options((msg, "Echo".to_string(), "rev_echo".to_string()), opts)
+    }
+
+    pub fn slow_echo(
+        &self,
+        msg: super::echo::EchoRequest,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::echo::EchoResponse,
+            super::echo::EchoRequest,
+        >,
+    > {
+        self.slow_echo_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn slow_echo_opts(
+        &self,
+        msg: super::echo::EchoRequest,
+        opts: ::copra::stub::CallOptions,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::echo::EchoResponse,
+            super::echo::EchoRequest,
+        >,
+    > {
+        self.slow_echo_wrapper
+            .call_with_options((msg, "Echo".to_string(), "slow_echo".to_string()), opts)
     }
 }
@@ -99,6 +99,10 @@ where
         
         entries
     }
+
+    fn file_descriptor(&self) -> Option<Vec<u8>> {
+        ::protobuf::Message::write_to_bytes(super::http_hello::file_descriptor_proto()).ok()
+    }
 }
 
 impl<S> ::copra::dispatcher::NamedRegistrant for HelloRegistrant<S> 
@@ -111,16 +115,16 @@ where
 }
 
 #[derive(Clone)]
-pub struct HelloStub<'a> {
-    hello_general_wrapper: ::copra::stub::RpcWrapper<'a,
+pub struct HelloStub {
+    hello_general_wrapper: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<super::http_hello::HelloResponse, super::http_hello::HelloRequest>>,
 
-    hello_to_wrapper: ::copra::stub::RpcWrapper<'a,
+    hello_to_wrapper: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<super::http_hello::HelloResponse, super::http_hello::HelloRequest>>,
 }
 
-impl<'a> HelloStub<'a> {
-    pub fn new(channel: &'a ::copra::channel::Channel) -> Self {
+impl HelloStub {
+    pub fn new(channel: &::copra::channel::Channel) -> Self {
         HelloStub {
             hello_general_wrapper: ::copra::stub::RpcWrapper::new(
                 ::copra::codec::ProtobufCodec::new(), channel
@@ -132,9 +136,26 @@ impl<'a> HelloStub<'a> {
         }
     }
 
+    pub fn new_sync(channel: &::copra::sync::SyncChannel) -> Self {
+        HelloStub::new(channel.channel())
+    }
+
     pub fn hello_general(
-        &'a self, 
+        &self,
+        msg: super::http_hello::HelloRequest,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::http_hello::HelloResponse,
+            super::http_hello::HelloRequest,
+        >,
+    > {
+        self.hello_general_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn hello_general_opts(
+        &self,
         msg: super::http_hello::HelloRequest,
+        opts: ::copra::stub::CallOptions,
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             super::http_hello::HelloResponse,
@@ -142,12 +163,25 @@ impl<'a> HelloStub<'a> {
         >,
     > {
         self.hello_general_wrapper
-            .call((msg, "Hello".to_string(), "hello_general".to_string()))
+            .call_with_options((msg, "Hello".to_string(), "hello_general".to_string()), opts)
     }
 
     pub fn hello_to(
-        &'a self, 
+        &self,
+        msg: super::http_hello::HelloRequest,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::http_hello::HelloResponse,
+            super::http_hello::HelloRequest,
+        >,
+    > {
+        self.hello_to_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn hello_to_opts(
+        &self,
         msg: super::http_hello::HelloRequest,
+        opts: ::copra::stub::CallOptions,
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             super::http_hello::HelloResponse,
@@ -155,6 +189,6 @@ impl<'a> HelloStub<'a> {
         >,
     > {
         self.hello_to_wrapper
-            .call((msg, "Hello".to_string(), "hello_to".to_string()))
+            .call_with_options((msg, "Hello".to_string(), "hello_to".to_string()), opts)
     }
 }
@@ -0,0 +1,139 @@
+//! Addresses of the server of a single server channel
+
+use std::borrow::Cow;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+/// The server of a channel, as addresses or a host name to resolve
+///
+/// Created by [`IntoServerAddr`].
+///
+/// [`IntoServerAddr`]: trait.IntoServerAddr.html
+#[derive(Clone, Debug)]
+pub struct ServerAddr<'a>(pub(crate) Target<'a>);
+
+#[derive(Clone, Debug)]
+pub(crate) enum Target<'a> {
+    /// An address, or a host name and a port as "host:port"
+    Str(Cow<'a, str>),
+    /// A host name, or an IP address, and a port
+    Host(Cow<'a, str>, u16),
+    /// Addresses needing no resolution
+    Addrs(Vec<SocketAddr>),
+}
+
+/// Conversion into the server of a channel
+///
+/// It is implemented for the types of the standard library which implement
+/// `ToSocketAddrs`, except that the host names are resolved by the resolver
+/// of the channel, off the event loop, instead of blocking.
+pub trait IntoServerAddr<'a> {
+    /// Convert into the server of a channel.
+    fn into_server_addr(self) -> ServerAddr<'a>;
+}
+
+impl<'a> IntoServerAddr<'a> for ServerAddr<'a> {
+    fn into_server_addr(self) -> ServerAddr<'a> {
+        self
+    }
+}
+
+impl<'a> IntoServerAddr<'a> for &'a str {
+    fn into_server_addr(self) -> ServerAddr<'a> {
+        ServerAddr(Target::Str(Cow::Borrowed(self)))
+    }
+}
+
+impl<'a> IntoServerAddr<'a> for &'a String {
+    fn into_server_addr(self) -> ServerAddr<'a> {
+        ServerAddr(Target::Str(Cow::Borrowed(self.as_str())))
+    }
+}
+
+impl<'a> IntoServerAddr<'a> for String {
+    fn into_server_addr(self) -> ServerAddr<'a> {
+        ServerAddr(Target::Str(Cow::Owned(self)))
+    }
+}
+
+impl<'a> IntoServerAddr<'a> for (&'a str, u16) {
+    fn into_server_addr(self) -> ServerAddr<'a> {
+        ServerAddr(Target::Host(Cow::Borrowed(self.0), self.1))
+    }
+}
+
+impl<'a> IntoServerAddr<'a> for (String, u16) {
+    fn into_server_addr(self) -> ServerAddr<'a> {
+        ServerAddr(Target::Host(Cow::Owned(self.0), self.1))
+    }
+}
+
+impl<'a> IntoServerAddr<'a> for &'a [SocketAddr] {
+    fn into_server_addr(self) -> ServerAddr<'a> {
+        ServerAddr(Target::Addrs(self.to_vec()))
+    }
+}
+
+macro_rules! into_socket_addr {
+    ($($ty:ty),*) => {
+        $(
+            impl<'a> IntoServerAddr<'a> for $ty {
+                fn into_server_addr(self) -> ServerAddr<'a> {
+                    ServerAddr(Target::Addrs(vec![SocketAddr::from(self)]))
+                }
+            }
+        )*
+    };
+}
+
+into_socket_addr!(
+    SocketAddr,
+    SocketAddrV4,
+    SocketAddrV6,
+    (IpAddr, u16),
+    (Ipv4Addr, u16),
+    (Ipv6Addr, u16)
+);
+
+impl<'a> fmt::Display for Target<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Str(ref addr) => write!(f, "{}", addr),
+            Target::Host(ref host, port) => write!(f, "{}:{}", host, port),
+            Target::Addrs(ref addrs) => {
+                let addrs: Vec<_> = addrs.iter().map(SocketAddr::to_string).collect();
+                write!(f, "{}", addrs.join(","))
+            }
+        }
+    }
+}
+
+impl<'a> Target<'a> {
+    /// Split "host:port" into the host and the port, if it is not an
+    /// address already.
+    pub fn host_and_port(host: &str) -> Option<(&str, u16)> {
+        let colon = host.rfind(':')?;
+        let port = host[colon + 1..].parse().ok()?;
+        let host = &host[..colon];
+        if host.is_empty() {
+            None
+        } else {
+            Some((host, port))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_host_and_port() {
+        assert_eq!(Target::host_and_port("localhost:8000"), Some(("localhost", 8000)));
+        assert_eq!(Target::host_and_port("a.b.c:1"), Some(("a.b.c", 1)));
+        assert_eq!(Target::host_and_port("localhost"), None);
+        assert_eq!(Target::host_and_port(":8000"), None);
+        assert_eq!(Target::host_and_port("localhost:http"), None);
+        assert_eq!(Target::host_and_port("localhost:70000"), None);
+    }
+}
@@ -1,53 +1,1377 @@
 use futures::{Async, Future, Poll, Stream};
+use futures::future::Shared;
 use futures::stream::FuturesUnordered;
-use futures::sync::oneshot;
-use tokio_core::reactor::Handle;
+use futures::sync::{mpsc, oneshot};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Handle, Timeout};
 use tokio_service::Service;
+use tokio_timer::{Sleep, Timer};
 
-use super::{ChannelReceiver, OneShotSender, RequestPackage};
-use load_balancer::LoadBalance;
+use super::connector::Dialer;
+use super::event::{emit, ChannelEvent, EventHook};
+use super::health::HealthCheck;
+use super::keepalive::Keepalive;
+use super::{closed_error, connect, BackupCounters, Callback, ChannelMessage, ChannelReceiver,
+            CircuitBreaker, ConnectFuture, ConnectionOptions, Counted, OneShotSender,
+            RequestPackage, ResponsePackage, SendOptions, ServerCounters, ServerList,
+            ShutdownReceiver};
+use errno;
+use load_balancer::{BackendInfo, LbHint, LoadBalance, Random, ServerEndPort, ServerId};
+use naming::{ServerEndpoint, ServerStream};
 
 use super::{FeedbackHandle, FeedbackReceiver};
 
+/// Most calls kept while the servers of a lazy channel are connected, more
+/// fail at once
+const MAX_WAITING_CALLS: usize = 1024;
+
+/// Delays before connecting again to a server which can not be reached
+///
+/// The delay doubles with every failed attempt, from `min` up to `max`, and
+/// a random part of up to half of it is taken off, so that channels do not
+/// all connect again at once to a restarted server.
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    min: Duration,
+    max: Duration,
+    jitter: Random,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Backoff {
+            min,
+            max: max.max(min),
+            jitter: Random::new(),
+        }
+    }
+
+    /// Get the delay after `failures` failed attempts in a row.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let doubled = (1..failures).fold(self.min, |delay, _| {
+            delay.checked_mul(2).unwrap_or(self.max).min(self.max)
+        });
+        let nanos = doubled.as_secs() * 1_000_000_000 + u64::from(doubled.subsec_nanos());
+        let nanos = nanos - self.jitter.next() % (nanos / 2 + 1);
+        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+    }
+}
+
+enum State {
+    Connected(ServerEndPort),
+    Connecting(ConnectFuture),
+    Waiting(Sleep),
+    /// Not connected until a call needs it
+    Idle,
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            State::Connected(ref end_port) => write!(f, "State::Connected({:?})", end_port),
+            State::Connecting(_) => write!(f, "State::Connecting"),
+            State::Waiting(_) => write!(f, "State::Waiting"),
+            State::Idle => write!(f, "State::Idle"),
+        }
+    }
+}
+
+/// Tags of a server given by a naming service, `None` if there is none
+pub(crate) type Tags = Option<Arc<HashMap<String, String>>>;
+
+/// Get the tags of `server`.
+pub(crate) fn tags_of(server: &ServerEndpoint) -> Tags {
+    if server.tags().is_empty() {
+        None
+    } else {
+        Some(Arc::new(server.tags().clone()))
+    }
+}
+
+/// A connection to a server, established again in the background when it
+/// goes down
+///
+/// Each connection has its own request ids, so the calls in flight are
+/// tracked per connection.
+#[derive(Debug)]
+pub(crate) struct Connection {
+    state: State,
+    /// Failed attempts to connect since the connection was last up
+    failures: u32,
+    /// Whether the connection is not established yet for a lazy channel
+    first: bool,
+}
+
+impl Connection {
+    /// Use the connection `end_port` established to a server.
+    pub fn connected(end_port: ServerEndPort) -> Self {
+        Connection {
+            state: State::Connected(end_port),
+            failures: 0,
+            first: false,
+        }
+    }
+
+    /// Connect again to `addr` after the first delay of `backoff`, the first
+    /// attempt failed.
+    pub fn failed(
+        addr: SocketAddr,
+        timer: &Timer,
+        backoff: &Backoff,
+        events: Option<&EventHook>,
+    ) -> Self {
+        let delay = backoff.delay(1);
+        emit(events, ChannelEvent::ReconnectScheduled { addr, delay });
+        Connection {
+            state: State::Waiting(timer.sleep(delay)),
+            failures: 1,
+            first: false,
+        }
+    }
+
+    /// Connect once a call needs the connection.
+    pub fn idle() -> Self {
+        Connection {
+            state: State::Idle,
+            failures: 0,
+            first: true,
+        }
+    }
+
+    fn connecting(connect: ConnectFuture) -> Self {
+        Connection {
+            state: State::Connecting(connect),
+            failures: 0,
+            first: false,
+        }
+    }
+
+    /// Check if the connection is established for the first time, on
+    /// demand.
+    fn is_first_connect(&self) -> bool {
+        match self.state {
+            State::Connecting(_) => self.first,
+            _ => false,
+        }
+    }
+
+    fn end_port(&self) -> Option<&ServerEndPort> {
+        match self.state {
+            State::Connected(ref end_port) if end_port.is_connected() => Some(end_port),
+            _ => None,
+        }
+    }
+
+    /// Get the next state of the connection to `addr`, or `None` if it stays
+    /// the same.
+    fn advance(&mut self, addr: SocketAddr, ctx: &Reconnect) -> Option<State> {
+        let events = ctx.events;
+        let name = &ctx.conn_options.name;
+        match self.state {
+            State::Connected(ref end_port) => {
+                let link = end_port.link();
+                // woken as soon as the connection goes down
+                link.register();
+                if link.is_connected() {
+                    return None;
+                }
+                warn!("[{}] Connection to {} is down, reconnecting", name, addr);
+                let cause = link.take_cause().unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::ConnectionAborted, "connection lost")
+                });
+                emit(events, ChannelEvent::Disconnected { addr, cause });
+                let delay = Duration::from_secs(0);
+                emit(events, ChannelEvent::ReconnectScheduled { addr, delay });
+            }
+            State::Connecting(ref mut connect) => match connect.poll() {
+                Ok(Async::Ready(end_port)) => {
+                    if self.first {
+                        info!("[{}] Connected to {}", name, addr);
+                    } else {
+                        info!("[{}] Reconnected to {}", name, addr);
+                    }
+                    self.failures = 0;
+                    self.first = false;
+                    emit(events, ChannelEvent::Connected(addr));
+                    return Some(State::Connected(end_port));
+                }
+                Ok(Async::NotReady) => return None,
+                Err(e) => {
+                    self.first = false;
+                    self.failures += 1;
+                    let delay = ctx.backoff.delay(self.failures);
+                    debug!(
+                        "[{}] Failed to reconnect to {}, retrying in {:?}: {}",
+                        name, addr, delay, e
+                    );
+                    emit(events, ChannelEvent::ReconnectScheduled { addr, delay });
+                    return Some(State::Waiting(ctx.timer.sleep(delay)));
+                }
+            },
+            State::Waiting(ref mut sleep) => match sleep.poll() {
+                Ok(Async::NotReady) => return None,
+                // a failed timer retries at once
+                Ok(Async::Ready(())) | Err(_) => {}
+            },
+            State::Idle => return None,
+        }
+        let connect = connect(
+            ctx.conn_options,
+            addr,
+            ctx.handle,
+            ctx.timer,
+            ctx.connect_timeout,
+            ctx.dialer,
+        );
+        Some(State::Connecting(connect))
+    }
+}
+
+/// What the connections of a channel need to connect again
+struct Reconnect<'a> {
+    conn_options: &'a ConnectionOptions,
+    handle: &'a Handle,
+    timer: &'a Timer,
+    backoff: &'a Backoff,
+    connect_timeout: Option<Duration>,
+    dialer: &'a Dialer,
+    events: Option<&'a EventHook>,
+}
+
+/// The connections to one server of a channel
+///
+/// Calls are spread over the connections which are up in turn.
+#[derive(Debug)]
+struct Backend {
+    addr: SocketAddr,
+    conns: Vec<Connection>,
+    /// The connection tried first by the next call
+    next: usize,
+    counters: Arc<ServerCounters>,
+    tags: Tags,
+    /// Whether a health check probe is not answered yet
+    probing: bool,
+}
+
+impl Backend {
+    fn new(conns: Vec<Connection>, counters: Arc<ServerCounters>, tags: Tags) -> Self {
+        Backend {
+            addr: counters.addr,
+            conns,
+            next: 0,
+            counters,
+            tags,
+            probing: false,
+        }
+    }
+
+    /// Get a connection which is up, if any.
+    fn end_port(&self) -> Option<&ServerEndPort> {
+        self.conns.iter().filter_map(Connection::end_port).next()
+    }
+
+    /// Get the connection which is up to send the next call on.
+    fn pick(&mut self) -> Option<&ServerEndPort> {
+        let len = self.conns.len();
+        let start = self.next;
+        let found = (0..len)
+            .map(|i| (start + i) % len)
+            .find(|&i| self.conns[i].end_port().is_some());
+        match found {
+            Some(i) => {
+                self.next = (i + 1) % len;
+                self.conns[i].end_port()
+            }
+            None => None,
+        }
+    }
+
+    /// Get the information given to the load balancer, the server is shown
+    /// as not connected unless `usable`, healthy and let through by its
+    /// breaker.
+    fn info(&self, usable: bool) -> BackendInfo {
+        let inflight = self.counters.inflight.load(Ordering::SeqCst);
+        let connected = usable && self.end_port().is_some()
+            && self.counters.healthy.load(Ordering::SeqCst)
+            && self.counters.breaker.allows();
+        let info = BackendInfo::new(self.addr, connected, inflight)
+            .with_weight(self.counters.weight());
+        match self.tags {
+            Some(ref tags) => info.with_tags(tags.clone()),
+            None => info,
+        }
+    }
+
+    /// Start connecting the connections which wait for a call.
+    fn wake(
+        &mut self,
+        conn_options: &ConnectionOptions,
+        handle: &Handle,
+        timer: &Timer,
+        connect_timeout: Option<Duration>,
+        dialer: &Dialer,
+    ) {
+        for conn in &mut self.conns {
+            if let State::Idle = conn.state {
+                let connect =
+                    connect(conn_options, self.addr, handle, timer, connect_timeout, dialer);
+                conn.state = State::Connecting(connect);
+            }
+        }
+    }
+
+    /// Move every connection to its next state, and connect again the ones
+    /// which are down.
+    fn poll_connections(&mut self, ctx: &Reconnect) {
+        for conn in &mut self.conns {
+            // a new connection may be ready at once
+            while let Some(state) = conn.advance(self.addr, ctx) {
+                conn.state = state;
+            }
+        }
+    }
+}
+
+/// The naming service the servers of a channel follow
+pub(crate) struct Naming {
+    updates: ServerStream,
+    /// The servers shown by the channel
+    servers: ServerList,
+    ended: bool,
+}
+
+impl Naming {
+    pub fn new(updates: ServerStream, servers: ServerList) -> Self {
+        Naming {
+            updates,
+            servers,
+            ended: false,
+        }
+    }
+
+    /// Get the latest servers given by the naming service since the last
+    /// poll, if any, telling `name` as the name of the channel in the logs.
+    fn poll(&mut self, name: &str) -> Option<Vec<ServerEndpoint>> {
+        let mut latest = None;
+        while !self.ended {
+            match self.updates.poll() {
+                Ok(Async::NotReady) => break,
+                Ok(Async::Ready(Some(ref servers))) if servers.is_empty() => {
+                    warn!("[{}] Naming service gave no server, keeping the servers", name);
+                }
+                Ok(Async::Ready(Some(servers))) => latest = Some(servers),
+                Ok(Async::Ready(None)) => {
+                    info!("[{}] Naming service ended, keeping the servers for good", name);
+                    self.ended = true;
+                }
+                Err(e) => warn!("[{}] Naming service failed, keeping the servers: {}", name, e),
+            }
+        }
+        latest
+    }
+}
+
+/// A request sent to a server, counted in flight until it is answered or
+/// dropped
+///
+/// The load balancer and the circuit breaker of the server are told about
+/// the outcome, a dropped request counts as failed. Its response is then
+/// discarded by the connection.
+struct Attempt {
+    id: usize,
+    call: <ServerEndPort as Service>::Future,
+    counters: Arc<ServerCounters>,
+    lb: Arc<LoadBalance>,
+    start: Instant,
+    finished: bool,
+}
+
+impl Attempt {
+    fn new(
+        id: usize,
+        end_port: &ServerEndPort,
+        req: RequestPackage,
+        counters: Arc<ServerCounters>,
+        lb: Arc<LoadBalance>,
+    ) -> Self {
+        counters.inflight.fetch_add(1, Ordering::SeqCst);
+        counters.breaker.on_sent();
+        Attempt {
+            id,
+            call: end_port.call(req),
+            counters,
+            lb,
+            start: Instant::now(),
+            finished: false,
+        }
+    }
+
+    /// Check if any byte of the request is written to the connection.
+    fn is_sent(&self) -> bool {
+        self.call.is_sent()
+    }
+}
+
+impl Future for Attempt {
+    type Item = io::Result<ResponsePackage>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.call.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(resp)) => Ok(resp),
+            Err(e) => Err(e),
+        };
+        self.finished = true;
+        self.counters.inflight.fetch_sub(1, Ordering::SeqCst);
+        match result {
+            Ok((ref meta, _)) if meta.get_error_code() == errno::SUCCESS => {
+                self.counters.breaker.on_done(false);
+                self.lb.on_success(self.id, self.start.elapsed())
+            }
+            _ => {
+                self.counters.breaker.on_done(true);
+                self.lb.on_error(self.id, self.start.elapsed())
+            }
+        }
+        Ok(Async::Ready(result))
+    }
+}
+
+impl Drop for Attempt {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.counters.inflight.fetch_sub(1, Ordering::SeqCst);
+            self.counters.breaker.on_done(true);
+            self.lb.on_error(self.id, self.start.elapsed());
+        }
+    }
+}
+
+/// A backup request for the backend to send to a server other than
+/// `exclude`, or a call to send again as its server failed before its
+/// request was sent
+struct BackupRequest {
+    req: RequestPackage,
+    hint: Option<LbHint>,
+    exclude: usize,
+    failover: bool,
+    sender: oneshot::Sender<Attempt>,
+}
+
+type BackupSender = mpsc::UnboundedSender<BackupRequest>;
+
+type BackupReceiver = mpsc::UnboundedReceiver<BackupRequest>;
+
+/// The duplicate of a call, sent to another server if the call is not
+/// answered in time
+enum Backup {
+    /// Waiting for the delay, with the request, how it is sent and the way
+    /// to the backend
+    ///
+    /// The timer of the reactor is used, as the one of the channel may fire
+    /// a tick early, which matters to short delays.
+    Waiting(Timeout, Box<RequestPackage>, SendOptions, BackupSender),
+    /// Waiting for the backend to choose a server, the server is chosen
+    /// only now so that the load balancer is not asked for calls which need
+    /// no backup
+    Sending(oneshot::Receiver<Attempt>),
+    Sent(Attempt),
+}
+
+/// The way to send a call again to another server, if the connection to
+/// its server breaks before any byte of its request is written
+///
+/// The server never saw such a request, so it is sent again whatever the
+/// method, within the deadline of the call.
+struct Failover {
+    req: Box<RequestPackage>,
+    options: SendOptions,
+    /// Times the call may still be sent again
+    left: u32,
+    requests: BackupSender,
+    /// Waiting for the backend to choose another server, with the server
+    /// which failed and its error
+    sending: Option<(oneshot::Receiver<Attempt>, usize, io::Error)>,
+}
+
+/// A call waiting for its response, given up as soon as the caller stops
+/// waiting for it, or failed when the channel is shut down before it is
+/// answered
+///
+/// Resolves to the server which answered, the response and the sender to
+/// deliver it with, or to `None` if the call has timed out or been dropped.
+///
+/// With a backup request, the first response is taken and the other
+/// request is dropped. If the backup request is sent and one of them fails,
+/// the other is waited for.
+///
+/// A call whose request is not sent when its connection breaks fails over
+/// to another server, see `Failover`.
+struct PendingCall {
+    /// Name of the channel, told by the logs
+    name: Arc<str>,
+    attempt: Option<Attempt>,
+    backup: Option<Backup>,
+    failover: Option<Failover>,
+    backups: Arc<BackupCounters>,
+    sender: Option<OneShotSender>,
+    /// Resolves when the channel stops waiting for its calls
+    abort: Option<Shared<oneshot::Receiver<()>>>,
+}
+
+impl PendingCall {
+    /// Advance the backup request, and get its response if it is taken.
+    fn poll_backup(&mut self) -> Option<(usize, io::Result<ResponsePackage>)> {
+        loop {
+            let next = match self.backup {
+                Some(Backup::Waiting(ref mut delay, ref req, options, ref requests)) => {
+                    match delay.poll() {
+                        Ok(Async::NotReady) => return None,
+                        // a failed timer sends the backup request at once
+                        Ok(Async::Ready(())) | Err(_) => {}
+                    }
+                    // sent once the call fails over
+                    let exclude = match self.attempt {
+                        Some(ref attempt) => attempt.id,
+                        None => return None,
+                    };
+                    let (sender, receiver) = oneshot::channel();
+                    let mut req = (**req).clone();
+                    options.refresh_timeout(&mut req);
+                    let request = BackupRequest {
+                        req,
+                        hint: options.hint,
+                        exclude,
+                        failover: false,
+                        sender,
+                    };
+                    match requests.unbounded_send(request) {
+                        Ok(()) => Some(Backup::Sending(receiver)),
+                        Err(_) => None,
+                    }
+                }
+                Some(Backup::Sending(ref mut receiver)) => match receiver.poll() {
+                    Ok(Async::NotReady) => return None,
+                    Ok(Async::Ready(attempt)) => Some(Backup::Sent(attempt)),
+                    // no other server is connected
+                    Err(_) => None,
+                },
+                Some(Backup::Sent(ref mut attempt)) => {
+                    let result = match attempt.poll() {
+                        Ok(Async::Ready(result)) => result,
+                        Ok(Async::NotReady) | Err(()) => return None,
+                    };
+                    match result {
+                        Err(ref e) if self.attempt.is_some() => {
+                            debug!(
+                                "[{}] Backup request failed, waiting for the call: {}",
+                                self.name, e
+                            );
+                        }
+                        result => {
+                            if result.is_ok() {
+                                self.backups.won.fetch_add(1, Ordering::SeqCst);
+                            }
+                            return Some((attempt.id, result));
+                        }
+                    }
+                    None
+                }
+                None => return None,
+            };
+            self.backup = next;
+        }
+    }
+
+    /// Poll the call, and get its response if it is taken.
+    fn poll_attempt(&mut self) -> Option<(usize, io::Result<ResponsePackage>)> {
+        loop {
+            if let Some(answer) = self.poll_failover() {
+                return Some(answer);
+            }
+            let (id, result, sent) = match self.attempt {
+                Some(ref mut attempt) => match attempt.poll() {
+                    Ok(Async::Ready(result)) => (attempt.id, result, attempt.is_sent()),
+                    Ok(Async::NotReady) | Err(()) => return None,
+                },
+                None => return None,
+            };
+            self.attempt = None;
+            match (result, &self.backup) {
+                (Err(e), &Some(Backup::Sent(_))) => {
+                    debug!("[{}] Call failed, waiting for its backup request: {}", self.name, e);
+                    return None;
+                }
+                (Err(e), _) if !sent => {
+                    if let Err(e) = self.fail_over(id, e) {
+                        return Some((id, Err(e)));
+                    }
+                }
+                (result, _) => return Some((id, result)),
+            }
+        }
+    }
+
+    /// Ask the backend to send the call again to a server other than `id`,
+    /// whose connection failed with `e` before the request was sent, or get
+    /// `e` back if the call can not fail over.
+    fn fail_over(&mut self, id: usize, e: io::Error) -> Result<(), io::Error> {
+        // a request which can not be encoded fails the same anywhere
+        if e.kind() == io::ErrorKind::InvalidData {
+            return Err(e);
+        }
+        let failover = match self.failover {
+            Some(ref mut failover) if failover.left > 0 => failover,
+            _ => return Err(e),
+        };
+        if let Some(deadline) = failover.options.deadline {
+            if Instant::now() >= deadline {
+                return Err(e);
+            }
+        }
+        let (sender, receiver) = oneshot::channel();
+        let mut req = (*failover.req).clone();
+        failover.options.refresh_timeout(&mut req);
+        let request = BackupRequest {
+            req,
+            hint: failover.options.hint,
+            exclude: id,
+            failover: true,
+            sender,
+        };
+        if failover.requests.unbounded_send(request).is_err() {
+            return Err(e);
+        }
+        debug!("[{}] Request to server {} was not sent, failing over: {}", self.name, id, e);
+        failover.left -= 1;
+        failover.sending = Some((receiver, id, e));
+        Ok(())
+    }
+
+    /// Take the call sent again once the backend chose its server, or get
+    /// the error of the failed server if no other one is connected.
+    fn poll_failover(&mut self) -> Option<(usize, io::Result<ResponsePackage>)> {
+        let failover = match self.failover {
+            Some(ref mut failover) => failover,
+            None => return None,
+        };
+        let (mut receiver, id, e) = failover.sending.take()?;
+        match receiver.poll() {
+            Ok(Async::NotReady) => {
+                failover.sending = Some((receiver, id, e));
+                None
+            }
+            Ok(Async::Ready(attempt)) => {
+                self.attempt = Some(attempt);
+                None
+            }
+            Err(_) => Some((id, Err(e))),
+        }
+    }
+}
+
+impl Future for PendingCall {
+    type Item = Option<(usize, io::Result<ResponsePackage>, OneShotSender)>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let answer = match self.poll_attempt() {
+            Some(answer) => Some(answer),
+            None => self.poll_backup(),
+        };
+        if let Some((id, result)) = answer {
+            // the other request is given up
+            self.attempt = None;
+            self.backup = None;
+            let sender = self.sender.take().expect("polled after completion");
+            return Ok(Async::Ready(Some((id, result, sender))));
+        }
+
+        let aborted = match self.abort {
+            Some(ref mut abort) => match abort.poll() {
+                Ok(Async::NotReady) => false,
+                Ok(Async::Ready(_)) => true,
+                // the channel is dropped, the call is waited for as before
+                Err(_) => {
+                    self.abort = None;
+                    false
+                }
+            },
+            None => false,
+        };
+        if aborted {
+            self.attempt = None;
+            self.backup = None;
+            let sender = self.sender.take().expect("polled after completion");
+            let _ = sender.send(Err(closed_error()));
+            return Ok(Async::Ready(None));
+        }
+
+        let sender = self.sender.as_mut().expect("polled after completion");
+        match sender.poll_cancel() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(())) | Err(()) => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+/// A shutdown in progress, waiting for the calls in flight
+struct Closing {
+    /// When the calls in flight are given up, `None` if they are given up
+    /// at once
+    deadline: Option<Timeout>,
+    /// Notified once the channel is shut down
+    done: Vec<oneshot::Sender<()>>,
+}
+
+/// Sends the calls of a channel to its servers, and keeps the connections
+///
+/// The backend runs on the event loop the channel is built with, and ends
+/// once the channel and all its clones are dropped, or once the channel is
+/// shut down.
 #[must_use = "Channel backend must be spawned in a reactor, otherwise no request will be sent"]
 pub struct ChannelBackend {
     handle: Handle,
-    lb: Box<LoadBalance>,
+    lb: Arc<LoadBalance>,
+    backends: Vec<Backend>,
+    /// Servers no longer resolved, kept until their calls are answered
+    draining: Vec<Backend>,
+    naming: Option<Naming>,
+    conn_options: ConnectionOptions,
+    timer: Timer,
+    backoff: Backoff,
+    connect_timeout: Option<Duration>,
+    dialer: Dialer,
+    /// Connections made to every server
+    pool_size: usize,
+    /// Whether the servers are connected only once the first call is sent
+    lazy: bool,
+    /// Calls sent while the servers of a lazy channel are connected
+    waiting: VecDeque<ChannelMessage>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    health_check: Option<HealthCheck>,
+    keepalive: Option<Keepalive>,
+    events: Option<EventHook>,
+    /// Times a call whose request is not sent may be sent to another server
+    max_failover: u32,
+    backups: Arc<BackupCounters>,
+    backup_sender: BackupSender,
+    backup_receiver: BackupReceiver,
     recv: ChannelReceiver,
     feedbacks: FuturesUnordered<FeedbackReceiver>,
+    /// Resolve once their calls are answered or given up
+    calls: FuturesUnordered<oneshot::Receiver<()>>,
+    shutdown: Option<ShutdownReceiver>,
+    drain_timeout: Duration,
+    closing: Option<Closing>,
+    /// Tells the calls in flight to give up
+    abort_sender: Option<oneshot::Sender<()>>,
+    abort: Shared<oneshot::Receiver<()>>,
+}
+
+impl fmt::Debug for ChannelBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ChannelBackend")
+            .field("servers", &self.backends.len())
+            .field("draining", &self.draining.len())
+            .field("waiting", &self.waiting.len())
+            .field("closing", &self.closing.is_some())
+            .finish()
+    }
 }
 
 impl ChannelBackend {
-    pub fn new<L>(recv: ChannelReceiver, handle: Handle, lb: L) -> Self
-    where
-        L: LoadBalance + 'static,
-    {
+    /// Create a backend sending calls to `servers`, with the connections to
+    /// each server.
+    pub(crate) fn new(
+        recv: ChannelReceiver,
+        handle: Handle,
+        lb: Arc<LoadBalance>,
+        servers: Vec<(Vec<Connection>, Arc<ServerCounters>, Tags)>,
+        conn_options: ConnectionOptions,
+        timer: Timer,
+        backoff: Backoff,
+    ) -> Self {
+        let backends = servers
+            .into_iter()
+            .map(|(conns, counters, tags)| Backend::new(conns, counters, tags))
+            .collect();
+        let (backup_sender, backup_receiver) = mpsc::unbounded();
+        let (abort_sender, abort) = oneshot::channel();
         ChannelBackend {
             recv,
             handle,
-            lb: Box::new(lb) as Box<LoadBalance>,
+            lb,
+            backends,
+            draining: Vec::new(),
+            naming: None,
+            conn_options,
+            timer,
+            backoff,
+            connect_timeout: None,
+            dialer: Dialer::default(),
+            pool_size: 1,
+            lazy: false,
+            waiting: VecDeque::new(),
+            circuit_breaker: None,
+            health_check: None,
+            keepalive: None,
+            events: None,
+            max_failover: 0,
+            backups: Arc::new(BackupCounters::default()),
+            backup_sender,
+            backup_receiver,
             feedbacks: FuturesUnordered::new(),
+            calls: FuturesUnordered::new(),
+            shutdown: None,
+            drain_timeout: Duration::from_secs(5),
+            closing: None,
+            abort_sender: Some(abort_sender),
+            abort: abort.shared(),
+        }
+    }
+
+    /// Give up connecting to a server after `timeout`.
+    pub(crate) fn with_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Open the new connections with `dialer`.
+    pub(crate) fn with_dialer(mut self, dialer: Dialer) -> Self {
+        self.dialer = dialer;
+        self
+    }
+
+    /// Make `n` connections to every server added later.
+    pub(crate) fn with_pool_size(mut self, n: usize) -> Self {
+        self.pool_size = n;
+        self
+    }
+
+    /// Connect to the servers once the first call is sent, the connections
+    /// given to `new` are expected to be idle.
+    pub(crate) fn with_lazy_connect(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Count the backup requests in `backups`.
+    pub(crate) fn with_backup_counters(mut self, backups: Arc<BackupCounters>) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    /// Eject the servers whose calls fail too often, as `breaker` tells.
+    pub(crate) fn with_circuit_breaker(mut self, breaker: Option<Arc<CircuitBreaker>>) -> Self {
+        self.circuit_breaker = breaker;
+        self
+    }
+
+    /// Ping the idle connections with `keepalive`.
+    pub(crate) fn with_keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Report the events of the connections to `events`.
+    pub(crate) fn with_events(mut self, events: Option<EventHook>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Probe the servers with `check`.
+    /// Send a call whose request is not sent to up to `n` other servers.
+    pub(crate) fn with_max_failover(mut self, n: u32) -> Self {
+        self.max_failover = n;
+        self
+    }
+
+    pub(crate) fn with_health_check(mut self, check: HealthCheck) -> Self {
+        self.health_check = Some(check);
+        self
+    }
+
+    /// Shut down when asked by `shutdown`, waiting up to `drain_timeout`
+    /// for the calls in flight.
+    pub(crate) fn with_shutdown(
+        mut self,
+        shutdown: ShutdownReceiver,
+        drain_timeout: Duration,
+    ) -> Self {
+        self.shutdown = Some(shutdown);
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Follow the servers resolved by `naming`.
+    pub(crate) fn with_naming(mut self, naming: Naming) -> Self {
+        self.naming = Some(naming);
+        self
+    }
+
+    fn poll_naming(&mut self) {
+        let servers = match self.naming {
+            Some(ref mut naming) => naming.poll(&self.conn_options.name),
+            None => None,
+        };
+        if let Some(servers) = servers {
+            self.update_servers(&servers);
+        }
+        // the connection of a removed server is closed once it is idle
+        self.draining
+            .retain(|backend| backend.counters.inflight.load(Ordering::SeqCst) > 0);
+    }
+
+    /// Connect to the servers in `servers` which are new, update the
+    /// weights and tags of the others, and stop sending calls to the servers
+    /// not in `servers`.
+    fn update_servers(&mut self, servers: &[ServerEndpoint]) {
+        let mut backends = Vec::with_capacity(servers.len());
+        for mut backend in self.backends.drain(..) {
+            match servers.iter().find(|server| server.addr() == backend.addr) {
+                Some(server) => {
+                    backend.counters.set_weight(server.weight().unwrap_or(1));
+                    backend.tags = tags_of(server);
+                    backends.push(backend);
+                }
+                None => {
+                    info!("[{}] Removed server {}", self.conn_options.name, backend.addr);
+                    self.draining.push(backend);
+                }
+            }
+        }
+        for server in servers {
+            let addr = server.addr();
+            if backends.iter().any(|backend| backend.addr == addr) {
+                continue;
+            }
+            info!("[{}] Added server {}", self.conn_options.name, addr);
+            let conns = (0..self.pool_size)
+                .map(|_| {
+                    if self.lazy {
+                        return Connection::idle();
+                    }
+                    Connection::connecting(connect(
+                        &self.conn_options,
+                        addr,
+                        &self.handle,
+                        &self.timer,
+                        self.connect_timeout,
+                        &self.dialer,
+                    ))
+                })
+                .collect();
+            let weight = server.weight().unwrap_or(1);
+            let breaker = self.circuit_breaker.clone();
+            let name = &self.conn_options.name;
+            let counters = Arc::new(ServerCounters::new(name, addr, weight, breaker));
+            let backend = Backend::new(conns, counters, tags_of(server));
+            backends.push(backend);
+        }
+        self.backends = backends;
+
+        if let Some(ref naming) = self.naming {
+            let counters = self.backends.iter().map(|b| b.counters.clone()).collect();
+            *naming.servers.lock().unwrap() = counters;
+        }
+    }
+
+    fn poll_connections(&mut self) {
+        let ctx = Reconnect {
+            conn_options: &self.conn_options,
+            handle: &self.handle,
+            timer: &self.timer,
+            backoff: &self.backoff,
+            connect_timeout: self.connect_timeout,
+            dialer: &self.dialer,
+            events: self.events.as_ref(),
+        };
+        for backend in &mut self.backends {
+            backend.poll_connections(&ctx);
         }
     }
 
-    fn spawn(&mut self, resp_sender: OneShotSender, req: RequestPackage) {
-        trace!("Spawned a new rpc request.");
+    /// Ping the idle connections when it is time to.
+    fn poll_keepalive(&mut self) {
+        let keepalive = match self.keepalive {
+            Some(ref mut keepalive) => keepalive,
+            None => return,
+        };
+        if keepalive.poll_tick() {
+            for backend in &self.backends {
+                for end_port in backend.conns.iter().filter_map(Connection::end_port) {
+                    keepalive.ping(backend.addr, end_port, &self.handle);
+                }
+            }
+        }
+        keepalive.poll_pings();
+    }
 
-        let (server_id, end_port) = self.lb.select_server();
+    /// Probe the servers when it is time to, and mark them up or down as the
+    /// probes tell.
+    fn poll_health(&mut self) {
+        let check = match self.health_check {
+            Some(ref mut check) => check,
+            None => return,
+        };
+        if check.poll_tick() {
+            for backend in &mut self.backends {
+                if !backend.probing {
+                    let counters = backend.counters.clone();
+                    let end_port = backend.end_port();
+                    backend.probing = check.probe(counters, end_port, &self.handle, &self.dialer);
+                }
+            }
+        }
+        while let Ok(Async::Ready(Some((counters, up)))) = check.poll() {
+            let probed = self.backends
+                .iter_mut()
+                .chain(self.draining.iter_mut())
+                .find(|backend| Arc::ptr_eq(&backend.counters, &counters));
+            if let Some(backend) = probed {
+                backend.probing = false;
+            }
+            if counters.healthy.swap(up, Ordering::SeqCst) != up {
+                if up {
+                    let name = &self.conn_options.name;
+                    info!("[{}] Server {} passed its health check", name, counters.addr);
+                } else {
+                    let name = &self.conn_options.name;
+                    warn!("[{}] Server {} failed its health check", name, counters.addr);
+                }
+            }
+        }
+    }
+
+    /// Ask the load balancer for a connected server other than `exclude`.
+    fn select_server(&self, hint: Option<&LbHint>, exclude: Option<usize>) -> Option<usize> {
+        let infos: Vec<_> = self.backends
+            .iter()
+            .enumerate()
+            .map(|(id, backend)| backend.info(Some(id) != exclude))
+            .collect();
+        match self.lb.select(&infos, hint) {
+            Some(id) if id < self.backends.len() && infos[id].is_connected() => Some(id),
+            Some(id) => {
+                warn!(
+                    "[{}] Load balancer selected server {}, which is not connected",
+                    self.conn_options.name, id
+                );
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Send the backup requests whose delay is over, and the calls which
+    /// fail over.
+    fn poll_backups(&mut self) {
+        while let Ok(Async::Ready(Some(request))) = self.backup_receiver.poll() {
+            let id = match self.select_server(request.hint.as_ref(), Some(request.exclude)) {
+                Some(id) => id,
+                // dropping the sender gives up the backup request, or fails
+                // the call
+                None => continue,
+            };
+            let name = &self.conn_options.name;
+            let backend = &mut self.backends[id];
+            let counters = backend.counters.clone();
+            if request.failover {
+                trace!("[{}] Failing a call over to server {}", name, id);
+                counters.retries.fetch_add(1, Ordering::SeqCst);
+            } else {
+                trace!("[{}] Sending a backup request to server {}", name, id);
+                self.backups.sent.fetch_add(1, Ordering::SeqCst);
+            }
+            let end_port = backend.pick().expect("selected server is connected");
+            let attempt = Attempt::new(id, end_port, request.req, counters, self.lb.clone());
+            let _ = request.sender.send(attempt);
+        }
+    }
+
+    /// Connect to the servers of a lazy channel, as a call is sent.
+    fn wake(&mut self) {
+        if !self.lazy {
+            return;
+        }
+        debug!("[{}] Connecting to the servers for the first call", self.conn_options.name);
+        self.lazy = false;
+        for backend in &mut self.backends {
+            backend.wake(
+                &self.conn_options,
+                &self.handle,
+                &self.timer,
+                self.connect_timeout,
+                &self.dialer,
+            );
+        }
+        // register the new connections with the task
+        self.poll_connections();
+    }
+
+    /// Check if a server of a lazy channel is connected for the first time.
+    fn first_connects(&self) -> bool {
+        self.backends
+            .iter()
+            .flat_map(|backend| &backend.conns)
+            .any(Connection::is_first_connect)
+    }
+
+    /// Send the calls which wait for the first connections once one is up,
+    /// or fail them once all of them failed.
+    fn poll_waiting(&mut self) {
+        if self.waiting.is_empty() {
+            return;
+        }
+        let connected = self.backends
+            .iter()
+            .any(|backend| backend.end_port().is_some());
+        if connected || !self.first_connects() {
+            let waiting: Vec<_> = self.waiting.drain(..).collect();
+            for (callback, req, options, queued) in waiting {
+                self.spawn(callback, req, options, queued);
+            }
+        }
+    }
+
+    fn spawn(
+        &mut self,
+        callback: Callback,
+        req: RequestPackage,
+        options: SendOptions,
+        queued: Counted,
+    ) {
+        self.wake();
+        let id = match self.select_server(options.hint.as_ref(), None) {
+            Some(id) => id,
+            None if self.first_connects() && self.waiting.len() < MAX_WAITING_CALLS => {
+                trace!(
+                    "[{}] Queued a call until the first connection is up",
+                    self.conn_options.name
+                );
+                self.waiting.push_back((callback, req, options, queued));
+                return;
+            }
+            None => {
+                match callback {
+                    Callback::Response(resp_sender) => {
+                        let e = io::Error::new(io::ErrorKind::NotConnected, "no server available");
+                        let _ = resp_sender.send(Err(e));
+                    }
+                    // dropping the sender fails the call
+                    Callback::Sent(_) => warn!(
+                        "[{}] Dropped a oneway rpc request, no server available",
+                        self.conn_options.name
+                    ),
+                }
+                return;
+            }
+        };
+        match callback {
+            Callback::Response(resp_sender) => {
+                self.spawn_call(id, resp_sender, req, options, queued)
+            }
+            Callback::Sent(ack_sender) => {
+                trace!("[{}] Spawned a new oneway rpc request.", self.conn_options.name);
+
+                let backend = &mut self.backends[id];
+                backend.counters.calls.fetch_add(1, Ordering::SeqCst);
+                let end_port = backend.pick().expect("selected server is connected");
+                let (done, finished) = oneshot::channel::<()>();
+                let call = end_port.call(req);
+                call.hold(queued);
+                call.ack_when_sent(ack_sender);
+                let fut = call.then(move |_| {
+                    drop(done);
+                    Ok(())
+                });
+                self.calls.push(finished);
+                self.handle.spawn(fut);
+            }
+        }
+    }
+
+    fn spawn_call(
+        &mut self,
+        id: usize,
+        resp_sender: OneShotSender,
+        req: RequestPackage,
+        options: SendOptions,
+        queued: Counted,
+    ) {
+        trace!("[{}] Spawned a new rpc request.", self.conn_options.name);
+
+        // a backup request needs another connected server
+        let connected = self.backends
+            .iter()
+            .filter(|backend| backend.end_port().is_some())
+            .count();
+        let backup = match options.backup_after {
+            Some(delay) if connected > 1 => Timeout::new(delay, &self.handle).ok().map(|delay| {
+                let sender = self.backup_sender.clone();
+                Backup::Waiting(delay, Box::new(req.clone()), options, sender)
+            }),
+            _ => None,
+        };
+        let failover = if self.max_failover > 0 && self.backends.len() > 1 {
+            Some(Failover {
+                req: Box::new(req.clone()),
+                options,
+                left: self.max_failover,
+                requests: self.backup_sender.clone(),
+                sending: None,
+            })
+        } else {
+            None
+        };
+
+        let backend = &mut self.backends[id];
+        let counters = backend.counters.clone();
+        if options.retry {
+            counters.retries.fetch_add(1, Ordering::SeqCst);
+        } else {
+            counters.calls.fetch_add(1, Ordering::SeqCst);
+        }
+        let end_port = backend.pick().expect("selected server is connected");
+        let attempt = Attempt::new(id, end_port, req, counters, self.lb.clone());
+        attempt.call.hold(queued);
         let (fb_sender, fb_recv) = oneshot::channel();
-        let fut = end_port.call(req).then(move |result| {
-            let fb_handle = FeedbackHandle::new(server_id, fb_sender);
-            // TODO: Or maybe just ignore this error, for the rpc request might be cancelled.
-            resp_sender
-                .send(result.map(move |r| (r, fb_handle)))
-                .expect("The receiving end of the oneshot is dropped.");
-
-            Ok(())
+        let name = self.conn_options.name.clone();
+        let pending = PendingCall {
+            name: name.clone(),
+            attempt: Some(attempt),
+            backup,
+            failover,
+            backups: self.backups.clone(),
+            sender: Some(resp_sender),
+            abort: Some(self.abort.clone()),
+        };
+        let (done, finished) = oneshot::channel::<()>();
+        let fut = pending.map(move |answer| {
+            let _done = done;
+            let (id, result, resp_sender) = match answer {
+                Some(answer) => answer,
+                None => {
+                    debug!("[{}] Gave up a call which is no longer waited for", name);
+                    return;
+                }
+            };
+            let fb_handle = FeedbackHandle::new(id as ServerId, fb_sender);
+            // the call may still give up between the response and now
+            if resp_sender.send(result.map(move |r| (r, fb_handle))).is_err() {
+                debug!("[{}] Discarded a response whose call is no longer waiting", name);
+            }
         });
 
         self.feedbacks.push(fb_recv);
+        self.calls.push(finished);
         self.handle.spawn(fut);
     }
+
+    /// Start to shut down when asked to, and check if the shutdown is done.
+    fn poll_shutdown(&mut self) -> bool {
+        loop {
+            let done = match self.shutdown {
+                Some(ref mut shutdown) => match shutdown.poll() {
+                    Ok(Async::Ready(Some(done))) => done,
+                    _ => break,
+                },
+                None => break,
+            };
+            match self.closing {
+                Some(ref mut closing) => closing.done.push(done),
+                None => {
+                    info!(
+                        "[{}] Shutting down channel, draining the calls in flight",
+                        self.conn_options.name
+                    );
+                    let deadline = match Timeout::new(self.drain_timeout, &self.handle) {
+                        Ok(deadline) => Some(deadline),
+                        Err(e) => {
+                            warn!(
+                                "[{}] Failed to wait for the calls in flight: {}",
+                                self.conn_options.name, e
+                            );
+                            None
+                        }
+                    };
+                    self.closing = Some(Closing {
+                        deadline,
+                        done: vec![done],
+                    });
+                }
+            }
+        }
+
+        // forget the calls answered
+        while let Ok(Async::Ready(Some(()))) | Err(_) = self.calls.poll() {}
+        let expired = match self.closing {
+            Some(Closing {
+                deadline: Some(ref mut deadline),
+                ..
+            }) => match deadline.poll() {
+                Ok(Async::NotReady) => false,
+                Ok(Async::Ready(())) | Err(_) => true,
+            },
+            Some(Closing { deadline: None, .. }) => true,
+            None => return false,
+        };
+        let drained = self.calls.is_empty() && self.waiting.is_empty();
+        if !drained && !expired {
+            return false;
+        }
+        if !drained {
+            warn!(
+                "[{}] Gave up the calls in flight of the channel being shut down",
+                self.conn_options.name
+            );
+        }
+        self.close();
+        true
+    }
+
+    /// Fail the calls still in flight, close the connections and tell the
+    /// callers of `Channel::shutdown`.
+    fn close(&mut self) {
+        if let Some(abort) = self.abort_sender.take() {
+            let _ = abort.send(());
+        }
+        self.recv.close();
+        let mut queued: Vec<_> = self.waiting.drain(..).collect();
+        while let Ok(Async::Ready(Some(msg))) = self.recv.poll() {
+            queued.push(msg);
+        }
+        for (callback, _, _, _) in queued {
+            if let Callback::Response(resp_sender) = callback {
+                let _ = resp_sender.send(Err(closed_error()));
+            }
+        }
+
+        for backend in self.backends.drain(..).chain(self.draining.drain(..)) {
+            for conn in &backend.conns {
+                if let Some(end_port) = conn.end_port() {
+                    end_port.close();
+                }
+            }
+        }
+        self.health_check = None;
+        self.keepalive = None;
+        self.naming = None;
+        emit(self.events.as_ref(), ChannelEvent::Closed);
+        let closing = self.closing.take().expect("channel is shutting down");
+        for done in closing.done {
+            let _ = done.send(());
+        }
+        info!("[{}] Channel is shut down", self.conn_options.name);
+    }
 }
 
 impl Future for ChannelBackend {
@@ -55,18 +1379,52 @@ impl Future for ChannelBackend {
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.poll_naming();
+        self.poll_connections();
+        self.poll_waiting();
+        self.poll_health();
+        self.poll_keepalive();
+        self.poll_backups();
         loop {
-            // check returned feedback info
-            // TODO: error handling?
-            //       the oneshot channel might be broken (due to dropped sender).
-            if let Ok(Async::Ready(Some((server_id, call_info)))) = self.feedbacks.poll() {
-                self.lb.feed_back(server_id, call_info);
-            }
+            // drain the feedback sent by the stubs, the load balancer is
+            // told about every call by `on_success` and `on_error` instead
+            while let Ok(Async::Ready(Some(_))) = self.feedbacks.poll() {}
             // spawn new request
-            match try_ready!(self.recv.poll()) {
-                Some((resp_sender, req)) => self.spawn(resp_sender, req),
-                None => return Ok(Async::Ready(())),
+            match self.recv.poll() {
+                Ok(Async::Ready(Some((callback, req, options, queued)))) => {
+                    self.spawn(callback, req, options, queued)
+                }
+                // a channel being shut down waits for its calls
+                Ok(Async::Ready(None)) | Err(()) if self.closing.is_none() => {
+                    emit(self.events.as_ref(), ChannelEvent::Closed);
+                    return Ok(Async::Ready(()));
+                }
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) | Err(()) => break,
+            }
+        }
+        if self.poll_shutdown() {
+            return Ok(Async::Ready(()));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+        let bounds = [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (40, 1000)];
+        for &(failures, max) in &bounds {
+            for _ in 0..20 {
+                let delay = backoff.delay(failures);
+                assert!(delay <= Duration::from_millis(max), "{:?}", delay);
+                assert!(delay >= Duration::from_millis(max / 2), "{:?}", delay);
             }
         }
+        let fixed = Backoff::new(Duration::from_secs(2), Duration::from_secs(1));
+        assert!(fixed.delay(3) >= Duration::from_secs(1));
     }
 }
@@ -0,0 +1,298 @@
+//! Circuit breakers of the servers of a channel
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// When the servers of a channel are ejected for failing calls
+///
+/// Every server has a circuit breaker, which looks at the outcomes of the
+/// latest calls sent to it. A call fails if the connection fails, the
+/// server answers with an error code, or the call is given up, e.g. because
+/// it timed out. When enough of the latest calls failed, the breaker opens:
+/// the server is ejected, and the load balancer sees it as not connected.
+/// After a cool-down, a few probe calls are let through. If they all
+/// succeed the breaker closes and the server is restored, otherwise it is
+/// ejected again.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    window: usize,
+    min_calls: usize,
+    max_error_rate: f64,
+    cooldown: Duration,
+    probes: u32,
+}
+
+impl CircuitBreaker {
+    /// Create a breaker which opens when half of the latest 20 calls
+    /// failed, and is let through again after 5 seconds.
+    pub fn new() -> Self {
+        CircuitBreaker {
+            window: 20,
+            min_calls: 10,
+            max_error_rate: 0.5,
+            cooldown: Duration::from_secs(5),
+            probes: 3,
+        }
+    }
+
+    /// Set the number of the latest calls looked at, and the number of
+    /// calls needed before the breaker may open.
+    ///
+    /// Default to 20 and 10. The window holds at least `min_calls` calls.
+    pub fn window(mut self, window: usize, min_calls: usize) -> Self {
+        self.min_calls = min_calls.max(1);
+        self.window = window.max(self.min_calls);
+        self
+    }
+
+    /// Set the rate of failed calls above which the breaker opens, between
+    /// 0 and 1. A rate of 1 or more never opens it.
+    ///
+    /// Default to 0.5.
+    pub fn max_error_rate(mut self, rate: f64) -> Self {
+        self.max_error_rate = rate;
+        self
+    }
+
+    /// Set how long a server is ejected before probe calls are let
+    /// through.
+    ///
+    /// Default to 5 seconds.
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Set the number of probe calls which must succeed to restore an
+    /// ejected server.
+    ///
+    /// Default to 3, at least one probe is needed.
+    pub fn probes(mut self, probes: u32) -> Self {
+        self.probes = probes.max(1);
+        self
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker::new()
+    }
+}
+
+/// State of the circuit breaker of a server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls are sent to the server
+    Closed,
+    /// The server is ejected
+    Open,
+    /// Probe calls are sent to the server
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum State {
+    /// Outcomes of the latest calls, `true` for the failed ones
+    Closed(VecDeque<bool>),
+    Open(Instant),
+    HalfOpen {
+        /// Probe calls sent
+        sent: u32,
+        /// Probe calls succeeded
+        passed: u32,
+    },
+}
+
+/// The circuit breaker of a server, which never opens without options
+#[derive(Debug)]
+pub(crate) struct Breaker {
+    /// Name of the channel, told by the logs
+    name: Arc<str>,
+    addr: SocketAddr,
+    options: Option<Arc<CircuitBreaker>>,
+    state: Mutex<State>,
+    ejections: AtomicUsize,
+}
+
+impl Breaker {
+    pub fn new(addr: SocketAddr, options: Option<Arc<CircuitBreaker>>) -> Self {
+        Breaker {
+            name: Arc::from(""),
+            addr,
+            options,
+            state: Mutex::new(State::Closed(VecDeque::new())),
+            ejections: AtomicUsize::new(0),
+        }
+    }
+
+    /// Tell `name` as the name of the channel in the logs.
+    pub fn named(mut self, name: Arc<str>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Get the current state.
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed(_) => CircuitState::Closed,
+            State::Open(_) => CircuitState::Open,
+            State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Get the number of times the server is ejected.
+    pub fn ejections(&self) -> usize {
+        self.ejections.load(Ordering::SeqCst)
+    }
+
+    /// Check if a call may be sent to the server now.
+    pub fn allows(&self) -> bool {
+        let options = match self.options {
+            Some(ref options) => options,
+            None => return true,
+        };
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed(_) => true,
+            State::Open(until) => {
+                if Instant::now() < until {
+                    return false;
+                }
+                info!("[{}] Sending probe calls to ejected server {}", self.name, self.addr);
+                *state = State::HalfOpen { sent: 0, passed: 0 };
+                true
+            }
+            State::HalfOpen { sent, .. } => sent < options.probes,
+        }
+    }
+
+    /// Record that a call is sent to the server.
+    pub fn on_sent(&self) {
+        if let State::HalfOpen { ref mut sent, .. } = *self.state.lock().unwrap() {
+            *sent += 1;
+        }
+    }
+
+    /// Record the outcome of a call sent to the server.
+    pub fn on_done(&self, failed: bool) {
+        let options = match self.options {
+            Some(ref options) => options,
+            None => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        let next = match *state {
+            State::Closed(ref mut outcomes) => {
+                outcomes.push_back(failed);
+                if outcomes.len() > options.window {
+                    outcomes.pop_front();
+                }
+                let errors = outcomes.iter().filter(|&&failed| failed).count();
+                let rate = errors as f64 / outcomes.len() as f64;
+                if outcomes.len() < options.min_calls || rate <= options.max_error_rate {
+                    return;
+                }
+                warn!(
+                    "[{}] Ejected server {}, {} of the latest {} calls failed",
+                    self.name,
+                    self.addr,
+                    errors,
+                    outcomes.len()
+                );
+                self.ejections.fetch_add(1, Ordering::SeqCst);
+                State::Open(Instant::now() + options.cooldown)
+            }
+            // a call sent before the server is ejected
+            State::Open(_) => return,
+            State::HalfOpen { ref mut passed, .. } => {
+                if failed {
+                    warn!(
+                        "[{}] Probe call to server {} failed, ejected it again",
+                        self.name,
+                        self.addr
+                    );
+                    self.ejections.fetch_add(1, Ordering::SeqCst);
+                    State::Open(Instant::now() + options.cooldown)
+                } else {
+                    *passed += 1;
+                    if *passed < options.probes {
+                        return;
+                    }
+                    info!(
+                        "[{}] Restored server {}, the probe calls succeeded",
+                        self.name,
+                        self.addr
+                    );
+                    State::Closed(VecDeque::new())
+                }
+            }
+        };
+        *state = next;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread::sleep;
+
+    fn breaker() -> Breaker {
+        let options = CircuitBreaker::new()
+            .window(4, 2)
+            .max_error_rate(0.5)
+            .cooldown(Duration::from_millis(20))
+            .probes(2);
+        Breaker::new(([127, 0, 0, 1], 8000).into(), Some(Arc::new(options)))
+    }
+
+    #[test]
+    fn opens_on_errors_and_closes_after_probes() {
+        let breaker = breaker();
+        // one failure out of two is not above the rate
+        breaker.on_done(false);
+        breaker.on_done(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.on_done(true);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allows());
+        assert_eq!(breaker.ejections(), 1);
+
+        sleep(Duration::from_millis(30));
+        assert!(breaker.allows());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.on_sent();
+        breaker.on_sent();
+        // no more probes until they are answered
+        assert!(!breaker.allows());
+        breaker.on_done(false);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.on_done(false);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allows());
+    }
+
+    #[test]
+    fn failed_probe_ejects_again() {
+        let breaker = breaker();
+        breaker.on_done(true);
+        breaker.on_done(true);
+        sleep(Duration::from_millis(30));
+        assert!(breaker.allows());
+        breaker.on_sent();
+        breaker.on_done(true);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.ejections(), 2);
+    }
+
+    #[test]
+    fn never_opens_without_options() {
+        let breaker = Breaker::new(([127, 0, 0, 1], 8000).into(), None);
+        for _ in 0..100 {
+            breaker.on_done(true);
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allows());
+    }
+}
@@ -0,0 +1,698 @@
+//! The codec of the client connections of a channel
+
+use bytes::{Bytes, BytesMut};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::task;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::codec::{Decoder, Encoder, Framed, FramedParts};
+use tokio_proto::multiplex::RequestId;
+
+use message::{RequestPackage, RpcResponseMeta};
+use protocol::ProtoCodecClient;
+use super::connector::{Connector, Link};
+
+/// The correlation ids of oneway requests have this bit set, and those of
+/// other requests do not
+const ONEWAY_ID_BIT: RequestId = 1 << 63;
+
+/// The codec of a client connection
+///
+/// The multiplexer numbers the requests by itself, so every request is sent
+/// with a correlation id of its own, one not taken by a request in flight
+/// on the connection. Once the ids wrap around, those of the requests still
+/// waiting for their responses are skipped, and a response is only passed
+/// to the request its id was given to. Responses to no request in flight
+/// are dropped.
+///
+/// Oneway requests wait for no response, and take no id: they are numbered
+/// apart, with [`ONEWAY_ID_BIT`] set, so that the response of a server
+/// answering them anyway is told apart and dropped.
+///
+/// [`ONEWAY_ID_BIT`]: constant.ONEWAY_ID_BIT.html
+///
+/// The responses of protocols answering in order carry no ids, and are
+/// passed to the only request in flight, see [`ClientTransport`].
+///
+/// [`ClientTransport`]: struct.ClientTransport.html
+#[derive(Debug)]
+pub struct ClientCodec {
+    /// Name of the channel, told by the logs
+    name: Arc<str>,
+    codec: ProtoCodecClient,
+    in_order: bool,
+    // the ids on the wire are within 0..=last
+    last: RequestId,
+    next_id: RequestId,
+    next_oneway: RequestId,
+    // multiplexer id of each request in flight, by the id on the wire
+    inflight: HashMap<RequestId, RequestId>,
+    // told where the requests start in the stream
+    link: Option<Arc<Link>>,
+}
+
+impl ClientCodec {
+    pub fn new(codec: ProtoCodecClient) -> Self {
+        ClientCodec::with_last_id(codec, ONEWAY_ID_BIT - 1)
+    }
+
+    /// Create a codec sending the ids in `0..size` only.
+    #[cfg(test)]
+    fn with_id_space(codec: ProtoCodecClient, size: RequestId) -> Self {
+        assert!(size > 0, "empty id space");
+        ClientCodec::with_last_id(codec, size - 1)
+    }
+
+    fn with_last_id(codec: ProtoCodecClient, last: RequestId) -> Self {
+        ClientCodec {
+            name: Arc::from(""),
+            in_order: codec.answers_in_order(),
+            codec,
+            last,
+            next_id: 0,
+            next_oneway: 0,
+            inflight: HashMap::new(),
+            link: None,
+        }
+    }
+
+    /// Tell `name` as the name of the channel in the logs.
+    pub fn named(mut self, name: Arc<str>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Get an id not used by any request in flight, or fail if all of them
+    /// are.
+    fn fresh_id(&mut self) -> io::Result<RequestId> {
+        if self.inflight.len() as RequestId > self.last {
+            return Err(io::Error::other(
+                "all correlation ids are taken by requests in flight",
+            ));
+        }
+        loop {
+            let id = self.next_id;
+            self.next_id = if id == self.last { 0 } else { id + 1 };
+            if !self.inflight.contains_key(&id) {
+                return Ok(id);
+            }
+        }
+    }
+}
+
+impl Decoder for ClientCodec {
+    type Item = (RequestId, (RpcResponseMeta, Bytes));
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            let (wire_id, response) = match self.codec.decode(buf)? {
+                Some(item) => item,
+                None => return Ok(None),
+            };
+            let wire_id = match self.inflight.keys().next() {
+                Some(&only) if self.in_order => only,
+                _ => wire_id,
+            };
+            if wire_id & ONEWAY_ID_BIT != 0 && !self.in_order {
+                debug!("[{}] Dropped a response to a oneway request", self.name);
+                continue;
+            }
+            match self.inflight.remove(&wire_id) {
+                Some(id) => return Ok(Some((id, response))),
+                None => warn!(
+                    "[{}] Dropped a response to no request in flight, id {}",
+                    self.name, wire_id
+                ),
+            }
+        }
+    }
+}
+
+impl Encoder for ClientCodec {
+    type Item = (RequestId, RequestPackage);
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        let (id, request) = msg;
+        let oneway = is_oneway(&request) && !self.in_order;
+        let wire_id = if oneway {
+            let wire_id = self.next_oneway | ONEWAY_ID_BIT;
+            self.next_oneway = (self.next_oneway + 1) & !ONEWAY_ID_BIT;
+            wire_id
+        } else {
+            self.fresh_id()?
+        };
+        if let Some(ref link) = self.link {
+            link.encoded(id, buf.len());
+        }
+        self.codec.encode((wire_id, request), buf)?;
+        if !oneway {
+            self.inflight.insert(wire_id, id);
+        }
+        Ok(())
+    }
+}
+
+/// Check if `request` is a oneway request, which the server does not answer.
+fn is_oneway(request: &RequestPackage) -> bool {
+    request.0.get_oneway()
+}
+
+/// The transport of a client connection
+///
+/// The requests of protocols answering in order are sent one at a time:
+/// those handed over while a request is in flight are queued, and sent once
+/// it is answered. Requests of other protocols are sent at once.
+///
+/// A request given up by its caller is answered at once with an error, so
+/// that the multiplexer forgets it, and its response is dropped when it
+/// arrives. A request given up before it is sent is not sent at all. The
+/// server is not told, as the protocols have no message for it.
+///
+/// A oneway request of a protocol answering out of order is answered as
+/// soon as it is written, so that the multiplexer forgets it. Protocols
+/// answering in order have no oneway requests, the server answers them.
+#[derive(Debug)]
+pub struct ClientTransport<S> {
+    /// Name of the channel, told by the logs
+    name: Arc<str>,
+    framed: Framed<Connector<S>, ClientCodec>,
+    link: Arc<Link>,
+    in_order: bool,
+    // requests waiting for the one in flight to be answered
+    queue: VecDeque<(RequestId, RequestPackage)>,
+    waiting: bool,
+    // requests handed over, numbered from zero by the multiplexer
+    handed: RequestId,
+    // requests handed over and not answered
+    inflight: HashSet<RequestId>,
+    // requests sent and given up, whose responses are to be dropped
+    given_up: HashSet<RequestId>,
+    // requests given up before they are handed over
+    early: HashSet<RequestId>,
+    // requests given up, not yet answered to the multiplexer
+    unanswered: VecDeque<RequestId>,
+    // oneway requests handed over, answered to the multiplexer once written
+    oneway: VecDeque<RequestId>,
+}
+
+impl<S: AsyncRead + AsyncWrite> ClientTransport<S> {
+    /// Create a transport reading responses into a buffer of
+    /// `read_buffer_size` bytes at first, or the default of 8 KiB.
+    pub fn new(conn: Connector<S>, codec: ClientCodec, read_buffer_size: Option<usize>) -> Self {
+        let in_order = codec.in_order;
+        let name = codec.name.clone();
+        let link = conn.link().clone();
+        let codec = ClientCodec {
+            link: Some(link.clone()),
+            ..codec
+        };
+        let framed = match read_buffer_size {
+            Some(size) => {
+                let parts = FramedParts {
+                    inner: conn,
+                    readbuf: BytesMut::with_capacity(size),
+                    writebuf: BytesMut::new(),
+                };
+                Framed::from_parts(parts, codec)
+            }
+            None => conn.framed(codec),
+        };
+        ClientTransport {
+            name,
+            link,
+            in_order,
+            framed,
+            queue: VecDeque::new(),
+            waiting: false,
+            handed: 0,
+            inflight: HashSet::new(),
+            given_up: HashSet::new(),
+            early: HashSet::new(),
+            unanswered: VecDeque::new(),
+            oneway: VecDeque::new(),
+        }
+    }
+
+    /// Give up the requests whose callers no longer wait for them.
+    fn take_cancelled(&mut self) {
+        for id in self.link.take_cancelled() {
+            if id >= self.handed {
+                self.early.insert(id);
+                continue;
+            }
+            // or answered already
+            if !self.inflight.remove(&id) {
+                continue;
+            }
+            let queued = self.queue.iter().position(|&(queued, _)| queued == id);
+            match queued {
+                Some(i) => {
+                    self.queue.remove(i);
+                }
+                None => {
+                    self.given_up.insert(id);
+                }
+            }
+            debug!("[{}] Gave up request {}", self.name, id);
+            self.unanswered.push_back(id);
+        }
+    }
+
+    /// Check if the first oneway request is written.
+    fn oneway_written(&self) -> bool {
+        match self.oneway.front() {
+            Some(&id) => self.link.is_sent(id),
+            None => false,
+        }
+    }
+
+    /// Send the first queued request if no request is in flight.
+    fn send_queued(&mut self) -> io::Result<()> {
+        if self.waiting {
+            return Ok(());
+        }
+        if let Some(request) = self.queue.pop_front() {
+            match self.framed.start_send(request)? {
+                AsyncSink::Ready => self.waiting = true,
+                AsyncSink::NotReady(request) => self.queue.push_front(request),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Stream for ClientTransport<S> {
+    type Item = (RequestId, (RpcResponseMeta, Bytes));
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.take_cancelled();
+        if let Some(id) = self.unanswered.pop_front() {
+            let mut meta = RpcResponseMeta::new();
+            meta.set_error_text("request given up".to_string());
+            return Ok(Async::Ready(Some((id, (meta, Bytes::new())))));
+        }
+        if self.oneway_written() {
+            let id = self.oneway.pop_front().unwrap();
+            return Ok(Async::Ready(Some((id, (RpcResponseMeta::new(), Bytes::new())))));
+        }
+        loop {
+            let response = match self.framed.poll() {
+                Ok(Async::Ready(response)) => response,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // a response which can not be decoded breaks the connection too
+                Err(e) => return Err(self.framed.get_ref().broken(e)),
+            };
+            let id = match response {
+                Some((id, _)) => id,
+                None => return Ok(Async::Ready(None)),
+            };
+            if self.in_order {
+                self.waiting = false;
+                self.send_queued()?;
+            }
+            if self.given_up.remove(&id) {
+                debug!("[{}] Dropped the response to given up request {}", self.name, id);
+                continue;
+            }
+            self.inflight.remove(&id);
+            return Ok(Async::Ready(response));
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Sink for ClientTransport<S> {
+    type SinkItem = (RequestId, RequestPackage);
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let id = item.0;
+        if self.early.remove(&id) {
+            debug!("[{}] Gave up request {} before sending it", self.name, id);
+            self.handed = id + 1;
+            self.unanswered.push_back(id);
+            return Ok(AsyncSink::Ready);
+        }
+        if !self.in_order {
+            let oneway = is_oneway(&item.1);
+            let sent = self.framed.start_send(item)?;
+            if sent.is_ready() {
+                self.handed = id + 1;
+                if oneway {
+                    self.oneway.push_back(id);
+                } else {
+                    self.inflight.insert(id);
+                }
+            }
+            return Ok(sent);
+        }
+        // the multiplexer takes a request it can not hand over as an error
+        self.handed = id + 1;
+        self.inflight.insert(id);
+        self.queue.push_back(item);
+        self.send_queued()?;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.send_queued()?;
+        let flushed = self.framed.poll_complete()?;
+        // answer the oneway requests just written
+        if self.oneway_written() {
+            task::current().notify();
+        }
+        Ok(flushed)
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.framed.close()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{future, Future};
+    use futures::sync::oneshot;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::rc::Rc;
+
+    use super::*;
+    use compress::CompressType;
+    use controller::Controller;
+    use message::{RpcMeta, RpcRequestMeta};
+    use protocol::{BrpcProtocol, RpcProtocol};
+
+    const SEED: [u32; 4] = [11, 22, 33, 44];
+
+    fn codec(size: RequestId) -> ClientCodec {
+        ClientCodec::with_id_space(ProtoCodecClient::new(Box::new(BrpcProtocol::new())), size)
+    }
+
+    /// Send a request carrying `id` in its body, returning the id on the wire.
+    fn send(codec: &mut ClientCodec, id: RequestId) -> RequestId {
+        let mut buf = BytesMut::new();
+        let body = Bytes::from(id.to_string());
+        let request = (RpcRequestMeta::new(), Controller::default(), body);
+        codec.encode((id, request), &mut buf).unwrap();
+        let (wire_id, _) = BrpcProtocol::new().try_parse(&mut buf).unwrap();
+        wire_id
+    }
+
+    /// Answer the request sent with `wire_id`, echoing `body`.
+    fn answer(wire_id: RequestId, body: Bytes, buf: &mut BytesMut) {
+        let mut meta = RpcMeta::new();
+        meta.set_response(RpcResponseMeta::new());
+        meta.set_correlation_id(wire_id);
+        BrpcProtocol::new()
+            .write_package((meta, Controller::default(), body), buf)
+            .unwrap();
+    }
+
+    #[test]
+    fn request_compressed_as_controller_tells() {
+        let mut codec = codec(4);
+        let body = Bytes::from("hello ".repeat(10));
+        for &(compress, value) in &[
+            (CompressType::Snappy, 1),
+            (CompressType::Gzip, 2),
+            (CompressType::Zlib, 3),
+        ] {
+            let controller = Controller {
+                request_compress_type: Some(compress),
+                ..Default::default()
+            };
+            let mut buf = BytesMut::new();
+            let request = (RpcRequestMeta::new(), controller, body.clone());
+            codec.encode((0, request), &mut buf).unwrap();
+            let (wire_id, (meta, _, sent)) = BrpcProtocol::new().try_parse(&mut buf).unwrap();
+            assert_eq!(meta.get_compress_type(), value);
+            assert_eq!(compress.decompress(&sent).unwrap(), &body[..]);
+            answer(wire_id, Bytes::new(), &mut buf);
+            codec.decode(&mut buf).unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn ids_wrap_around() {
+        let mut codec = ClientCodec::new(ProtoCodecClient::new(Box::new(BrpcProtocol::new())));
+        codec.next_id = ONEWAY_ID_BIT - 1;
+        assert_eq!(send(&mut codec, 1), ONEWAY_ID_BIT - 1);
+        assert_eq!(send(&mut codec, 2), 0);
+    }
+
+    /// Send a oneway request, returning the id on the wire.
+    fn send_oneway(codec: &mut ClientCodec, id: RequestId, buf: &mut BytesMut) -> RequestId {
+        let mut meta = RpcRequestMeta::new();
+        meta.set_oneway(true);
+        codec.encode((id, (meta, Controller::default(), Bytes::new())), buf).unwrap();
+        let (wire_id, (meta, _, _)) = BrpcProtocol::new().try_parse(buf).unwrap();
+        assert!(meta.get_request().get_oneway());
+        wire_id
+    }
+
+    #[test]
+    fn oneway_requests_take_no_id() {
+        let mut codec = codec(2);
+        let mut buf = BytesMut::new();
+        let oneway: Vec<_> = (0..3).map(|id| send_oneway(&mut codec, id, &mut buf)).collect();
+        assert_eq!(oneway, vec![ONEWAY_ID_BIT, ONEWAY_ID_BIT + 1, ONEWAY_ID_BIT + 2]);
+        assert!(codec.inflight.is_empty());
+        let id = send(&mut codec, 3);
+        assert_eq!(id, 0);
+
+        // answered anyway, the responses are dropped
+        for &wire_id in &oneway {
+            answer(wire_id, Bytes::new(), &mut buf);
+        }
+        answer(id, Bytes::from("3"), &mut buf);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().0, 3);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn ids_in_flight_are_skipped() {
+        let mut codec = codec(3);
+        let slow = send(&mut codec, 100);
+        assert_eq!(slow, 0);
+        let mut buf = BytesMut::new();
+        for id in 0..10 {
+            let wire_id = send(&mut codec, id);
+            assert!(wire_id != slow);
+            answer(wire_id, Bytes::new(), &mut buf);
+            assert_eq!(codec.decode(&mut buf).unwrap().unwrap().0, id);
+        }
+        answer(slow, Bytes::new(), &mut buf);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().0, 100);
+        assert!(codec.inflight.is_empty());
+    }
+
+    #[test]
+    fn exhausted_ids_fail() {
+        let mut codec = codec(2);
+        send(&mut codec, 0);
+        send(&mut codec, 1);
+        let mut buf = BytesMut::new();
+        assert!(codec
+            .encode((2, (RpcRequestMeta::new(), Controller::default(), Bytes::new())), &mut buf)
+            .is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn stale_response_is_dropped() {
+        let mut codec = codec(4);
+        let wire_id = send(&mut codec, 7);
+        let mut buf = BytesMut::new();
+        answer(wire_id, Bytes::from("7"), &mut buf);
+        // answered twice, or by a confused server
+        answer(wire_id, Bytes::from("7"), &mut buf);
+        answer(3, Bytes::from("3"), &mut buf);
+        assert_eq!(codec.decode(&mut buf).unwrap().unwrap().0, 7);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn overlapping_calls_get_their_own_responses() {
+        let mut rng = XorShiftRng::from_seed(SEED);
+        let mut codec = codec(8);
+        // requests in flight on the server, by the id on the wire
+        let mut server: Vec<(RequestId, Bytes)> = Vec::new();
+        let mut buf = BytesMut::new();
+        for id in 0..10_000 {
+            let wire_id = send(&mut codec, id);
+            assert!(server.iter().all(|&(taken, _)| taken != wire_id));
+            server.push((wire_id, Bytes::from(id.to_string())));
+
+            // answer some of the requests in any order, keeping the id space
+            // crowded, while a few requests stay in flight for long
+            while server.len() >= 6 || (!server.is_empty() && rng.gen_weighted_bool(3)) {
+                let (wire_id, body) = server.swap_remove(rng.gen_range(0, server.len()));
+                answer(wire_id, body, &mut buf);
+            }
+            while let Some((id, (_, body))) = codec.decode(&mut buf).unwrap() {
+                assert_eq!(body, Bytes::from(id.to_string()));
+            }
+        }
+        for (wire_id, body) in server.drain(..) {
+            answer(wire_id, body, &mut buf);
+        }
+        while let Some((id, (_, body))) = codec.decode(&mut buf).unwrap() {
+            assert_eq!(body, Bytes::from(id.to_string()));
+        }
+        assert!(codec.inflight.is_empty());
+    }
+
+    /// A stream reading what the test feeds it, and keeping what is written
+    #[derive(Clone, Default)]
+    struct MockStream {
+        input: Rc<RefCell<BytesMut>>,
+        output: Rc<RefCell<BytesMut>>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut input = self.input.borrow_mut();
+            if input.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(input.len());
+            buf[..n].copy_from_slice(&input.split_to(n));
+            Ok(n)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for MockStream {}
+
+    impl AsyncWrite for MockStream {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    type Response = Async<Option<(RequestId, (RpcResponseMeta, Bytes))>>;
+
+    fn answered(response: Response) -> (RequestId, RpcResponseMeta) {
+        match response {
+            Async::Ready(Some((id, (meta, _)))) => (id, meta),
+            _ => panic!("no response"),
+        }
+    }
+
+    #[test]
+    fn given_up_requests_are_forgotten() {
+        future::lazy(|| {
+            let stream = MockStream::default();
+            let link = Arc::new(Link::default());
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let conn = Connector::from_stream(addr, stream.clone(), link.clone());
+            let mut transport = ClientTransport::new(conn, codec(8), None);
+            let request = |id: RequestId| {
+                let body = Bytes::from(id.to_string());
+                (id, (RpcRequestMeta::new(), Controller::default(), body))
+            };
+            for id in 0..2 {
+                assert!(transport.start_send(request(id)).unwrap().is_ready());
+            }
+            transport.poll_complete().unwrap();
+            let mut wire_ids = Vec::new();
+            for _ in 0..2 {
+                let mut output = stream.output.borrow_mut();
+                wire_ids.push(BrpcProtocol::new().try_parse(&mut output).unwrap().0);
+            }
+
+            // answered at once, its response is dropped when it arrives
+            link.cancel(0);
+            let (id, meta) = answered(transport.poll().unwrap());
+            assert_eq!(id, 0);
+            assert!(!meta.get_error_text().is_empty());
+            assert!(transport.poll().unwrap().is_not_ready());
+            for (&wire_id, body) in wire_ids.iter().zip(&["0", "1"]) {
+                answer(wire_id, Bytes::from(*body), &mut stream.input.borrow_mut());
+            }
+            assert_eq!(answered(transport.poll().unwrap()).0, 1);
+            assert!(transport.poll().unwrap().is_not_ready());
+
+            // not sent if given up before it is handed over
+            link.cancel(2);
+            assert!(transport.poll().unwrap().is_not_ready());
+            assert!(transport.start_send(request(2)).unwrap().is_ready());
+            transport.poll_complete().unwrap();
+            assert!(stream.output.borrow().is_empty());
+            assert_eq!(answered(transport.poll().unwrap()).0, 2);
+
+            // already answered
+            link.cancel(1);
+            assert!(transport.poll().unwrap().is_not_ready());
+            assert!(transport.early.is_empty() && transport.inflight.is_empty());
+            Ok::<_, ()>(())
+        }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn requests_are_sent_once_written() {
+        future::lazy(|| {
+            let stream = MockStream::default();
+            let link = Arc::new(Link::default());
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let conn = Connector::from_stream(addr, stream.clone(), link.clone());
+            let mut transport = ClientTransport::new(conn, codec(8), None);
+            let request = |id: RequestId| {
+                (id, (RpcRequestMeta::new(), Controller::default(), Bytes::new()))
+            };
+            assert!(transport.start_send(request(0)).unwrap().is_ready());
+            assert!(!link.is_sent(0));
+            transport.poll_complete().unwrap();
+            assert!(link.is_sent(0));
+            assert!(transport.start_send(request(1)).unwrap().is_ready());
+            assert!(!link.is_sent(1));
+            Ok::<_, ()>(())
+        }).wait()
+            .unwrap();
+    }
+
+    #[test]
+    fn oneway_requests_are_answered_once_written() {
+        future::lazy(|| {
+            let stream = MockStream::default();
+            let link = Arc::new(Link::default());
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let conn = Connector::from_stream(addr, stream.clone(), link.clone());
+            let mut transport = ClientTransport::new(conn, codec(8), None);
+            let (ack, acked) = oneshot::channel();
+            link.ack_when_sent(0, ack);
+            let mut meta = RpcRequestMeta::new();
+            meta.set_oneway(true);
+            let request = (0, (meta, Controller::default(), Bytes::new()));
+            assert!(transport.start_send(request).unwrap().is_ready());
+            assert!(transport.poll().unwrap().is_not_ready());
+
+            transport.poll_complete().unwrap();
+            assert!(link.is_sent(0));
+            let (id, meta) = answered(transport.poll().unwrap());
+            assert_eq!((id, meta.get_error_code()), (0, 0));
+            assert!(transport.inflight.is_empty());
+            acked.wait().unwrap();
+            Ok::<_, ()>(())
+        }).wait()
+            .unwrap();
+    }
+}
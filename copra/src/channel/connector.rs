@@ -1,208 +1,406 @@
-use futures::{Async, Future, Poll};
+use futures::{future, Async, Future, Poll};
+use futures::task::AtomicTask;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::io::{self, ErrorKind, Read, Write};
-use std::mem;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use tokio_core::net::TcpStream;
 use tokio_core::reactor::Handle;
-use tokio_core::net::{TcpStream, TcpStreamNew};
 use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_proto::multiplex::RequestId;
+#[cfg(unix)]
+use tokio_uds::UnixStream;
 
-enum State {
-    Connected(TcpStream),
-    Connecting(TcpStreamNew),
-    Disconnected,
+use server::TcpOptions;
+use super::{AckSender, Counted};
+#[cfg(feature = "tls")]
+use super::tls::TlsConnector;
+
+/// A stream to a server, over TCP or TLS
+pub trait ClientStream: AsyncRead + AsyncWrite + fmt::Debug {}
+
+impl<S: AsyncRead + AsyncWrite + fmt::Debug> ClientStream for S {}
+
+pub type BoxStream = Box<ClientStream>;
+
+/// A future resolving to a stream ready for requests
+pub(crate) type DialFuture = Box<Future<Item = BoxStream, Error = io::Error>>;
+
+/// How the streams to the servers are opened, and set up before the
+/// requests, shared by the connections of a channel
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Dialer {
+    /// The socket connected to instead of the address of the server
+    #[cfg(unix)]
+    unix: Option<Arc<PathBuf>>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConnector>,
+    /// Socket options of the TCP streams
+    tcp: TcpOptions,
 }
 
-impl fmt::Debug for State {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            State::Connected(ref tcp) => write!(f, "State::Connected({:?})", tcp),
-            State::Connecting(_) => write!(f, "State::Connecting"),
-            State::Disconnected => write!(f, "State::Disconnected"),
+impl Dialer {
+    /// Connect to the unix socket at `path`.
+    #[cfg(unix)]
+    pub fn unix(mut self, path: PathBuf) -> Self {
+        self.unix = Some(Arc::new(path));
+        self
+    }
+
+    /// Wrap the streams in TLS, as `connector` tells.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, connector: TlsConnector) -> Self {
+        self.tls = Some(connector);
+        self
+    }
+
+    /// Set `options` on the TCP streams once they are connected.
+    pub fn tcp_options(mut self, options: TcpOptions) -> Self {
+        self.tcp = options;
+        self
+    }
+
+    /// Name the server at `addr` in messages.
+    pub fn target(&self, addr: SocketAddr) -> String {
+        #[cfg(unix)]
+        {
+            if let Some(ref path) = self.unix {
+                return format!("unix:{}", path.display());
+            }
+        }
+        addr.to_string()
+    }
+
+    /// Open a stream to the server at `addr`, without setting it up.
+    pub fn probe(
+        &self,
+        addr: SocketAddr,
+        handle: &Handle,
+    ) -> Box<Future<Item = (), Error = io::Error>> {
+        #[cfg(unix)]
+        {
+            if let Some(ref path) = self.unix {
+                return Box::new(future::result(connect_unix(path, handle).map(|_| ())));
+            }
+        }
+        Box::new(TcpStream::connect(&addr, handle).map(|_| ()))
+    }
+
+    /// Open a stream to the server at `addr`, and set it up.
+    pub fn dial(&self, addr: SocketAddr, handle: &Handle) -> DialFuture {
+        #[cfg(unix)]
+        {
+            if let Some(ref path) = self.unix {
+                return self.set_up(addr, future::result(connect_unix(path, handle)));
+            }
+        }
+        let options = self.tcp;
+        let tcp = TcpStream::connect(&addr, handle).and_then(move |tcp| {
+            options.apply(&tcp)?;
+            Ok(tcp)
+        });
+        self.set_up(addr, tcp)
+    }
+
+    fn set_up<F>(&self, addr: SocketAddr, stream: F) -> DialFuture
+    where
+        F: Future<Error = io::Error> + 'static,
+        F::Item: ClientStream + 'static,
+    {
+        #[cfg(feature = "tls")]
+        {
+            if let Some(ref tls) = self.tls {
+                let tls = tls.clone();
+                return Box::new(stream.and_then(move |stream| tls.connect(addr, stream)));
+            }
+        }
+        let _ = addr;
+        Box::new(stream.map(|stream| Box::new(stream) as BoxStream))
+    }
+}
+
+/// Connect to the unix socket at `path`, telling a missing socket file from
+/// a stale one.
+#[cfg(unix)]
+fn connect_unix(path: &Path, handle: &Handle) -> io::Result<UnixStream> {
+    UnixStream::connect(path, handle).map_err(|e| {
+        let msg = match e.kind() {
+            ErrorKind::NotFound => "no socket file",
+            ErrorKind::ConnectionRefused => "nothing listens on the socket file",
+            _ => return e,
+        };
+        io::Error::new(e.kind(), format!("{} {}", msg, path.display()))
+    })
+}
+
+/// The state of a connection, shared by its connector, the load balancer
+/// and the backend of the channel
+#[derive(Debug, Default)]
+pub(crate) struct Link {
+    /// Whether the stream is usable
+    connected: AtomicBool,
+    /// Whether the channel is shut down, the stream then ends
+    closed: AtomicBool,
+    /// Whether data is received since the keepalive last looked
+    active: AtomicBool,
+    /// Why the stream went down, until the backend takes it
+    cause: Mutex<Option<io::Error>>,
+    /// The backend, woken when the stream goes down
+    task: AtomicTask,
+    /// Id the multiplexer gives to the next request
+    next_request: AtomicUsize,
+    /// Requests given up by their callers, until the transport takes them
+    cancelled: Mutex<Vec<RequestId>>,
+    /// The transport, woken when a request is given up
+    transport: AtomicTask,
+    /// Bytes written to the stream
+    written: AtomicUsize,
+    /// Where the requests none of whose bytes are written yet start in the
+    /// stream, in the order they are encoded
+    unwritten: Mutex<VecDeque<(RequestId, usize)>>,
+    /// Every request encoded below this id has bytes written to the stream
+    written_below: AtomicUsize,
+    /// Calls counted as queued until their request is written
+    queued: Mutex<HashMap<RequestId, Counted>>,
+    /// Oneway calls told once their request is written
+    acks: Mutex<HashMap<RequestId, AckSender>>,
+}
+
+impl Link {
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    /// Check if data is received since the last call.
+    pub fn take_active(&self) -> bool {
+        self.active.swap(false, Ordering::SeqCst)
+    }
+
+    /// Mark the stream as down because of `cause`, and end it.
+    pub fn fail(&self, cause: &io::Error) {
+        self.down(cause);
+        self.close();
+    }
+
+    /// Get why the stream went down, once.
+    pub fn take_cause(&self) -> Option<io::Error> {
+        self.cause.lock().unwrap().take()
+    }
+
+    /// Wake the current task when the stream goes down.
+    pub fn register(&self) {
+        self.task.register();
+    }
+
+    /// Get the id of a request about to be handed to the multiplexer.
+    ///
+    /// The multiplexer numbers the requests from zero in the order they are
+    /// handed over, which is followed here as it does not tell the ids.
+    pub fn next_request_id(&self) -> RequestId {
+        self.next_request.fetch_add(1, Ordering::SeqCst) as RequestId
+    }
+
+    /// Count the call of the request `id` in `queued` until any byte of the
+    /// request is written, or the call is over.
+    pub fn hold(&self, id: RequestId, queued: Counted) {
+        if !self.is_sent(id) {
+            self.queued.lock().unwrap().insert(id, queued);
+        }
+    }
+
+    /// Tell `ack` once any byte of the request `id` is written. It is
+    /// dropped instead if the call is over before.
+    pub fn ack_when_sent(&self, id: RequestId, ack: AckSender) {
+        let mut acks = self.acks.lock().unwrap();
+        if self.is_sent(id) {
+            let _ = ack.send(());
+        } else {
+            acks.insert(id, ack);
+        }
+    }
+
+    /// Stop counting the call of the request `id` as queued, as it is over.
+    pub fn release(&self, id: RequestId) {
+        self.queued.lock().unwrap().remove(&id);
+        self.acks.lock().unwrap().remove(&id);
+    }
+
+    /// Give up the request `id`, whose response is no longer waited for.
+    pub fn cancel(&self, id: RequestId) {
+        self.release(id);
+        self.cancelled.lock().unwrap().push(id);
+        self.transport.notify();
+    }
+
+    /// Get the requests given up since the last call, and wake the current
+    /// task when more are.
+    pub fn take_cancelled(&self) -> Vec<RequestId> {
+        self.transport.register();
+        let mut cancelled = self.cancelled.lock().unwrap();
+        cancelled.drain(..).collect()
+    }
+
+    /// Note that the request `id` is encoded after `pending` bytes not yet
+    /// written to the stream.
+    pub fn encoded(&self, id: RequestId, pending: usize) {
+        let start = self.written.load(Ordering::SeqCst) + pending;
+        self.unwritten.lock().unwrap().push_back((id, start));
+    }
+
+    /// Check if any byte of the request `id` is written to the stream, the
+    /// server may then have handled it.
+    ///
+    /// A request which is not sent when the stream breaks was never seen by
+    /// the server, and can be sent to another one.
+    pub fn is_sent(&self, id: RequestId) -> bool {
+        (id as usize) < self.written_below.load(Ordering::SeqCst)
+    }
+
+    /// Count `n` more bytes written to the stream.
+    fn wrote(&self, n: usize) {
+        let written = self.written.fetch_add(n, Ordering::SeqCst) + n;
+        let mut unwritten = self.unwritten.lock().unwrap();
+        while let Some(&(id, start)) = unwritten.front() {
+            if start >= written {
+                break;
+            }
+            unwritten.pop_front();
+            self.written_below.store(id as usize + 1, Ordering::SeqCst);
+            if let Some(ack) = self.acks.lock().unwrap().remove(&id) {
+                let _ = ack.send(());
+            }
+            self.release(id);
+        }
+    }
+
+    /// Mark the stream as down because of `cause`.
+    fn down(&self, cause: &io::Error) {
+        if self.connected.swap(false, Ordering::SeqCst) {
+            *self.cause.lock().unwrap() = Some(io::Error::new(cause.kind(), cause.to_string()));
+            self.task.notify();
         }
     }
 }
 
-#[derive(Debug)]
-pub struct Connector {
+/// The transport of a connection, which reports to its link when the
+/// stream breaks
+pub struct Connector<S> {
     addr: SocketAddr,
-    state: State,
-    handle: Handle,
+    stream: Option<S>,
+    link: Arc<Link>,
 }
 
-impl Connector {
-    pub fn from_stream(addr: SocketAddr, stream: TcpStream, handle: Handle) -> Self {
+impl<S> fmt::Debug for Connector<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connector")
+            .field("addr", &self.addr)
+            .field("connected", &self.stream.is_some())
+            .field("link", &self.link)
+            .finish()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Connector<S> {
+    pub(crate) fn from_stream(addr: SocketAddr, stream: S, link: Arc<Link>) -> Self {
+        link.connected.store(true, Ordering::SeqCst);
         Connector {
             addr,
-            state: State::Connected(stream),
-            handle,
+            stream: Some(stream),
+            link,
         }
     }
 
-    pub fn poll_ready(&mut self) -> Poll<(), io::Error> {
-        match mem::replace(&mut self.state, State::Disconnected) {
-            State::Connected(io) => {
-                self.state = State::Connected(io);
-                Ok(Async::Ready(()))
-            }
-            State::Connecting(mut fut) => match fut.poll()? {
-                Async::Ready(io) => {
-                    self.state = State::Connected(io);
-                    Ok(Async::Ready(()))
-                }
-                Async::NotReady => {
-                    self.state = State::Connecting(fut);
-                    Ok(Async::NotReady)
-                }
-            },
-            State::Disconnected => unreachable!(),
+    /// Mark the connection as down if `e` is not a `WouldBlock`.
+    ///
+    /// The error is passed on, which fails the calls in flight, and the
+    /// channel connects to the server again.
+    pub(crate) fn broken(&self, e: io::Error) -> io::Error {
+        if e.kind() != ErrorKind::WouldBlock {
+            self.link.down(&e);
         }
+        e
     }
 
-    fn reconnect(&mut self) {
-        let new = TcpStream::connect(&self.addr, &self.handle);
-        self.state = State::Connecting(new);
+    /// Get the shared state of the connection.
+    pub(crate) fn link(&self) -> &Arc<Link> {
+        &self.link
+    }
+
+    /// Get the stream, unless it is closed.
+    fn stream(&mut self) -> io::Result<&mut S> {
+        match self.stream {
+            Some(ref mut stream) => Ok(stream),
+            None => Err(io::Error::new(ErrorKind::NotConnected, "connection closed")),
+        }
     }
 }
 
-impl Read for Connector {
+impl<S: AsyncRead + AsyncWrite> Read for Connector<S> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        loop {
-            match mem::replace(&mut self.state, State::Disconnected) {
-                State::Connected(mut io) => {
-                    let r = io.read(buf);
-                    match r {
-                        Ok(_) => {
-                            self.state = State::Connected(io);
-                            return r;
-                        }
-                        Err(e) => {
-                            if e.kind() == ErrorKind::WouldBlock {
-                                self.state = State::Connected(io);
-                                return Err(e);
-                            }
-                            // TODO: elaborate err conditions.
-                            // Currently we assume all the errors except for Wouldblock
-                            // are caused by a broken connection.
-                        }
-                    };
-                }
-                State::Connecting(mut fut) => match fut.poll()? {
-                    Async::Ready(io) => {
-                        self.state = State::Connected(io);
-                    }
-                    Async::NotReady => {
-                        self.state = State::Connecting(fut);
-                        return Err(io::Error::new(
-                            ErrorKind::WouldBlock,
-                            "Waiting for connection to be established",
-                        ));
-                    }
-                },
-                State::Disconnected => {
-                    self.reconnect();
-                }
+        if self.link.closed.load(Ordering::SeqCst) {
+            self.link.connected.store(false, Ordering::SeqCst);
+            self.stream = None;
+            return Ok(0);
+        }
+        let r = self.stream()?.read(buf);
+        match r {
+            // The server closed the connection. This is an error rather
+            // than the end of the stream, so that the calls in flight fail
+            // at once instead of waiting for responses which never come.
+            Ok(0) if !buf.is_empty() => {
+                let e = io::Error::new(
+                    ErrorKind::ConnectionAborted,
+                    "connection closed by the server",
+                );
+                self.link.down(&e);
+                Err(e)
             }
+            Ok(n) => {
+                self.link.active.store(true, Ordering::SeqCst);
+                Ok(n)
+            }
+            Err(e) => Err(self.broken(e)),
         }
     }
 }
 
-impl Write for Connector {
+impl<S: AsyncRead + AsyncWrite> Write for Connector<S> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        loop {
-            match mem::replace(&mut self.state, State::Disconnected) {
-                State::Connected(mut io) => {
-                    let r = io.write(buf);
-                    match r {
-                        Ok(_) => {
-                            self.state = State::Connected(io);
-                            return r;
-                        }
-                        Err(e) => {
-                            if e.kind() == ErrorKind::WouldBlock {
-                                self.state = State::Connected(io);
-                                return Err(e);
-                            }
-                        }
-                    };
-                }
-                State::Connecting(mut fut) => match fut.poll()? {
-                    Async::Ready(io) => {
-                        self.state = State::Connected(io);
-                    }
-                    Async::NotReady => {
-                        self.state = State::Connecting(fut);
-                        return Err(io::Error::new(
-                            ErrorKind::WouldBlock,
-                            "Waiting for connection to be established",
-                        ));
-                    }
-                },
-                State::Disconnected => {
-                    self.reconnect();
-                }
-            }
+        let r = self.stream()?.write(buf);
+        if let Ok(n) = r {
+            self.link.wrote(n);
         }
+        r.map_err(|e| self.broken(e))
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        loop {
-            match mem::replace(&mut self.state, State::Disconnected) {
-                State::Connected(mut io) => {
-                    let r = io.flush();
-                    match r {
-                        Ok(_) => {
-                            self.state = State::Connected(io);
-                            return r;
-                        }
-                        Err(e) => {
-                            if e.kind() == ErrorKind::WouldBlock {
-                                self.state = State::Connected(io);
-                                return Err(e);
-                            }
-                        }
-                    };
-                }
-                State::Connecting(mut fut) => match fut.poll()? {
-                    Async::Ready(io) => {
-                        self.state = State::Connected(io);
-                    }
-                    Async::NotReady => {
-                        self.state = State::Connecting(fut);
-                        return Err(io::Error::new(
-                            ErrorKind::WouldBlock,
-                            "Waiting for connection to be established",
-                        ));
-                    }
-                },
-                State::Disconnected => {
-                    self.reconnect();
-                }
-            }
-        }
+        let r = self.stream()?.flush();
+        r.map_err(|e| self.broken(e))
     }
 }
 
-impl AsyncRead for Connector {
-    // Ported from <TcpStream as AsyncRead>
-    unsafe fn prepare_uninitialized_buffer(&self, _: &mut [u8]) -> bool {
-        false
+impl<S: AsyncRead + AsyncWrite> AsyncRead for Connector<S> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        match self.stream {
+            Some(ref stream) => stream.prepare_uninitialized_buffer(buf),
+            None => false,
+        }
     }
 }
 
-impl AsyncWrite for Connector {
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for Connector<S> {
     fn shutdown(&mut self) -> Poll<(), io::Error> {
-        match mem::replace(&mut self.state, State::Disconnected) {
-            State::Connected(mut io) => {
-                let r = <AsyncWrite>::shutdown(&mut io);
-                self.state = State::Connected(io);
-                r
-            }
-            State::Connecting(_) => {
-                self.state = State::Disconnected;
-                Ok(Async::Ready(()))
-            }
-            State::Disconnected => Ok(Async::Ready(())),
+        match self.stream {
+            Some(ref mut stream) => stream.shutdown(),
+            None => Ok(Async::Ready(())),
         }
     }
 }
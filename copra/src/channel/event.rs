@@ -0,0 +1,80 @@
+//! Events of the connections of a channel
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Something which happened to the connections of a channel, passed to the
+/// callback set by [`ChannelBuilder::on_channel_event`]
+///
+/// [`ChannelBuilder::on_channel_event`]: struct.ChannelBuilder.html#method.on_channel_event
+#[derive(Debug)]
+pub enum ChannelEvent {
+    /// A connection to the server is established
+    Connected(SocketAddr),
+    /// A connection to the server went down, the calls in flight on it
+    /// fail
+    Disconnected {
+        /// Address of the server
+        addr: SocketAddr,
+        /// Why the connection went down
+        cause: io::Error,
+    },
+    /// The server is connected to again after `delay`, as connecting to it
+    /// failed or its connection went down
+    ReconnectScheduled {
+        /// Address of the server
+        addr: SocketAddr,
+        /// Time before the next attempt
+        delay: Duration,
+    },
+    /// The channel is shut down, or all its handles are dropped, no event
+    /// follows
+    Closed,
+}
+
+type EventFn = Fn(&str, &ChannelEvent) + Send + Sync;
+
+/// A shared channel event callback, with the name of the channel
+#[derive(Clone)]
+pub(crate) struct EventHook {
+    f: Arc<EventFn>,
+    name: Arc<str>,
+}
+
+impl EventHook {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&str, &ChannelEvent) + Send + Sync + 'static,
+    {
+        EventHook {
+            f: Arc::new(f),
+            name: Arc::from(""),
+        }
+    }
+
+    /// Pass `name` as the name of the channel.
+    pub fn named(mut self, name: Arc<str>) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn emit(&self, event: &ChannelEvent) {
+        (self.f)(&self.name, event)
+    }
+}
+
+impl fmt::Debug for EventHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EventHook({})", self.name)
+    }
+}
+
+/// Pass `event` to `hook`, if any.
+pub(crate) fn emit(hook: Option<&EventHook>, event: ChannelEvent) {
+    if let Some(hook) = hook {
+        hook.emit(&event);
+    }
+}
@@ -0,0 +1,168 @@
+//! Health checking of the servers of a channel
+
+use bytes::Bytes;
+use futures::{future, Async, Future, Poll, Stream};
+use futures::stream::FuturesUnordered;
+use protobuf::{self, Message};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Interval, Timeout};
+use tokio_service::Service;
+
+use controller::Controller;
+use errno;
+use load_balancer::ServerEndPort;
+use message::{HealthCheckRequest, HealthCheckResponse, RpcRequestMeta, ServingStatus};
+use server::health::SERVICE_NAME;
+use stub::timeout_ms;
+
+use super::connector::Dialer;
+use super::{RequestPackage, ServerCounters};
+
+/// Shortest interval between the probes of a server
+const MIN_INTERVAL_MS: u64 = 100;
+
+/// How the servers of a channel are probed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthCheckMode {
+    /// Open a new TCP connection to the server, and close it once
+    /// established.
+    Connect,
+    /// Call the built-in health check service over the connection of the
+    /// server, asking about the server as a whole. A server which does not
+    /// register the service is up as long as it answers.
+    Rpc,
+}
+
+type ProbeFuture = Box<Future<Item = (Arc<ServerCounters>, bool), Error = ()>>;
+
+/// Probes the servers of a channel on an interval
+///
+/// A server is probed at most once per interval, and never while its last
+/// probe is not answered. A probe not answered within the interval fails.
+pub(crate) struct HealthCheck {
+    /// Name of the channel, told by the logs
+    name: Arc<str>,
+    interval: Duration,
+    mode: HealthCheckMode,
+    tick: Interval,
+    probes: FuturesUnordered<ProbeFuture>,
+}
+
+impl HealthCheck {
+    pub fn new(interval: Duration, mode: HealthCheckMode, handle: &Handle) -> io::Result<Self> {
+        let interval = interval.max(Duration::from_millis(MIN_INTERVAL_MS));
+        Ok(HealthCheck {
+            name: Arc::from(""),
+            interval,
+            mode,
+            tick: Interval::new(interval, handle)?,
+            probes: FuturesUnordered::new(),
+        })
+    }
+
+    /// Tell `name` as the name of the channel in the logs.
+    pub fn named(mut self, name: Arc<str>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Check if it is time to probe the servers again.
+    pub fn poll_tick(&mut self) -> bool {
+        let mut ticked = false;
+        // skip the ticks missed while the reactor is busy
+        while let Ok(Async::Ready(Some(()))) = self.tick.poll() {
+            ticked = true;
+        }
+        ticked
+    }
+
+    /// Probe the server of `counters`, over `end_port` if it is connected,
+    /// or by opening a stream with `dialer`.
+    ///
+    /// Returns whether a probe is sent.
+    pub fn probe(
+        &mut self,
+        counters: Arc<ServerCounters>,
+        end_port: Option<&ServerEndPort>,
+        handle: &Handle,
+        dialer: &Dialer,
+    ) -> bool {
+        let probe: Box<Future<Item = bool, Error = io::Error>> = match (self.mode, end_port) {
+            (HealthCheckMode::Connect, _) => {
+                Box::new(dialer.probe(counters.addr, handle).map(|()| true))
+            }
+            (HealthCheckMode::Rpc, Some(end_port)) => {
+                Box::new(check(end_port, Some(self.interval)))
+            }
+            // the connection is down, there is nothing to call
+            (HealthCheckMode::Rpc, None) => return false,
+        };
+        let timeout = match Timeout::new(self.interval, handle) {
+            Ok(timeout) => timeout,
+            Err(e) => {
+                warn!("[{}] Failed to probe server {}: {}", self.name, counters.addr, e);
+                return false;
+            }
+        };
+        let name = self.name.clone();
+        let probe = probe
+            .select(timeout.map(|_| false))
+            .then(move |result| {
+                let up = match result {
+                    Ok((up, _)) => up,
+                    Err((e, _)) => {
+                        debug!("[{}] Probe of server {} failed: {}", name, counters.addr, e);
+                        false
+                    }
+                };
+                Ok((counters, up))
+            });
+        self.probes.push(Box::new(probe));
+        true
+    }
+}
+
+impl Stream for HealthCheck {
+    type Item = (Arc<ServerCounters>, bool);
+    type Error = ();
+
+    /// Get the outcome of a probe, the stream never ends.
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.probes.poll() {
+            Ok(Async::Ready(Some(outcome))) => Ok(Async::Ready(Some(outcome))),
+            _ => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Call the health check service of a server, resolving to whether it is
+/// serving. The server is told to give up the call after `timeout`, if any.
+pub(crate) fn check(
+    end_port: &ServerEndPort,
+    timeout: Option<Duration>,
+) -> Box<Future<Item = bool, Error = io::Error>> {
+    let body = match HealthCheckRequest::new().write_to_bytes() {
+        Ok(body) => body,
+        Err(e) => return Box::new(future::err(io::Error::from(e))),
+    };
+    let mut meta = RpcRequestMeta::new();
+    meta.set_service_name(SERVICE_NAME.to_string());
+    meta.set_method_name("check".to_string());
+    if let Some(timeout) = timeout {
+        meta.set_timeout_ms(timeout_ms(timeout));
+    }
+    let req: RequestPackage = (meta, Controller::default(), Bytes::from(body));
+    Box::new(end_port.call(req).and_then(|(meta, body)| {
+        match meta.get_error_code() {
+            errno::SUCCESS => {
+                let resp: HealthCheckResponse = protobuf::parse_from_bytes(&body)?;
+                Ok(resp.get_status() == ServingStatus::SERVING)
+            }
+            // the server does not register the health check service
+            errno::ENOSERVICE | errno::ENOMETHOD => Ok(true),
+            _ => Ok(false),
+        }
+    }))
+}
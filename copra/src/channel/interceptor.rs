@@ -0,0 +1,78 @@
+//! Code that runs around the calls of a channel
+
+use std::fmt;
+
+use controller::Controller;
+use message::{RpcRequestMeta, RpcResponseMeta};
+use service::MethodError;
+
+/// Code that runs around every call made by a stub, on the client
+///
+/// Interceptors are added by [`ChannelBuilder::add_interceptor`], and run in
+/// the order they are added. Before a call is sent, each one can modify the
+/// request meta, whose service and method names are set, and the
+/// [`Controller`] of the call, e.g. to set the `authentication_data` sent
+/// to the server. Once the server answers, each one can read the response
+/// meta.
+///
+/// # Examples
+///
+/// Send a token with every call:
+///
+/// ```
+/// # extern crate copra;
+/// use copra::{Controller, MethodError};
+/// use copra::channel::ClientInterceptor;
+/// use copra::message::RpcRequestMeta;
+///
+/// struct Token(Vec<u8>);
+///
+/// impl ClientInterceptor for Token {
+///     fn before_call(
+///         &self,
+///         _meta: &mut RpcRequestMeta,
+///         controller: &mut Controller,
+///     ) -> Result<(), MethodError> {
+///         controller.authentication_data = self.0.clone();
+///         Ok(())
+///     }
+/// }
+/// # fn main() {}
+/// ```
+///
+/// [`ChannelBuilder::add_interceptor`]: struct.ChannelBuilder.html#method.add_interceptor
+/// [`Controller`]: ../controller/struct.Controller.html
+pub trait ClientInterceptor: Send + Sync {
+    /// Prepare a call before it is sent, oneway calls included.
+    ///
+    /// An error fails the call at once with `RpcError::Rejected` holding
+    /// it, the call is not sent and the interceptors after this one do not
+    /// run.
+    fn before_call(
+        &self,
+        meta: &mut RpcRequestMeta,
+        controller: &mut Controller,
+    ) -> Result<(), MethodError>;
+
+    /// Look at the response meta of a call answered by the server, whatever
+    /// its error code. Does nothing by default.
+    fn after_call(&self, _meta: &RpcRequestMeta, _response: &RpcResponseMeta) {}
+}
+
+impl fmt::Debug for ClientInterceptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ClientInterceptor")
+    }
+}
+
+/// Run `interceptors` in order on a call about to be sent.
+pub(crate) fn before_call(
+    interceptors: &[Box<ClientInterceptor>],
+    meta: &mut RpcRequestMeta,
+    controller: &mut Controller,
+) -> Result<(), MethodError> {
+    for interceptor in interceptors {
+        interceptor.before_call(meta, controller)?;
+    }
+    Ok(())
+}
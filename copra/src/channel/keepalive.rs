@@ -0,0 +1,99 @@
+//! Keepalive pings on the idle connections of a channel
+
+use futures::{Async, Future, Stream};
+use futures::stream::FuturesUnordered;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Interval, Timeout};
+
+use load_balancer::ServerEndPort;
+
+use super::health::check;
+
+/// Shortest interval between the pings of a connection
+const MIN_INTERVAL_MS: u64 = 100;
+
+type PingFuture = Box<Future<Item = (), Error = ()>>;
+
+/// Pings the idle connections of a channel on an interval
+///
+/// A connection which received nothing since the last tick is sent a call
+/// to the health check service of the server. Any response keeps the
+/// connection, while a failed call or no response within the interval
+/// marks it down, so that the server is connected to again.
+pub(crate) struct Keepalive {
+    /// Name of the channel, told by the logs
+    name: Arc<str>,
+    interval: Duration,
+    tick: Interval,
+    pings: FuturesUnordered<PingFuture>,
+}
+
+impl Keepalive {
+    pub fn new(interval: Duration, handle: &Handle) -> io::Result<Self> {
+        let interval = interval.max(Duration::from_millis(MIN_INTERVAL_MS));
+        Ok(Keepalive {
+            name: Arc::from(""),
+            interval,
+            tick: Interval::new(interval, handle)?,
+            pings: FuturesUnordered::new(),
+        })
+    }
+
+    /// Tell `name` as the name of the channel in the logs.
+    pub fn named(mut self, name: Arc<str>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Check if it is time to look for idle connections again.
+    pub fn poll_tick(&mut self) -> bool {
+        let mut ticked = false;
+        // skip the ticks missed while the reactor is busy
+        while let Ok(Async::Ready(Some(()))) = self.tick.poll() {
+            ticked = true;
+        }
+        ticked
+    }
+
+    /// Ping the server at `addr` over `end_port`, unless the connection
+    /// received something since the last tick.
+    pub fn ping(&mut self, addr: SocketAddr, end_port: &ServerEndPort, handle: &Handle) {
+        if end_port.link().take_active() {
+            return;
+        }
+        let timeout = match Timeout::new(self.interval, handle) {
+            Ok(timeout) => timeout,
+            Err(e) => {
+                warn!("[{}] Failed to ping server {}: {}", self.name, addr, e);
+                return;
+            }
+        };
+        let (link, interval) = (end_port.link().clone(), self.interval);
+        let name = self.name.clone();
+        let ping = check(end_port, Some(interval))
+            .map(|_| true)
+            .select(timeout.map(|()| false))
+            .then(move |result| {
+                let cause = match result {
+                    Ok((true, _)) => return Ok(()),
+                    Ok((false, _)) => {
+                        let msg = format!("keepalive ping not answered within {:?}", interval);
+                        io::Error::new(io::ErrorKind::TimedOut, msg)
+                    }
+                    Err((e, _)) => e,
+                };
+                warn!("[{}] Connection to {} is dead: {}", name, addr, cause);
+                link.fail(&cause);
+                Ok(())
+            });
+        self.pings.push(Box::new(ping));
+    }
+
+    /// Drive the pings in flight.
+    pub fn poll_pings(&mut self) {
+        while let Ok(Async::Ready(Some(()))) = self.pings.poll() {}
+    }
+}
@@ -0,0 +1,297 @@
+//! Adaptive concurrency limit of a channel
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// How the number of calls in flight on a channel adapts to their latency
+///
+/// The limit is raised while the calls are fast, and lowered as soon as
+/// they slow down, in the manner of an AIMD congestion window. A call is
+/// fast if it is answered within `tolerance` times the lowest latency seen
+/// lately, which is taken as the latency of an idle server.
+///
+/// Every fast call raises the limit by one over the limit, i.e. by one once
+/// as many calls as the limit are answered, as long as at least half of the
+/// limit is in use. A slow call, or one which gets no response, multiplies
+/// the limit by `backoff`. The calls issued before the limit is lowered do
+/// not lower it again, so that a burst of slow calls lowers it once.
+///
+/// Calls issued above the limit fail at once with
+/// `RpcError::ConcurrencyLimited`, and are not sent.
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimit {
+    initial: usize,
+    min: usize,
+    max: usize,
+    tolerance: f64,
+    backoff: f64,
+    baseline_window: Duration,
+}
+
+impl ConcurrencyLimit {
+    /// Create a limit starting at 20 calls, between 1 and 1000, lowered by
+    /// 10 percent when a call is twice as slow as the lowest latency.
+    pub fn new() -> Self {
+        ConcurrencyLimit {
+            initial: 20,
+            min: 1,
+            max: 1000,
+            tolerance: 2.0,
+            backoff: 0.9,
+            baseline_window: Duration::from_secs(30),
+        }
+    }
+
+    /// Set the limit of calls in flight before any call is answered.
+    ///
+    /// Default to 20, kept between the bounds of the limit.
+    pub fn initial(mut self, initial: usize) -> Self {
+        self.initial = initial;
+        self
+    }
+
+    /// Set the bounds of the limit.
+    ///
+    /// Default to 1 and 1000. At least one call is allowed.
+    pub fn bounds(mut self, min: usize, max: usize) -> Self {
+        self.min = min.max(1);
+        self.max = max.max(self.min);
+        self
+    }
+
+    /// Set how many times slower than the lowest latency a call may be
+    /// before it lowers the limit.
+    ///
+    /// Default to 2, at least 1.
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance.max(1.0);
+        self
+    }
+
+    /// Set the ratio the limit is multiplied by when it is lowered, between
+    /// 0 and 1.
+    ///
+    /// Default to 0.9.
+    pub fn backoff(mut self, backoff: f64) -> Self {
+        self.backoff = if backoff > 1.0 { 1.0 } else { backoff.max(0.0) };
+        self
+    }
+
+    /// Set how long the lowest latency is kept before it is measured again,
+    /// so that the limit follows a server which got slower for good.
+    ///
+    /// Default to 30 seconds.
+    pub fn baseline_window(mut self, window: Duration) -> Self {
+        self.baseline_window = window;
+        self
+    }
+}
+
+impl Default for ConcurrencyLimit {
+    fn default() -> Self {
+        ConcurrencyLimit::new()
+    }
+}
+
+#[derive(Debug)]
+struct LimitState {
+    limit: f64,
+    /// Lowest latency seen since `baseline_since`
+    baseline: Option<Duration>,
+    baseline_since: Instant,
+    /// When the limit was last lowered
+    lowered_at: Option<Instant>,
+}
+
+/// The adaptive concurrency limit of a channel, shared by its clones
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    /// Name of the channel, told by the logs
+    name: Arc<str>,
+    options: ConcurrencyLimit,
+    inflight: AtomicUsize,
+    state: Mutex<LimitState>,
+}
+
+impl Limiter {
+    pub fn new(options: ConcurrencyLimit) -> Self {
+        let limit = options.initial.max(options.min).min(options.max);
+        Limiter {
+            name: Arc::from(""),
+            options,
+            inflight: AtomicUsize::new(0),
+            state: Mutex::new(LimitState {
+                limit: limit as f64,
+                baseline: None,
+                baseline_since: Instant::now(),
+                lowered_at: None,
+            }),
+        }
+    }
+
+    /// Tell `name` as the name of the channel in the logs.
+    pub fn named(mut self, name: Arc<str>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Get the current limit.
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit as usize
+    }
+
+    /// Get the number of calls in flight.
+    pub fn inflight(&self) -> usize {
+        self.inflight.load(Ordering::SeqCst)
+    }
+
+    /// Let a call in, or `None` if as many calls as the limit are in
+    /// flight.
+    pub fn acquire(limiter: &Arc<Limiter>) -> Option<LimitPermit> {
+        let limit = limiter.limit();
+        if limiter.inflight.fetch_add(1, Ordering::SeqCst) >= limit {
+            limiter.inflight.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(LimitPermit {
+            limiter: limiter.clone(),
+            start: Instant::now(),
+            answered: false,
+        })
+    }
+
+    /// Adjust the limit to a call issued at `start`, which was answered or
+    /// not. `inflight` is the number of calls in flight, the call included.
+    fn record(&self, start: Instant, answered: bool, inflight: usize) {
+        let now = Instant::now();
+        let options = &self.options;
+        let mut state = self.state.lock().unwrap();
+        let latency = now - start;
+        if now - state.baseline_since >= options.baseline_window {
+            state.baseline = None;
+            state.baseline_since = now;
+        }
+        let baseline = match state.baseline {
+            Some(baseline) if baseline <= latency => baseline,
+            _ if answered => {
+                state.baseline = Some(latency);
+                latency
+            }
+            _ => latency,
+        };
+
+        let slow = duration_secs(latency) > duration_secs(baseline) * options.tolerance;
+        let lowered = match state.lowered_at {
+            Some(at) => start < at,
+            None => false,
+        };
+        if answered && !slow {
+            // the limit is only known to be fine if it is used
+            if inflight as f64 * 2.0 >= state.limit {
+                state.limit = (state.limit + 1.0 / state.limit).min(options.max as f64);
+            }
+        } else if !lowered {
+            state.limit = (state.limit * options.backoff).max(options.min as f64);
+            state.lowered_at = Some(now);
+            debug!("[{}] Concurrency limit lowered to {}", self.name, state.limit as usize);
+        }
+    }
+}
+
+fn duration_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9
+}
+
+/// A call let in by the concurrency limit, in flight until it is dropped
+///
+/// The call counts as not answered unless `answered` is called.
+#[derive(Debug)]
+pub(crate) struct LimitPermit {
+    limiter: Arc<Limiter>,
+    start: Instant,
+    answered: bool,
+}
+
+impl LimitPermit {
+    /// Mark the call answered.
+    pub fn answered(&mut self) {
+        self.answered = true;
+    }
+}
+
+impl Drop for LimitPermit {
+    fn drop(&mut self) {
+        let inflight = self.limiter.inflight.fetch_sub(1, Ordering::SeqCst);
+        self.limiter.record(self.start, self.answered, inflight);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    fn finish(limiter: &Arc<Limiter>, n: usize, latency: Duration, answered: bool) {
+        let permits: Vec<_> = (0..n).map(|_| Limiter::acquire(limiter).unwrap()).collect();
+        for mut permit in permits {
+            permit.start -= latency;
+            if answered {
+                permit.answered();
+            }
+        }
+    }
+
+    #[test]
+    fn calls_above_limit_are_rejected() {
+        let limiter = Arc::new(Limiter::new(ConcurrencyLimit::new().initial(2)));
+        let mut first = Limiter::acquire(&limiter).unwrap();
+        first.answered();
+        let _second = Limiter::acquire(&limiter).unwrap();
+        assert!(Limiter::acquire(&limiter).is_none());
+        assert_eq!(limiter.inflight(), 2);
+        drop(first);
+        assert_eq!(limiter.inflight(), 1);
+        assert!(Limiter::acquire(&limiter).is_some());
+    }
+
+    #[test]
+    fn limit_follows_latency() {
+        let options = ConcurrencyLimit::new().initial(10).bounds(2, 12);
+        let limiter = Arc::new(Limiter::new(options));
+        let fast = Duration::from_millis(10);
+
+        // raised by about one per two limits of fast calls, up to the bound
+        finish(&limiter, 10, fast, true);
+        assert_eq!(limiter.limit(), 10);
+        finish(&limiter, 10, fast, true);
+        finish(&limiter, 10, fast, true);
+        assert_eq!(limiter.limit(), 11);
+        for _ in 0..10 {
+            let limit = limiter.limit();
+            finish(&limiter, limit, fast, true);
+        }
+        assert_eq!(limiter.limit(), 12);
+
+        // a burst of slow calls lowers it once, each later one again
+        finish(&limiter, 12, Duration::from_millis(50), true);
+        assert_eq!(limiter.limit(), 10);
+        thread::sleep(Duration::from_millis(60));
+        finish(&limiter, 1, Duration::from_millis(50), true);
+        assert_eq!(limiter.limit(), 9);
+        // calls without response lower it down to the bound
+        for _ in 0..20 {
+            finish(&limiter, 1, Duration::from_millis(0), false);
+        }
+        assert_eq!(limiter.limit(), 2);
+    }
+
+    #[test]
+    fn unused_limit_is_not_raised() {
+        let limiter = Arc::new(Limiter::new(ConcurrencyLimit::new().initial(10)));
+        for _ in 0..100 {
+            finish(&limiter, 1, Duration::from_millis(10), true);
+        }
+        assert_eq!(limiter.limit(), 10);
+    }
+}
@@ -9,6 +9,7 @@
 //! # extern crate tokio_core;
 //! # use std::error::Error;
 //! use copra::ChannelBuilder;
+//! use std::time::Duration;
 //! use tokio_core::reactor::Core;
 //!
 //! # fn main() {
@@ -16,51 +17,118 @@
 //! # }
 //! # fn try_main() -> Result<(), Box<Error>> {
 //! let mut core = Core::new()?;
-//! let builder = ChannelBuilder::single_server("127.0.0.1:8000", core.handle());
-//! let channel = core.run(builder.build())?;
+//! let handle = core.handle();
+//! let channel = ChannelBuilder::single_server_spawned("127.0.0.1:8000", &handle, |builder| {
+//!     builder.rpc_timeout(Duration::from_secs(1))
+//! });
+//! let channel = core.run(channel)?;
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! # Threads
+//!
+//! The backend of a channel, which owns its connections, runs on the event
+//! loop it is built on, and so do the futures returned by `build` and
+//! `build_split`, which are not `Send`. The `Channel` only talks to its
+//! backend through queues: it is `Send` and `Sync`, and so are the stubs
+//! bound to it and the futures of their calls, which may be driven on other
+//! threads, e.g. on a `CpuPool`.
 
 use bytes::Bytes;
 use tokio_core::net::TcpStream;
 use tokio_core::reactor::Handle;
-use tokio_io::AsyncRead;
-use tokio_io::codec::Framed;
-use tokio_proto::multiplex::ClientProto;
-use tokio_proto::TcpClient;
-use futures::{Async, Future, IntoFuture, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_proto::BindClient;
+use tokio_proto::multiplex::{ClientProto, Multiplex};
+use tokio_timer::{TimeoutError, Timer};
+use futures::{future, Async, Future, Poll, Stream};
 use futures::sync::mpsc;
 use futures::sync::oneshot;
 use std::error::Error;
 use std::fmt;
 use std::io;
-use std::net::{AddrParseError, SocketAddr};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Duration;
-
-use protocol::{BrpcProtocol, ProtoCodecClient, Protocol, RpcProtocol};
-use load_balancer::{CallInfo, ServerEndPort, ServerId};
-use load_balancer::single_server::SingleServerLoadBalancer;
+use std::net::{AddrParseError, IpAddr, SocketAddr};
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tls")]
+use native_tls;
+use compress::CompressType;
+use controller::Controller;
+use protocol::{BrpcProtocol, HttpClientProtocol, ProtoCodecClient, Protocol, RpcProtocol};
+use load_balancer::{CallInfo, RoundRobin, ServerEndPort, ServerId, WeightedRoundRobin};
 use message::{RpcRequestMeta, RpcResponseMeta};
+use monitor::ChannelMetrics;
+use server::TcpOptions;
+use naming::{DnsNaming, DnsResolver, FileNaming, Resolve, ServerStream};
+use stub::timeout_ms;
+use timer;
+
+use self::addr::Target;
+use self::backend::{tags_of, Backoff, Connection, Naming};
+use self::breaker::Breaker;
+use self::codec::{ClientCodec, ClientTransport};
+use self::health::{check, HealthCheck};
+use self::keepalive::Keepalive;
+use self::uri::{ChannelUri, UriTarget};
+use self::connector::{BoxStream, Connector, Dialer, Link};
+use self::event::{emit, EventHook};
+use self::limit::{LimitPermit, Limiter};
 
-use self::backend::ChannelBackend;
-use self::connector::Connector;
+pub use load_balancer::{BackendInfo, LbHint, LoadBalance};
+pub use naming::{NamingOptions, NamingService, ServerEndpoint};
+pub use self::addr::{IntoServerAddr, ServerAddr};
+pub use self::backend::ChannelBackend;
+pub use self::breaker::{CircuitBreaker, CircuitState};
+pub use self::event::ChannelEvent;
+pub use self::health::HealthCheckMode;
+pub use self::interceptor::ClientInterceptor;
+pub use self::limit::ConcurrencyLimit;
+pub use self::retry::RetryPolicy;
+#[cfg(feature = "tls")]
+pub use self::tls::ClientTlsConfig;
 
+mod addr;
 mod backend;
+mod breaker;
+mod codec;
+mod event;
+mod health;
+pub(crate) mod interceptor;
+mod keepalive;
 pub(crate) mod connector;
+mod limit;
+mod retry;
+#[cfg(feature = "tls")]
+mod tls;
+mod uri;
 
 /// A future returned by `ChannelBuilder::build` which will resolve to a `Channel`
 /// when the channel is ready for use.
 pub type ChannelBuildFuture = Box<Future<Item = Channel, Error = ChannelBuildError>>;
 
+/// A future resolving to a channel and the backend sending its calls
+pub type ChannelSplitFuture =
+    Box<Future<Item = (Channel, ChannelBackend), Error = ChannelBuildError>>;
+
+/// A future resolving to the connection to a server
+pub(crate) type ConnectFuture = Box<Future<Item = ServerEndPort, Error = io::Error>>;
+
+
 // TODO: make this fully public
 // TODO: move this to a better place
-pub(crate) type RequestPackage = (RpcRequestMeta, Bytes);
+pub(crate) type RequestPackage = (RpcRequestMeta, Controller, Bytes);
 
 pub(crate) type ResponsePackage = (RpcResponseMeta, Bytes);
 
+/// The interceptors of a channel, shared by its clones
+pub(crate) type Interceptors = Arc<Vec<Box<ClientInterceptor>>>;
+
 type FeedbackSender = oneshot::Sender<(ServerId, CallInfo)>;
 
 type FeedbackReceiver = oneshot::Receiver<(ServerId, CallInfo)>;
@@ -69,24 +137,166 @@ type OneShotSender = oneshot::Sender<io::Result<(ResponsePackage, FeedbackHandle
 
 type OneShotReceiver = oneshot::Receiver<io::Result<(ResponsePackage, FeedbackHandle)>>;
 
-type ChannelSender = mpsc::UnboundedSender<(OneShotSender, RequestPackage)>;
+pub(crate) type AckSender = oneshot::Sender<()>;
+
+type AckReceiver = oneshot::Receiver<()>;
+
+/// A call for the backend, counted as queued until its request is written
+type ChannelMessage = (Callback, RequestPackage, SendOptions, Counted);
+
+type ChannelSender = mpsc::UnboundedSender<ChannelMessage>;
+
+type ChannelReceiver = mpsc::UnboundedReceiver<ChannelMessage>;
+
+/// Asks the backend to shut down, and is notified once it is done
+pub(crate) type ShutdownSender = mpsc::UnboundedSender<oneshot::Sender<()>>;
+
+pub(crate) type ShutdownReceiver = mpsc::UnboundedReceiver<oneshot::Sender<()>>;
+
+/// Gives the backend the servers set by `Channel::update_endpoints`
+type EndpointSender = mpsc::UnboundedSender<Vec<ServerEndpoint>>;
+
+/// The servers a channel currently sends calls to
+pub(crate) type ServerList = Arc<Mutex<Vec<Arc<ServerCounters>>>>;
+
+/// Counters of a server of a channel
+#[derive(Debug)]
+pub(crate) struct ServerCounters {
+    addr: SocketAddr,
+    /// Calls sent to the server, not counting retries
+    calls: AtomicUsize,
+    /// Retries of failed calls sent to the server
+    retries: AtomicUsize,
+    /// Calls sent to the server and not answered yet
+    inflight: AtomicUsize,
+    /// Weight of the server given to the load balancer
+    weight: AtomicUsize,
+    /// Ejects the server when too many calls fail
+    breaker: Breaker,
+    /// Whether the server passed its last health check
+    healthy: AtomicBool,
+}
+
+impl ServerCounters {
+    fn new(
+        name: &Arc<str>,
+        addr: SocketAddr,
+        weight: u32,
+        breaker: Option<Arc<CircuitBreaker>>,
+    ) -> Self {
+        ServerCounters {
+            addr,
+            calls: AtomicUsize::new(0),
+            retries: AtomicUsize::new(0),
+            inflight: AtomicUsize::new(0),
+            weight: AtomicUsize::new(weight as usize),
+            breaker: Breaker::new(addr, breaker).named(name.clone()),
+            healthy: AtomicBool::new(true),
+        }
+    }
+
+    fn weight(&self) -> u32 {
+        self.weight.load(Ordering::SeqCst) as u32
+    }
+
+    fn set_weight(&self, weight: u32) {
+        self.weight.store(weight as usize, Ordering::SeqCst);
+    }
+}
+
+/// Numbers of the backup requests of a channel
+#[derive(Debug, Default)]
+pub(crate) struct BackupCounters {
+    /// Backup requests sent
+    sent: AtomicUsize,
+    /// Backup requests answered before the call they duplicate
+    won: AtomicUsize,
+}
+
+/// How the backend sends a request
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SendOptions {
+    /// The hint passed to the load balancer
+    pub hint: Option<LbHint>,
+    /// Whether the request retries a failed call
+    pub retry: bool,
+    /// Send the request to another server as well if it is not answered
+    /// within this delay
+    pub backup_after: Option<Duration>,
+    /// When the caller gives up, the time left is sent in the request meta
+    pub deadline: Option<Instant>,
+}
+
+impl SendOptions {
+    /// Set the time left before the deadline, if any, in the meta of `req`
+    /// which is about to be sent.
+    pub fn refresh_timeout(&self, req: &mut RequestPackage) {
+        if let Some(deadline) = self.deadline {
+            let now = Instant::now();
+            let left = if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            };
+            req.0.set_timeout_ms(timeout_ms(left));
+        }
+    }
+}
 
-type ChannelReceiver = mpsc::UnboundedReceiver<(OneShotSender, RequestPackage)>;
+/// How the backend reports the progress of a request
+#[derive(Debug)]
+pub(crate) enum Callback {
+    /// Deliver the response
+    Response(OneShotSender),
+    /// Notify that the request is written, the server sends no response
+    Sent(AckSender),
+}
 
 /// The error when building a channel
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum ChannelBuildError {
     /// An error occured when parsing `SocketAddr` from string
     AddrParseError(AddrParseError),
-    /// Failed to connect to a server or a cluster
-    ConnectError,
+    /// Failed to connect to a server or a cluster, with the first server
+    /// which could not be connected to and the error of its connection
+    ConnectError {
+        /// The address of the server
+        addr: SocketAddr,
+        /// The error of the connection, e.g. of kind `ConnectionRefused`
+        source: io::Error,
+    },
+    /// No server could be connected to within the connect timeout
+    ConnectTimeout,
+    /// Failed to resolve the servers of a name
+    ResolveError(String),
+    /// Failed to resolve the host name of the server
+    ResolveHostError(String, io::Error),
+    /// The URI given to `ChannelBuilder::from_uri` is not valid, with the
+    /// reason
+    InvalidUri(String),
+    /// Failed to set up the event loop of a `SyncChannel`
+    IoError(io::Error),
+    /// The TLS settings given to `ChannelBuilder::tls` can not be used
+    #[cfg(feature = "tls")]
+    TlsError(native_tls::Error),
 }
 
 impl fmt::Display for ChannelBuildError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ChannelBuildError::AddrParseError(ref e) => write!(f, "address parse error: {}", e),
-            ChannelBuildError::ConnectError => write!(f, "connection error"),
+            ChannelBuildError::ConnectError { addr, ref source } => {
+                write!(f, "failed to connect to {}: {}", addr, source)
+            }
+            ChannelBuildError::ConnectTimeout => write!(f, "connection timed out"),
+            ChannelBuildError::ResolveError(ref e) => write!(f, "resolve error: {}", e),
+            ChannelBuildError::ResolveHostError(ref host, ref e) => {
+                write!(f, "failed to resolve host {}: {}", host, e)
+            }
+            ChannelBuildError::InvalidUri(ref e) => write!(f, "invalid channel URI: {}", e),
+            ChannelBuildError::IoError(ref e) => write!(f, "io error: {}", e),
+            #[cfg(feature = "tls")]
+            ChannelBuildError::TlsError(ref e) => write!(f, "TLS error: {}", e),
         }
     }
 }
@@ -95,14 +305,28 @@ impl Error for ChannelBuildError {
     fn description(&self) -> &str {
         match *self {
             ChannelBuildError::AddrParseError(_) => "failed to parse socket address from raw string",
-            ChannelBuildError::ConnectError => "failed to connect to a remote server",
+            ChannelBuildError::ConnectError { .. } => "failed to connect to a remote server",
+            ChannelBuildError::ConnectTimeout => "timed out connecting to a remote server",
+            ChannelBuildError::ResolveError(_) => "failed to resolve the servers of a name",
+            ChannelBuildError::ResolveHostError(..) => "failed to resolve the host of a server",
+            ChannelBuildError::InvalidUri(_) => "invalid channel URI",
+            ChannelBuildError::IoError(_) => "failed to set up the event loop of a channel",
+            #[cfg(feature = "tls")]
+            ChannelBuildError::TlsError(_) => "invalid TLS settings",
         }
     }
 
-    fn cause(&self) -> Option<&Error> {
+    fn source(&self) -> Option<&(Error + 'static)> {
         match *self {
             ChannelBuildError::AddrParseError(ref e) => Some(e),
-            ChannelBuildError::ConnectError => None,
+            ChannelBuildError::ConnectError { ref source, .. } => Some(source),
+            ChannelBuildError::ResolveHostError(_, ref e)
+            | ChannelBuildError::IoError(ref e) => Some(e),
+            #[cfg(feature = "tls")]
+            ChannelBuildError::TlsError(ref e) => Some(e),
+            ChannelBuildError::ConnectTimeout
+            | ChannelBuildError::ResolveError(_)
+            | ChannelBuildError::InvalidUri(_) => None,
         }
     }
 }
@@ -113,8 +337,13 @@ pub enum ChannelError {
     /// Can not issue new request because the number of pending requests has
     /// reached the concurrency limit
     ConcurrencyLimitReached,
+    /// Can not issue new request because as many calls as the channel allows
+    /// are waiting to be written or answered
+    ChannelFull,
     /// Io error from TCP socket
     IoError(io::Error),
+    /// The channel is shut down
+    Closed,
     /// [WIP] Other errors that need to be explicated
     UnknownError,
 }
@@ -123,7 +352,9 @@ impl fmt::Display for ChannelError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ChannelError::ConcurrencyLimitReached => write!(f, "Concurrency limit reached"),
+            ChannelError::ChannelFull => write!(f, "Too many pending calls"),
             ChannelError::IoError(ref e) => write!(f, "Io error: {}", e),
+            ChannelError::Closed => write!(f, "Channel is closed"),
             ChannelError::UnknownError => write!(f, "other errors might be worth discussion"),
         }
     }
@@ -133,7 +364,9 @@ impl Error for ChannelError {
     fn description(&self) -> &str {
         match *self {
             ChannelError::ConcurrencyLimitReached => "concurrency limit reached",
+            ChannelError::ChannelFull => "channel full",
             ChannelError::IoError(_) => "io error from TCP socket",
+            ChannelError::Closed => "channel closed",
             ChannelError::UnknownError => "[WIP] other errors",
         }
     }
@@ -146,18 +379,64 @@ impl Error for ChannelError {
     }
 }
 
+/// The reason of the calls given up when a channel is shut down
+#[derive(Debug)]
+struct Closed;
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl Error for Closed {
+    fn description(&self) -> &str {
+        "channel closed"
+    }
+}
+
+/// Get the error of the calls given up when a channel is shut down.
+pub(crate) fn closed_error() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, Closed)
+}
+
+/// Convert the error of a call which got no response.
+fn call_error(e: io::Error) -> ChannelError {
+    let closed = match e.get_ref() {
+        Some(inner) => inner.is::<Closed>(),
+        None => false,
+    };
+    if closed {
+        ChannelError::Closed
+    } else {
+        ChannelError::IoError(e)
+    }
+}
+
 impl From<AddrParseError> for ChannelBuildError {
     fn from(e: AddrParseError) -> Self {
         ChannelBuildError::AddrParseError(e)
     }
 }
 
+/// How the connections of a channel talk to the servers
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionOptions {
+    /// Name of the channel, told by the logs
+    pub name: Arc<str>,
+    pub protocol: Protocol,
+    pub max_response_size: Option<usize>,
+    pub read_buffer_size: Option<usize>,
+}
+
 //TODO: make this private
 #[doc(hidden)]
 pub struct MetaClientProtocol {
+    name: Arc<str>,
     proto: Box<RpcProtocol>,
-    handle: Handle,
     addr: SocketAddr,
+    link: Arc<Link>,
+    read_buffer_size: Option<usize>,
 }
 
 impl fmt::Debug for MetaClientProtocol {
@@ -168,37 +447,243 @@ impl fmt::Debug for MetaClientProtocol {
 
 impl MetaClientProtocol {
     /// Create a new instance.
-    pub fn new(proto_type: &Protocol, handle: Handle, addr: SocketAddr) -> Self {
-        let proto = match proto_type {
+    pub(crate) fn new(options: &ConnectionOptions, addr: SocketAddr) -> Self {
+        let mut proto: Box<RpcProtocol> = match options.protocol {
             // TODO: unify construction interface of protocols
-            &Protocol::Brpc => Box::new(BrpcProtocol::new()),
-            _ => unimplemented!(),
+            Protocol::Brpc => Box::new(BrpcProtocol::new()),
+            Protocol::Http => Box::new(HttpClientProtocol::new(&addr.to_string())),
         };
+        proto.set_max_package_size(options.max_response_size);
         MetaClientProtocol {
+            name: options.name.clone(),
             proto,
-            handle,
             addr,
+            link: Arc::new(Link::default()),
+            read_buffer_size: options.read_buffer_size,
         }
     }
 }
 
-impl ClientProto<TcpStream> for MetaClientProtocol {
+/// Connect to the server at `addr` with `dialer`, failing with a `TimedOut`
+/// error if the stream is not ready within `timeout`.
+pub(crate) fn connect(
+    options: &ConnectionOptions,
+    addr: SocketAddr,
+    handle: &Handle,
+    timer: &Timer,
+    timeout: Option<Duration>,
+    dialer: &Dialer,
+) -> ConnectFuture {
+    let proto = MetaClientProtocol::new(options, addr);
+    let link = proto.link.clone();
+    let handle = handle.clone();
+    let fut = dialer.dial(addr, &handle).map(move |stream| {
+        let service = BindClient::<Multiplex, BoxStream>::bind_client(&proto, &handle, stream);
+        ServerEndPort::new(service, link)
+    });
+    match timeout {
+        Some(timeout) => Box::new(timer.timeout(fut, timeout).map_err(move |e| match e {
+            TimeoutError::Inner(e) => e,
+            TimeoutError::TimedOut(_) => {
+                let msg = format!("connection not established within {:?}", timeout);
+                io::Error::new(io::ErrorKind::TimedOut, msg)
+            }
+            // the timeout is longer than the timer supports, or the timer is full
+            TimeoutError::Timer(_, e) => io::Error::new(io::ErrorKind::InvalidInput, e),
+        })),
+        None => Box::new(fut),
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + 'static> ClientProto<S> for MetaClientProtocol {
     type Request = RequestPackage;
     type Response = ResponsePackage;
-    type Transport = Framed<Connector, ProtoCodecClient>;
+    type Transport = ClientTransport<S>;
     type BindTransport = Result<Self::Transport, io::Error>;
 
-    fn bind_transport(&self, io: TcpStream) -> Self::BindTransport {
-        let conn = Connector::from_stream(self.addr.clone(), io, self.handle.clone());
-        let codec = ProtoCodecClient::new(self.proto.new_boxed());
-        let framed = conn.framed(codec);
-        Ok(framed)
+    fn bind_transport(&self, io: S) -> Self::BindTransport {
+        let conn = Connector::from_stream(self.addr, io, self.link.clone());
+        let codec = ClientCodec::new(ProtoCodecClient::new(self.proto.new_boxed()))
+            .named(self.name.clone());
+        Ok(ClientTransport::new(conn, codec, self.read_buffer_size))
     }
 }
 
 #[derive(Debug)]
 enum ConnectMode<'a> {
-    Single(&'a str),
+    Single(Target<'a>),
+    Multi(Vec<&'a str>),
+    Weighted(Vec<(&'a str, u32)>),
+    Dns {
+        host: &'a str,
+        port: u16,
+        refresh: Duration,
+    },
+    Naming(Box<NamingService>, NamingOptions),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl<'a> ConnectMode<'a> {
+    /// Get the name of a channel to the servers, unless it is named.
+    fn default_name(&self) -> String {
+        match *self {
+            ConnectMode::Single(ref target) => target.to_string(),
+            ConnectMode::Multi(ref addrs) => addrs.join(","),
+            ConnectMode::Weighted(ref servers) => {
+                let addrs: Vec<_> = servers.iter().map(|&(addr, _)| addr).collect();
+                addrs.join(",")
+            }
+            ConnectMode::Dns { host, port, .. } => format!("dns://{}:{}", host, port),
+            ConnectMode::Naming(..) => "naming".to_string(),
+            #[cfg(unix)]
+            ConnectMode::Unix(ref path) => format!("unix:{}", path.display()),
+        }
+    }
+}
+
+/// A future resolving to the first servers of a channel, and the updates of
+/// its naming service if any
+type ServersFuture =
+    Box<Future<Item = (Vec<ServerEndpoint>, Option<ServerStream>), Error = ChannelBuildError>>;
+
+fn parse_servers(servers: Vec<(&str, u32)>) -> ServersFuture {
+    let parsed = servers
+        .into_iter()
+        .map(|(addr, weight)| {
+            addr.parse()
+                .map(|addr| ServerEndpoint::new(addr).with_weight(weight))
+        })
+        .collect::<Result<Vec<_>, AddrParseError>>()
+        .map(|servers| (servers, None))
+        .map_err(ChannelBuildError::AddrParseError);
+    Box::new(future::result(parsed))
+}
+
+/// Get the addresses of the server of a single server channel, looking up
+/// its host name with `resolver` if needed.
+fn resolve_target(target: Target, resolver: Option<Box<Resolve>>) -> ServersFuture {
+    let (host, port) = match target {
+        Target::Addrs(addrs) => return single_addrs(Ok(addrs)),
+        Target::Str(addr) => {
+            if let Ok(addr) = addr.parse() {
+                return single_addrs(Ok(vec![addr]));
+            }
+            match Target::host_and_port(&addr) {
+                Some((host, port)) => (host.to_string(), port),
+                None => {
+                    let e = addr.parse::<SocketAddr>().unwrap_err();
+                    return Box::new(future::err(ChannelBuildError::AddrParseError(e)));
+                }
+            }
+        }
+        Target::Host(host, port) => (host.into_owned(), port),
+    };
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return single_addrs(Ok(vec![SocketAddr::new(ip, port)]));
+    }
+
+    let resolver = resolver.unwrap_or_else(|| Box::new(DnsResolver::new()));
+    let fut = resolver.resolve(&host, port).then(move |result| {
+        let addrs = result.and_then(|addrs| {
+            if addrs.is_empty() {
+                Err(io::Error::new(io::ErrorKind::NotFound, "no address"))
+            } else {
+                Ok(addrs)
+            }
+        });
+        addrs.map_err(|e| ChannelBuildError::ResolveHostError(host, e))
+    });
+    Box::new(fut.map(|addrs| {
+        let servers = addrs.into_iter().map(ServerEndpoint::new).collect();
+        (servers, None)
+    }))
+}
+
+fn single_addrs(addrs: Result<Vec<SocketAddr>, ChannelBuildError>) -> ServersFuture {
+    let servers = addrs.map(|addrs| (addrs.into_iter().map(ServerEndpoint::new).collect(), None));
+    Box::new(future::result(servers))
+}
+
+/// Keep the first of `servers` which accepts a connection, or the first of
+/// them if none does.
+fn first_reachable(
+    servers: Vec<ServerEndpoint>,
+    name: &Arc<str>,
+    handle: &Handle,
+    timer: &Timer,
+    timeout: Option<Duration>,
+) -> Box<Future<Item = ServerEndpoint, Error = ChannelBuildError>> {
+    let (name, handle, timer) = (name.clone(), handle.clone(), timer.clone());
+    let fut = future::loop_fn(0, move |i| {
+        let addr = servers[i].addr();
+        let connect = TcpStream::connect(&addr, &handle);
+        let connect: Box<Future<Item = TcpStream, Error = io::Error>> = match timeout {
+            Some(timeout) => Box::new(timer.timeout(connect, timeout).map_err(|e| match e {
+                TimeoutError::Inner(e) => e,
+                _ => io::Error::new(io::ErrorKind::TimedOut, "connection timed out"),
+            })),
+            None => Box::new(connect),
+        };
+        let (servers, name) = (servers.clone(), name.clone());
+        connect.then(move |result| match result {
+            Ok(_) => Ok(future::Loop::Break(servers[i].clone())),
+            Err(ref e) if i + 1 < servers.len() => {
+                debug!(
+                    "[{}] Failed to connect to {}, trying the next address: {}",
+                    name, addr, e
+                );
+                Ok(future::Loop::Continue(i + 1))
+            }
+            // building the channel fails as it connects to the first one
+            Err(_) => Ok(future::Loop::Break(servers[0].clone())),
+        })
+    });
+    Box::new(fut)
+}
+
+/// Ping a connection made by a warm-up, failing with a `TimedOut` error if
+/// it is not answered within `timeout`.
+fn warm_up_ping(
+    end_port: ServerEndPort,
+    timer: &Timer,
+    timeout: Option<Duration>,
+) -> ConnectFuture {
+    let ping = check(&end_port, timeout);
+    let ping = match timeout {
+        Some(timeout) => future::Either::A(timer.timeout(ping, timeout).map_err(move |e| match e {
+            TimeoutError::Inner(e) => e,
+            TimeoutError::TimedOut(_) => {
+                let msg = format!("warm-up ping not answered within {:?}", timeout);
+                io::Error::new(io::ErrorKind::TimedOut, msg)
+            }
+            TimeoutError::Timer(_, e) => io::Error::new(io::ErrorKind::InvalidInput, e),
+        })),
+        None => future::Either::B(ping),
+    };
+    Box::new(ping.map(move |_| end_port))
+}
+
+/// Subscribe to `naming` and wait for the first servers.
+fn subscribe(naming: &NamingService, options: &NamingOptions, handle: &Handle) -> ServersFuture {
+    let without_server = options.get_succeed_without_server();
+    let fut = naming
+        .subscribe(handle)
+        .into_future()
+        .then(move |result| match result {
+            Ok((Some(servers), updates)) => {
+                if servers.is_empty() && !without_server {
+                    let e = "naming service gave no server".to_string();
+                    return Err(ChannelBuildError::ResolveError(e));
+                }
+                Ok((servers, Some(updates)))
+            }
+            Ok((None, _)) => Err(ChannelBuildError::ResolveError(
+                "naming service ended without giving servers".to_string(),
+            )),
+            Err((e, _)) => Err(ChannelBuildError::ResolveError(e.to_string())),
+        });
+    Box::new(fut)
 }
 
 /// Channel factory, which can be used to setup a new channel
@@ -210,199 +695,1814 @@ pub struct ChannelBuilder<'a> {
     mode: ConnectMode<'a>,
     handle: Handle,
     protocol: Option<Protocol>,
-    deadline: Option<Option<Duration>>,
-    max_retry: Option<u32>,
+    max_response_size: Option<usize>,
+    read_buffer_size: Option<usize>,
+    rpc_timeout: Option<Duration>,
+    retry_policy: Option<RetryPolicy>,
+    backup_request_after: Option<Duration>,
+    max_failover: Option<u32>,
+    circuit_breaker: Option<CircuitBreaker>,
+    health_check: Option<(Duration, HealthCheckMode)>,
+    keepalive_interval: Option<Duration>,
+    tcp_options: TcpOptions,
     max_concurrency: Option<u32>,
+    max_pending_calls: Option<usize>,
+    inflight_gauge: Option<Arc<AtomicUsize>>,
+    queued_gauge: Option<Arc<AtomicUsize>>,
+    concurrency_limit: Option<ConcurrencyLimit>,
+    load_balancer: Option<Box<LoadBalance>>,
+    resolver: Option<Box<Resolve>>,
+    reconnect_backoff: Option<(Duration, Duration)>,
+    connect_timeout: Option<Duration>,
+    connections_per_backend: Option<usize>,
+    lazy_connect: bool,
+    warm_up: bool,
+    min_ready: Option<usize>,
+    warm_up_ping: bool,
+    name: Option<String>,
+    drain_timeout: Option<Duration>,
+    events: Option<EventHook>,
+    metrics: Option<ChannelMetrics>,
+    interceptors: Vec<Box<ClientInterceptor>>,
+    compress_over: Option<(usize, CompressType)>,
+    #[cfg(feature = "tls")]
+    tls: Option<ClientTlsConfig>,
 }
 
 impl<'a> ChannelBuilder<'a> {
-    /// Connect to a server by IP address.
+    /// Connect to a server by its address, or its host name and port.
+    ///
+    /// The server may be given as a string, as "127.0.0.1:8000", "[::1]:8000"
+    /// or "localhost:8000", as a `SocketAddr`, or as any type of the standard
+    /// library implementing `ToSocketAddrs`, see [`IntoServerAddr`]. A host
+    /// name is resolved by the [`resolver`] of the channel when it is built,
+    /// and its addresses are tried in order until one accepts a connection.
     ///
     /// This method will create a new channel builder.
-    pub fn single_server(addr: &'a str, handle: Handle) -> Self {
+    ///
+    /// [`IntoServerAddr`]: trait.IntoServerAddr.html
+    /// [`resolver`]: #method.resolver
+    pub fn single_server<A: IntoServerAddr<'a>>(addr: A, handle: Handle) -> Self {
         ChannelBuilder {
-            mode: ConnectMode::Single(addr),
+            mode: ConnectMode::Single(addr.into_server_addr().0),
             handle: handle,
             protocol: None,
-            deadline: None,
-            max_retry: None,
+            max_response_size: None,
+            read_buffer_size: None,
+            rpc_timeout: None,
+            retry_policy: None,
+            backup_request_after: None,
+            max_failover: None,
+            circuit_breaker: None,
+            health_check: None,
+            keepalive_interval: None,
+            tcp_options: TcpOptions {
+                nodelay: Some(true),
+                ..TcpOptions::default()
+            },
             max_concurrency: None,
+            max_pending_calls: None,
+            inflight_gauge: None,
+            queued_gauge: None,
+            concurrency_limit: None,
+            load_balancer: None,
+            resolver: None,
+            reconnect_backoff: None,
+            connect_timeout: None,
+            connections_per_backend: None,
+            lazy_connect: false,
+            warm_up: false,
+            min_ready: None,
+            warm_up_ping: false,
+            name: None,
+            drain_timeout: None,
+            events: None,
+            metrics: None,
+            interceptors: Vec::new(),
+            compress_over: None,
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 
-    /// [WIP] Choose a communication protocol.
+    /// Connect to a server as [`single_server`] does, with the builder set
+    /// up by `configure`, and build the channel with its backend spawned on
+    /// `handle`.
     ///
-    /// This RPC framework is intended to support multiple communication protocols
-    /// (e.g. grpc and plain http). Currently, it is recommended to leave this to
-    /// default value.
+    /// E.g. `|builder| builder.rpc_timeout(timeout)`, and `|builder|
+    /// builder` keeps the defaults. This is the same as calling [`build`]
+    /// on the configured builder, the channel is ready for calls as soon as
+    /// the future resolves, and its backend ends once it is shut down or
+    /// dropped.
     ///
-    /// Default to `brpc` protocol (`brpc` is a pure protobuf message protocol
-    /// used in [brpc] framework).
+    /// [`single_server`]: #method.single_server
+    /// [`build`]: #method.build
+    pub fn single_server_spawned<A, F>(addr: A, handle: &Handle, configure: F) -> ChannelBuildFuture
+    where
+        A: IntoServerAddr<'a>,
+        F: FnOnce(ChannelBuilder<'a>) -> ChannelBuilder<'a>,
+    {
+        configure(ChannelBuilder::single_server(addr, handle.clone())).build()
+    }
+
+    /// Connect to several servers by IP address, and distribute calls to
+    /// them round-robin.
     ///
-    /// [brpc]: https://github.com/brpc/brpc
+    /// One connection is kept to every server. Servers whose connection is
+    /// down are skipped, and connected again in the background. Building
+    /// the channel fails only if none of the servers can be reached.
     ///
-    pub fn protocol(mut self, protocol: Protocol) -> Self {
-        self.protocol = Some(protocol);
-        self
+    /// This method will create a new channel builder.
+    pub fn multi_server(addrs: Vec<&'a str>, handle: Handle) -> Self {
+        ChannelBuilder {
+            mode: ConnectMode::Multi(addrs),
+            ..Self::single_server("", handle)
+        }
     }
 
-    /// [WIP] Set request deadline.
+    /// Connect to several servers by IP address, each with a weight, and
+    /// distribute calls to them in proportion to their weights.
     ///
-    /// A request will be set to failed if it reaches its deadline.
+    /// Unless another load balancer is chosen, calls are distributed by
+    /// [`WeightedRoundRobin`], e.g. with
+    /// `&[("10.0.0.1:8000", 4), ("10.0.0.2:8000", 1)]` the first server
+    /// receives four calls out of five. See [`multi_server`] for the
+    /// connections.
     ///
-    /// Default to `None`. which means we will wait until the reponse is returned or
-    /// some error is raised.
-    pub fn deadline(mut self, deadline: Option<Duration>) -> Self {
-        self.deadline = Some(deadline);
-        self
+    /// This method will create a new channel builder.
+    ///
+    /// [`WeightedRoundRobin`]: ../load_balancer/weighted_round_robin/struct.WeightedRoundRobin.html
+    /// [`multi_server`]: #method.multi_server
+    pub fn multi_server_weighted(servers: &[(&'a str, u32)], handle: Handle) -> Self {
+        ChannelBuilder {
+            mode: ConnectMode::Weighted(servers.to_vec()),
+            ..Self::single_server("", handle)
+        }
     }
 
-    /// Set concurrency limit.
+    /// Connect to the server listening on the unix domain socket at `path`.
     ///
-    /// The number of unresolved requests will be confined below `max_concurrency`.
-    /// When this limit is reached, new request will fail immidiately. Thus, it can
-    /// be used to model backpressure.
+    /// The calls are sent with the same protocol as over TCP. Connecting
+    /// fails with a `NotFound` error if there is no socket file at `path`,
+    /// and with a `ConnectionRefused` error if the file is stale, i.e.
+    /// nothing listens on it. A lost connection is connected to again as
+    /// for a TCP server, so that a restarted server is picked up.
     ///
-    /// Default to `None`, no limit imposed.
-    pub fn max_concurrency(mut self, max_concurrency: u32) -> Self {
-        self.max_concurrency = Some(max_concurrency);
-        self
+    /// The server has no IP address, it is reported as `0.0.0.0:0` by
+    /// methods such as [`Channel::server_calls`], by the channel events and
+    /// in `ChannelBuildError::ConnectError`.
+    ///
+    /// This method will create a new channel builder, it is only available
+    /// on unix.
+    ///
+    /// [`Channel::server_calls`]: struct.Channel.html#method.server_calls
+    #[cfg(unix)]
+    pub fn unix_socket<P: AsRef<Path>>(path: P, handle: Handle) -> Self {
+        ChannelBuilder {
+            mode: ConnectMode::Unix(path.as_ref().to_path_buf()),
+            ..Self::single_server("", handle)
+        }
     }
 
-    /// Consume the builder and begin to prepare connection.
+    /// Connect to the servers a host name resolves to, and resolve it again
+    /// every `refresh`.
     ///
-    /// This method returns a future that will resolve to a `Channel`.
+    /// Every address of `host` is a server, reached at `port`, and calls
+    /// are distributed to them as with [`multi_server`]. When the addresses
+    /// change, the new servers are connected to, and the servers gone stop
+    /// receiving calls. Their connections are closed once the calls already
+    /// sent are answered. A failed resolution keeps the servers as they are.
     ///
-    /// # Errors
-    /// The future will yield a `ChannelBuildError` if any error occurs when seting
-    /// up the connection.
-    pub fn build(self) -> ChannelBuildFuture {
-        // TODO: use Default trait
-        let protocol = self.protocol.unwrap_or(Protocol::Brpc);
-        // TODO: add timeout and retry
-        let _deadline = self.deadline.unwrap_or(None);
-        let max_concurrency = self.max_concurrency.unwrap_or(1_000_000);
-        let handle = self.handle;
-
-        let (tx, rx) = mpsc::unbounded();
-        let channel = Channel::new(tx, max_concurrency);
-
-        match self.mode {
-            ConnectMode::Single(addr) => {
-                let parse = addr.parse::<SocketAddr>()
-                    .map_err(|e| ChannelBuildError::AddrParseError(e))
-                    .into_future();
-                let fut = parse.and_then(move |addr| {
-                    let proto = MetaClientProtocol::new(&protocol, handle.clone(), addr.clone());
-                    TcpClient::new(proto)
-                        .connect(&addr, &handle)
-                        .map_err(|_| ChannelBuildError::ConnectError)
-                        .map(move |service| {
-                            let end_port = ServerEndPort::new(service);
-                            let lb = SingleServerLoadBalancer::new(end_port);
-                            let backend = ChannelBackend::new(rx, handle.clone(), lb);
-                            handle.spawn(backend);
-                            channel
-                        })
-                });
-                Box::new(fut)
-            }
+    /// Names are looked up by [`DnsResolver`] unless another resolver is
+    /// set. Building the channel fails if the first resolution does.
+    ///
+    /// This method will create a new channel builder.
+    ///
+    /// [`multi_server`]: #method.multi_server
+    /// [`DnsResolver`]: ../naming/struct.DnsResolver.html
+    pub fn dns(host: &'a str, port: u16, refresh: Duration, handle: Handle) -> Self {
+        ChannelBuilder {
+            mode: ConnectMode::Dns {
+                host,
+                port,
+                refresh,
+            },
+            ..Self::single_server("", handle)
         }
     }
-}
-
-/// A future used internally by the framework. It will resolve to a serialized response.
-#[derive(Debug)]
-pub struct ChannelFuture {
-    rx: Option<OneShotReceiver>,
-    counter: Arc<AtomicUsize>,
-}
 
-impl ChannelFuture {
-    /// Create a new future, used internally
-    pub fn new(rx: Option<OneShotReceiver>, counter: Arc<AtomicUsize>) -> Self {
-        ChannelFuture { rx, counter }
+    /// Connect to the servers listed in the file at `path`, and read it
+    /// again every `interval` if it has changed.
+    ///
+    /// The file lists a server per line, see the [`naming`] module for the
+    /// format. Calls are distributed in proportion to the weights of the
+    /// servers by [`WeightedRoundRobin`], unless another load balancer is
+    /// chosen. When the file changes, the servers are updated as for
+    /// [`dns`], and the connections to the servers still listed are kept. A
+    /// file which can not be read, or lists no server, keeps the servers as
+    /// they are.
+    ///
+    /// Building the channel fails if the file can not be read or lists no
+    /// server.
+    ///
+    /// This method will create a new channel builder.
+    ///
+    /// [`naming`]: ../naming/index.html
+    /// [`WeightedRoundRobin`]: ../load_balancer/weighted_round_robin/struct.WeightedRoundRobin.html
+    /// [`dns`]: #method.dns
+    pub fn file_naming<P: AsRef<Path> + ?Sized>(
+        path: &'a P,
+        interval: Duration,
+        handle: Handle,
+    ) -> Self {
+        let naming = FileNaming::new(path, interval);
+        ChannelBuilder::with_naming(Box::new(naming), NamingOptions::new(), handle)
     }
-}
-
-impl Future for ChannelFuture {
-    type Item = (ResponsePackage, FeedbackHandle);
-
-    type Error = ChannelError;
-
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        if let Some(ref mut rx) = self.rx {
-            let result = try_ready!(
-                rx.poll()
-                    .map_err(|_| panic!("The sending end of the oneshot is dropped"))
-            );
-            self.counter.fetch_sub(1, Ordering::Relaxed);
 
-            result
-                .map_err(|e| ChannelError::IoError(e))
-                .map(|resp| Async::Ready(resp))
-        } else {
-            Err(ChannelError::ConcurrencyLimitReached)
+    /// Connect to the servers given by a naming service, and follow them as
+    /// they change.
+    ///
+    /// The channel subscribes to `naming` when built, and is ready once the
+    /// first servers are given, see [`NamingService::subscribe`]. Calls
+    /// are distributed in proportion to the weights of the servers by
+    /// [`WeightedRoundRobin`], unless another load balancer is chosen. The
+    /// servers are updated as for [`dns`].
+    ///
+    /// Building the channel fails if the first servers can not be given or
+    /// connected to, unless `options` tells otherwise.
+    ///
+    /// This method will create a new channel builder.
+    ///
+    /// [`NamingService::subscribe`]: ../naming/trait.NamingService.html#tymethod.subscribe
+    /// [`WeightedRoundRobin`]: ../load_balancer/weighted_round_robin/struct.WeightedRoundRobin.html
+    /// [`dns`]: #method.dns
+    pub fn with_naming(naming: Box<NamingService>, options: NamingOptions, handle: Handle) -> Self {
+        ChannelBuilder {
+            mode: ConnectMode::Naming(naming, options),
+            ..Self::single_server("", handle)
         }
     }
-}
-
-/// Communication channel between servers
-///
-/// The `Channel` implements `Clone`, `Send`, and `Sync`. Once a channel is create from
-/// a `ChannelBuilder`, it can be cloned and used in multiple threads.
-#[derive(Clone, Debug)]
-pub struct Channel {
-    sender: ChannelSender,
-    counter: Arc<AtomicUsize>,
-    max_concurrency: usize,
-}
 
-impl Channel {
-    /// Create a new channel.
+    /// Create a builder from a URI, which names the servers, the protocol
+    /// and some options of the channel.
     ///
-    /// This method is used by `ChannelBuilder`.
-    pub fn new(sender: ChannelSender, max_concurrency: u32) -> Self {
-        Channel {
-            sender,
-            counter: Arc::new(AtomicUsize::new(0)),
-            max_concurrency: max_concurrency as usize,
+    /// The scheme tells the protocol, and how the servers are found:
+    ///
+    /// - `brpc://10.1.2.3:8000` and `http://gateway:8080` connect to a
+    ///   single server, as [`single_server`]. The port of http defaults to
+    ///   80.
+    /// - `dns://echo.service:8000` connects to the servers a host name
+    ///   resolves to, as [`dns`].
+    /// - `file:///etc/app/backends.txt` connects to the servers listed in a
+    ///   file, as [`file_naming`].
+    ///
+    /// The protocol of `dns` and `file` is brpc, and http with `dns+http`
+    /// and `file+http`. These query parameters are understood:
+    ///
+    /// - `connect_timeout_ms`, see [`connect_timeout`]
+    /// - `timeout_ms`, see [`rpc_timeout`]
+    /// - `max_concurrency`, see [`max_concurrency`]
+    /// - `connections`, see [`connections_per_backend`]
+    /// - `lb`, the load balancer, one of `rr`, `wrr`, `random` and `chash`
+    /// - `refresh_ms`, how often the servers of `dns` and `file` are looked
+    ///   up, default to 10 seconds
+    ///
+    /// # Errors
+    /// Fails with `ChannelBuildError::InvalidUri` if the scheme, the host,
+    /// the port or a query parameter is not understood.
+    ///
+    /// [`single_server`]: #method.single_server
+    /// [`dns`]: #method.dns
+    /// [`file_naming`]: #method.file_naming
+    /// [`connect_timeout`]: #method.connect_timeout
+    /// [`rpc_timeout`]: #method.rpc_timeout
+    /// [`max_concurrency`]: #method.max_concurrency
+    /// [`connections_per_backend`]: #method.connections_per_backend
+    pub fn from_uri(uri: &'a str, handle: Handle) -> Result<Self, ChannelBuildError> {
+        let name = uri;
+        let uri = ChannelUri::parse(uri).map_err(ChannelBuildError::InvalidUri)?;
+        let mut builder = match uri.target {
+            UriTarget::Single(addr) => ChannelBuilder::single_server(addr, handle),
+            UriTarget::Host(host, port) => ChannelBuilder::single_server((host, port), handle),
+            UriTarget::Dns {
+                host,
+                port,
+                refresh,
+            } => ChannelBuilder::dns(host, port, refresh, handle),
+            UriTarget::File { path, refresh } => {
+                let naming = FileNaming::new(&path, refresh);
+                ChannelBuilder::with_naming(Box::new(naming), NamingOptions::new(), handle)
+            }
+        };
+        builder = builder.protocol(uri.protocol).name(name);
+        if let Some(timeout) = uri.connect_timeout {
+            builder = builder.connect_timeout(timeout);
         }
+        if let Some(timeout) = uri.rpc_timeout {
+            builder = builder.rpc_timeout(timeout);
+        }
+        if let Some(max) = uri.max_concurrency {
+            builder = builder.max_concurrency(max);
+        }
+        if let Some(n) = uri.connections {
+            builder = builder.connections_per_backend(n);
+        }
+        if let Some(lb) = uri.lb {
+            builder = builder.load_balancer(lb.load_balancer());
+        }
+        Ok(builder)
     }
 
-    /// Issue a request.
+    /// Look up the servers of a channel built by [`dns`], or the host name
+    /// given to [`single_server`], with `resolver`.
     ///
-    /// This method deals with serialized, untyped message. It is meaned to be used
-    /// internally by the framework. More ergonomic interfaces are provided by the 
-    /// auto-generated stubs.
-    pub fn call(&self, req: RequestPackage) -> ChannelFuture {
-        let (tx, rx) = oneshot::channel();
-        let rx = if self.counter.load(Ordering::SeqCst) < self.max_concurrency {
-            self.counter.fetch_add(1, Ordering::SeqCst);
-            self.sender
-                .unbounded_send((tx, req))
-                .expect("The receiving end is dropped");
-            Some(rx)
-        } else {
-            None
-        };
+    /// Default to [`DnsResolver`].
+    ///
+    /// [`dns`]: #method.dns
+    /// [`single_server`]: #method.single_server
+    /// [`DnsResolver`]: ../naming/struct.DnsResolver.html
+    pub fn resolver(mut self, resolver: Box<Resolve>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
 
-        ChannelFuture::new(rx, self.counter.clone())
+    /// Choose a communication protocol.
+    ///
+    /// Default to `brpc` protocol (`brpc` is a pure protobuf message protocol
+    /// used in [brpc] framework).
+    ///
+    /// Over `Protocol::Http`, a call is sent as `POST /<service>/<method>`
+    /// with the serialized request as the body, and a response of a status
+    /// other than `2xx` fails the call, with the body as the error text, see
+    /// [`HttpClientProtocol`]. Connections are kept alive across calls, but
+    /// only one call is in flight on a connection at a time, the others
+    /// wait for it to be answered.
+    ///
+    /// [brpc]: https://github.com/brpc/brpc
+    /// [`HttpClientProtocol`]: ../protocol/http/struct.HttpClientProtocol.html
+    ///
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = Some(protocol);
+        self
     }
 
-    // TODO: deprecate this
-    /// Check if the channel is currently congested (i.e. concurrency limit is reached). 
-    pub fn congested(&self) -> bool {
-        let current = self.counter.load(Ordering::Relaxed);
-        current >= self.max_concurrency
+    /// Set the maximum size of a response in bytes, headers included.
+    ///
+    /// The size declared by a response is checked before its payload is
+    /// read, so an oversized response is never buffered. The connection
+    /// skips the payload, and the call fails with
+    /// `RpcError::ResponseTooLarge`, which is not retried. If the call of
+    /// the response can not even be told, the connection is closed, failing
+    /// the calls in flight, and made again. This mirrors
+    /// [`ServerBuilder::max_request_size`].
+    ///
+    /// Default to no limit.
+    ///
+    /// [`ServerBuilder::max_request_size`]: ../server/struct.ServerBuilder.html#method.max_request_size
+    pub fn max_response_size(mut self, bytes: usize) -> Self {
+        self.max_response_size = Some(bytes);
+        self
     }
-}
 
+    /// Set the size in bytes of the buffer a connection reads responses
+    /// into at first.
+    ///
+    /// The buffer grows to fit larger responses. Channels receiving large
+    /// responses at a high rate can start with a larger buffer, to grow it
+    /// less often. Sizes below 8 KiB are taken as 8 KiB.
+    ///
+    /// Default to 8 KiB.
+    pub fn read_buffer_size(mut self, bytes: usize) -> Self {
+        self.read_buffer_size = Some(bytes);
+        self
+    }
 
-/// [WIP] Feedback handle to load balancers
-#[derive(Debug)]
-pub struct FeedbackHandle {
-    id: ServerId,
+    /// Set request deadline.
+    ///
+    /// Same as [`rpc_timeout`], `None` means no timeout.
+    ///
+    /// [`rpc_timeout`]: #method.rpc_timeout
+    pub fn deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.rpc_timeout = deadline;
+        self
+    }
+
+    /// Set the timeout of every call issued on the channel.
+    ///
+    /// A call fails with `RpcError::Timeout` if no response is received
+    /// within `timeout`, and its response is discarded if it arrives later.
+    /// A timeout set by `CallOptions::timeout` takes precedence.
+    ///
+    /// Default to `None`, which means we will wait until the reponse is
+    /// returned or some error is raised.
+    pub fn rpc_timeout(mut self, timeout: Duration) -> Self {
+        self.rpc_timeout = Some(timeout);
+        self
+    }
+
+    /// Retry the failed calls as `policy` tells.
+    ///
+    /// Default to `None`, failed calls are not retried.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Send a backup request to another server when a call is not answered
+    /// within `delay`, and take whichever response comes first.
+    ///
+    /// This cuts the latency of the calls which hit a slow server. The
+    /// other request is given up once a response is taken, and if one of
+    /// them fails the other is waited for. Backup requests are only sent
+    /// when another server is connected, and are counted by
+    /// [`backup_requests`] rather than by `server_calls`. With a retry
+    /// policy, every attempt of a call may send one. A delay set by
+    /// `CallOptions::backup_request_after` takes precedence.
+    ///
+    /// Default to `None`, no backup request is sent.
+    ///
+    /// [`backup_requests`]: struct.Channel.html#method.backup_requests
+    pub fn backup_request_after(mut self, delay: Duration) -> Self {
+        self.backup_request_after = Some(delay);
+        self
+    }
+
+    /// Send a call to up to `n` other servers when the connection to its
+    /// server breaks before any byte of its request is written.
+    ///
+    /// The server never saw such a request, so the call fails over whatever
+    /// the method and the retry policy, as long as its deadline is not
+    /// over. Servers which are down are not chosen, nor the ones the call
+    /// failed on. A call sent again is counted by [`server_retries`]. Only
+    /// channels with several servers fail over, and a call fails with the
+    /// last error once no other server is connected.
+    ///
+    /// Default to 2, 0 turns failover off.
+    ///
+    /// [`server_retries`]: struct.Channel.html#method.server_retries
+    pub fn max_failover(mut self, n: u32) -> Self {
+        self.max_failover = Some(n);
+        self
+    }
+
+    /// Eject the servers whose calls fail too often, as `breaker` tells.
+    ///
+    /// An ejected server is shown to the load balancer as not connected
+    /// until its probe calls succeed. The state of every server is given by
+    /// [`circuit_states`], and the times it is ejected by
+    /// [`server_ejections`]. Oneway calls are not looked at.
+    ///
+    /// Default to `None`, servers are never ejected.
+    ///
+    /// [`circuit_states`]: struct.Channel.html#method.circuit_states
+    /// [`server_ejections`]: struct.Channel.html#method.server_ejections
+    pub fn circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(breaker);
+        self
+    }
+
+    /// Probe every server on `interval` as `mode` tells, and send no call
+    /// to a server whose last probe failed.
+    ///
+    /// A server is probed at most once per interval, and never while its
+    /// last probe is not answered. Intervals shorter than 100 milliseconds
+    /// are raised to it, and a probe not answered within the interval
+    /// fails. Probes are not counted by the metrics of the channel, nor
+    /// seen by the load balancer or the circuit breakers. The outcome of
+    /// the last probes is given by [`server_health`].
+    ///
+    /// Default to `None`, servers are not probed.
+    ///
+    /// [`server_health`]: struct.Channel.html#method.server_health
+    pub fn health_check(mut self, interval: Duration, mode: HealthCheckMode) -> Self {
+        self.health_check = Some((interval, mode));
+        self
+    }
+
+    /// Ping the connections which receive nothing for `interval`.
+    ///
+    /// The ping is a call to the health check service of the server over the
+    /// idle connection, any response, even an error, keeps the connection
+    /// up. A ping not answered within `interval` marks the connection as
+    /// dead, it is closed and connected to again, so that a connection
+    /// silently dropped by a middlebox or the idle timer of the server is
+    /// replaced before a call needs it. A dead connection is found within
+    /// about twice the interval.
+    ///
+    /// The interval is at least 100 milliseconds. Default to `None`, the
+    /// connections are not pinged.
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Set TCP keepalive on the connections to the servers.
+    ///
+    /// `Some(idle)` enables keepalive probes after the connection is idle for
+    /// `idle`, `None` disables them. This is cheaper than
+    /// [`keepalive_interval`], but a dead connection is only found once the
+    /// system gives up its probes.
+    ///
+    /// Default to the system setting.
+    ///
+    /// [`keepalive_interval`]: #method.keepalive_interval
+    pub fn tcp_keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.tcp_options.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Set `TCP_NODELAY` on the connections to the servers.
+    ///
+    /// With Nagle's algorithm, a small request written while an earlier one
+    /// is not acknowledged yet waits for the acknowledgement, which the
+    /// server may delay for tens of milliseconds. Requests are flushed as a
+    /// whole, so disabling it costs no extra packets.
+    ///
+    /// Default to `true`.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp_options.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Set the size of the receive buffer of the connections to the servers
+    /// in bytes.
+    ///
+    /// Default to the system setting.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.tcp_options.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size of the send buffer of the connections to the servers in
+    /// bytes.
+    ///
+    /// Default to the system setting.
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.tcp_options.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set concurrency limit.
+    ///
+    /// The number of unresolved requests will be confined below `max_concurrency`.
+    /// When this limit is reached, new request will fail immidiately. Thus, it can
+    /// be used to model backpressure.
+    ///
+    /// Default to `None`, no limit imposed.
+    pub fn max_concurrency(mut self, max_concurrency: u32) -> Self {
+        self.max_concurrency = Some(max_concurrency);
+        self
+    }
+
+    /// Limit the number of calls waiting to be written or answered.
+    ///
+    /// Once `max` calls, oneway calls included, are pending, new calls fail
+    /// at once with `RpcError::ChannelFull` instead of being buffered,
+    /// so that a stalled server does not make the client grow without
+    /// bound. A call stops being pending when it is answered, times out or
+    /// is dropped, and a oneway call when it is written to the connection.
+    ///
+    /// Default to `None`, no limit imposed.
+    pub fn max_pending_calls(mut self, max: usize) -> Self {
+        self.max_pending_calls = Some(max);
+        self
+    }
+
+    /// Count the calls in flight, given by [`Channel::inflight`], in
+    /// `inflight` rather than in a counter of the channel.
+    ///
+    /// The application can then read it without the channel, e.g. to shed
+    /// load. The gauge is not reset, it should only be given to one channel.
+    ///
+    /// [`Channel::inflight`]: struct.Channel.html#method.inflight
+    pub fn inflight_gauge(mut self, inflight: Arc<AtomicUsize>) -> Self {
+        self.inflight_gauge = Some(inflight);
+        self
+    }
+
+    /// Count the calls whose request is not written yet, given by
+    /// [`Channel::queued`], in `queued` rather than in a counter of the
+    /// channel, see [`inflight_gauge`].
+    ///
+    /// [`Channel::queued`]: struct.Channel.html#method.queued
+    /// [`inflight_gauge`]: #method.inflight_gauge
+    pub fn queued_gauge(mut self, queued: Arc<AtomicUsize>) -> Self {
+        self.queued_gauge = Some(queued);
+        self
+    }
+
+    /// Adapt the number of calls in flight to their latency, see
+    /// [`ConcurrencyLimit`].
+    ///
+    /// New calls fail at once with `RpcError::ConcurrencyLimited` while
+    /// as many calls as the current limit are in flight, shedding the load
+    /// before the servers collapse under it. Retries and backup requests
+    /// are counted as calls, oneway calls are not limited. The limit and
+    /// the calls in flight are given by [`Channel::concurrency_limit`].
+    ///
+    /// Default to `None`, no limit imposed.
+    ///
+    /// [`ConcurrencyLimit`]: struct.ConcurrencyLimit.html
+    /// [`Channel::concurrency_limit`]: struct.Channel.html#method.concurrency_limit
+    pub fn concurrency_limit(mut self, limit: ConcurrencyLimit) -> Self {
+        self.concurrency_limit = Some(limit);
+        self
+    }
+
+    /// Compress the request bodies larger than `bytes` with `compress`.
+    ///
+    /// The compression is set in the request meta, as brpc does, and the
+    /// server decompresses the body before decoding it. A copra server
+    /// built with `ServerBuilder::compress_responses_over` compresses its
+    /// large responses the same way. Calls can decide for themselves with
+    /// [`CallOptions::compress`], which takes precedence. Only the brpc
+    /// protocol compresses.
+    ///
+    /// Default to only compress as calls decide.
+    ///
+    /// [`CallOptions::compress`]: ../stub/struct.CallOptions.html#method.compress
+    pub fn compress_requests_over(mut self, bytes: usize, compress: CompressType) -> Self {
+        self.compress_over = Some((bytes, compress));
+        self
+    }
+
+    /// Choose the server of every call with `load_balancer`.
+    ///
+    /// Default to [`RoundRobin`].
+    ///
+    /// [`RoundRobin`]: ../load_balancer/round_robin/struct.RoundRobin.html
+    pub fn load_balancer(mut self, load_balancer: Box<LoadBalance>) -> Self {
+        self.load_balancer = Some(load_balancer);
+        self
+    }
+
+    /// Set the delays before connecting again to a server whose connection
+    /// is down.
+    ///
+    /// The first attempt is made at once, then the delay starts at `min`
+    /// and doubles with every failed attempt up to `max`. Up to half of
+    /// every delay is taken off at random. Calls sent to the server while
+    /// it is down fail at once, as do the calls in flight when the
+    /// connection breaks.
+    ///
+    /// Default to 100 milliseconds and 10 seconds.
+    pub fn reconnect_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.reconnect_backoff = Some((min, max));
+        self
+    }
+
+    /// Give up connecting to a server after `timeout`.
+    ///
+    /// This bounds both the connections made when building the channel and
+    /// the connections made again after one breaks. A server which can not
+    /// be connected to in time is treated as down. Building the channel
+    /// fails with `ChannelBuildError::ConnectTimeout` if every server timed
+    /// out.
+    ///
+    /// Default to `None`, the timeout of the operating system is used.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Keep `n` connections to every server, and spread the calls to a
+    /// server over them in turn.
+    ///
+    /// A single connection serializes every call to its server through one
+    /// socket, a few more raise the throughput to a busy server. Each
+    /// connection is established again on its own when it goes down, and a
+    /// server is connected as long as one of them is up.
+    ///
+    /// Default to 1, at least one connection is kept.
+    pub fn connections_per_backend(mut self, n: usize) -> Self {
+        self.connections_per_backend = Some(n.max(1));
+        self
+    }
+
+    /// Connect to the servers only once the first call is sent.
+    ///
+    /// The channel is then built without touching the network, beside
+    /// resolving the servers. The first call connects to every server, and
+    /// the calls sent until a connection is up wait for it, up to 1024 of
+    /// them. If every connection fails, the waiting calls fail, and the
+    /// servers are connected again as `reconnect_backoff` tells.
+    ///
+    /// Default to `false`, the servers are connected when building the
+    /// channel.
+    pub fn lazy_connect(mut self, lazy: bool) -> Self {
+        self.lazy_connect = lazy;
+        self
+    }
+
+    /// Build the channel only once its servers are connected.
+    ///
+    /// Every server is connected when building the channel, even with
+    /// [`lazy_connect`], and building fails with a `ConnectError`, or a
+    /// `ConnectTimeout` if every server timed out, unless [`min_ready`] of
+    /// them are connected. The first calls then pay for no connection, and
+    /// with [`warm_up_ping`] for no cold server either. The connections are
+    /// bounded by [`connect_timeout`], as the pings are.
+    ///
+    /// Default to `false`, building succeeds as soon as one server is
+    /// connected.
+    ///
+    /// [`lazy_connect`]: #method.lazy_connect
+    /// [`min_ready`]: #method.min_ready
+    /// [`warm_up_ping`]: #method.warm_up_ping
+    /// [`connect_timeout`]: #method.connect_timeout
+    pub fn warm_up(mut self, warm_up: bool) -> Self {
+        self.warm_up = warm_up;
+        self
+    }
+
+    /// Set how many servers must be connected for a [`warm_up`] to
+    /// succeed.
+    ///
+    /// A server is connected as soon as one of its connections is. The
+    /// servers which are not are connected again as `reconnect_backoff`
+    /// tells.
+    ///
+    /// Default to every server.
+    ///
+    /// [`warm_up`]: #method.warm_up
+    pub fn min_ready(mut self, n: usize) -> Self {
+        self.min_ready = Some(n);
+        self
+    }
+
+    /// Ping every connection made by a [`warm_up`] before building the
+    /// channel.
+    ///
+    /// The ping is a call to the health check service of the server, as
+    /// [`keepalive_interval`] sends. Any response counts, while a
+    /// connection whose ping fails is not connected.
+    ///
+    /// Default to `false`.
+    ///
+    /// [`warm_up`]: #method.warm_up
+    /// [`keepalive_interval`]: #method.keepalive_interval
+    pub fn warm_up_ping(mut self, ping: bool) -> Self {
+        self.warm_up_ping = ping;
+        self
+    }
+
+    /// Wait up to `timeout` for the calls in flight when the channel is
+    /// shut down.
+    ///
+    /// See [`Channel::shutdown`]. Default to 5 seconds.
+    ///
+    /// [`Channel::shutdown`]: struct.Channel.html#method.shutdown
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = Some(timeout);
+        self
+    }
+
+    /// Connect to the servers over TLS, as `config` tells.
+    ///
+    /// The handshake is done on every connection, reconnections included,
+    /// before any request is sent. A failed handshake fails the connection
+    /// as a refused one would, building the channel fails with a
+    /// `ConnectError` and a lost connection is tried again after the
+    /// reconnect backoff.
+    ///
+    /// This method requires the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: ClientTlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Call `f` with the events of the connections of the channel.
+    ///
+    /// The events tell when a server is connected, when a connection goes
+    /// down and why, when it is connected to again, and when the channel is
+    /// closed, see [`ChannelEvent`]. `f` is called with the [`name`] of the
+    /// channel and the event, on the event loop of the channel, it should
+    /// return quickly.
+    ///
+    /// [`ChannelEvent`]: enum.ChannelEvent.html
+    /// [`name`]: #method.name
+    pub fn on_channel_event<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str, &ChannelEvent) + Send + Sync + 'static,
+    {
+        self.events = Some(EventHook::new(f));
+        self
+    }
+
+    /// Name the channel, as its logs and events tell.
+    ///
+    /// Every line logged by the channel and its connections starts with the
+    /// name in brackets, so that the lines of several channels can be told
+    /// apart. The name is given by [`Channel::name`].
+    ///
+    /// Default to the servers given to the builder, e.g. "10.0.0.1:8000",
+    /// or to the URI given to [`from_uri`].
+    ///
+    /// [`Channel::name`]: struct.Channel.html#method.name
+    /// [`from_uri`]: #method.from_uri
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Count the calls made through the channel in `metrics`.
+    ///
+    /// The outcome, latency and body sizes of every call are recorded when
+    /// it ends, read them from a clone of `metrics`, see
+    /// [`ChannelMetrics`].
+    ///
+    /// Default to `None`, nothing is recorded.
+    ///
+    /// [`ChannelMetrics`]: ../monitor/struct.ChannelMetrics.html
+    pub fn metrics(mut self, metrics: ChannelMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Run `interceptor` around every call made by a stub.
+    ///
+    /// Interceptors run in the order they are added, before the call is
+    /// sent and after it is answered. See [`ClientInterceptor`] for
+    /// details.
+    ///
+    /// [`ClientInterceptor`]: trait.ClientInterceptor.html
+    pub fn add_interceptor(mut self, interceptor: Box<ClientInterceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Consume the builder and begin to prepare connection.
+    ///
+    /// This method returns a future that will resolve to a `Channel`. The
+    /// backend of the channel is spawned on the handle given to the builder,
+    /// the channel can then be cloned and sent to other threads.
+    ///
+    /// # Errors
+    /// The future will yield a `ChannelBuildError` if any error occurs when seting
+    /// up the connection.
+    pub fn build(self) -> ChannelBuildFuture {
+        let handle = self.handle.clone();
+        let fut = self.build_split().map(move |(channel, backend)| {
+            handle.spawn(backend);
+            channel
+        });
+        Box::new(fut)
+    }
+
+    /// Consume the builder and begin to prepare connection, without
+    /// spawning the backend.
+    ///
+    /// This method returns a future that will resolve to a `Channel` and
+    /// its `ChannelBackend`. No call is sent until the backend is run, on
+    /// the event loop of the handle given to the builder: a backend which
+    /// is dropped or never run leaves the calls of the channel waiting
+    /// forever. Use [`build`] or [`single_server_spawned`] unless the
+    /// backend needs to run on a loop of its own.
+    ///
+    /// [`build`]: #method.build
+    /// [`single_server_spawned`]: #method.single_server_spawned
+    ///
+    /// # Errors
+    /// Same as `build`.
+    pub fn build_split(self) -> ChannelSplitFuture {
+        // TODO: use Default trait
+        let name: Arc<str> = match self.name {
+            Some(ref name) => Arc::from(&name[..]),
+            None => Arc::from(&self.mode.default_name()[..]),
+        };
+        let conn_options = ConnectionOptions {
+            name: name.clone(),
+            protocol: self.protocol.unwrap_or(Protocol::Brpc),
+            max_response_size: self.max_response_size,
+            read_buffer_size: self.read_buffer_size,
+        };
+        let rpc_timeout = self.rpc_timeout;
+        let retry_policy = self.retry_policy.map(Arc::new);
+        let backup_request_after = self.backup_request_after;
+        let max_failover = self.max_failover.unwrap_or(2);
+        let backups = Arc::new(BackupCounters::default());
+        let circuit_breaker = self.circuit_breaker.map(Arc::new);
+        let health_check = self.health_check;
+        let keepalive_interval = self.keepalive_interval;
+        let max_concurrency = self.max_concurrency.unwrap_or(1_000_000);
+        let max_pending_calls = self.max_pending_calls;
+        let (inflight_gauge, queued_gauge) = (self.inflight_gauge, self.queued_gauge);
+        let limiter = self.concurrency_limit
+            .map(|limit| Arc::new(Limiter::new(limit).named(name.clone())));
+        let (min_backoff, max_backoff) = self.reconnect_backoff
+            .unwrap_or((Duration::from_millis(100), Duration::from_secs(10)));
+        let backoff = Backoff::new(min_backoff, max_backoff);
+        let connect_timeout = self.connect_timeout;
+        let connections = self.connections_per_backend.unwrap_or(1);
+        let warm_up = self.warm_up;
+        let min_ready = self.min_ready;
+        let ping = warm_up && self.warm_up_ping;
+        let lazy = self.lazy_connect && !warm_up;
+        let drain_timeout = self.drain_timeout.unwrap_or(Duration::from_secs(5));
+        let events = self.events.map(|events| events.named(name.clone()));
+        let metrics = self.metrics;
+        let interceptors = Arc::new(self.interceptors);
+        let compress_over = self.compress_over;
+        let dialer = Dialer::default().tcp_options(self.tcp_options);
+        #[cfg(feature = "tls")]
+        let dialer = match self.tls.as_ref().map(|tls| tls.connector(name.clone())) {
+            Some(Ok(connector)) => dialer.tls(connector),
+            Some(Err(e)) => return Box::new(future::err(ChannelBuildError::TlsError(e))),
+            None => dialer,
+        };
+        #[cfg(unix)]
+        let dialer = match self.mode {
+            ConnectMode::Unix(ref path) => dialer.unix(path.clone()),
+            _ => dialer,
+        };
+        let handle = self.handle;
+
+        let (tx, rx) = mpsc::unbounded();
+        let (shutdown_tx, shutdown_rx) = mpsc::unbounded();
+        let (endpoints_tx, endpoints_rx) = mpsc::unbounded();
+        let timer = timer::new();
+        let lb: Arc<LoadBalance> = match (self.load_balancer, &self.mode) {
+            (Some(lb), _) => Arc::from(lb),
+            (None, &ConnectMode::Weighted(_)) | (None, &ConnectMode::Naming(..)) => {
+                Arc::new(WeightedRoundRobin::new())
+            }
+            (None, _) => Arc::new(RoundRobin::new()),
+        };
+
+        let without_server = match self.mode {
+            ConnectMode::Naming(_, ref options) => options.get_succeed_without_server(),
+            _ => false,
+        };
+        let servers = match self.mode {
+            ConnectMode::Single(target) => {
+                let (name, handle, timer) = (name.clone(), handle.clone(), timer.clone());
+                let fut = resolve_target(target, self.resolver).and_then(move |(servers, _)| {
+                    if servers.len() == 1 || lazy {
+                        let first = servers.into_iter().next();
+                        return future::Either::A(future::ok((first.into_iter().collect(), None)));
+                    }
+                    let first = first_reachable(servers, &name, &handle, &timer, connect_timeout);
+                    future::Either::B(first.map(|server| (vec![server], None)))
+                });
+                Box::new(fut)
+            }
+            ConnectMode::Multi(addrs) => {
+                parse_servers(addrs.into_iter().map(|addr| (addr, 1)).collect())
+            }
+            ConnectMode::Weighted(servers) => parse_servers(servers),
+            ConnectMode::Dns {
+                host,
+                port,
+                refresh,
+            } => {
+                let naming = match self.resolver {
+                    Some(resolver) => DnsNaming::new(host, port, refresh).resolver(resolver),
+                    None => DnsNaming::new(host, port, refresh),
+                };
+                subscribe(&naming, &NamingOptions::new(), &handle)
+            }
+            ConnectMode::Naming(naming, options) => subscribe(&*naming, &options, &handle),
+            #[cfg(unix)]
+            ConnectMode::Unix(_) => {
+                let server = ServerEndpoint::new(SocketAddr::from(([0, 0, 0, 0], 0)));
+                Box::new(future::ok((vec![server], None)))
+            }
+        };
+
+        let fut = servers.and_then(move |(servers, updates)| {
+            let connects: Vec<_> = servers
+                .iter()
+                .map(|server| {
+                    let addr = server.addr();
+                    // `None` for the connections of a lazy channel
+                    let pool: Vec<_> = (0..connections)
+                        .map(|_| {
+                            if lazy {
+                                return future::Either::A(future::ok(None));
+                            }
+                            let connect = connect(
+                                &conn_options,
+                                addr,
+                                &handle,
+                                &timer,
+                                connect_timeout,
+                                &dialer,
+                            );
+                            let connect = if ping {
+                                let timer = timer.clone();
+                                let pinged = connect.and_then(move |end_port| {
+                                    warm_up_ping(end_port, &timer, connect_timeout)
+                                });
+                                future::Either::A(pinged)
+                            } else {
+                                future::Either::B(connect)
+                            };
+                            let (target, name) = (dialer.target(addr), name.clone());
+                            future::Either::B(connect.then(move |result| {
+                                if let Err(ref e) = result {
+                                    warn!("[{}] Failed to connect to {}: {}", name, target, e);
+                                }
+                                Ok::<_, ChannelBuildError>(Some(result))
+                            }))
+                        })
+                        .collect();
+                    future::join_all(pool)
+                })
+                .collect();
+            future::join_all(connects).and_then(move |results| {
+                let failed = |result: &Option<io::Result<ServerEndPort>>| {
+                    result.as_ref().and_then(|result| result.as_ref().err()).is_some()
+                };
+                let down = |pool: &&Vec<_>| pool.iter().all(&failed);
+                let ready = results.len() - results.iter().filter(down).count();
+                let required = match (warm_up, without_server) {
+                    (true, _) => min_ready.unwrap_or(results.len()),
+                    (false, true) => 0,
+                    (false, false) => 1,
+                };
+                if ready < required {
+                    let timed_out = results.iter().flatten().all(|result| match *result {
+                        Some(Err(ref e)) => e.kind() == io::ErrorKind::TimedOut,
+                        _ => false,
+                    });
+                    if timed_out && !results.is_empty() {
+                        return Err(ChannelBuildError::ConnectTimeout);
+                    }
+                    let mut errors = servers.iter().zip(results).flat_map(|(server, pool)| {
+                        let addr = server.addr();
+                        pool.into_iter().filter_map(move |result| match result {
+                            Some(Err(source)) => Some(ChannelBuildError::ConnectError {
+                                addr,
+                                source,
+                            }),
+                            _ => None,
+                        })
+                    });
+                    return Err(errors.next().unwrap_or_else(|| {
+                        let msg = match ready {
+                            0 => "no server to connect to".to_string(),
+                            _ => format!("only {} of {} servers to connect to", ready, required),
+                        };
+                        ChannelBuildError::ResolveError(msg)
+                    }));
+                }
+                let events_ref = events.as_ref();
+                let pools: Vec<Vec<_>> = results
+                    .into_iter()
+                    .zip(servers.iter().map(ServerEndpoint::addr))
+                    .map(|(pool, addr)| {
+                        pool.into_iter()
+                            .map(|result| match result {
+                                Some(Ok(end_port)) => {
+                                    emit(events_ref, ChannelEvent::Connected(addr));
+                                    Connection::connected(end_port)
+                                }
+                                Some(Err(_)) => {
+                                    Connection::failed(addr, &timer, &backoff, events_ref)
+                                }
+                                None => Connection::idle(),
+                            })
+                            .collect()
+                    })
+                    .collect();
+                let counters: Vec<_> = servers
+                    .iter()
+                    .map(|server| {
+                        let weight = server.weight().unwrap_or(1);
+                        let breaker = circuit_breaker.clone();
+                        Arc::new(ServerCounters::new(&name, server.addr(), weight, breaker))
+                    })
+                    .collect();
+                let list = Arc::new(Mutex::new(counters.clone()));
+                let channel =
+                    Channel::new(tx, shutdown_tx, max_concurrency, timer.clone(), list.clone())
+                    .with_rpc_timeout(rpc_timeout)
+                    .with_max_pending_calls(max_pending_calls)
+                    .with_gauges(inflight_gauge, queued_gauge)
+                    .with_limiter(limiter.clone())
+                    .with_metrics(metrics.clone())
+                    .with_interceptors(interceptors.clone())
+                    .with_compress_over(compress_over)
+                    .with_retry_policy(retry_policy)
+                    .with_backups(backup_request_after, backups.clone())
+                    .with_endpoints(endpoints_tx)
+                    .with_name(name.clone());
+                let servers = pools
+                    .into_iter()
+                    .zip(counters)
+                    .zip(servers.iter().map(tags_of))
+                    .map(|((conns, counters), tags)| (conns, counters, tags))
+                    .collect();
+                let mut backend = ChannelBackend::new(
+                    rx,
+                    handle.clone(),
+                    lb,
+                    servers,
+                    conn_options,
+                    timer,
+                    backoff,
+                ).with_connect_timeout(connect_timeout)
+                    .with_dialer(dialer)
+                    .with_pool_size(connections)
+                    .with_lazy_connect(lazy)
+                    .with_shutdown(shutdown_rx, drain_timeout)
+                    .with_backup_counters(backups)
+                    .with_max_failover(max_failover)
+                    .with_circuit_breaker(circuit_breaker)
+                    .with_events(events);
+                if let Some((interval, mode)) = health_check {
+                    match HealthCheck::new(interval, mode, &handle) {
+                        Ok(check) => {
+                            backend = backend.with_health_check(check.named(name.clone()))
+                        }
+                        Err(e) => warn!("[{}] Failed to start the health check: {}", name, e),
+                    }
+                }
+                if let Some(interval) = keepalive_interval {
+                    match Keepalive::new(interval, &handle) {
+                        Ok(keepalive) => {
+                            backend = backend.with_keepalive(keepalive.named(name.clone()))
+                        }
+                        Err(e) => warn!("[{}] Failed to start the keepalive: {}", name, e),
+                    }
+                }
+                // the servers set by the channel are taken as given by a
+                // naming service, until it gives new ones
+                // an unbounded receiver never fails
+                let endpoints = endpoints_rx.map_err(|()| unreachable!());
+                let updates: ServerStream = match updates {
+                    Some(updates) => Box::new(updates.select(endpoints)),
+                    None => Box::new(endpoints),
+                };
+                backend = backend.with_naming(Naming::new(updates, list));
+                Ok((channel, backend))
+            })
+        });
+        Box::new(fut)
+    }
+}
+
+/// A future used internally by the framework. It will resolve to a serialized response.
+#[derive(Debug)]
+pub struct ChannelFuture {
+    rx: Option<OneShotReceiver>,
+    counter: Arc<AtomicUsize>,
+    pending: Option<Counted>,
+    permit: Option<LimitPermit>,
+    finished: bool,
+    closed: bool,
+    full: bool,
+}
+
+impl ChannelFuture {
+    /// Create a new future, used internally
+    pub fn new(rx: Option<OneShotReceiver>, counter: Arc<AtomicUsize>) -> Self {
+        ChannelFuture {
+            rx,
+            counter,
+            pending: None,
+            permit: None,
+            finished: false,
+            closed: false,
+            full: false,
+        }
+    }
+
+    /// Create a future failing as the channel is shut down.
+    fn closed(counter: Arc<AtomicUsize>) -> Self {
+        ChannelFuture {
+            rx: None,
+            counter,
+            pending: None,
+            permit: None,
+            finished: false,
+            closed: true,
+            full: false,
+        }
+    }
+
+    /// Create a future failing as too many calls are pending.
+    fn full(counter: Arc<AtomicUsize>) -> Self {
+        ChannelFuture {
+            rx: None,
+            counter,
+            pending: None,
+            permit: None,
+            finished: false,
+            closed: false,
+            full: true,
+        }
+    }
+
+    /// Count the call as pending until it is answered or dropped.
+    fn with_pending(mut self, pending: Option<Counted>) -> Self {
+        self.pending = pending;
+        self
+    }
+
+    /// Count the call by the concurrency limit until it is answered or
+    /// dropped.
+    fn with_permit(mut self, permit: Option<LimitPermit>) -> Self {
+        self.permit = permit;
+        self
+    }
+}
+
+impl Drop for ChannelFuture {
+    fn drop(&mut self) {
+        // release the concurrency slot if the call is abandoned halfway
+        if self.rx.is_some() && !self.finished {
+            self.counter.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Future for ChannelFuture {
+    type Item = (ResponsePackage, FeedbackHandle);
+
+    type Error = ChannelError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut rx) = self.rx {
+            let result = match rx.poll() {
+                Ok(Async::Ready(result)) => result,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // the backend is gone, after the channel is shut down
+                Err(_) => Err(closed_error()),
+            };
+            self.counter.fetch_sub(1, Ordering::Relaxed);
+            self.finished = true;
+            self.pending = None;
+            if let Some(mut permit) = self.permit.take() {
+                if result.is_ok() {
+                    permit.answered();
+                }
+            }
+
+            result.map_err(call_error).map(|resp| Async::Ready(resp))
+        } else if self.closed {
+            Err(ChannelError::Closed)
+        } else if self.full {
+            Err(ChannelError::ChannelFull)
+        } else {
+            Err(ChannelError::ConcurrencyLimitReached)
+        }
+    }
+}
+
+/// A future used internally by the framework. It will resolve when a oneway
+/// request has been written to the connection.
+#[derive(Debug)]
+pub struct OnewayFuture {
+    rx: Option<AckReceiver>,
+    pending: Option<Counted>,
+    full: bool,
+}
+
+impl Future for OnewayFuture {
+    type Item = ();
+
+    type Error = ChannelError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.rx {
+            Some(ref mut rx) => rx.poll(),
+            None if self.full => return Err(ChannelError::ChannelFull),
+            None => return Err(ChannelError::Closed),
+        };
+        if let Ok(Async::NotReady) = result {
+            return Ok(Async::NotReady);
+        }
+        self.pending = None;
+        // the sender is dropped when no server is connected, or when the
+        // connection fails before the request is written
+        result.map_err(|_| {
+            let e = io::Error::new(io::ErrorKind::NotConnected, "request not sent");
+            ChannelError::IoError(e)
+        })
+    }
+}
+
+/// A call counted by a gauge of a channel, until it is dropped
+#[derive(Debug)]
+pub(crate) struct Counted(Arc<AtomicUsize>);
+
+impl Counted {
+    /// Count a call in `gauge`.
+    pub fn new(gauge: &Arc<AtomicUsize>) -> Self {
+        gauge.fetch_add(1, Ordering::SeqCst);
+        Counted(gauge.clone())
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A future returned by `Channel::shutdown`, which resolves once the channel
+/// is shut down.
+#[derive(Debug)]
+pub struct ShutdownFuture {
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+impl Future for ShutdownFuture {
+    type Item = ();
+
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.rx {
+            // the backend is also gone when it is done
+            Some(ref mut rx) => Ok(rx.poll().unwrap_or(Async::Ready(()))),
+            None => Ok(Async::Ready(())),
+        }
+    }
+}
+
+/// Communication channel between servers
+///
+/// The `Channel` implements `Clone`, `Send`, and `Sync`. Once a channel is create from
+/// a `ChannelBuilder`, it can be cloned and used in multiple threads.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    sender: ChannelSender,
+    counter: Arc<AtomicUsize>,
+    max_concurrency: usize,
+    /// Calls waiting to be written or answered
+    pending: Arc<AtomicUsize>,
+    max_pending_calls: Option<usize>,
+    /// Calls whose request is not written yet
+    queued: Arc<AtomicUsize>,
+    limiter: Option<Arc<Limiter>>,
+    timer: Timer,
+    servers: ServerList,
+    rpc_timeout: Option<Duration>,
+    retry_policy: Option<Arc<RetryPolicy>>,
+    backup_request_after: Option<Duration>,
+    backups: Arc<BackupCounters>,
+    metrics: Option<ChannelMetrics>,
+    interceptors: Interceptors,
+    compress_over: Option<(usize, CompressType)>,
+    shutdown: ShutdownSender,
+    endpoints: Option<EndpointSender>,
+    name: Arc<str>,
+    /// Whether the channel is shut down, shared by its clones
+    closed: Arc<AtomicBool>,
+}
+
+impl Channel {
+    /// Create a new channel.
+    ///
+    /// This method is used by `ChannelBuilder`.
+    pub(crate) fn new(
+        sender: ChannelSender,
+        shutdown: ShutdownSender,
+        max_concurrency: u32,
+        timer: Timer,
+        servers: ServerList,
+    ) -> Self {
+        Channel {
+            sender,
+            shutdown,
+            closed: Arc::new(AtomicBool::new(false)),
+            counter: Arc::new(AtomicUsize::new(0)),
+            max_concurrency: max_concurrency as usize,
+            pending: Arc::new(AtomicUsize::new(0)),
+            max_pending_calls: None,
+            queued: Arc::new(AtomicUsize::new(0)),
+            limiter: None,
+            timer,
+            servers,
+            rpc_timeout: None,
+            retry_policy: None,
+            backup_request_after: None,
+            backups: Arc::new(BackupCounters::default()),
+            metrics: None,
+            interceptors: Arc::new(Vec::new()),
+            compress_over: None,
+            endpoints: None,
+            name: Arc::from(""),
+        }
+    }
+
+    fn with_name(mut self, name: Arc<str>) -> Self {
+        self.name = name;
+        self
+    }
+
+    fn with_endpoints(mut self, endpoints: EndpointSender) -> Self {
+        self.endpoints = Some(endpoints);
+        self
+    }
+
+    fn with_rpc_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.rpc_timeout = timeout;
+        self
+    }
+
+    fn with_max_pending_calls(mut self, max: Option<usize>) -> Self {
+        self.max_pending_calls = max;
+        self
+    }
+
+    fn with_gauges(
+        mut self,
+        inflight: Option<Arc<AtomicUsize>>,
+        queued: Option<Arc<AtomicUsize>>,
+    ) -> Self {
+        if let Some(inflight) = inflight {
+            self.pending = inflight;
+        }
+        if let Some(queued) = queued {
+            self.queued = queued;
+        }
+        self
+    }
+
+    fn with_limiter(mut self, limiter: Option<Arc<Limiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    fn with_metrics(mut self, metrics: Option<ChannelMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    fn with_compress_over(mut self, compress_over: Option<(usize, CompressType)>) -> Self {
+        self.compress_over = compress_over;
+        self
+    }
+
+    fn with_interceptors(mut self, interceptors: Interceptors) -> Self {
+        self.interceptors = interceptors;
+        self
+    }
+
+    fn with_retry_policy(mut self, policy: Option<Arc<RetryPolicy>>) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    fn with_backups(mut self, after: Option<Duration>, counters: Arc<BackupCounters>) -> Self {
+        self.backup_request_after = after;
+        self.backups = counters;
+        self
+    }
+
+    /// Get the name of the channel, see [`ChannelBuilder::name`].
+    ///
+    /// [`ChannelBuilder::name`]: struct.ChannelBuilder.html#method.name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the timer shared by the calls issued on this channel.
+    pub fn timer(&self) -> &Timer {
+        &self.timer
+    }
+
+    /// Get the timeout of the calls which do not set their own.
+    pub fn rpc_timeout(&self) -> Option<Duration> {
+        self.rpc_timeout
+    }
+
+    /// Get the policy failed calls are retried with.
+    pub(crate) fn retry_policy(&self) -> Option<Arc<RetryPolicy>> {
+        self.retry_policy.clone()
+    }
+
+    /// Get the metrics the calls are recorded in.
+    pub(crate) fn metrics(&self) -> Option<&ChannelMetrics> {
+        self.metrics.as_ref()
+    }
+
+    /// Get the size above which request bodies are compressed, and how.
+    pub(crate) fn compress_requests_over(&self) -> Option<(usize, CompressType)> {
+        self.compress_over
+    }
+
+    /// Get the interceptors run around the calls made by stubs.
+    pub(crate) fn interceptors(&self) -> &Interceptors {
+        &self.interceptors
+    }
+
+    /// Get the delay after which the calls which do not set their own send
+    /// a backup request.
+    pub fn backup_request_after(&self) -> Option<Duration> {
+        self.backup_request_after
+    }
+
+    /// Issue a request.
+    ///
+    /// This method deals with serialized, untyped message. It is meaned to be used
+    /// internally by the framework. More ergonomic interfaces are provided by the 
+    /// auto-generated stubs.
+    pub fn call(&self, req: RequestPackage) -> ChannelFuture {
+        self.call_with_hint(req, None)
+    }
+
+    /// Issue a request, passing `hint` to the load balancer.
+    pub fn call_with_hint(&self, req: RequestPackage, hint: Option<LbHint>) -> ChannelFuture {
+        let options = SendOptions {
+            hint,
+            ..Default::default()
+        };
+        self.send_call(req, options)
+    }
+
+    /// Issue a request again after it failed, passing `hint` to the load
+    /// balancer.
+    ///
+    /// The request is counted as a retry by `server_retries` rather than
+    /// by `server_calls`.
+    pub fn retry_with_hint(&self, req: RequestPackage, hint: Option<LbHint>) -> ChannelFuture {
+        let options = SendOptions {
+            hint,
+            retry: true,
+            ..Default::default()
+        };
+        self.send_call(req, options)
+    }
+
+    /// Issue a request as `options` tell.
+    pub(crate) fn send_call(&self, mut req: RequestPackage, options: SendOptions) -> ChannelFuture {
+        if self.is_closed() {
+            return ChannelFuture::closed(self.counter.clone());
+        }
+        let pending = match self.pending_call() {
+            Ok(pending) => pending,
+            Err(()) => return ChannelFuture::full(self.counter.clone()),
+        };
+        let permit = match self.limiter {
+            Some(ref limiter) => match Limiter::acquire(limiter) {
+                Some(permit) => Some(permit),
+                None => return ChannelFuture::new(None, self.counter.clone()),
+            },
+            None => None,
+        };
+        options.refresh_timeout(&mut req);
+        let (tx, rx) = oneshot::channel();
+        let rx = if self.counter.load(Ordering::SeqCst) < self.max_concurrency {
+            self.counter.fetch_add(1, Ordering::SeqCst);
+            let queued = Counted::new(&self.queued);
+            let sent = self.sender
+                .unbounded_send((Callback::Response(tx), req, options, queued));
+            if sent.is_err() {
+                // the backend is gone once the channel is shut down
+                self.counter.fetch_sub(1, Ordering::SeqCst);
+                return ChannelFuture::closed(self.counter.clone());
+            }
+            Some(rx)
+        } else {
+            None
+        };
+
+        ChannelFuture::new(rx, self.counter.clone())
+            .with_pending(Some(pending))
+            .with_permit(permit)
+    }
+
+    /// Issue a request without waiting for the response.
+    ///
+    /// The request is marked oneway in its meta, so that the server sends no
+    /// response, and a response sent anyway is discarded. The returned
+    /// future resolves once the request is written to the connection, and
+    /// fails if the connection fails before. Oneway requests are never
+    /// retried nor hedged, and are not limited by `max_concurrency`.
+    pub fn call_oneway(&self, mut req: RequestPackage) -> OnewayFuture {
+        req.0.set_oneway(true);
+        if self.is_closed() {
+            return OnewayFuture {
+                rx: None,
+                pending: None,
+                full: false,
+            };
+        }
+        let pending = match self.pending_call() {
+            Ok(pending) => pending,
+            Err(()) => {
+                return OnewayFuture {
+                    rx: None,
+                    pending: None,
+                    full: true,
+                }
+            }
+        };
+        let (tx, rx) = oneshot::channel();
+        let queued = Counted::new(&self.queued);
+        let sent = self.sender
+            .unbounded_send((Callback::Sent(tx), req, SendOptions::default(), queued));
+
+        OnewayFuture {
+            rx: sent.ok().map(|()| rx),
+            pending: Some(pending),
+            full: false,
+        }
+    }
+
+    /// Count a new pending call, or fail if as many calls as allowed are
+    /// pending.
+    fn pending_call(&self) -> Result<Counted, ()> {
+        let before = self.pending.fetch_add(1, Ordering::SeqCst);
+        let pending = Counted(self.pending.clone());
+        match self.max_pending_calls {
+            // dropping it takes the count back
+            Some(max) if before >= max => Err(()),
+            _ => Ok(pending),
+        }
+    }
+
+    /// Shut down the channel, and all its clones.
+    ///
+    /// New calls fail at once with `RpcError::ChannelClosed`. The calls in
+    /// flight are waited for, up to the drain timeout set by
+    /// [`ChannelBuilder::drain_timeout`], after which they fail the same
+    /// way. The connections are then closed, and the returned future
+    /// resolves. The backend of the channel ends with it, so an event loop
+    /// running nothing else can exit.
+    ///
+    /// A channel dropped without being shut down keeps its connections until
+    /// the calls in flight are answered.
+    ///
+    /// [`ChannelBuilder::drain_timeout`]: struct.ChannelBuilder.html#method.drain_timeout
+    pub fn shutdown(&self) -> ShutdownFuture {
+        self.closed.store(true, Ordering::SeqCst);
+        let (tx, rx) = oneshot::channel();
+        let rx = self.shutdown.unbounded_send(tx).ok().map(|()| rx);
+        ShutdownFuture { rx }
+    }
+
+    /// Replace the servers of the channel by `addrs`, for the channel and
+    /// all its clones. It can be called from any thread.
+    ///
+    /// The backend connects to the new servers, and stops sending calls to
+    /// the servers not in `addrs`. Their calls in flight are still
+    /// answered, and their connections are closed once idle. A channel
+    /// following a naming service, e.g. built by [`ChannelBuilder::dns`],
+    /// goes back to the servers it gives on its next update.
+    ///
+    /// An empty `addrs` is ignored, keeping the servers.
+    ///
+    /// # Errors
+    /// A `Closed` error if the channel is shut down.
+    ///
+    /// [`ChannelBuilder::dns`]: struct.ChannelBuilder.html#method.dns
+    pub fn update_endpoints(&self, addrs: Vec<SocketAddr>) -> Result<(), ChannelError> {
+        if self.is_closed() {
+            return Err(ChannelError::Closed);
+        }
+        if addrs.is_empty() {
+            warn!("[{}] No server to update the channel to, keeping the servers", self.name);
+            return Ok(());
+        }
+        let servers = addrs.into_iter().map(ServerEndpoint::new).collect();
+        match self.endpoints {
+            Some(ref endpoints) => endpoints
+                .unbounded_send(servers)
+                .map_err(|_| ChannelError::Closed),
+            None => Err(ChannelError::Closed),
+        }
+    }
+
+    /// Check if the channel is shut down.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Get the number of calls sent to each server, in the order the servers
+    /// are given to the builder. Retries are not counted.
+    ///
+    /// For a channel built by [`ChannelBuilder::dns`] these are the servers
+    /// currently resolved, in the order they are first resolved.
+    ///
+    /// [`ChannelBuilder::dns`]: struct.ChannelBuilder.html#method.dns
+    pub fn server_calls(&self) -> Vec<(SocketAddr, usize)> {
+        self.servers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|server| (server.addr, server.calls.load(Ordering::SeqCst)))
+            .collect()
+    }
+
+    /// Get the number of retries sent to each server, in the same order as
+    /// [`server_calls`].
+    ///
+    /// [`server_calls`]: #method.server_calls
+    pub fn server_retries(&self) -> Vec<(SocketAddr, usize)> {
+        self.servers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|server| (server.addr, server.retries.load(Ordering::SeqCst)))
+            .collect()
+    }
+
+    /// Get the number of calls issued on the channel and its clones which
+    /// are not answered yet, oneway calls until they are written to a
+    /// connection.
+    ///
+    /// These are the calls counted by [`ChannelBuilder::max_pending_calls`].
+    ///
+    /// [`ChannelBuilder::max_pending_calls`]: struct.ChannelBuilder.html#method.max_pending_calls
+    pub fn inflight(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Get the number of calls in flight whose request is not written to a
+    /// connection yet, as the backend has not run since they are issued,
+    /// no server is connected yet, or the connection is busy writing
+    /// other requests.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Get the number of backup requests sent, see
+    /// [`ChannelBuilder::backup_request_after`].
+    ///
+    /// [`ChannelBuilder::backup_request_after`]: struct.ChannelBuilder.html#method.backup_request_after
+    pub fn backup_requests(&self) -> usize {
+        self.backups.sent.load(Ordering::SeqCst)
+    }
+
+    /// Get the number of backup requests whose successful response is taken
+    /// instead of the one of the call they duplicate.
+    pub fn backup_wins(&self) -> usize {
+        self.backups.won.load(Ordering::SeqCst)
+    }
+
+    /// Get the state of the circuit breaker of each server, in the same
+    /// order as [`server_calls`].
+    ///
+    /// [`server_calls`]: #method.server_calls
+    pub fn circuit_states(&self) -> Vec<(SocketAddr, CircuitState)> {
+        self.servers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|server| (server.addr, server.breaker.state()))
+            .collect()
+    }
+
+    /// Get the number of times each server is ejected by its circuit
+    /// breaker, in the same order as [`server_calls`].
+    ///
+    /// [`server_calls`]: #method.server_calls
+    pub fn server_ejections(&self) -> Vec<(SocketAddr, usize)> {
+        self.servers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|server| (server.addr, server.breaker.ejections()))
+            .collect()
+    }
+
+    /// Get whether each server passed its last health check, in the same
+    /// order as [`server_calls`].
+    ///
+    /// Servers are healthy until a probe fails, see
+    /// [`ChannelBuilder::health_check`].
+    ///
+    /// [`server_calls`]: #method.server_calls
+    /// [`ChannelBuilder::health_check`]: struct.ChannelBuilder.html#method.health_check
+    pub fn server_health(&self) -> Vec<(SocketAddr, bool)> {
+        self.servers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|server| (server.addr, server.healthy.load(Ordering::SeqCst)))
+            .collect()
+    }
+
+    /// Get the current limit of the calls in flight, and the number of calls
+    /// in flight, or `None` without a limit set by
+    /// [`ChannelBuilder::concurrency_limit`].
+    ///
+    /// [`ChannelBuilder::concurrency_limit`]: struct.ChannelBuilder.html#method.concurrency_limit
+    pub fn concurrency_limit(&self) -> Option<(usize, usize)> {
+        self.limiter
+            .as_ref()
+            .map(|limiter| (limiter.limit(), limiter.inflight()))
+    }
+
+    // TODO: deprecate this
+    /// Check if the channel is currently congested (i.e. concurrency limit is reached). 
+    pub fn congested(&self) -> bool {
+        let current = self.counter.load(Ordering::Relaxed);
+        current >= self.max_concurrency
+    }
+}
+
+
+/// [WIP] Feedback handle to load balancers
+#[derive(Debug)]
+pub struct FeedbackHandle {
+    id: ServerId,
     sender: FeedbackSender,
 }
 
@@ -418,9 +2518,10 @@ impl FeedbackHandle {
     }
 
     /// Send feedback massage.
+    ///
+    /// The feedback is dropped if the backend of the channel is gone, e.g.
+    /// shut down after every handle of the channel is dropped.
     pub fn call(self, info: CallInfo) {
-        self.sender
-            .send((self.id, info))
-            .expect("The receiving end of the feedback channel is dropped");
+        let _ = self.sender.send((self.id, info));
     }
 }
@@ -0,0 +1,129 @@
+//! Retry of failed calls
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use errno;
+
+use super::backend::Backoff;
+
+/// How the failed calls of a channel are sent again
+///
+/// A call is retried if the connection to its server failed, or the server
+/// answered with one of the retryable error codes, by default
+/// `errno::ELIMIT` (the server is busy). Calls whose response can not be
+/// decoded, and calls which timed out, are never retried. Retries are
+/// spread over the servers by the load balancer like any call, and all the
+/// attempts of a call share its timeout.
+///
+/// Only idempotent methods should be retried, a call can opt out with
+/// `CallOptions::max_retry(0)`.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Arc<Backoff>,
+    connection_failures: bool,
+    error_codes: Vec<i32>,
+}
+
+impl RetryPolicy {
+    /// Create a policy which makes up to 3 attempts, waiting 10 milliseconds
+    /// before the first retry.
+    pub fn new() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Arc::new(Backoff::new(
+                Duration::from_millis(10),
+                Duration::from_secs(1),
+            )),
+            connection_failures: true,
+            error_codes: vec![errno::ELIMIT],
+        }
+    }
+
+    /// Set the number of attempts of a call, including the first one.
+    ///
+    /// At least one attempt is made.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set the delays between the attempts of a call.
+    ///
+    /// The delay starts at `min` and doubles with every retry up to `max`,
+    /// with up to half of it taken off at random. Default to 10 milliseconds
+    /// and 1 second.
+    pub fn backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.backoff = Arc::new(Backoff::new(min, max));
+        self
+    }
+
+    /// Set whether the calls failed by a broken connection, or by no server
+    /// being connected, are retried.
+    ///
+    /// Default to `true`.
+    pub fn retry_connection_failures(mut self, retry: bool) -> Self {
+        self.connection_failures = retry;
+        self
+    }
+
+    /// Set the error codes of the responses which are retried.
+    ///
+    /// See [`errno`] for the values. Default to `errno::ELIMIT`.
+    ///
+    /// [`errno`]: ../errno/index.html
+    pub fn retry_on(mut self, error_codes: &[i32]) -> Self {
+        self.error_codes = error_codes.to_vec();
+        self
+    }
+
+    /// Get the number of attempts of a call, including the first one.
+    pub fn get_max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Check if a call failed by the connection is retried.
+    pub(crate) fn retries_connection_failures(&self) -> bool {
+        self.connection_failures
+    }
+
+    /// Check if a response with `code` is retried.
+    pub(crate) fn retries_code(&self, code: i32) -> bool {
+        self.error_codes.contains(&code)
+    }
+
+    /// Get the delay before the `retries`th retry.
+    pub(crate) fn delay(&self, retries: u32) -> Duration {
+        self.backoff.delay(retries)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn retried_failures() {
+        let policy = RetryPolicy::new();
+        assert_eq!(policy.get_max_attempts(), 3);
+        assert!(policy.retries_connection_failures());
+        assert!(policy.retries_code(errno::ELIMIT));
+        assert!(!policy.retries_code(errno::EINTERNAL));
+
+        let policy = policy
+            .max_attempts(0)
+            .retry_connection_failures(false)
+            .retry_on(&[errno::EINTERNAL]);
+        assert_eq!(policy.get_max_attempts(), 1);
+        assert!(!policy.retries_connection_failures());
+        assert!(policy.retries_code(errno::EINTERNAL));
+        assert!(!policy.retries_code(errno::ELIMIT));
+    }
+}
@@ -0,0 +1,114 @@
+//! TLS on the connections of a channel
+
+use futures::Future;
+use native_tls::{self, Certificate};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio_tls;
+
+use super::connector::{BoxStream, ClientStream, DialFuture};
+
+/// TLS settings of the connections of a channel
+///
+/// The server certificate is verified against the system roots and the ones
+/// added by [`root_certificate`], for the domain given to [`new`], which is
+/// also sent as the SNI hostname.
+///
+/// [`root_certificate`]: #method.root_certificate
+/// [`new`]: #method.new
+#[derive(Clone)]
+pub struct ClientTlsConfig {
+    domain: String,
+    roots: Vec<Certificate>,
+    accept_invalid_certs: bool,
+}
+
+impl fmt::Debug for ClientTlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientTlsConfig")
+            .field("domain", &self.domain)
+            .field("roots", &self.roots.len())
+            .field("accept_invalid_certs", &self.accept_invalid_certs)
+            .finish()
+    }
+}
+
+impl ClientTlsConfig {
+    /// Connect to the servers of `domain`.
+    pub fn new<S: Into<String>>(domain: S) -> Self {
+        ClientTlsConfig {
+            domain: domain.into(),
+            roots: Vec::new(),
+            accept_invalid_certs: false,
+        }
+    }
+
+    /// Trust `cert`, e.g. the self-signed certificate of the server.
+    pub fn root_certificate(mut self, cert: Certificate) -> Self {
+        self.roots.push(cert);
+        self
+    }
+
+    /// Accept any certificate of the server, for development only.
+    ///
+    /// The connections are still encrypted, but anyone on the way can
+    /// impersonate the server. Default to `false`.
+    pub fn accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Create the connector of the channel named `name`.
+    pub(crate) fn connector(&self, name: Arc<str>) -> Result<TlsConnector, native_tls::Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+        for cert in &self.roots {
+            builder.add_root_certificate(cert.clone());
+        }
+        if self.accept_invalid_certs {
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+        Ok(TlsConnector {
+            inner: builder.build()?.into(),
+            domain: Arc::new(self.domain.clone()),
+            name,
+        })
+    }
+}
+
+/// Perform the TLS handshake on new connections
+#[derive(Clone)]
+pub(crate) struct TlsConnector {
+    inner: tokio_tls::TlsConnector,
+    domain: Arc<String>,
+    /// Name of the channel, told by the logs
+    name: Arc<str>,
+}
+
+impl fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TlsConnector({})", self.domain)
+    }
+}
+
+impl TlsConnector {
+    /// Start the handshake with the server at `addr` over `stream`.
+    pub fn connect<S>(&self, addr: SocketAddr, stream: S) -> DialFuture
+    where
+        S: ClientStream + 'static,
+    {
+        let name = self.name.clone();
+        let handshake = self.inner.connect(&self.domain, stream).then(move |result| match result {
+            Ok(stream) => Ok(Box::new(stream) as BoxStream),
+            Err(e) => {
+                warn!("[{}] TLS handshake with {} failed: {}", name, addr, e);
+                let msg = format!("TLS handshake failed: {}", e);
+                Err(io::Error::new(io::ErrorKind::Other, msg))
+            }
+        });
+        Box::new(handshake)
+    }
+}
@@ -0,0 +1,319 @@
+//! Parsing of the URIs naming the servers of a channel
+
+use std::str::FromStr;
+use std::time::Duration;
+use url::form_urlencoded;
+use url::percent_encoding::percent_decode;
+
+use load_balancer::{ConsistentHash, LoadBalance, Random, RoundRobin, WeightedRoundRobin};
+use protocol::Protocol;
+
+/// Interval between the resolutions of the servers of a dns or file URI,
+/// unless `refresh_ms` tells otherwise
+const DEFAULT_REFRESH_MS: u64 = 10_000;
+
+/// Where the servers of a channel are found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum UriTarget<'a> {
+    /// A server, as "host:port"
+    Single(&'a str),
+    /// A server given without a port
+    Host(&'a str, u16),
+    /// The servers a host name resolves to
+    Dns {
+        host: &'a str,
+        port: u16,
+        refresh: Duration,
+    },
+    /// The servers listed in a file
+    File { path: String, refresh: Duration },
+}
+
+/// A load balancer chosen by the `lb` query parameter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LbKind {
+    RoundRobin,
+    WeightedRoundRobin,
+    Random,
+    ConsistentHash,
+}
+
+impl LbKind {
+    pub fn load_balancer(self) -> Box<LoadBalance> {
+        match self {
+            LbKind::RoundRobin => Box::new(RoundRobin::new()),
+            LbKind::WeightedRoundRobin => Box::new(WeightedRoundRobin::new()),
+            LbKind::Random => Box::new(Random::new()),
+            LbKind::ConsistentHash => Box::new(ConsistentHash::new()),
+        }
+    }
+}
+
+/// A parsed channel URI
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ChannelUri<'a> {
+    pub target: UriTarget<'a>,
+    pub protocol: Protocol,
+    pub connect_timeout: Option<Duration>,
+    pub rpc_timeout: Option<Duration>,
+    pub max_concurrency: Option<u32>,
+    pub connections: Option<usize>,
+    pub lb: Option<LbKind>,
+}
+
+impl<'a> ChannelUri<'a> {
+    /// Parse `uri`, telling what is wrong with it on failure.
+    pub fn parse(uri: &'a str) -> Result<Self, String> {
+        let pos = uri.find("://")
+            .ok_or_else(|| format!("missing scheme in {:?}", uri))?;
+        let (scheme, rest) = (&uri[..pos], &uri[pos + 3..]);
+        let (rest, query) = match rest.find('?') {
+            Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+            None => (rest, ""),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, ""),
+        };
+
+        let mut naming = scheme.splitn(2, '+');
+        let naming = (naming.next().unwrap_or(""), naming.next());
+        let (kind, protocol) = match naming {
+            ("brpc", None) => ("single", Protocol::Brpc),
+            ("http", None) => ("single", Protocol::Http),
+            (kind @ "dns", protocol) | (kind @ "file", protocol) => {
+                let protocol = match protocol {
+                    None | Some("brpc") => Protocol::Brpc,
+                    Some("http") => Protocol::Http,
+                    Some(protocol) => return Err(format!("unknown protocol {:?}", protocol)),
+                };
+                (kind, protocol)
+            }
+            _ => {
+                return Err(format!(
+                    "unknown scheme {:?}, expected brpc, http, dns or file",
+                    scheme
+                ))
+            }
+        };
+
+        let (mut connect_timeout, mut rpc_timeout, mut max_concurrency) = (None, None, None);
+        let (mut connections, mut lb, mut refresh) = (None, None, None);
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match &*key {
+                "connect_timeout_ms" => {
+                    connect_timeout = Some(Duration::from_millis(number(&key, &value)?))
+                }
+                "timeout_ms" => rpc_timeout = Some(Duration::from_millis(number(&key, &value)?)),
+                "max_concurrency" => max_concurrency = Some(number(&key, &value)?),
+                "connections" => connections = Some(number(&key, &value)?),
+                "refresh_ms" if kind != "single" => {
+                    refresh = Some(Duration::from_millis(number(&key, &value)?))
+                }
+                "lb" => {
+                    lb = Some(match &*value {
+                        "rr" => LbKind::RoundRobin,
+                        "wrr" => LbKind::WeightedRoundRobin,
+                        "random" => LbKind::Random,
+                        "chash" => LbKind::ConsistentHash,
+                        _ => return Err(format!("unknown load balancer {:?}", value)),
+                    })
+                }
+                _ => return Err(format!("unknown parameter {:?} for scheme {:?}", key, scheme)),
+            }
+        }
+        let refresh = refresh.unwrap_or(Duration::from_millis(DEFAULT_REFRESH_MS));
+
+        let target = match kind {
+            "file" => {
+                if !authority.is_empty() && authority != "localhost" {
+                    return Err(format!("file URI with a host {:?}", authority));
+                }
+                if path.is_empty() {
+                    return Err("missing file path".to_string());
+                }
+                let path = percent_decode(path.as_bytes())
+                    .decode_utf8()
+                    .map_err(|_| format!("file path {:?} is not UTF-8", path))?;
+                UriTarget::File {
+                    path: path.into_owned(),
+                    refresh,
+                }
+            }
+            _ => {
+                if !path.is_empty() && path != "/" {
+                    return Err(format!("unexpected path {:?}", path));
+                }
+                let (host, port) = split_port(authority)?;
+                match (kind, port) {
+                    ("dns", Some(port)) => UriTarget::Dns {
+                        host,
+                        port,
+                        refresh,
+                    },
+                    (_, Some(_)) => UriTarget::Single(authority),
+                    // the default port of http
+                    (_, None) if protocol == Protocol::Http => UriTarget::Host(host, 80),
+                    (_, None) => return Err(format!("missing port in {:?}", authority)),
+                }
+            }
+        };
+        Ok(ChannelUri {
+            target,
+            protocol,
+            connect_timeout,
+            rpc_timeout,
+            max_concurrency,
+            connections,
+            lb,
+        })
+    }
+}
+
+/// Split "host:port", or "[v6]:port", into the host and the port if any.
+fn split_port(authority: &str) -> Result<(&str, Option<u16>), String> {
+    let (host, port) = if authority.starts_with('[') {
+        let end = authority
+            .find(']')
+            .ok_or_else(|| format!("unclosed bracket in {:?}", authority))?;
+        let port = &authority[end + 1..];
+        if !port.is_empty() && !port.starts_with(':') {
+            return Err(format!("invalid authority {:?}", authority));
+        }
+        (&authority[1..end], if port.is_empty() { None } else { Some(&port[1..]) })
+    } else {
+        match authority.rfind(':') {
+            Some(pos) => (&authority[..pos], Some(&authority[pos + 1..])),
+            None => (authority, None),
+        }
+    };
+    if host.is_empty() {
+        return Err(format!("missing host in {:?}", authority));
+    }
+    let port = match port {
+        Some(port) => Some(
+            port.parse()
+                .map_err(|_| format!("invalid port {:?}", port))?,
+        ),
+        None => None,
+    };
+    Ok((host, port))
+}
+
+fn number<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} {:?}, expected a number", key, value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn single(target: UriTarget, protocol: Protocol) -> ChannelUri {
+        ChannelUri {
+            target,
+            protocol,
+            connect_timeout: None,
+            rpc_timeout: None,
+            max_concurrency: None,
+            connections: None,
+            lb: None,
+        }
+    }
+
+    #[test]
+    fn parse_uris() {
+        let refresh = Duration::from_millis(DEFAULT_REFRESH_MS);
+        let table = vec![
+            (
+                "brpc://10.1.2.3:8000",
+                single(UriTarget::Single("10.1.2.3:8000"), Protocol::Brpc),
+            ),
+            (
+                "brpc://[::1]:8000/",
+                single(UriTarget::Single("[::1]:8000"), Protocol::Brpc),
+            ),
+            (
+                "http://gateway:8080",
+                single(UriTarget::Single("gateway:8080"), Protocol::Http),
+            ),
+            (
+                "http://gateway",
+                single(UriTarget::Host("gateway", 80), Protocol::Http),
+            ),
+            (
+                "dns://echo.service:8000",
+                single(
+                    UriTarget::Dns {
+                        host: "echo.service",
+                        port: 8000,
+                        refresh,
+                    },
+                    Protocol::Brpc,
+                ),
+            ),
+            (
+                "dns+http://echo.service:80?refresh_ms=500",
+                single(
+                    UriTarget::Dns {
+                        host: "echo.service",
+                        port: 80,
+                        refresh: Duration::from_millis(500),
+                    },
+                    Protocol::Http,
+                ),
+            ),
+            (
+                "file:///etc/app/backends%20v2.txt",
+                single(
+                    UriTarget::File {
+                        path: "/etc/app/backends v2.txt".to_string(),
+                        refresh,
+                    },
+                    Protocol::Brpc,
+                ),
+            ),
+            (
+                "brpc://10.1.2.3:8000?connect_timeout_ms=500&timeout_ms=2000&lb=rr\
+                 &max_concurrency=64&connections=4",
+                ChannelUri {
+                    connect_timeout: Some(Duration::from_millis(500)),
+                    rpc_timeout: Some(Duration::from_secs(2)),
+                    max_concurrency: Some(64),
+                    connections: Some(4),
+                    lb: Some(LbKind::RoundRobin),
+                    ..single(UriTarget::Single("10.1.2.3:8000"), Protocol::Brpc)
+                },
+            ),
+        ];
+        for (uri, expected) in table {
+            assert_eq!(ChannelUri::parse(uri), Ok(expected), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn reject_bad_uris() {
+        let table = vec![
+            ("10.1.2.3:8000", "missing scheme"),
+            ("ftp://10.1.2.3:8000", "unknown scheme \"ftp\""),
+            ("dns+grpc://echo:8000", "unknown protocol \"grpc\""),
+            ("brpc://10.1.2.3", "missing port"),
+            ("brpc://10.1.2.3:port", "invalid port"),
+            ("brpc://:8000", "missing host"),
+            ("brpc://[::1:8000", "unclosed bracket"),
+            ("http://gateway:8080/api", "unexpected path"),
+            ("dns://echo.service", "missing port"),
+            ("file://backends.txt", "file URI with a host"),
+            ("file://", "missing file path"),
+            ("brpc://10.1.2.3:8000?lb=fastest", "unknown load balancer"),
+            ("brpc://10.1.2.3:8000?timeout_ms=soon", "invalid timeout_ms"),
+            ("brpc://10.1.2.3:8000?refresh_ms=10", "unknown parameter \"refresh_ms\""),
+            ("brpc://10.1.2.3:8000?retries=3", "unknown parameter \"retries\""),
+        ];
+        for (uri, expected) in table {
+            let e = ChannelUri::parse(uri).unwrap_err();
+            assert!(e.contains(expected), "{}: {}", uri, e);
+        }
+    }
+}
@@ -23,6 +23,29 @@ pub trait MethodCodec {
     fn encode(&self, msg: Self::Response) -> Result<Bytes, Self::Error>;
 }
 
+/// Codec passing the bodies as they are
+///
+/// It is the codec of [`DynamicStub`], for messages whose types are not
+/// known.
+///
+/// [`DynamicStub`]: ../stub/struct.DynamicStub.html
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BytesCodec;
+
+impl MethodCodec for BytesCodec {
+    type Request = Bytes;
+    type Response = Bytes;
+    type Error = ();
+
+    fn decode(&self, buf: Bytes) -> Result<Self::Request, Self::Error> {
+        Ok(buf)
+    }
+
+    fn encode(&self, msg: Self::Response) -> Result<Bytes, Self::Error> {
+        Ok(msg)
+    }
+}
+
 /// Codec for protobuf messages
 #[derive(Clone, Debug)]
 pub struct ProtobufCodec<T, U> {
@@ -0,0 +1,134 @@
+//! Compression of message bodies
+//!
+//! The compression of a brpc package is given by the `compress_type` of its
+//! meta. The values and formats are compatible with brpc, so that compressed
+//! packages can be exchanged with brpc:
+//!
+//! * snappy, in the raw format without framing;
+//! * gzip, with the gzip header and trailer;
+//! * zlib, with the zlib header and trailer.
+//!
+//! brpc also reserves a value for lz4, which is not supported.
+
+use flate2::Compression;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use snap;
+use std::io::{self, Read, Write};
+
+/// No compression
+const COMPRESS_TYPE_NONE: i32 = 0;
+const COMPRESS_TYPE_SNAPPY: i32 = 1;
+const COMPRESS_TYPE_GZIP: i32 = 2;
+const COMPRESS_TYPE_ZLIB: i32 = 3;
+
+/// Compression algorithm of a message body
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressType {
+    /// Snappy
+    Snappy,
+    /// Gzip
+    Gzip,
+    /// Zlib
+    Zlib,
+}
+
+impl CompressType {
+    /// Get the compression from the `compress_type` of brpc meta.
+    ///
+    /// Returns `Ok(None)` if the body is not compressed, or an error if the
+    /// compression is not supported.
+    pub fn from_meta(value: i32) -> io::Result<Option<Self>> {
+        match value {
+            COMPRESS_TYPE_NONE => Ok(None),
+            COMPRESS_TYPE_SNAPPY => Ok(Some(CompressType::Snappy)),
+            COMPRESS_TYPE_GZIP => Ok(Some(CompressType::Gzip)),
+            COMPRESS_TYPE_ZLIB => Ok(Some(CompressType::Zlib)),
+            value => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported compress type {}", value),
+            )),
+        }
+    }
+
+    /// The value of `compress_type` in brpc meta.
+    pub fn to_meta(self) -> i32 {
+        match self {
+            CompressType::Snappy => COMPRESS_TYPE_SNAPPY,
+            CompressType::Gzip => COMPRESS_TYPE_GZIP,
+            CompressType::Zlib => COMPRESS_TYPE_ZLIB,
+        }
+    }
+
+    /// Compress `data`.
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            CompressType::Snappy => snap::raw::Encoder::new()
+                .compress_vec(data)
+                .map_err(io::Error::from),
+            CompressType::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            CompressType::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    /// Decompress `data`.
+    pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        match self {
+            CompressType::Snappy => {
+                decompressed = snap::raw::Decoder::new()
+                    .decompress_vec(data)
+                    .map_err(io::Error::from)?;
+            }
+            CompressType::Gzip => {
+                GzDecoder::new(data).read_to_end(&mut decompressed)?;
+            }
+            CompressType::Zlib => {
+                ZlibDecoder::new(data).read_to_end(&mut decompressed)?;
+            }
+        }
+        Ok(decompressed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ALL: &[CompressType] = &[CompressType::Snappy, CompressType::Gzip, CompressType::Zlib];
+
+    #[test]
+    fn round_trip() {
+        let data = b"hello hello hello hello hello".to_vec();
+        for &compress in ALL {
+            let compressed = compress.compress(&data).unwrap();
+            assert_eq!(compress.decompress(&compressed).unwrap(), data);
+            let value = compress.to_meta();
+            assert_eq!(CompressType::from_meta(value).unwrap(), Some(compress));
+        }
+        assert_eq!(CompressType::from_meta(0).unwrap(), None);
+        // lz4 in brpc
+        assert!(CompressType::from_meta(4).is_err());
+    }
+
+    #[test]
+    fn formats_match_brpc() {
+        // raw snappy: the varint length, then a literal
+        let compressed = CompressType::Snappy.compress(b"hello").unwrap();
+        assert_eq!(compressed, b"\x05\x10hello");
+        let compressed = CompressType::Gzip.compress(b"hello").unwrap();
+        assert_eq!(&compressed[..2], b"\x1f\x8b");
+        let compressed = CompressType::Zlib.compress(b"hello").unwrap();
+        assert_eq!(compressed[0], 0x78);
+
+        assert!(CompressType::Gzip.decompress(b"hello").is_err());
+    }
+}
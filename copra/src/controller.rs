@@ -1,8 +1,13 @@
 //! [WIP] Service controller
 
+use futures::IntoFuture;
+use futures_cpupool::CpuFuture;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
+use compress::CompressType;
 use protocol::http::HttpStatus;
+use server::{AuthContext, BlockingPool};
 
 /// Expose more message details to service provider, and help to process
 /// http requests.
@@ -18,6 +23,27 @@ pub struct Controller {
     pub request_body: Vec<u8>,
     /// Response body in raw bytes
     pub response_body: Vec<u8>,
+    /// Credential sent with the request, i.e. the authentication data in brpc
+    /// meta, or the `Authorization` header in http
+    pub authentication_data: Vec<u8>,
+    /// Identity of the client, set if the server has an authenticator
+    pub auth_context: Option<AuthContext>,
+    /// When the client gives up on the request, set from the timeout in
+    /// brpc meta. On the client, the deadline of the call.
+    pub deadline: Option<Instant>,
+    /// Thread pool for blocking work, set if the server has one
+    pub blocking_pool: Option<BlockingPool>,
+    /// Compression of the request body, set from the compress type in brpc
+    /// meta. The client also accepts responses compressed this way. On the
+    /// client, the request body is compressed this way before it is sent.
+    pub request_compress_type: Option<CompressType>,
+    /// Compression of the response body, set by the handler to compress the
+    /// response. Only the brpc protocol compresses.
+    pub response_compress_type: Option<CompressType>,
+    /// Id of the request in the server logs, the log id in brpc meta if the
+    /// client sends one, otherwise generated by the server. On the client,
+    /// sent as the log id unless it is zero.
+    pub request_id: u64,
 }
 
 impl Controller {
@@ -26,4 +52,42 @@ impl Controller {
         self.headers
             .insert("Content-Type".to_string(), s.to_string());
     }
+
+    /// Run `f` on the blocking pool of the server.
+    ///
+    /// Handlers calling blocking code should use this method, so that other
+    /// requests are not stalled on the event loop.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server is not built with [`ServerBuilder::blocking_pool`].
+    ///
+    /// [`ServerBuilder::blocking_pool`]: ../server/struct.ServerBuilder.html#method.blocking_pool
+    pub fn spawn_blocking<F, R>(&self, f: F) -> CpuFuture<R::Item, R::Error>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoFuture + 'static,
+        R::Future: Send + 'static,
+        R::Item: Send + 'static,
+        R::Error: Send + 'static,
+    {
+        self.blocking_pool
+            .as_ref()
+            .expect("the server has no blocking pool, see ServerBuilder::blocking_pool")
+            .spawn_fn(f)
+    }
+
+    /// Time left before the deadline, zero if it has passed.
+    ///
+    /// Returns `None` if the request has no deadline.
+    pub fn remaining_time(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| {
+            let now = Instant::now();
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
 }
@@ -1,16 +1,22 @@
 //! Find method by service name and method name
 
+use bytes::Bytes;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::{Arc, RwLock};
 
-use service::{EncapService, NewEncapService};
+use controller::Controller;
+use service::{EncapService, MethodFuture, NewEncapService};
 
 /// Manage service registration and request dispatch
 ///
 /// This struct is required to build a server. Service providers should add
 /// their services to this struct.
+#[derive(Clone)]
 pub struct ServiceRegistry {
-    registry: HashMap<String, HashMap<String, NewEncapService>>,
+    registry: HashMap<String, Arc<HashMap<String, NewEncapService>>>,
+    descriptors: HashMap<String, Vec<u8>>,
+    default_handler: Option<Arc<DefaultHandler>>,
 }
 
 impl fmt::Debug for ServiceRegistry {
@@ -30,6 +36,8 @@ impl ServiceRegistry {
     pub fn new() -> Self {
         ServiceRegistry {
             registry: HashMap::new(),
+            descriptors: HashMap::new(),
+            default_handler: None,
         }
     }
 
@@ -38,11 +46,75 @@ impl ServiceRegistry {
     where
         T: NamedRegistrant,
     {
+        let name = <T as NamedRegistrant>::name().to_string();
         let mut map = HashMap::new();
         for (method_name, encap) in registrant.methods().into_iter() {
             map.insert(method_name, encap);
         }
-        self.registry.insert(<T as NamedRegistrant>::name().to_string(), map);
+        match registrant.file_descriptor() {
+            Some(descriptor) => self.descriptors.insert(name.clone(), descriptor),
+            None => self.descriptors.remove(&name),
+        };
+        self.registry.insert(name, Arc::new(map));
+    }
+
+    /// Remove a service from the registry.
+    ///
+    /// Returns `false` if the service is not registered.
+    pub fn unregister_service(&mut self, service_name: &str) -> bool {
+        self.descriptors.remove(service_name);
+        self.registry.remove(service_name).is_some()
+    }
+
+    /// Get the names of the registered services, in alphabetical order.
+    pub fn services(&self) -> Vec<&str> {
+        let mut services: Vec<_> = self.registry.keys().map(|s| s.as_str()).collect();
+        services.sort();
+        services
+    }
+
+    /// Get the method names of a service, in alphabetical order.
+    ///
+    /// Returns `None` if the service is not registered.
+    pub fn methods(&self, service_name: &str) -> Option<Vec<&str>> {
+        self.registry.get(service_name).map(|methods| {
+            let mut methods: Vec<_> = methods.keys().map(|s| s.as_str()).collect();
+            methods.sort();
+            methods
+        })
+    }
+
+    /// Get the serialized `FileDescriptorProto` of the file defining a
+    /// service.
+    ///
+    /// Returns `None` if the service is not registered, or its registrant
+    /// does not provide the descriptor.
+    pub fn file_descriptor(&self, service_name: &str) -> Option<&[u8]> {
+        self.descriptors.get(service_name).map(|d| d.as_slice())
+    }
+
+    /// Whether a service is registered.
+    pub fn has_service(&self, service_name: &str) -> bool {
+        self.registry.contains_key(service_name)
+    }
+
+    /// Serve the requests to services or methods not in the registry with
+    /// `handler`, instead of answering with an error.
+    ///
+    /// Replaces the handler set before.
+    pub fn set_default_handler<H>(&mut self, handler: H)
+    where
+        H: DefaultHandler + 'static,
+    {
+        self.default_handler = Some(Arc::new(handler));
+    }
+
+    /// Get the handler of requests to unknown services or methods, if any.
+    pub fn default_handler(&self) -> Option<&DefaultHandler> {
+        match self.default_handler {
+            Some(ref handler) => Some(&**handler),
+            None => None,
+        }
     }
 
     /// Get a method by service name and method name.
@@ -56,6 +128,142 @@ impl ServiceRegistry {
     }
 }
 
+/// Handle to change the services of a running server
+///
+/// Obtained from [`Server::registry_handle`] or
+/// [`ServerHandle::registry_handle`]. Changes take effect for the requests
+/// that arrive afterwards, requests already dispatched keep running on the
+/// old method. The handle can be cloned and sent to other threads.
+///
+/// Every change copies the registry and swaps it in, so that serving a
+/// request only takes a snapshot of the current registry, without holding
+/// a lock while the method runs. Changes are expected to be rare.
+///
+/// [`Server::registry_handle`]: ../server/struct.Server.html#method.registry_handle
+/// [`ServerHandle::registry_handle`]: ../server/struct.ServerHandle.html#method.registry_handle
+#[derive(Clone, Debug)]
+pub struct RegistryHandle {
+    current: Arc<RwLock<Arc<ServiceRegistry>>>,
+}
+
+impl RegistryHandle {
+    pub(crate) fn new(registry: ServiceRegistry) -> Self {
+        RegistryHandle {
+            current: Arc::new(RwLock::new(Arc::new(registry))),
+        }
+    }
+
+    /// Get the registry as it is now.
+    pub fn snapshot(&self) -> Arc<ServiceRegistry> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Add a new service.
+    ///
+    /// Returns `false` and leaves the registry unchanged if a service with
+    /// the same name is registered, use [`replace_service`] to swap it.
+    ///
+    /// [`replace_service`]: #method.replace_service
+    pub fn register_service<T>(&self, registrant: T) -> bool
+    where
+        T: NamedRegistrant,
+    {
+        self.update(|registry| {
+            if registry.has_service(<T as NamedRegistrant>::name()) {
+                false
+            } else {
+                registry.register_service(registrant);
+                true
+            }
+        })
+    }
+
+    /// Add a service, replacing the one with the same name.
+    ///
+    /// Returns `true` if a service is replaced.
+    pub fn replace_service<T>(&self, registrant: T) -> bool
+    where
+        T: NamedRegistrant,
+    {
+        self.update(|registry| {
+            let replaced = registry.has_service(<T as NamedRegistrant>::name());
+            registry.register_service(registrant);
+            replaced
+        })
+    }
+
+    /// Remove a service.
+    ///
+    /// Returns `false` if the service is not registered.
+    pub fn unregister_service(&self, service_name: &str) -> bool {
+        self.update(|registry| registry.unregister_service(service_name))
+    }
+
+    fn update<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut ServiceRegistry) -> R,
+    {
+        let mut current = self.current.write().unwrap();
+        let mut registry = ServiceRegistry::clone(&current);
+        let result = f(&mut registry);
+        *current = Arc::new(registry);
+        result
+    }
+}
+
+/// Handler of the requests to services or methods not in the registry
+///
+/// Set by [`ServiceRegistry::set_default_handler`]. The handler receives the
+/// requested names and the raw request body, and answers with the raw
+/// response body, e.g. to forward the request to another server.
+///
+/// # Examples
+///
+/// Echo the names and the body back:
+///
+/// ```
+/// # extern crate bytes;
+/// # extern crate copra;
+/// # extern crate futures;
+/// use bytes::Bytes;
+/// use copra::{Controller, ServiceRegistry};
+/// use copra::dispatcher::DefaultHandler;
+/// use copra::service::MethodFuture;
+/// use futures::future;
+///
+/// struct Mirror;
+///
+/// impl DefaultHandler for Mirror {
+///     fn call(&self, service: &str, method: &str, body: Bytes, ctrl: Controller) -> MethodFuture {
+///         let mut resp = format!("{}.{}:", service, method).into_bytes();
+///         resp.extend_from_slice(&body);
+///         Box::new(future::ok((Bytes::from(resp), ctrl)))
+///     }
+/// }
+///
+/// # fn main() {
+/// let mut registry = ServiceRegistry::new();
+/// registry.set_default_handler(Mirror);
+/// # }
+/// ```
+///
+/// [`ServiceRegistry::set_default_handler`]: struct.ServiceRegistry.html#method.set_default_handler
+pub trait DefaultHandler: Send + Sync {
+    /// Serve a request to `method_name` of `service_name`.
+    fn call(
+        &self,
+        service_name: &str,
+        method_name: &str,
+        body: Bytes,
+        controller: Controller,
+    ) -> MethodFuture;
+}
+
+impl fmt::Debug for DefaultHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DefaultHandler")
+    }
+}
 
 /// Link method names with methods
 /// 
@@ -64,6 +272,12 @@ impl ServiceRegistry {
 pub trait Registrant {
     /// Get a list of name-method pairs.
     fn methods(&self) -> Vec<(String, NewEncapService)>;
+
+    /// Get the serialized `FileDescriptorProto` of the file defining the
+    /// service, which is served by the reflection service.
+    fn file_descriptor(&self) -> Option<Vec<u8>> {
+        None
+    }
 }
 
 /// Link service name to a registrant
@@ -0,0 +1,66 @@
+//! Error codes carried in `RpcResponseMeta`
+//!
+//! The values are compatible with brpc, so that errors can be understood by
+//! both sides when talking to brpc. Codes only used by copra are marked.
+
+/// The request is processed successfully.
+pub const SUCCESS: i32 = 0;
+
+/// Unclassified error.
+pub const EUNKNOWN: i32 = 1;
+
+/// The requested service is not found.
+pub const ENOSERVICE: i32 = 1001;
+
+/// The requested method is not found.
+pub const ENOMETHOD: i32 = 1002;
+
+/// The request is malformed, e.g. the body can not be decoded.
+pub const EREQUEST: i32 = 1003;
+
+/// The request is rejected by the authenticator of the server.
+pub const ERPCAUTH: i32 = 1004;
+
+/// The response is malformed, e.g. the body can not be decoded. Not sent by
+/// servers, it marks the calls failed on the client side.
+pub const ERESPONSE: i32 = 1005;
+
+/// The request did not finish before its deadline.
+pub const ERPCTIMEDOUT: i32 = 1008;
+
+/// The connection to the server is broken. Not sent by servers, it marks
+/// the calls failed on the client side.
+pub const EFAILEDSOCKET: i32 = 1009;
+
+/// Too many calls are waiting to be written or answered on the channel.
+/// Not sent by servers, it marks the calls failed on the client side.
+pub const EOVERCROWDED: i32 = 1011;
+
+/// The service handler failed to process the request.
+pub const EINTERNAL: i32 = 2001;
+
+/// The server reached a limit on resources, e.g. the concurrency limit of
+/// the method.
+pub const ELIMIT: i32 = 2004;
+
+/// The channel is shut down. Not sent by servers, it marks the calls failed
+/// on the client side.
+pub const ECLOSE: i32 = 2005;
+
+/// (copra only) The service handler panicked.
+pub const EPANIC: i32 = 2100;
+
+/// (copra only) The request exceeds the size limit of the server.
+pub const ETOOLARGE: i32 = 2101;
+
+/// (copra only) The client sent more requests than its rate limit allows.
+pub const ERATELIMIT: i32 = 2102;
+
+/// (copra only) The response exceeds the size limit of the channel. Not
+/// sent by servers, it marks the calls failed on the client side.
+pub const ERESPONSETOOLARGE: i32 = 2103;
+
+/// (copra only) As many calls as the concurrency limit of the channel
+/// allows are in flight. Not sent by servers, it marks the calls failed on
+/// the client side.
+pub const ECONCURRENCYLIMIT: i32 = 2104;
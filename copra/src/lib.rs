@@ -19,6 +19,9 @@
 //! `proto-3.*.*-your-arch.zip` (`copra` needs protocol version 3), extract the
 //! `protoc` executable to a folder you like, then add `protoc` to your `PATH`.
 //!
+//! If you can not install `protoc`, enable the `vendored-protoc` feature of
+//! `protoc-rust-copra`, which falls back to a bundled `protoc` binary.
+//!
 //! [protobuf]: https://developers.google.com/protocol-buffers/
 //! [this website]: https://github.com/google/protobuf/releases
 //!
@@ -36,6 +39,17 @@
 //! protoc-rust-copra = "0.1"
 //! ```
 //!
+//! ## Optional features
+//!
+//! - `tls`: serve TLS connections with [`ServerBuilder::tls`], and connect
+//!   channels over TLS with [`ChannelBuilder::tls`], backed by [native-tls].
+//!   The `native_tls` crate is re-exported for building the acceptor and
+//!   loading certificates.
+//!
+//! [`ServerBuilder::tls`]: server/struct.ServerBuilder.html#method.tls
+//! [`ChannelBuilder::tls`]: channel/struct.ChannelBuilder.html#method.tls
+//! [native-tls]: https://crates.io/crates/native-tls
+//!
 //! # Examples
 //!
 //! Here is an example of implementing an echo RPC. First, create a file named
@@ -84,7 +98,6 @@
 //!
 //! use copra::{ChannelBuilder, Controller, MethodError, ServerBuilder, ServiceRegistry};
 //! use futures::future::{self, Future, FutureResult};
-//! use std::thread;
 //! use tokio_core::reactor::Core;
 //!
 //! use protos::echo::EchoMessage;
@@ -124,20 +137,21 @@
 //!     let addr = "127.0.0.1:8989";
 //!
 //!     // server side
-//!     thread::spawn(move || {
-//!         // register the service provider, so that it can be accessed
-//!         let registrant = EchoRegistrant::new(Echo);
-//!         let mut registry = ServiceRegistry::new();
-//!         registry.register_service(registrant);
+//!     // register the service provider, so that it can be accessed
+//!     let registrant = EchoRegistrant::new(Echo);
+//!     let mut registry = ServiceRegistry::new();
+//!     registry.register_service(registrant);
 //!
-//!         let server = ServerBuilder::new(addr, registry).build().unwrap();
-//!         server.start();
-//!     });
+//!     // the server is listening when `start_background` returns
+//!     let server = ServerBuilder::new(addr, registry)
+//!         .build()
+//!         .unwrap()
+//!         .start_background();
 //!
 //!     // client side
 //!     let mut core = Core::new().unwrap();
 //!     let handle = core.handle();
-//!     let channel = core.run(ChannelBuilder::single_server(addr, handle).build())
+//!     let channel = core.run(ChannelBuilder::single_server_spawned(addr, &handle, |b| b))
 //!         .unwrap();
 //!     let stub = EchoStub::new(&channel);
 //!
@@ -149,6 +163,8 @@
 //!
 //!     let (response, _info) = core.run(stub.reverse_echo(request)).unwrap();
 //!     println!("{}", response.msg);
+//!
+//!     server.stop().unwrap();
 //! }
 //! ```
 //!
@@ -160,6 +176,11 @@
 //! ```
 //!
 //! [build-scripts]: https://doc.rust-lang.org/cargo/reference/build-scripts.html
+//!
+//! Synchronous programs can call services without running a `Core`
+//! themselves, through a [`SyncChannel`] and `EchoStub::new_sync`.
+//!
+//! [`SyncChannel`]: sync/struct.SyncChannel.html
 //! 
 //! # Note
 //! 
@@ -169,18 +190,30 @@
 #![warn(missing_docs, missing_debug_implementations)]
 
 extern crate bytes;
+extern crate flate2;
 #[macro_use]
 extern crate futures;
+extern crate futures_cpupool;
 extern crate httparse;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "tls")]
+pub extern crate native_tls;
+extern crate net2;
 extern crate protobuf;
+#[cfg(all(unix, feature = "signals"))]
+extern crate signal_hook;
 extern crate smallvec;
+extern crate snap;
 extern crate tokio_core;
 extern crate tokio_io;
 extern crate tokio_proto;
 extern crate tokio_service;
 extern crate tokio_timer;
+#[cfg(feature = "tls")]
+extern crate tokio_tls;
+#[cfg(unix)]
+extern crate tokio_uds;
 extern crate url;
 
 #[cfg(test)]
@@ -188,18 +221,24 @@ extern crate rand;
 
 pub use channel::ChannelBuilder;
 pub use controller::Controller;
-pub use dispatcher::ServiceRegistry;
+pub use dispatcher::{RegistryHandle, ServiceRegistry};
 pub use server::ServerBuilder;
 pub use service::MethodError;
 
+mod timer;
+
 pub mod channel;
 pub mod controller;
 pub mod codec;
+pub mod compress;
 pub mod dispatcher;
+pub mod errno;
 pub mod load_balancer;
 pub mod message;
+pub mod naming;
 pub mod protocol;
 pub mod service;
 pub mod stub;
 pub mod server;
 pub mod monitor;
+pub mod sync;
@@ -0,0 +1,230 @@
+//! Consistent hash load balancing
+
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{BackendInfo, LbHint, LoadBalance};
+
+/// Number of points every server has on the ring by default
+pub const DEFAULT_VIRTUAL_NODES: usize = 160;
+
+/// A 64-bit FNV-1a hasher with a final mix
+///
+/// Unlike `DefaultHasher`, the hash of a key is the same in every process and
+/// with every version of Rust, so clients agree on the server of a key.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct KeyHasher(u64);
+
+impl Default for KeyHasher {
+    fn default() -> Self {
+        KeyHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for KeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        // FNV spreads short keys poorly over the high bits, which decide
+        // the position on the ring
+        let mut h = self.0;
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        h ^ (h >> 33)
+    }
+}
+
+/// Get the hash of `key` used by [`LbHint::from_key`].
+///
+/// [`LbHint::from_key`]: ../struct.LbHint.html#method.from_key
+pub(crate) fn hash_key<K: Hash + ?Sized>(key: &K) -> u64 {
+    let mut hasher = KeyHasher::default();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug, Default)]
+struct Ring {
+    // the servers the ring is built for
+    addrs: Vec<SocketAddr>,
+    // points on the ring and the index of their server, sorted by point
+    points: Vec<(u64, usize)>,
+}
+
+impl Ring {
+    fn build(backends: &[BackendInfo], virtual_nodes: usize) -> Self {
+        let addrs: Vec<_> = backends.iter().map(BackendInfo::addr).collect();
+        let mut points = Vec::with_capacity(addrs.len() * virtual_nodes);
+        for (i, addr) in addrs.iter().enumerate() {
+            for node in 0..virtual_nodes {
+                points.push((hash_key(&(addr, node)), i));
+            }
+        }
+        points.sort();
+        Ring { addrs, points }
+    }
+
+    fn matches(&self, backends: &[BackendInfo]) -> bool {
+        self.addrs.len() == backends.len()
+            && self.addrs
+                .iter()
+                .zip(backends)
+                .all(|(addr, backend)| *addr == backend.addr())
+    }
+
+    /// Find the first connected server at or after `key` on the ring.
+    fn lookup(&self, key: u64, backends: &[BackendInfo]) -> Option<usize> {
+        let start = match self.points.binary_search(&(key, 0)) {
+            Ok(pos) | Err(pos) => pos,
+        };
+        let len = self.points.len();
+        (0..len)
+            .map(|i| self.points[(start + i) % len].1)
+            .find(|&i| backends[i].is_connected())
+    }
+}
+
+/// Send the calls with the same key to the same server
+///
+/// The key of a call is set by [`CallOptions::hash_key`]. Every server is
+/// placed at a number of points on a hash ring, and a call goes to the
+/// server of the first point at or after its key. When that server is not
+/// connected the ring is walked on to the next connected one, so only the
+/// keys of a down server move, and they spread over all the others. Calls
+/// without a key are spread over the ring in turn.
+///
+/// Points are derived from server addresses, so channels to the same
+/// servers agree on the server of a key.
+///
+/// [`CallOptions::hash_key`]: ../../stub/struct.CallOptions.html#method.hash_key
+#[derive(Debug)]
+pub struct ConsistentHash {
+    virtual_nodes: usize,
+    ring: Mutex<Ring>,
+    next: AtomicUsize,
+}
+
+impl ConsistentHash {
+    /// Create a new instance with [`DEFAULT_VIRTUAL_NODES`] points per
+    /// server.
+    ///
+    /// [`DEFAULT_VIRTUAL_NODES`]: constant.DEFAULT_VIRTUAL_NODES.html
+    pub fn new() -> Self {
+        ConsistentHash::with_virtual_nodes(DEFAULT_VIRTUAL_NODES)
+    }
+
+    /// Create a new instance with `virtual_nodes` points per server.
+    ///
+    /// More points spread the keys more evenly, at the cost of memory and a
+    /// slower lookup. At least one point is used.
+    pub fn with_virtual_nodes(virtual_nodes: usize) -> Self {
+        ConsistentHash {
+            virtual_nodes: virtual_nodes.max(1),
+            ring: Mutex::new(Ring::default()),
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Default for ConsistentHash {
+    fn default() -> Self {
+        ConsistentHash::new()
+    }
+}
+
+impl LoadBalance for ConsistentHash {
+    fn select(&self, backends: &[BackendInfo], hint: Option<&LbHint>) -> Option<usize> {
+        let key = match hint {
+            Some(hint) => hint.key(),
+            None => hash_key(&self.next.fetch_add(1, Ordering::SeqCst)),
+        };
+        let mut ring = self.ring.lock().unwrap();
+        if !ring.matches(backends) {
+            *ring = Ring::build(backends, self.virtual_nodes);
+        }
+        ring.lookup(key, backends)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn backends(connected: &[bool]) -> Vec<BackendInfo> {
+        connected
+            .iter()
+            .enumerate()
+            .map(|(i, &up)| BackendInfo::new(([127, 0, 0, 1], 8000 + i as u16).into(), up, 0))
+            .collect()
+    }
+
+    fn select(lb: &ConsistentHash, backends: &[BackendInfo], key: u64) -> usize {
+        lb.select(backends, Some(&LbHint::from_key(&key))).unwrap()
+    }
+
+    #[test]
+    fn same_key_same_server() {
+        let servers = backends(&[true; 4]);
+        let lb = ConsistentHash::new();
+        let other = ConsistentHash::new();
+        let mut counts = [0; 4];
+        for key in 0..1000 {
+            let selected = select(&lb, &servers, key);
+            assert_eq!(select(&lb, &servers, key), selected);
+            assert_eq!(select(&other, &servers, key), selected);
+            counts[selected] += 1;
+        }
+        // every server gets a fair share
+        assert!(counts.iter().all(|&n| n > 150 && n < 350), "{:?}", counts);
+    }
+
+    #[test]
+    fn only_keys_of_removed_server_move() {
+        let lb = ConsistentHash::new();
+        let all = backends(&[true; 4]);
+        let before: Vec<_> = (0..1000).map(|key| select(&lb, &all, key)).collect();
+
+        // server 2 goes down, the ring is walked past it
+        let down = backends(&[true, true, false, true]);
+        // server 2 is removed, the ring is built again without it
+        let mut removed = all.clone();
+        removed.remove(2);
+        for key in 0..1000 {
+            let was = before[key as usize];
+            let now_down = select(&lb, &down, key);
+            let now_removed = select(&lb, &removed, key);
+            let now_removed = if now_removed >= 2 { now_removed + 1 } else { now_removed };
+            if was != 2 {
+                assert_eq!(now_down, was);
+                assert_eq!(now_removed, was);
+            } else {
+                assert_ne!(now_down, 2);
+                assert_eq!(now_removed, now_down);
+            }
+        }
+        let moved = before.iter().filter(|&&s| s == 2).count();
+        assert!(moved < 350, "{} keys moved", moved);
+    }
+
+    #[test]
+    fn without_key_spread_and_all_down() {
+        let lb = ConsistentHash::with_virtual_nodes(0);
+        let servers = backends(&[true; 4]);
+        let mut counts = [0; 4];
+        for _ in 0..400 {
+            counts[lb.select(&servers, None).unwrap()] += 1;
+        }
+        assert!(counts.iter().all(|&n| n > 0), "{:?}", counts);
+        assert_eq!(lb.select(&backends(&[false; 4]), None), None);
+        assert_eq!(lb.select(&[], None), None);
+    }
+}
@@ -1,27 +1,69 @@
-//! [WIP] Load balancer traits and algorithms
+//! Load balancer traits and algorithms
+//!
+//! A channel connected to several servers asks its [`LoadBalance`] policy
+//! which server every call goes to. [`RoundRobin`] is the default, and a
+//! policy is chosen by [`ChannelBuilder::load_balancer`].
+//!
+//! [`LoadBalance`]: trait.LoadBalance.html
+//! [`RoundRobin`]: round_robin/struct.RoundRobin.html
+//! [`ChannelBuilder::load_balancer`]: ../channel/struct.ChannelBuilder.html#method.load_balancer
 
-use tokio_core::net::TcpStream;
-use tokio_proto::multiplex::ClientService;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use futures::{Async, Future, Poll};
+use tokio_proto::multiplex::{ClientService, RequestId};
 use tokio_service::Service;
 
-use channel::MetaClientProtocol;
+use channel::{AckSender, Counted, MetaClientProtocol};
+use channel::connector::{BoxStream, Link};
 use service::MethodError;
 
-pub mod single_server;
+pub use self::consistent_hash::ConsistentHash;
+pub use self::random::Random;
+pub use self::round_robin::RoundRobin;
+pub use self::weighted_round_robin::WeightedRoundRobin;
 
-type InnerService = ClientService<TcpStream, MetaClientProtocol>;
+pub mod consistent_hash;
+pub mod random;
+pub mod round_robin;
+pub mod weighted_round_robin;
+
+type InnerService = ClientService<BoxStream, MetaClientProtocol>;
 
 /// Server ID
 pub type ServerId = u64;
 
 
 /// Represent a load lalancing unit
-#[derive(Debug)]
-pub struct ServerEndPort(InnerService);
+#[derive(Clone, Debug)]
+pub struct ServerEndPort {
+    service: InnerService,
+    link: Arc<Link>,
+}
 
 impl ServerEndPort {
-    pub(crate) fn new(service: InnerService) -> Self {
-        ServerEndPort(service)
+    pub(crate) fn new(service: InnerService, link: Arc<Link>) -> Self {
+        ServerEndPort { service, link }
+    }
+
+    /// Check if the connection to the server is currently up.
+    pub fn is_connected(&self) -> bool {
+        self.link.is_connected()
+    }
+
+    /// Close the connection once every handle to it is dropped, even if
+    /// calls are still in flight.
+    pub(crate) fn close(&self) {
+        self.link.close();
+    }
+
+    /// Get the shared state of the connection.
+    pub(crate) fn link(&self) -> &Arc<Link> {
+        &self.link
     }
 }
 
@@ -29,10 +71,78 @@ impl Service for ServerEndPort {
     type Request = <InnerService as Service>::Request;
     type Response = <InnerService as Service>::Response;
     type Error = <InnerService as Service>::Error;
-    type Future = <InnerService as Service>::Future;
+    type Future = CallFuture;
 
     fn call(&self, req: Self::Request) -> Self::Future {
-        self.0.call(req)
+        let id = self.link.next_request_id();
+        CallFuture {
+            inner: self.service.call(req),
+            link: self.link.clone(),
+            id,
+            done: false,
+        }
+    }
+}
+
+/// A request sent to a server, resolving to its response
+///
+/// Dropping it before the response arrives gives up the request: the
+/// connection forgets it at once, and discards its response. Neither
+/// protocol can tell the server, which still handles the request.
+pub struct CallFuture {
+    inner: <InnerService as Service>::Future,
+    link: Arc<Link>,
+    id: RequestId,
+    done: bool,
+}
+
+impl fmt::Debug for CallFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CallFuture")
+            .field("id", &self.id)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+impl CallFuture {
+    /// Check if any byte of the request is written to the connection. A
+    /// request which failed before it is sent was not seen by the server.
+    pub(crate) fn is_sent(&self) -> bool {
+        self.link.is_sent(self.id)
+    }
+
+    /// Count the call in `queued` until the request is written.
+    pub(crate) fn hold(&self, queued: Counted) {
+        self.link.hold(self.id, queued);
+    }
+
+    /// Tell `ack` once the request is written.
+    pub(crate) fn ack_when_sent(&self, ack: AckSender) {
+        self.link.ack_when_sent(self.id, ack);
+    }
+}
+
+impl Future for CallFuture {
+    type Item = <InnerService as Service>::Response;
+    type Error = <InnerService as Service>::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = self.inner.poll();
+        if let Ok(Async::NotReady) = result {
+            return result;
+        }
+        self.done = true;
+        self.link.release(self.id);
+        result
+    }
+}
+
+impl Drop for CallFuture {
+    fn drop(&mut self) {
+        if !self.done {
+            self.link.cancel(self.id);
+        }
     }
 }
 
@@ -52,12 +162,137 @@ impl CallInfo {
     }
 }
 
+/// The state of a server of a channel, as seen by the load balancer
+#[derive(Clone, Debug)]
+pub struct BackendInfo {
+    addr: SocketAddr,
+    connected: bool,
+    inflight: usize,
+    weight: u32,
+    tags: Option<Arc<HashMap<String, String>>>,
+}
 
-/// Something can serve as a load balancer
-pub trait LoadBalance {
-    /// Select a server to send request.
-    fn select_server(&mut self) -> (ServerId, &ServerEndPort);
+impl BackendInfo {
+    /// Create a new instance with weight 1.
+    pub fn new(addr: SocketAddr, connected: bool, inflight: usize) -> Self {
+        BackendInfo {
+            addr,
+            connected,
+            inflight,
+            weight: 1,
+            tags: None,
+        }
+    }
 
-    /// Update load balancing state.
-    fn feed_back(&mut self, id: ServerId, call_info: CallInfo);
+    /// Set the weight of the server.
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Set the tags of the server.
+    pub fn with_tags(mut self, tags: Arc<HashMap<String, String>>) -> Self {
+        self.tags = Some(tags);
+        self
+    }
+
+    /// Get the address of the server.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Check if the connection to the server is currently up.
+    ///
+    /// Calls sent to a server which is not connected fail at once.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Get the number of calls sent to the server and not answered yet.
+    pub fn inflight(&self) -> usize {
+        self.inflight
+    }
+
+    /// Get the weight of the server, 1 unless given to the builder by
+    /// [`ChannelBuilder::multi_server_weighted`] or by the naming service.
+    ///
+    /// [`ChannelBuilder::multi_server_weighted`]: ../channel/struct.ChannelBuilder.html#method.multi_server_weighted
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    /// Get the value of the tag `key` of the server, as given by the naming
+    /// service in [`ServerEndpoint::with_tag`].
+    ///
+    /// [`ServerEndpoint::with_tag`]: ../naming/struct.ServerEndpoint.html#method.with_tag
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .as_ref()
+            .and_then(|tags| tags.get(key))
+            .map(String::as_str)
+    }
+}
+
+/// A hint passed with a call to the load balancer
+///
+/// It is set by [`CallOptions::lb_hint`], e.g. to the hash of a key for
+/// consistent hashing. Of the built-in policies only [`ConsistentHash`]
+/// reads it.
+///
+/// [`CallOptions::lb_hint`]: ../stub/struct.CallOptions.html#method.lb_hint
+/// [`ConsistentHash`]: consistent_hash/struct.ConsistentHash.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LbHint {
+    key: u64,
+}
+
+impl LbHint {
+    /// Create a new hint.
+    pub fn new(key: u64) -> Self {
+        LbHint { key }
+    }
+
+    /// Create a hint whose key is the hash of `key`.
+    ///
+    /// The hash is stable across processes and builds, so every client
+    /// sends the same key to the same server.
+    pub fn from_key<K: Hash + ?Sized>(key: &K) -> Self {
+        LbHint::new(consistent_hash::hash_key(key))
+    }
+
+    /// Get the key of the hint.
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+}
+
+/// A policy choosing the server of every call of a channel
+///
+/// The policy is shared by all clones of the channel, so it keeps its state
+/// behind atomics or locks.
+pub trait LoadBalance: Send + Sync {
+    /// Choose the server to send a call to, by its index in `backends`.
+    ///
+    /// `backends` are in the order the servers are given to the builder.
+    /// Returning `None`, or a server which is not connected, fails the call.
+    ///
+    /// The servers of a channel built by `ChannelBuilder::dns` change as
+    /// the name is resolved again, so indices passed to `on_success` and
+    /// `on_error` refer to the servers when the call is sent.
+    fn select(&self, backends: &[BackendInfo], hint: Option<&LbHint>) -> Option<usize>;
+
+    /// Called when a call sent to the server at index `backend` succeeds,
+    /// `latency` after it is sent.
+    fn on_success(&self, _backend: usize, _latency: Duration) {}
+
+    /// Called when a call sent to the server at index `backend` fails,
+    /// either with a broken connection or an error response, `latency` after
+    /// it is sent.
+    fn on_error(&self, _backend: usize, _latency: Duration) {}
+}
+
+impl fmt::Debug for LoadBalance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LoadBalance")
+    }
 }
@@ -0,0 +1,82 @@
+//! Random load balancing
+
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{BackendInfo, LbHint, LoadBalance};
+
+/// Send every call to a connected server chosen at random
+#[derive(Debug)]
+pub struct Random {
+    // state of a xorshift64* generator, never zero
+    state: Mutex<u64>,
+}
+
+impl Random {
+    /// Create a new instance seeded by the current time.
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_secs() ^ u64::from(t.subsec_nanos()))
+            .unwrap_or(0);
+        Random::with_seed(seed)
+    }
+
+    /// Create a new instance with a fixed seed, which selects the same
+    /// servers every time.
+    pub fn with_seed(seed: u64) -> Self {
+        Random {
+            state: Mutex::new(seed | 1),
+        }
+    }
+
+    pub(crate) fn next(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::new()
+    }
+}
+
+impl LoadBalance for Random {
+    fn select(&self, backends: &[BackendInfo], _: Option<&LbHint>) -> Option<usize> {
+        let connected: Vec<_> = (0..backends.len())
+            .filter(|&i| backends[i].is_connected())
+            .collect();
+        if connected.is_empty() {
+            return None;
+        }
+        let pick = (self.next() % connected.len() as u64) as usize;
+        Some(connected[pick])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_connected_servers_selected() {
+        let lb = Random::with_seed(42);
+        let backends: Vec<_> = (0..4)
+            .map(|i| BackendInfo::new(([127, 0, 0, 1], 8000 + i).into(), i != 2, 0))
+            .collect();
+        let mut selected = [0; 4];
+        for _ in 0..300 {
+            selected[lb.select(&backends, None).unwrap()] += 1;
+        }
+        assert_eq!(selected[2], 0);
+        for &count in &[selected[0], selected[1], selected[3]] {
+            assert!(count > 50, "{:?}", selected);
+        }
+        let down = vec![BackendInfo::new(([127, 0, 0, 1], 8000).into(), false, 0)];
+        assert_eq!(lb.select(&down, None), None);
+    }
+}
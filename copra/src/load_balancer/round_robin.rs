@@ -0,0 +1,58 @@
+//! Round-robin load balancing
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{BackendInfo, LbHint, LoadBalance};
+
+/// Send calls to the connected servers in turn
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl RoundRobin {
+    /// Create a new instance.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl LoadBalance for RoundRobin {
+    fn select(&self, backends: &[BackendInfo], _: Option<&LbHint>) -> Option<usize> {
+        let count = backends.len();
+        let start = self.next.load(Ordering::SeqCst);
+        let selected = (0..count)
+            .map(|i| (start + i) % count)
+            .find(|&i| backends[i].is_connected())?;
+        self.next.store(selected + 1, Ordering::SeqCst);
+        Some(selected)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn backends(connected: &[bool]) -> Vec<BackendInfo> {
+        connected
+            .iter()
+            .enumerate()
+            .map(|(i, &up)| BackendInfo::new(([127, 0, 0, 1], 8000 + i as u16).into(), up, 0))
+            .collect()
+    }
+
+    #[test]
+    fn connected_servers_in_turn() {
+        let lb = RoundRobin::new();
+        let backends = backends(&[true, false, true]);
+        let selected: Vec<_> = (0..4).map(|_| lb.select(&backends, None)).collect();
+        assert_eq!(selected, vec![Some(0), Some(2), Some(0), Some(2)]);
+    }
+
+    #[test]
+    fn nothing_selected_when_all_down() {
+        let lb = RoundRobin::new();
+        assert_eq!(lb.select(&backends(&[false, false]), None), None);
+        assert_eq!(lb.select(&[], None), None);
+    }
+}
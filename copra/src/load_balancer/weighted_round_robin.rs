@@ -0,0 +1,104 @@
+//! Smooth weighted round-robin load balancing
+
+use std::sync::Mutex;
+
+use super::{BackendInfo, LbHint, LoadBalance};
+
+/// Send calls to the connected servers in turn, in proportion to their
+/// weights
+///
+/// This is the smooth algorithm of nginx: calls to a heavy server are
+/// interleaved with the calls to the others instead of being sent in a
+/// burst, e.g. weights 5, 1 and 1 give the sequence `a a b a c a a`.
+/// Weights are read at every selection, so a changed weight applies to the
+/// next call. Servers of weight 0 are not selected.
+#[derive(Debug, Default)]
+pub struct WeightedRoundRobin {
+    // the current weight of every server, by index
+    current: Mutex<Vec<i64>>,
+}
+
+impl WeightedRoundRobin {
+    /// Create a new instance.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl LoadBalance for WeightedRoundRobin {
+    fn select(&self, backends: &[BackendInfo], _: Option<&LbHint>) -> Option<usize> {
+        let mut current = self.current.lock().unwrap();
+        // start over when the servers change
+        if current.len() != backends.len() {
+            *current = vec![0; backends.len()];
+        }
+
+        let mut total = 0;
+        let mut selected: Option<usize> = None;
+        for (i, backend) in backends.iter().enumerate() {
+            let weight = i64::from(backend.weight());
+            if !backend.is_connected() || weight == 0 {
+                continue;
+            }
+            current[i] += weight;
+            total += weight;
+            selected = match selected {
+                Some(best) if current[best] >= current[i] => Some(best),
+                _ => Some(i),
+            };
+        }
+        let selected = selected?;
+        current[selected] -= total;
+        Some(selected)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn backends(weights: &[u32]) -> Vec<BackendInfo> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| {
+                BackendInfo::new(([127, 0, 0, 1], 8000 + i as u16).into(), true, 0)
+                    .with_weight(weight)
+            })
+            .collect()
+    }
+
+    fn sequence(lb: &WeightedRoundRobin, backends: &[BackendInfo], n: usize) -> Vec<usize> {
+        (0..n).map(|_| lb.select(backends, None).unwrap()).collect()
+    }
+
+    #[test]
+    fn smooth_sequence() {
+        let lb = WeightedRoundRobin::new();
+        let servers = backends(&[5, 1, 1]);
+        assert_eq!(sequence(&lb, &servers, 7), vec![0, 0, 1, 0, 2, 0, 0]);
+        // the sequence repeats
+        assert_eq!(sequence(&lb, &servers, 7), vec![0, 0, 1, 0, 2, 0, 0]);
+
+        let lb = WeightedRoundRobin::new();
+        let servers = backends(&[4, 1]);
+        assert_eq!(sequence(&lb, &servers, 5), vec![0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn equal_weights_in_turn() {
+        let lb = WeightedRoundRobin::new();
+        assert_eq!(sequence(&lb, &backends(&[1, 1, 1]), 6), vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn down_and_zero_weight_skipped() {
+        let lb = WeightedRoundRobin::new();
+        let mut backends = backends(&[2, 0, 1]);
+        assert_eq!(sequence(&lb, &backends, 3), vec![0, 2, 0]);
+        backends[0] = BackendInfo::new(backends[0].addr(), false, 0).with_weight(2);
+        assert_eq!(sequence(&lb, &backends, 2), vec![2, 2]);
+        backends[2] = BackendInfo::new(backends[2].addr(), false, 0);
+        assert_eq!(lb.select(&backends, None), None);
+    }
+}
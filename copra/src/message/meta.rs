@@ -26,7 +26,9 @@ pub struct RpcMeta {
     // message fields
     pub request: ::protobuf::SingularPtrField<RpcRequestMeta>,
     pub response: ::protobuf::SingularPtrField<RpcResponseMeta>,
+    pub compress_type: i32,
     pub correlation_id: u64,
+    pub authentication_data: ::std::vec::Vec<u8>,
     // special fields
     unknown_fields: ::protobuf::UnknownFields,
     cached_size: ::protobuf::CachedSize,
@@ -132,6 +134,29 @@ impl RpcMeta {
         &mut self.response
     }
 
+    // int32 compress_type = 3;
+
+    pub fn clear_compress_type(&mut self) {
+        self.compress_type = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_compress_type(&mut self, v: i32) {
+        self.compress_type = v;
+    }
+
+    pub fn get_compress_type(&self) -> i32 {
+        self.compress_type
+    }
+
+    fn get_compress_type_for_reflect(&self) -> &i32 {
+        &self.compress_type
+    }
+
+    fn mut_compress_type_for_reflect(&mut self) -> &mut i32 {
+        &mut self.compress_type
+    }
+
     // uint64 correlation_id = 4;
 
     pub fn clear_correlation_id(&mut self) {
@@ -154,6 +179,40 @@ impl RpcMeta {
     fn mut_correlation_id_for_reflect(&mut self) -> &mut u64 {
         &mut self.correlation_id
     }
+
+    // bytes authentication_data = 7;
+
+    pub fn clear_authentication_data(&mut self) {
+        self.authentication_data.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_authentication_data(&mut self, v: ::std::vec::Vec<u8>) {
+        self.authentication_data = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_authentication_data(&mut self) -> &mut ::std::vec::Vec<u8> {
+        &mut self.authentication_data
+    }
+
+    // Take field
+    pub fn take_authentication_data(&mut self) -> ::std::vec::Vec<u8> {
+        ::std::mem::replace(&mut self.authentication_data, ::std::vec::Vec::new())
+    }
+
+    pub fn get_authentication_data(&self) -> &[u8] {
+        &self.authentication_data
+    }
+
+    fn get_authentication_data_for_reflect(&self) -> &::std::vec::Vec<u8> {
+        &self.authentication_data
+    }
+
+    fn mut_authentication_data_for_reflect(&mut self) -> &mut ::std::vec::Vec<u8> {
+        &mut self.authentication_data
+    }
 }
 
 impl ::protobuf::Message for RpcMeta {
@@ -181,6 +240,13 @@ impl ::protobuf::Message for RpcMeta {
                 2 => {
                     ::protobuf::rt::read_singular_message_into(wire_type, is, &mut self.response)?;
                 },
+                3 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_int32()?;
+                    self.compress_type = tmp;
+                },
                 4 => {
                     if wire_type != ::protobuf::wire_format::WireTypeVarint {
                         return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
@@ -188,6 +254,9 @@ impl ::protobuf::Message for RpcMeta {
                     let tmp = is.read_uint64()?;
                     self.correlation_id = tmp;
                 },
+                7 => {
+                    ::protobuf::rt::read_singular_proto3_bytes_into(wire_type, is, &mut self.authentication_data)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -208,9 +277,15 @@ impl ::protobuf::Message for RpcMeta {
             let len = v.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         }
+        if self.compress_type != 0 {
+            my_size += ::protobuf::rt::value_size(3, self.compress_type, ::protobuf::wire_format::WireTypeVarint);
+        }
         if self.correlation_id != 0 {
             my_size += ::protobuf::rt::value_size(4, self.correlation_id, ::protobuf::wire_format::WireTypeVarint);
         }
+        if !self.authentication_data.is_empty() {
+            my_size += ::protobuf::rt::bytes_size(7, &self.authentication_data);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -227,9 +302,15 @@ impl ::protobuf::Message for RpcMeta {
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         }
+        if self.compress_type != 0 {
+            os.write_int32(3, self.compress_type)?;
+        }
         if self.correlation_id != 0 {
             os.write_uint64(4, self.correlation_id)?;
         }
+        if !self.authentication_data.is_empty() {
+            os.write_bytes(7, &self.authentication_data)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -284,11 +365,21 @@ impl ::protobuf::MessageStatic for RpcMeta {
                     RpcMeta::get_response_for_reflect,
                     RpcMeta::mut_response_for_reflect,
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeInt32>(
+                    "compress_type",
+                    RpcMeta::get_compress_type_for_reflect,
+                    RpcMeta::mut_compress_type_for_reflect,
+                ));
                 fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
                     "correlation_id",
                     RpcMeta::get_correlation_id_for_reflect,
                     RpcMeta::mut_correlation_id_for_reflect,
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBytes>(
+                    "authentication_data",
+                    RpcMeta::get_authentication_data_for_reflect,
+                    RpcMeta::mut_authentication_data_for_reflect,
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<RpcMeta>(
                     "RpcMeta",
                     fields,
@@ -303,7 +394,9 @@ impl ::protobuf::Clear for RpcMeta {
     fn clear(&mut self) {
         self.clear_request();
         self.clear_response();
+        self.clear_compress_type();
         self.clear_correlation_id();
+        self.clear_authentication_data();
         self.unknown_fields.clear();
     }
 }
@@ -326,6 +419,8 @@ pub struct RpcRequestMeta {
     pub service_name: ::std::string::String,
     pub method_name: ::std::string::String,
     pub log_id: i64,
+    pub timeout_ms: i32,
+    pub oneway: bool,
     // special fields
     unknown_fields: ::protobuf::UnknownFields,
     cached_size: ::protobuf::CachedSize,
@@ -439,6 +534,52 @@ impl RpcRequestMeta {
     fn mut_log_id_for_reflect(&mut self) -> &mut i64 {
         &mut self.log_id
     }
+
+    // int32 timeout_ms = 8;
+
+    pub fn clear_timeout_ms(&mut self) {
+        self.timeout_ms = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_timeout_ms(&mut self, v: i32) {
+        self.timeout_ms = v;
+    }
+
+    pub fn get_timeout_ms(&self) -> i32 {
+        self.timeout_ms
+    }
+
+    fn get_timeout_ms_for_reflect(&self) -> &i32 {
+        &self.timeout_ms
+    }
+
+    fn mut_timeout_ms_for_reflect(&mut self) -> &mut i32 {
+        &mut self.timeout_ms
+    }
+
+    // bool oneway = 100;
+
+    pub fn clear_oneway(&mut self) {
+        self.oneway = false;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_oneway(&mut self, v: bool) {
+        self.oneway = v;
+    }
+
+    pub fn get_oneway(&self) -> bool {
+        self.oneway
+    }
+
+    fn get_oneway_for_reflect(&self) -> &bool {
+        &self.oneway
+    }
+
+    fn mut_oneway_for_reflect(&mut self) -> &mut bool {
+        &mut self.oneway
+    }
 }
 
 impl ::protobuf::Message for RpcRequestMeta {
@@ -463,6 +604,20 @@ impl ::protobuf::Message for RpcRequestMeta {
                     let tmp = is.read_int64()?;
                     self.log_id = tmp;
                 },
+                8 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_int32()?;
+                    self.timeout_ms = tmp;
+                },
+                100 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_bool()?;
+                    self.oneway = tmp;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -484,6 +639,12 @@ impl ::protobuf::Message for RpcRequestMeta {
         if self.log_id != 0 {
             my_size += ::protobuf::rt::value_size(3, self.log_id, ::protobuf::wire_format::WireTypeVarint);
         }
+        if self.timeout_ms != 0 {
+            my_size += ::protobuf::rt::value_size(8, self.timeout_ms, ::protobuf::wire_format::WireTypeVarint);
+        }
+        if self.oneway != false {
+            my_size += 3;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -499,6 +660,12 @@ impl ::protobuf::Message for RpcRequestMeta {
         if self.log_id != 0 {
             os.write_int64(3, self.log_id)?;
         }
+        if self.timeout_ms != 0 {
+            os.write_int32(8, self.timeout_ms)?;
+        }
+        if self.oneway != false {
+            os.write_bool(100, self.oneway)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -558,6 +725,16 @@ impl ::protobuf::MessageStatic for RpcRequestMeta {
                     RpcRequestMeta::get_log_id_for_reflect,
                     RpcRequestMeta::mut_log_id_for_reflect,
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeInt32>(
+                    "timeout_ms",
+                    RpcRequestMeta::get_timeout_ms_for_reflect,
+                    RpcRequestMeta::mut_timeout_ms_for_reflect,
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeBool>(
+                    "oneway",
+                    RpcRequestMeta::get_oneway_for_reflect,
+                    RpcRequestMeta::mut_oneway_for_reflect,
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<RpcRequestMeta>(
                     "RpcRequestMeta",
                     fields,
@@ -573,6 +750,8 @@ impl ::protobuf::Clear for RpcRequestMeta {
         self.clear_service_name();
         self.clear_method_name();
         self.clear_log_id();
+        self.clear_timeout_ms();
+        self.clear_oneway();
         self.unknown_fields.clear();
     }
 }
@@ -809,15 +988,18 @@ impl ::protobuf::reflect::ProtobufValue for RpcResponseMeta {
 }
 
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\x1ccopra/src/message/meta.proto\"\x89\x01\n\x07RpcMeta\x12)\n\x07requ\
+    \n\x1ccopra/src/message/meta.proto\"\xdf\x01\n\x07RpcMeta\x12)\n\x07requ\
     est\x18\x01\x20\x01(\x0b2\x0f.RpcRequestMetaR\x07request\x12,\n\x08respo\
-    nse\x18\x02\x20\x01(\x0b2\x10.RpcResponseMetaR\x08response\x12%\n\x0ecor\
-    relation_id\x18\x04\x20\x01(\x04R\rcorrelationId\"k\n\x0eRpcRequestMeta\
+    nse\x18\x02\x20\x01(\x0b2\x10.RpcResponseMetaR\x08response\x12#\n\rcompr\
+    ess_type\x18\x03\x20\x01(\x05R\x0ccompressType\x12%\n\x0ecorrelation_id\
+    \x18\x04\x20\x01(\x04R\rcorrelationId\x12/\n\x13authentication_data\x18\
+    \x07\x20\x01(\x0cR\x12authenticationData\"\xa2\x01\n\x0eRpcRequestMeta\
     \x12!\n\x0cservice_name\x18\x01\x20\x01(\tR\x0bserviceName\x12\x1f\n\x0b\
     method_name\x18\x02\x20\x01(\tR\nmethodName\x12\x15\n\x06log_id\x18\x03\
-    \x20\x01(\x03R\x05logId\"O\n\x0fRpcResponseMeta\x12\x1d\n\nerror_code\
-    \x18\x01\x20\x01(\x05R\terrorCode\x12\x1d\n\nerror_text\x18\x02\x20\x01(\
-    \tR\terrorTextb\x06proto3\
+    \x20\x01(\x03R\x05logId\x12\x1d\n\ntimeout_ms\x18\x08\x20\x01(\x05R\ttim\
+    eoutMs\x12\x16\n\x06oneway\x18d\x20\x01(\x08R\x06oneway\"O\n\x0fRpcRespo\
+    nseMeta\x12\x1d\n\nerror_code\x18\x01\x20\x01(\x05R\terrorCode\x12\x1d\n\
+    \nerror_text\x18\x02\x20\x01(\tR\terrorTextb\x06proto3\
 ";
 
 static mut file_descriptor_proto_lazy: ::protobuf::lazy::Lazy<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::lazy::Lazy {
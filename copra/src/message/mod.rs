@@ -3,16 +3,27 @@
 use bytes::Bytes;
 
 use controller::Controller;
+use service::MethodError;
 
+mod health;
 mod meta;
+mod reflection;
 mod test;
 
+pub use self::health::{HealthCheckRequest, HealthCheckResponse, ServingStatus};
 pub use self::meta::{RpcMeta, RpcRequestMeta, RpcResponseMeta};
+pub use self::reflection::{FileDescriptorRequest, FileDescriptorResponse, ListMethodsRequest,
+                           ListMethodsResponse, ListServicesRequest, ListServicesResponse};
+pub(crate) use self::health::file_descriptor_proto as health_file_descriptor;
+pub(crate) use self::reflection::file_descriptor_proto as reflection_file_descriptor;
 
 /// RPC request headers and parameters
 pub type RequestPackage = (RpcRequestMeta, Controller, Bytes);
 /// RPC response headers and body
 pub type ResponsePackage = (RpcResponseMeta, Controller, Bytes);
+/// A request decoded by the server, or the error to respond with if the
+/// request is rejected while decoding
+pub type DecodedRequest = Result<RequestPackage, MethodError>;
 
 #[cfg(test)]
 pub(crate) use self::test::TestMessage;
@@ -1,29 +1,55 @@
-//! [WIP] Built-in service for monitoring the server
+//! [WIP] Built-in service for monitoring the server, and the numbers of
+//! the calls sent through a channel
 
 use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use std::collections::BTreeMap;
 use std::io;
-use std::sync::Arc;
+use std::mem;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use tokio_proto::multiplex::RequestId;
 use tokio_timer::{Interval, Timer};
 
-use message::{RequestPackage, ResponsePackage};
+use errno;
+use message::{DecodedRequest, ResponsePackage};
+
+/// Numbers of responses sent since the throughput is last calculated, by
+/// error code
+#[derive(Debug, Default)]
+pub(crate) struct FinishedCount(Mutex<BTreeMap<i32, usize>>);
+
+impl FinishedCount {
+    /// Move the numbers in `counts` here.
+    fn add(&self, counts: &mut BTreeMap<i32, usize>) {
+        let mut finished = self.0.lock().unwrap();
+        for (&code, &n) in counts.iter() {
+            *finished.entry(code).or_insert(0) += n;
+        }
+        counts.clear();
+    }
+
+    fn take(&self) -> BTreeMap<i32, usize> {
+        let mut taken = BTreeMap::new();
+        mem::swap(&mut taken, &mut *self.0.lock().unwrap());
+        taken
+    }
+}
 
 /// A transport middleware that counts the processed mssages
 #[derive(Debug)]
 pub struct TrafficCounting<T> {
-    buffered: Arc<AtomicUsize>,
-    flushed: Arc<AtomicUsize>,
+    buffered: BTreeMap<i32, usize>,
+    flushed: Arc<FinishedCount>,
     io: T,
 }
 
 impl<T> TrafficCounting<T> {
-    /// Create a new transport middleware on top of `io`, store the number in
-    /// `flushed`.
-    pub fn new(flushed: Arc<AtomicUsize>, io: T) -> Self {
+    /// Create a new transport middleware on top of `io`, store the numbers
+    /// in `flushed`.
+    pub(crate) fn new(flushed: Arc<FinishedCount>, io: T) -> Self {
         TrafficCounting {
-            buffered: Arc::new(AtomicUsize::new(0)),
+            buffered: BTreeMap::new(),
             flushed,
             io,
         }
@@ -32,9 +58,9 @@ impl<T> TrafficCounting<T> {
 
 impl<T> Stream for TrafficCounting<T>
 where
-    T: Stream<Item = (RequestId, RequestPackage), Error = io::Error>,
+    T: Stream<Item = (RequestId, DecodedRequest), Error = io::Error>,
 {
-    type Item = (RequestId, RequestPackage);
+    type Item = (RequestId, DecodedRequest);
 
     type Error = io::Error;
 
@@ -52,9 +78,10 @@ where
     type SinkError = io::Error;
 
     fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let code = (item.1).0.get_error_code();
         let res = self.io.start_send(item)?;
         if let AsyncSink::Ready = res {
-            self.buffered.fetch_add(1, Ordering::Relaxed);
+            *self.buffered.entry(code).or_insert(0) += 1;
         }
         Ok(res)
     }
@@ -62,13 +89,177 @@ where
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
         let res = self.io.poll_complete()?;
         if let Async::Ready(_) = res {
-            let buffered = self.buffered.swap(0, Ordering::SeqCst);
-            self.flushed.fetch_add(buffered, Ordering::SeqCst);
+            if !self.buffered.is_empty() {
+                self.flushed.add(&mut self.buffered);
+            }
         }
         Ok(res)
     }
 }
 
+/// Counters of requests the server turned away or found unusual, and gauges
+/// of its load
+///
+/// Obtained from [`Server::stats`], the counters are updated by the server
+/// as requests are served.
+///
+/// [`Server::stats`]: ../server/struct.Server.html#method.stats
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    connections: Arc<AtomicUsize>,
+    inflight_requests: AtomicUsize,
+    concurrency_rejected: AtomicUsize,
+    rate_limited: AtomicUsize,
+    slow_requests: AtomicUsize,
+}
+
+impl ServerStats {
+    /// Create the stats of a server, reading the number of open connections
+    /// from `connections`.
+    pub(crate) fn new(connections: Arc<AtomicUsize>) -> Self {
+        ServerStats {
+            connections,
+            ..Default::default()
+        }
+    }
+
+    /// Number of open connections.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Number of requests being executed by the handlers.
+    pub fn inflight_requests(&self) -> usize {
+        self.inflight_requests.load(Ordering::SeqCst)
+    }
+
+    /// Number of requests rejected because the method reached its
+    /// concurrency limit.
+    pub fn concurrency_rejected(&self) -> usize {
+        self.concurrency_rejected.load(Ordering::Relaxed)
+    }
+
+    /// Number of requests rejected by the rate limiter.
+    pub fn rate_limited(&self) -> usize {
+        self.rate_limited.load(Ordering::Relaxed)
+    }
+
+    /// Number of requests taking longer than the slow request threshold.
+    pub fn slow_requests(&self) -> usize {
+        self.slow_requests.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_concurrency_rejected(&self) {
+        self.concurrency_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_slow_request(&self) {
+        self.slow_requests.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Count a request as executing until dropped
+#[derive(Debug)]
+pub(crate) struct ExecutingGuard {
+    stats: Arc<ServerStats>,
+}
+
+impl ExecutingGuard {
+    pub fn new(stats: Arc<ServerStats>) -> Self {
+        stats.inflight_requests.fetch_add(1, Ordering::SeqCst);
+        ExecutingGuard { stats }
+    }
+}
+
+impl Drop for ExecutingGuard {
+    fn drop(&mut self) {
+        self.stats.inflight_requests.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Responses per second sent by a server, updated every second
+///
+/// Pass it to [`ServerBuilder::throughput_monitor`], and read the rates
+/// from a clone. Successful responses are the ones with error code
+/// `errno::SUCCESS`.
+///
+/// [`ServerBuilder::throughput_monitor`]: ../server/struct.ServerBuilder.html#method.throughput_monitor
+#[derive(Clone, Debug, Default)]
+pub struct Throughput {
+    total: Arc<AtomicUsize>,
+    rates: Arc<Rates>,
+}
+
+#[derive(Debug, Default)]
+struct Rates {
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+    by_error_code: Mutex<BTreeMap<i32, usize>>,
+}
+
+impl Throughput {
+    /// Create a new handle, all rates are zero until the server updates
+    /// them.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create a handle storing the total rate in `total`.
+    pub(crate) fn with_total(total: Arc<AtomicUsize>) -> Self {
+        Throughput {
+            total,
+            ..Default::default()
+        }
+    }
+
+    /// Responses per second.
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::SeqCst)
+    }
+
+    /// Successful responses per second.
+    pub fn succeeded(&self) -> usize {
+        self.rates.succeeded.load(Ordering::SeqCst)
+    }
+
+    /// Failed responses per second.
+    pub fn failed(&self) -> usize {
+        self.rates.failed.load(Ordering::SeqCst)
+    }
+
+    /// Failed responses per second of each error code, only including the
+    /// codes seen in the last second.
+    pub fn failed_by_error_code(&self) -> BTreeMap<i32, usize> {
+        self.rates.by_error_code.lock().unwrap().clone()
+    }
+
+    /// Calculate the rates from the responses sent in `elapsed` seconds.
+    fn update(&self, counts: &BTreeMap<i32, usize>, elapsed: f32) {
+        let rate = |n: usize| (n as f32 / elapsed).round() as usize;
+        let mut total = 0;
+        let mut succeeded = 0;
+        let mut by_error_code = BTreeMap::new();
+        for (&code, &n) in counts {
+            total += n;
+            if code == errno::SUCCESS {
+                succeeded += n;
+            } else {
+                by_error_code.insert(code, rate(n));
+            }
+        }
+        self.total.store(rate(total), Ordering::SeqCst);
+        self.rates.succeeded.store(rate(succeeded), Ordering::SeqCst);
+        self.rates
+            .failed
+            .store(rate(total - succeeded), Ordering::SeqCst);
+        *self.rates.by_error_code.lock().unwrap() = by_error_code;
+    }
+}
+
 /// A maintainer calculates throughput periodically
 ///
 /// This maintainer implements `Stream` so that it can be spawned
@@ -76,14 +267,14 @@ where
 #[derive(Debug)]
 pub struct ThroughputMaintainer {
     interval: Interval,
-    finished: Arc<AtomicUsize>,
-    throughput: Arc<AtomicUsize>,
+    finished: Arc<FinishedCount>,
+    throughput: Throughput,
     last_fired: SystemTime,
 }
 
 impl ThroughputMaintainer {
-    /// Create a new maintainer, export throughput value to `throughtput`.
-    pub fn new(timer: Timer, finished: Arc<AtomicUsize>, throughput: Arc<AtomicUsize>) -> Self {
+    /// Create a new maintainer, export the rates to `throughput`.
+    pub(crate) fn new(timer: Timer, finished: Arc<FinishedCount>, throughput: Throughput) -> Self {
         let interval = timer.interval(Duration::from_secs(1));
         ThroughputMaintainer {
             interval,
@@ -108,12 +299,295 @@ impl Stream for ThroughputMaintainer {
             .map_err(|_| error!("SystemTime compare error."))?;
         self.last_fired = new_time;
 
-        let finished = self.finished.swap(0, Ordering::SeqCst);
+        let finished = self.finished.take();
         let elapse = elapse.as_secs() as f32 + (elapse.subsec_nanos() as f32 / 1e9);
-        let throughput = (finished as f32 / elapse) as usize;
-        info!("New finished: {}, throughput {}", finished, throughput);
-        self.throughput.store(throughput, Ordering::SeqCst);
+        self.throughput.update(&finished, elapse);
+        info!(
+            "New finished: {}, throughput {}, failed {}",
+            finished.values().sum::<usize>(),
+            self.throughput.total(),
+            self.throughput.failed()
+        );
 
         Ok(Async::Ready(Some(())))
     }
 }
+
+/// Upper bounds of the latency buckets of the channel metrics, in
+/// microseconds, the last bucket holding the slower calls
+const LATENCY_BOUNDS_US: [u64; 16] = [
+    100, 200, 500, 1_000, 2_000, 5_000, 10_000, 20_000, 50_000, 100_000, 200_000, 500_000,
+    1_000_000, 2_000_000, 5_000_000, 10_000_000,
+];
+
+/// How a call sent through a channel ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CallOutcome {
+    /// The server answered with `errno::SUCCESS`
+    Succeeded,
+    /// The server answered with another error code
+    ServerError,
+    /// No response within the timeout of the call, or the server gave up
+    /// at the deadline of the call
+    TimedOut,
+    /// The connection failed, or no server is connected
+    TransportError,
+    /// The call failed in the client, e.g. the response can not be decoded
+    /// or the channel is shut down
+    Other,
+}
+
+/// Client side numbers of the calls sent through a channel
+///
+/// Pass it to [`ChannelBuilder::metrics`], and read the numbers from a
+/// clone with [`snapshot`]. Every call made by a stub is counted once it
+/// ends, after its retries, with the time from it being issued to it ending
+/// and the sizes of the request and response bodies. Calls dropped before
+/// they end are not counted. Oneway calls are counted apart, as they have
+/// neither response nor latency, see [`oneway_sent`].
+///
+/// [`oneway_sent`]: struct.ChannelMetricsSnapshot.html#structfield.oneway_sent
+///
+/// [`ChannelBuilder::metrics`]: ../channel/struct.ChannelBuilder.html#method.metrics
+/// [`snapshot`]: #method.snapshot
+#[derive(Clone, Debug, Default)]
+pub struct ChannelMetrics {
+    inner: Arc<CallCounters>,
+}
+
+#[derive(Debug, Default)]
+struct CallCounters {
+    succeeded: AtomicUsize,
+    server_errors: AtomicUsize,
+    timed_out: AtomicUsize,
+    transport_errors: AtomicUsize,
+    other_errors: AtomicUsize,
+    bytes_sent: AtomicUsize,
+    bytes_received: AtomicUsize,
+    latency_us: AtomicUsize,
+    latency_buckets: [AtomicUsize; 17],
+    oneway_sent: AtomicUsize,
+    oneway_failed: AtomicUsize,
+}
+
+impl ChannelMetrics {
+    /// Create a new handle, all numbers are zero until calls are made.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Read the numbers of the calls ended so far.
+    pub fn snapshot(&self) -> ChannelMetricsSnapshot {
+        let load = |n: &AtomicUsize| n.load(Ordering::SeqCst);
+        let inner = &self.inner;
+        let bounds = LATENCY_BOUNDS_US
+            .iter()
+            .map(|&us| Some(Duration::from_micros(us)))
+            .chain(Some(None));
+        ChannelMetricsSnapshot {
+            succeeded: load(&inner.succeeded),
+            server_errors: load(&inner.server_errors),
+            timed_out: load(&inner.timed_out),
+            transport_errors: load(&inner.transport_errors),
+            other_errors: load(&inner.other_errors),
+            bytes_sent: load(&inner.bytes_sent),
+            bytes_received: load(&inner.bytes_received),
+            total_latency: Duration::from_micros(load(&inner.latency_us) as u64),
+            latency_buckets: bounds.zip(inner.latency_buckets.iter().map(load)).collect(),
+            oneway_sent: load(&inner.oneway_sent),
+            oneway_failed: load(&inner.oneway_failed),
+        }
+    }
+
+    /// Count a call issued at `start` which just ended with `outcome`, with
+    /// `sent` and `received` bytes in the bodies.
+    pub(crate) fn record(
+        &self,
+        outcome: CallOutcome,
+        start: Instant,
+        sent: usize,
+        received: usize,
+    ) {
+        let inner = &self.inner;
+        let count = match outcome {
+            CallOutcome::Succeeded => &inner.succeeded,
+            CallOutcome::ServerError => &inner.server_errors,
+            CallOutcome::TimedOut => &inner.timed_out,
+            CallOutcome::TransportError => &inner.transport_errors,
+            CallOutcome::Other => &inner.other_errors,
+        };
+        count.fetch_add(1, Ordering::SeqCst);
+        inner.bytes_sent.fetch_add(sent, Ordering::SeqCst);
+        inner.bytes_received.fetch_add(received, Ordering::SeqCst);
+
+        let latency = start.elapsed();
+        let us = latency.as_secs() * 1_000_000 + u64::from(latency.subsec_micros());
+        inner.latency_us.fetch_add(us as usize, Ordering::SeqCst);
+        let bucket = LATENCY_BOUNDS_US
+            .iter()
+            .position(|&bound| us <= bound)
+            .unwrap_or(LATENCY_BOUNDS_US.len());
+        inner.latency_buckets[bucket].fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Count a oneway call which was written to a connection, or failed.
+    pub(crate) fn record_oneway(&self, sent: bool) {
+        let count = if sent {
+            &self.inner.oneway_sent
+        } else {
+            &self.inner.oneway_failed
+        };
+        count.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// The numbers of the calls sent through a channel at some point, see
+/// [`ChannelMetrics`]
+///
+/// [`ChannelMetrics`]: struct.ChannelMetrics.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelMetricsSnapshot {
+    /// Calls answered with `errno::SUCCESS`
+    pub succeeded: usize,
+    /// Calls answered with another error code
+    pub server_errors: usize,
+    /// Calls not answered within their timeout, or given up by the server
+    /// at their deadline
+    pub timed_out: usize,
+    /// Calls failed as the connection failed, or no server is connected
+    pub transport_errors: usize,
+    /// Calls failed in the client, e.g. as the response could not be
+    /// decoded or the channel is shut down
+    pub other_errors: usize,
+    /// Bytes of the request bodies, once per call whatever the retries
+    pub bytes_sent: usize,
+    /// Bytes of the response bodies of the calls answered
+    pub bytes_received: usize,
+    /// Sum of the latencies of the calls
+    pub total_latency: Duration,
+    /// Number of calls by latency, each bucket holding the calls up to its
+    /// bound and above the one before, the last one without a bound
+    pub latency_buckets: Vec<(Option<Duration>, usize)>,
+    /// Oneway calls written to a connection, not counted as calls
+    pub oneway_sent: usize,
+    /// Oneway calls failed before they are written, not counted as calls
+    pub oneway_failed: usize,
+}
+
+impl ChannelMetricsSnapshot {
+    /// Number of calls ended.
+    pub fn calls(&self) -> usize {
+        self.succeeded + self.server_errors + self.timed_out + self.transport_errors
+            + self.other_errors
+    }
+
+    /// Number of calls failed, whatever the reason.
+    pub fn failed(&self) -> usize {
+        self.calls() - self.succeeded
+    }
+
+    /// Mean latency of the calls, `None` before any call ends.
+    pub fn mean_latency(&self) -> Option<Duration> {
+        let calls = self.calls() as u32;
+        if calls == 0 {
+            None
+        } else {
+            Some(self.total_latency / calls)
+        }
+    }
+
+    /// Bound of the bucket holding the `quantile` latency, e.g. 0.99 for
+    /// the 99th percentile. `None` before any call ends, or if the latency
+    /// is above the highest bound.
+    pub fn latency_quantile(&self, quantile: f64) -> Option<Duration> {
+        let calls = self.calls();
+        if calls == 0 {
+            return None;
+        }
+        let rank = ((calls as f64 * quantile).ceil() as usize).max(1);
+        let mut seen = 0;
+        for &(bound, n) in &self.latency_buckets {
+            seen += n;
+            if seen >= rank {
+                return bound;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rates_split_by_error_code() {
+        let throughput = Throughput::new();
+        let mut counts = BTreeMap::new();
+        counts.insert(errno::SUCCESS, 40);
+        counts.insert(errno::EINTERNAL, 6);
+        counts.insert(errno::ENOMETHOD, 4);
+        throughput.update(&counts, 2.0);
+
+        assert_eq!(throughput.total(), 25);
+        assert_eq!(throughput.succeeded(), 20);
+        assert_eq!(throughput.failed(), 5);
+        let by_code = throughput.failed_by_error_code();
+        assert_eq!(by_code.len(), 2);
+        assert_eq!(by_code[&errno::EINTERNAL], 3);
+        assert_eq!(by_code[&errno::ENOMETHOD], 2);
+
+        // codes not seen in the last interval are dropped
+        throughput.update(&BTreeMap::new(), 1.0);
+        assert_eq!(throughput.total(), 0);
+        assert!(throughput.failed_by_error_code().is_empty());
+    }
+
+    #[test]
+    fn counts_moved_on_add() {
+        let finished = FinishedCount::default();
+        let mut counts = BTreeMap::new();
+        counts.insert(errno::SUCCESS, 2);
+        finished.add(&mut counts);
+        assert!(counts.is_empty());
+        counts.insert(errno::SUCCESS, 1);
+        counts.insert(errno::EINTERNAL, 1);
+        finished.add(&mut counts);
+
+        let taken = finished.take();
+        assert_eq!(taken[&errno::SUCCESS], 3);
+        assert_eq!(taken[&errno::EINTERNAL], 1);
+        assert!(finished.take().is_empty());
+    }
+
+    #[test]
+    fn channel_metrics_split_by_outcome() {
+        let metrics = ChannelMetrics::new();
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.calls(), 0);
+        assert_eq!(snapshot.mean_latency(), None);
+        assert_eq!(snapshot.latency_quantile(0.5), None);
+
+        let start = Instant::now();
+        for _ in 0..8 {
+            metrics.record(CallOutcome::Succeeded, start, 10, 20);
+        }
+        metrics.record(CallOutcome::ServerError, start, 10, 0);
+        metrics.record(CallOutcome::TimedOut, start - Duration::from_secs(20), 10, 0);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.calls(), 10);
+        assert_eq!(snapshot.succeeded, 8);
+        assert_eq!(snapshot.failed(), 2);
+        assert_eq!(snapshot.server_errors, 1);
+        assert_eq!(snapshot.timed_out, 1);
+        assert_eq!(snapshot.bytes_sent, 100);
+        assert_eq!(snapshot.bytes_received, 160);
+        assert_eq!(snapshot.latency_buckets.len(), LATENCY_BOUNDS_US.len() + 1);
+        assert!(snapshot.mean_latency().unwrap() >= Duration::from_secs(2));
+        assert!(snapshot.latency_quantile(0.9).unwrap() <= Duration::from_secs(10));
+        // the slowest call is above all the bounds
+        assert_eq!(snapshot.latency_quantile(1.0), None);
+        assert_eq!(snapshot.latency_buckets.last(), Some(&(None, 1)));
+    }
+}
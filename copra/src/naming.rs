@@ -0,0 +1,423 @@
+//! Find the servers of a channel by name
+//!
+//! A channel built by [`ChannelBuilder::with_naming`] subscribes to a
+//! [`NamingService`], and follows the servers it gives as they change.
+//! Clients of service registries implement this trait, and two
+//! implementations are built in.
+//!
+//! [`DnsNaming`], used by [`ChannelBuilder::dns`], asks its [`Resolve`]
+//! implementation for the addresses of a host name, and again at every
+//! refresh interval. [`DnsResolver`] is the default, and another resolver is
+//! chosen by [`ChannelBuilder::resolver`].
+//!
+//! [`FileNaming`], used by [`ChannelBuilder::file_naming`], reads the
+//! servers from a file instead, one `host:port` per line, optionally
+//! followed by the weight of the server:
+//!
+//! ```text
+//! # primary cluster
+//! 10.0.0.1:8000 4
+//! 10.0.0.2:8000
+//! backup.example.com:8000 1  # every address of the name
+//! ```
+//!
+//! Blank lines and `#` comments are ignored, and malformed lines are logged
+//! and skipped.
+//!
+//! [`ChannelBuilder::with_naming`]: ../channel/struct.ChannelBuilder.html#method.with_naming
+//! [`NamingService`]: trait.NamingService.html
+//! [`DnsNaming`]: struct.DnsNaming.html
+//! [`FileNaming`]: struct.FileNaming.html
+//! [`ChannelBuilder::dns`]: ../channel/struct.ChannelBuilder.html#method.dns
+//! [`ChannelBuilder::file_naming`]: ../channel/struct.ChannelBuilder.html#method.file_naming
+//! [`ChannelBuilder::resolver`]: ../channel/struct.ChannelBuilder.html#method.resolver
+//! [`Resolve`]: trait.Resolve.html
+//! [`DnsResolver`]: struct.DnsResolver.html
+
+use futures::{Future, Stream};
+use futures_cpupool::{Builder, CpuPool};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio_core::reactor::{Handle, Interval};
+
+/// A future resolving to the addresses of a name
+pub type ResolveFuture = Box<Future<Item = Vec<SocketAddr>, Error = io::Error>>;
+
+/// A stream of the servers given by a naming service
+pub type ServerStream = Box<Stream<Item = Vec<ServerEndpoint>, Error = io::Error>>;
+
+/// A server given by a naming service
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerEndpoint {
+    addr: SocketAddr,
+    weight: Option<u32>,
+    tags: HashMap<String, String>,
+}
+
+impl ServerEndpoint {
+    /// Create a new endpoint without weight or tags.
+    pub fn new(addr: SocketAddr) -> Self {
+        ServerEndpoint {
+            addr,
+            weight: None,
+            tags: HashMap::new(),
+        }
+    }
+
+    /// Set the weight of the server, used by weighted load balancers.
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = Some(weight);
+        self
+    }
+
+    /// Add a tag to the server.
+    ///
+    /// Tags mean nothing to the channel, they are passed to the load
+    /// balancer in [`BackendInfo::tag`], e.g. to prefer the servers of a
+    /// zone.
+    ///
+    /// [`BackendInfo::tag`]: ../load_balancer/struct.BackendInfo.html#method.tag
+    pub fn with_tag<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.tags.insert(key.into(), value.into());
+        self
+    }
+
+    /// Get the address of the server.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Get the weight of the server, if any.
+    pub fn weight(&self) -> Option<u32> {
+        self.weight
+    }
+
+    /// Get the tags of the server.
+    pub fn tags(&self) -> &HashMap<String, String> {
+        &self.tags
+    }
+}
+
+/// A source of the servers of a channel, e.g. a client of a service
+/// registry
+pub trait NamingService {
+    /// Subscribe to the servers.
+    ///
+    /// Every item of the stream is the complete list of servers. The
+    /// channel connects to the new servers, and stops sending calls to the
+    /// servers gone once their calls are answered. Building the channel
+    /// waits for the first item.
+    ///
+    /// An empty list or an error keeps the servers as they are, and the
+    /// stream is polled on after an error. When the stream ends the servers
+    /// stay as they are for good.
+    fn subscribe(&self, handle: &Handle) -> ServerStream;
+}
+
+impl fmt::Debug for NamingService {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NamingService")
+    }
+}
+
+/// Options of a channel following a naming service
+#[derive(Clone, Debug, Default)]
+pub struct NamingOptions {
+    succeed_without_server: bool,
+}
+
+impl NamingOptions {
+    /// Create a new set of options with default values.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Build the channel even if the naming service gives no server at
+    /// first, or none of them can be connected to.
+    ///
+    /// Calls fail until a server is connected. Default to `false`.
+    pub fn succeed_without_server(mut self, succeed: bool) -> Self {
+        self.succeed_without_server = succeed;
+        self
+    }
+
+    /// Check if the channel is built without any server.
+    pub fn get_succeed_without_server(&self) -> bool {
+        self.succeed_without_server
+    }
+}
+
+/// Look up with `lookup` at once, and again every `interval`.
+fn every<T, F>(interval: Duration, handle: &Handle, lookup: F) -> Box<Stream<Item = T, Error = io::Error>>
+where
+    F: Fn() -> Box<Future<Item = T, Error = io::Error>> + 'static,
+    T: 'static,
+{
+    let first = lookup();
+    let ticks = Interval::new(interval, handle).expect("The event loop of the channel is gone");
+    Box::new(first.into_stream().chain(ticks.and_then(move |()| lookup())))
+}
+
+/// A way to look up the addresses of the servers behind a name
+pub trait Resolve {
+    /// Get the addresses of `host`, with `port` as their port unless the
+    /// naming service tells otherwise.
+    ///
+    /// The future is polled on the event loop of the channel, so it must
+    /// not block.
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture;
+}
+
+impl fmt::Debug for Resolve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Resolve")
+    }
+}
+
+/// Resolve host names with the resolver of the system
+///
+/// The lookups block, so they run on a thread of their own instead of the
+/// event loop.
+#[derive(Debug)]
+pub struct DnsResolver {
+    pool: CpuPool,
+}
+
+impl DnsResolver {
+    /// Create a new instance with its own lookup thread.
+    pub fn new() -> Self {
+        let pool = Builder::new()
+            .pool_size(1)
+            .name_prefix("copra-dns-")
+            .create();
+        DnsResolver { pool }
+    }
+}
+
+impl Default for DnsResolver {
+    fn default() -> Self {
+        DnsResolver::new()
+    }
+}
+
+impl Resolve for DnsResolver {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        let host = host.to_string();
+        let fut = self.pool.spawn_fn(move || {
+            (host.as_str(), port)
+                .to_socket_addrs()
+                .map(|addrs| addrs.collect())
+        });
+        Box::new(fut)
+    }
+}
+
+/// Follow the addresses a host name resolves to
+///
+/// Every address is a server, the name is resolved at once and again at
+/// every refresh interval.
+#[derive(Debug)]
+pub struct DnsNaming {
+    resolver: Option<Arc<Resolve>>,
+    host: String,
+    port: u16,
+    refresh: Duration,
+}
+
+impl DnsNaming {
+    /// Resolve `host` every `refresh`, the servers are reached at `port`.
+    pub fn new(host: &str, port: u16, refresh: Duration) -> Self {
+        DnsNaming {
+            resolver: None,
+            host: host.to_string(),
+            port,
+            refresh,
+        }
+    }
+
+    /// Resolve the name with `resolver`.
+    ///
+    /// Default to [`DnsResolver`].
+    ///
+    /// [`DnsResolver`]: struct.DnsResolver.html
+    pub fn resolver(mut self, resolver: Box<Resolve>) -> Self {
+        self.resolver = Some(Arc::from(resolver));
+        self
+    }
+}
+
+impl NamingService for DnsNaming {
+    fn subscribe(&self, handle: &Handle) -> ServerStream {
+        let resolver = match self.resolver {
+            Some(ref resolver) => resolver.clone(),
+            None => Arc::new(DnsResolver::new()),
+        };
+        let host = self.host.clone();
+        let port = self.port;
+        let stream = every(self.refresh, handle, move || {
+            let name = host.clone();
+            let fut = resolver.resolve(&host, port).then(move |result| match result {
+                Ok(addrs) => Ok(addrs.into_iter().map(ServerEndpoint::new).collect()),
+                Err(e) => Err(io::Error::new(
+                    e.kind(),
+                    format!("failed to resolve {}: {}", name, e),
+                )),
+            });
+            Box::new(fut) as Box<Future<Item = _, Error = _>>
+        });
+        Box::new(stream)
+    }
+}
+
+/// Follow the servers listed in a file
+///
+/// The file is read at once, and again every interval if it has changed.
+/// See the [module documentation] for its format.
+///
+/// [module documentation]: index.html
+#[derive(Debug)]
+pub struct FileNaming {
+    path: PathBuf,
+    interval: Duration,
+}
+
+impl FileNaming {
+    /// Read the servers in the file at `path`, checking every `interval`
+    /// if it has changed.
+    pub fn new<P: AsRef<Path>>(path: P, interval: Duration) -> Self {
+        FileNaming {
+            path: path.as_ref().to_path_buf(),
+            interval,
+        }
+    }
+}
+
+impl NamingService for FileNaming {
+    fn subscribe(&self, handle: &Handle) -> ServerStream {
+        // host names in the file are resolved with blocking lookups
+        let pool = Builder::new()
+            .pool_size(1)
+            .name_prefix("copra-naming-")
+            .create();
+        let path = self.path.clone();
+        let last = Arc::new(Mutex::new(None));
+        let stream = every(self.interval, handle, move || {
+            let path = path.clone();
+            let last = last.clone();
+            let fut = pool.spawn_fn(move || {
+                read_server_file(&path, &last).map_err(|e| {
+                    io::Error::new(e.kind(), format!("failed to read {}: {}", path.display(), e))
+                })
+            });
+            Box::new(fut) as Box<Future<Item = _, Error = _>>
+        });
+        Box::new(stream.filter_map(|servers| servers))
+    }
+}
+
+// the modification time and length of a file
+type FileVersion = (SystemTime, u64);
+
+/// Read the servers in the file at `path`, or get `None` if the file is the
+/// same as when read last.
+fn read_server_file(
+    path: &Path,
+    last: &Mutex<Option<FileVersion>>,
+) -> io::Result<Option<Vec<ServerEndpoint>>> {
+    let metadata = fs::metadata(path)?;
+    let version = (metadata.modified()?, metadata.len());
+    let mut last = last.lock().unwrap();
+    if *last == Some(version) {
+        return Ok(None);
+    }
+
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+    *last = Some(version);
+    Ok(Some(parse_server_list(&content, &path.display().to_string())))
+}
+
+/// Parse the lines of a server file, logging and skipping malformed ones.
+fn parse_server_list(content: &str, name: &str) -> Vec<ServerEndpoint> {
+    let mut servers: Vec<ServerEndpoint> = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        let mut fields = line.split_whitespace();
+        let addr = match fields.next() {
+            Some(addr) => addr,
+            None => continue,
+        };
+        let weight = match (fields.next().map(str::parse::<u32>), fields.next()) {
+            (None, None) => None,
+            (Some(Ok(weight)), None) => Some(weight),
+            _ => {
+                warn!("Skipped malformed line {} of {}: {}", number + 1, name, line.trim());
+                continue;
+            }
+        };
+        let addrs = match addr.to_socket_addrs() {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                warn!("Skipped line {} of {}, can not resolve {}: {}", number + 1, name, addr, e);
+                continue;
+            }
+        };
+        for addr in addrs {
+            if servers.iter().any(|server| server.addr == addr) {
+                continue;
+            }
+            let server = ServerEndpoint::new(addr);
+            servers.push(match weight {
+                Some(weight) => server.with_weight(weight),
+                None => server,
+            });
+        }
+    }
+    servers
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolve_localhost_and_ip() {
+        let resolver = DnsResolver::new();
+        let addrs = resolver.resolve("localhost", 8000).wait().unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback() && addr.port() == 8000));
+
+        let addrs = resolver.resolve("127.0.0.1", 80).wait().unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1:80".parse().unwrap()]);
+    }
+
+    #[test]
+    fn parse_servers_with_weights_and_comments() {
+        let content = "# servers\n\
+                       127.0.0.1:8000 4\n\
+                       \n\
+                       127.0.0.2:8000  # no weight\n\
+                       127.0.0.3:8000 heavy\n\
+                       127.0.0.4:8000 1 2\n\
+                       127.0.0.5\n\
+                       127.0.0.1:8000 2\n\
+                       [::1]:9000 0\n";
+        let servers = parse_server_list(content, "servers.txt");
+        let expected = vec![
+            ServerEndpoint::new("127.0.0.1:8000".parse().unwrap()).with_weight(4),
+            ServerEndpoint::new("127.0.0.2:8000".parse().unwrap()),
+            ServerEndpoint::new("[::1]:9000".parse().unwrap()).with_weight(0),
+        ];
+        assert_eq!(servers, expected);
+        assert!(parse_server_list("\n  # nothing\n", "empty.txt").is_empty());
+    }
+}
@@ -18,6 +18,10 @@ enum BrpcParseState {
     ReadingHeader,
     ReadingLength,
     ReadingContent(u32, u32),
+    /// (package length, meta length) of a package over the size limit
+    ReadingOversizedMeta(u32, u32),
+    /// Number of bytes left to skip
+    Discarding(usize),
 }
 
 
@@ -25,6 +29,7 @@ enum BrpcParseState {
 #[derive(Clone, Debug)]
 pub struct BrpcProtocol {
     state: BrpcParseState,
+    max_size: Option<usize>,
 }
 
 impl BrpcProtocol {
@@ -32,6 +37,7 @@ impl BrpcProtocol {
     pub fn new() -> Self {
         BrpcProtocol {
             state: BrpcParseState::ReadingHeader,
+            max_size: None,
         }
     }
 }
@@ -62,7 +68,20 @@ impl RpcProtocol for BrpcProtocol {
                     }
                     let pkg_len = buf.split_to(4).into_buf().get_u32::<BigEndian>();
                     let meta_len = buf.split_to(4).into_buf().get_u32::<BigEndian>();
-                    self.state = BrpcParseState::ReadingContent(pkg_len, meta_len);
+                    if meta_len > pkg_len {
+                        return Err(ProtocolError::AbsolutelyWrong);
+                    }
+                    self.state = match self.max_size {
+                        Some(max) if pkg_len as usize > max => {
+                            // the meta is still read to answer with an error,
+                            // unless it is too large itself
+                            if meta_len as usize > max {
+                                return Err(ProtocolError::TooLarge(None));
+                            }
+                            BrpcParseState::ReadingOversizedMeta(pkg_len, meta_len)
+                        }
+                        _ => BrpcParseState::ReadingContent(pkg_len, meta_len),
+                    };
                 }
                 BrpcParseState::ReadingContent(pkg_len, meta_len) => {
                     if buf.len() < pkg_len as usize {
@@ -79,6 +98,27 @@ impl RpcProtocol for BrpcProtocol {
                         (meta, Controller::default(), body),
                     ));
                 }
+                BrpcParseState::ReadingOversizedMeta(pkg_len, meta_len) => {
+                    if buf.len() < meta_len as usize {
+                        return Err(ProtocolError::NeedMoreBytes);
+                    }
+                    let meta = parse_from_carllerche_bytes::<RpcMeta>(&buf.split_to(
+                        meta_len as usize,
+                    ).freeze())
+                        .map_err(|_| ProtocolError::AbsolutelyWrong)?;
+                    self.state = BrpcParseState::Discarding((pkg_len - meta_len) as usize);
+                    let id = meta.get_correlation_id();
+                    return Err(ProtocolError::TooLarge(Some((id, Box::new(Controller::default())))));
+                }
+                BrpcParseState::Discarding(left) => {
+                    let skipped = left.min(buf.len());
+                    buf.advance(skipped);
+                    if skipped < left {
+                        self.state = BrpcParseState::Discarding(left - skipped);
+                        return Err(ProtocolError::NeedMoreBytes);
+                    }
+                    self.state = BrpcParseState::ReadingHeader;
+                }
             }
         }
     }
@@ -86,6 +126,7 @@ impl RpcProtocol for BrpcProtocol {
     fn new_boxed(&self) -> Box<RpcProtocol> {
         Box::new(BrpcProtocol {
             state: BrpcParseState::ReadingHeader,
+            max_size: self.max_size,
         })
     }
 
@@ -115,6 +156,14 @@ impl RpcProtocol for BrpcProtocol {
     fn name(&self) -> &'static str {
         "brpc"
     }
+
+    fn set_max_package_size(&mut self, max: Option<usize>) {
+        self.max_size = max;
+    }
+
+    fn supports_compression(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(test)]
@@ -206,4 +255,38 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn oversized_package_is_skipped() {
+        let mut brpc_codec = BrpcProtocol::new();
+        brpc_codec.set_max_package_size(Some(64));
+
+        let mut request_meta = RpcRequestMeta::new();
+        request_meta.set_service_name(SERVICE.to_string());
+        request_meta.set_method_name(METHOD.to_string());
+
+        let mut meta = RpcMeta::new();
+        meta.set_correlation_id(CORRELATION_ID);
+        meta.set_request(request_meta);
+
+        let controller = Controller::default();
+        let small_body = convert_to_bytes(get_test_message());
+        let large_body = Bytes::from(vec![0_u8; 128]);
+
+        let mut buf = BytesMut::new();
+        brpc_codec
+            .write_package((meta.clone(), controller.clone(), large_body), &mut buf)
+            .expect("write_package failed");
+        brpc_codec
+            .write_package((meta.clone(), controller.clone(), small_body.clone()), &mut buf)
+            .expect("write_package failed");
+
+        let result = brpc_codec.try_parse(&mut buf);
+        assert_eq!(
+            result,
+            Err(ProtocolError::TooLarge(Some((CORRELATION_ID, Box::new(Controller::default())))))
+        );
+        let result = brpc_codec.try_parse(&mut buf);
+        assert_eq!(result, Ok((CORRELATION_ID, (meta, controller, small_body))));
+    }
 }
@@ -1,4 +1,29 @@
 //! [WIP] Http 1.X protocol
+//!
+//! Methods are called by `POST /<service>/<method>`, below an optional path
+//! prefix set by [`ServerBuilder::http_path_prefix`]. If the `Content-Type`
+//! of the request is `application/x-protobuf` or `application/protobuf`, the
+//! body is the serialized request message, and the serialized response is
+//! sent back with the same content type. Otherwise the body is only kept in
+//! [`Controller::request_body`], and the handler responds through
+//! [`Controller::response_body`], which takes precedence over the response
+//! message.
+//!
+//! Unknown paths are answered with `404 Not Found`, methods called other
+//! than by `POST` with `405 Method Not Allowed`, and requests failing to
+//! decode with `400 Bad Request`. Failed requests without a status set by
+//! the handler are answered with a status by the error code, and the error
+//! text as a plain text body.
+//!
+//! The built-in pages, `/health` and `/status`, are served at fixed paths
+//! whatever the prefix and the http method are.
+//!
+//! Channels call methods the same way with [`HttpClientProtocol`].
+//!
+//! [`ServerBuilder::http_path_prefix`]: ../../server/struct.ServerBuilder.html#method.http_path_prefix
+//! [`Controller::request_body`]: ../../controller/struct.Controller.html#structfield.request_body
+//! [`Controller::response_body`]: ../../controller/struct.Controller.html#structfield.response_body
+//! [`HttpClientProtocol`]: struct.HttpClientProtocol.html
 
 use bytes::{BufMut, Bytes, BytesMut};
 use std::io;
@@ -6,20 +31,45 @@ use std::str;
 use std::collections::HashMap;
 use tokio_proto::multiplex::RequestId;
 use httparse::Request as HttpRequest;
+use httparse::Response as HttpResponse;
 use httparse::Status;
 use httparse;
 
 use controller::Controller;
+use errno;
 use super::{ProtocolError, RpcProtocol};
-use message::{RpcMeta, RpcRequestMeta};
+use message::{RpcMeta, RpcRequestMeta, RpcResponseMeta};
+use server::{admin, status};
+use service::MethodError;
+
+/// Content types of requests whose body is the serialized message
+static PROTOBUF_CONTENT_TYPES: &[&str] = &["application/x-protobuf", "application/protobuf"];
 
 #[derive(Clone, PartialEq, Debug)]
 /// Status code in http response
 pub enum HttpStatus {
     /// 200 Ok
     Ok,
+    /// 400 Bad Request
+    BadRequest,
+    /// 401 Unauthorized
+    Unauthorized,
     /// 403 Forbidden
     Forbidden,
+    /// 404 Not Found
+    NotFound,
+    /// 405 Method Not Allowed
+    MethodNotAllowed,
+    /// 413 Payload Too Large
+    PayloadTooLarge,
+    /// 429 Too Many Requests
+    TooManyRequests,
+    /// 500 Internal Server Error
+    InternalServerError,
+    /// 503 Service Unavailable
+    ServiceUnavailable,
+    /// 504 Gateway Timeout
+    GatewayTimeout,
 }
 
 impl HttpStatus {
@@ -27,7 +77,16 @@ impl HttpStatus {
     pub fn to_code(&self) -> i32 {
         match *self {
             HttpStatus::Ok => 200,
+            HttpStatus::BadRequest => 400,
+            HttpStatus::Unauthorized => 401,
             HttpStatus::Forbidden => 403,
+            HttpStatus::NotFound => 404,
+            HttpStatus::MethodNotAllowed => 405,
+            HttpStatus::PayloadTooLarge => 413,
+            HttpStatus::TooManyRequests => 429,
+            HttpStatus::InternalServerError => 500,
+            HttpStatus::ServiceUnavailable => 503,
+            HttpStatus::GatewayTimeout => 504,
         }
     }
 
@@ -35,16 +94,51 @@ impl HttpStatus {
     pub fn to_status_line(&self) -> &'static str {
         match *self {
             HttpStatus::Ok => "200 OK",
+            HttpStatus::BadRequest => "400 Bad Request",
+            HttpStatus::Unauthorized => "401 Unauthorized",
             HttpStatus::Forbidden => "403 Forbidden",
+            HttpStatus::NotFound => "404 Not Found",
+            HttpStatus::MethodNotAllowed => "405 Method Not Allowed",
+            HttpStatus::PayloadTooLarge => "413 Payload Too Large",
+            HttpStatus::TooManyRequests => "429 Too Many Requests",
+            HttpStatus::InternalServerError => "500 Internal Server Error",
+            HttpStatus::ServiceUnavailable => "503 Service Unavailable",
+            HttpStatus::GatewayTimeout => "504 Gateway Timeout",
+        }
+    }
+
+    /// Status of a failed request, by the error code in the response meta.
+    fn from_error_code(code: i32) -> Self {
+        match code {
+            errno::ENOSERVICE | errno::ENOMETHOD => HttpStatus::NotFound,
+            errno::EREQUEST => HttpStatus::BadRequest,
+            errno::ERPCAUTH => HttpStatus::Unauthorized,
+            errno::ERPCTIMEDOUT => HttpStatus::GatewayTimeout,
+            errno::ELIMIT => HttpStatus::ServiceUnavailable,
+            errno::ETOOLARGE => HttpStatus::PayloadTooLarge,
+            errno::ERATELIMIT => HttpStatus::TooManyRequests,
+            _ => HttpStatus::InternalServerError,
         }
     }
 }
 
+/// What the path of a request refers to
+#[derive(Debug, PartialEq)]
+enum Route {
+    /// A built-in page, served whatever the http method is
+    Builtin(&'static str, &'static str),
+    /// A method, called by `POST`
+    Method(String, String),
+    NotFound,
+}
+
 #[derive(Clone, Debug)]
 enum HttpParseState {
     ReadingHeader,
     /// (header length, content length, controller)
-    ReadingContent(usize, usize, RpcMeta, Controller),
+    ReadingContent(usize, usize, RpcMeta, Box<Controller>),
+    /// Number of bytes left to skip
+    Discarding(usize),
 }
 
 
@@ -52,25 +146,79 @@ enum HttpParseState {
 #[derive(Clone, Debug)]
 pub struct HttpProtocol {
     state: HttpParseState,
+    max_size: Option<usize>,
+    prefix: String,
+    admin: bool,
 }
 
 impl HttpProtocol {
     /// Create a new instance.
     pub fn new() -> Self {
+        HttpProtocol::with_prefix("")
+    }
+
+    /// Create a new instance serving methods below the path `prefix`, e.g.
+    /// `/rpc`.
+    pub fn with_prefix(prefix: &str) -> Self {
+        let prefix = prefix.trim_matches('/');
+        let prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", prefix)
+        };
         HttpProtocol {
             state: HttpParseState::ReadingHeader,
+            max_size: None,
+            prefix,
+            admin: false,
         }
     }
 
-    fn parse_name(&self, path: &str) -> Result<(String, String), ProtocolError> {
-        let names: Vec<_> = path.split("/").filter(|s| s.len() > 0).collect();
+    /// Also serve the admin handlers below `/admin`.
+    pub fn with_admin(mut self) -> Self {
+        self.admin = true;
+        self
+    }
+
+    fn route(&self, path: &str) -> Route {
+        let path = path.split('?').next().unwrap_or_default();
+        let names: Vec<_> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        // probes expect the health check at a fixed path
+        if names == ["health"] {
+            return Route::Builtin("Health", "check");
+        }
+        if names.is_empty() || names == ["status"] {
+            return Route::Builtin(status::SERVICE_NAME, status::METHOD_NAME);
+        }
+        if self.admin && names.len() == 2 && names[0] == "admin" {
+            if let Some(method) = admin::method_name(names[1]) {
+                return Route::Builtin(admin::SERVICE_NAME, method);
+            }
+        }
 
-        if names.len() < 2 {
-            debug!("Http request: can not parse method name from path {}", path);
-            Err(ProtocolError::AbsolutelyWrong)
+        let rest = if path.starts_with(&self.prefix) {
+            &path[self.prefix.len()..]
         } else {
-            Ok((names[0].to_string(), names[1].to_string()))
+            return Route::NotFound;
+        };
+        let names: Vec<_> = rest.split('/').filter(|s| !s.is_empty()).collect();
+        // the admin handlers are only reachable through their own paths
+        if !rest.starts_with('/') || names.len() != 2 || names[0] == admin::SERVICE_NAME {
+            return Route::NotFound;
+        }
+        Route::Method(names[0].to_string(), names[1].to_string())
+    }
+}
+
+/// Whether the body of a request is the serialized message
+fn is_protobuf(headers: &HashMap<String, String>) -> bool {
+    match headers.get("Content-Type") {
+        Some(content_type) => {
+            let mime = content_type.split(';').next().unwrap_or_default().trim();
+            PROTOBUF_CONTENT_TYPES.contains(&mime)
         }
+        None => false,
     }
 }
 
@@ -128,14 +276,55 @@ impl RpcProtocol for HttpProtocol {
                                 None => 0,
                             };
 
+                            if let Some(max) = self.max_size {
+                                if header_len + content_len > max {
+                                    debug!("Http request: content of {} bytes is too large", content_len);
+                                    let reply = Controller {
+                                        status: Some(HttpStatus::PayloadTooLarge),
+                                        ..Default::default()
+                                    };
+                                    self.state = HttpParseState::Discarding(header_len + content_len);
+                                    return Err(ProtocolError::TooLarge(Some((id, Box::new(reply)))));
+                                }
+                            }
+
                             debug!("Http request path: {}", path);
+                            let (service, method) = match self.route(path) {
+                                Route::Builtin(service, method) => {
+                                    (service.to_string(), method.to_string())
+                                }
+                                Route::Method(ref service, ref method)
+                                    if req.method == Some("POST") =>
+                                {
+                                    (service.clone(), method.clone())
+                                }
+                                Route::Method(..) => {
+                                    debug!("Http request: {:?} {} is not allowed", req.method, path);
+                                    let mut reply = Controller {
+                                        status: Some(HttpStatus::MethodNotAllowed),
+                                        response_body: b"method not allowed, use POST\n".to_vec(),
+                                        ..Default::default()
+                                    };
+                                    reply.headers.insert("Allow".to_string(), "POST".to_string());
+                                    reply.set_content_type("text/plain");
+                                    let err = MethodError::MethodNotFound.with_controller(reply);
+                                    self.state = HttpParseState::Discarding(header_len + content_len);
+                                    return Err(ProtocolError::Rejected(id, err));
+                                }
+                                Route::NotFound => {
+                                    debug!("Http request: no method at path {}", path);
+                                    self.state = HttpParseState::Discarding(header_len + content_len);
+                                    return Err(ProtocolError::Rejected(id, MethodError::ServiceNotFound));
+                                }
+                            };
                             controller.http_url = Some(path.to_string());
                             controller.headers = header_map;
-
-                            let (service, method) = self.parse_name(path)?;
                             request_meta.set_service_name(service);
                             request_meta.set_method_name(method);
 
+                            if let Some(credential) = controller.headers.get("Authorization") {
+                                meta.set_authentication_data(credential.as_bytes().to_vec());
+                            }
                             meta.set_request(request_meta);
                             meta.set_correlation_id(id);
 
@@ -143,10 +332,18 @@ impl RpcProtocol for HttpProtocol {
                                 header_len,
                                 content_len,
                                 meta,
-                                controller,
+                                Box::new(controller),
                             );
                         }
-                        Ok(Status::Partial) => return Err(ProtocolError::NeedMoreBytes),
+                        Ok(Status::Partial) => {
+                            if let Some(max) = self.max_size {
+                                if buf.len() > max {
+                                    debug!("Http request: header is too large");
+                                    return Err(ProtocolError::TooLarge(None));
+                                }
+                            }
+                            return Err(ProtocolError::NeedMoreBytes);
+                        }
                         Err(e) => {
                             debug!("Http header parse error: {:?}", e);
                             return Err(ProtocolError::AbsolutelyWrong);
@@ -170,11 +367,25 @@ impl RpcProtocol for HttpProtocol {
                             "Http request: Parsed a package with the length of {}",
                             header_len + content_len
                         );
-                        return Ok((meta.get_correlation_id(), (meta, controller, Bytes::new())));
+                        let message = if is_protobuf(&controller.headers) {
+                            body
+                        } else {
+                            Bytes::new()
+                        };
+                        return Ok((meta.get_correlation_id(), (meta, *controller, message)));
                     } else {
                         unreachable!();
                     }
                 }
+                HttpParseState::Discarding(left) => {
+                    let skipped = left.min(buf.len());
+                    buf.advance(skipped);
+                    if skipped < left {
+                        self.state = HttpParseState::Discarding(left - skipped);
+                        return Err(ProtocolError::NeedMoreBytes);
+                    }
+                    self.state = HttpParseState::ReadingHeader;
+                }
             }
         }
     }
@@ -182,6 +393,9 @@ impl RpcProtocol for HttpProtocol {
     fn new_boxed(&self) -> Box<RpcProtocol> {
         Box::new(HttpProtocol {
             state: HttpParseState::ReadingHeader,
+            max_size: self.max_size,
+            prefix: self.prefix.clone(),
+            admin: self.admin,
         })
     }
 
@@ -190,53 +404,272 @@ impl RpcProtocol for HttpProtocol {
         meta: (RpcMeta, Controller, Bytes),
         buf: &mut BytesMut,
     ) -> io::Result<()> {
-        let (_meta, controller, _) = meta;
-        let status = controller.status.unwrap_or(HttpStatus::Ok);
+        let (meta, controller, body) = meta;
+        let mut headers = controller.headers;
+        let mut response_body = controller.response_body;
+        let error_code = meta.get_response().get_error_code();
+        let status = match controller.status {
+            Some(status) => status,
+            None if error_code != errno::SUCCESS => HttpStatus::from_error_code(error_code),
+            None => HttpStatus::Ok,
+        };
+        if response_body.is_empty() {
+            if error_code != errno::SUCCESS {
+                response_body = format!("{}\n", meta.get_response().get_error_text()).into_bytes();
+                headers.insert("Content-Type".to_string(), "text/plain".to_string());
+            } else if !body.is_empty() {
+                response_body = body.to_vec();
+                headers
+                    .entry("Content-Type".to_string())
+                    .or_insert_with(|| PROTOBUF_CONTENT_TYPES[0].to_string());
+            }
+        }
         let status_line = format!("HTTP/1.1 {}\r\n", status.to_status_line());
-        match status {
-            HttpStatus::Ok => {
-                let mut headers = controller.headers;
 
-                let content_len = controller.response_body.len();
-                headers
-                    .entry("Content-Length".to_string())
-                    .or_insert(content_len.to_string());
-                let header_len: usize = headers
-                    .iter()
-                    .map(|(key, val)| key.as_bytes().len() + val.as_bytes().len() + 4)
-                    .sum();
-                let response_len = status_line.as_bytes().len() + header_len + 2 + content_len;
-
-                let free_len = buf.remaining_mut();
-                debug!("Free {}, required {}", free_len, response_len);
-                if free_len < response_len {
-                    buf.reserve(response_len);
-                }
+        let content_len = response_body.len();
+        headers.insert("Content-Length".to_string(), content_len.to_string());
+        let header_len: usize = headers
+            .iter()
+            .map(|(key, val)| key.as_bytes().len() + val.as_bytes().len() + 4)
+            .sum();
+        let response_len = status_line.as_bytes().len() + header_len + 2 + content_len;
+
+        let free_len = buf.remaining_mut();
+        debug!("Free {}, required {}", free_len, response_len);
+        if free_len < response_len {
+            buf.reserve(response_len);
+        }
+
+        buf.put_slice(status_line.as_bytes());
+        for (key, val) in headers.iter() {
+            buf.put_slice(key.as_bytes());
+            buf.put_slice(b": ");
+            buf.put_slice(val.as_bytes());
+            buf.put_slice(b"\r\n");
+        }
+        buf.put_slice(b"\r\n");
+        buf.put_slice(&response_body);
+        Ok(())
+    }
 
-                buf.put_slice(status_line.as_bytes());
-                for (key, val) in headers.iter() {
-                    buf.put_slice(key.as_bytes());
-                    buf.put_slice(b": ");
-                    buf.put_slice(val.as_bytes());
-                    buf.put_slice(b"\r\n");
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn set_max_package_size(&mut self, max: Option<usize>) {
+        self.max_size = max;
+    }
+}
+
+/// Error code of a call failed with a http response of `status`
+fn error_code_of_status(status: u16) -> i32 {
+    match status {
+        400 => errno::EREQUEST,
+        401 | 403 => errno::ERPCAUTH,
+        404 | 405 => errno::ENOMETHOD,
+        413 => errno::ETOOLARGE,
+        429 => errno::ERATELIMIT,
+        503 => errno::ELIMIT,
+        504 => errno::ERPCTIMEDOUT,
+        _ if status < 500 => errno::EREQUEST,
+        _ => errno::EINTERNAL,
+    }
+}
+
+#[derive(Clone, Debug)]
+enum HttpResponseState {
+    ReadingHeader,
+    /// (header length, content length, meta)
+    ReadingContent(usize, usize, RpcMeta),
+    /// Number of bytes left to skip
+    Discarding(usize),
+}
+
+/// Http protocol on the client side
+///
+/// A call is sent as `POST /<service>/<method>`, with the serialized request
+/// as an `application/x-protobuf` body, and the `authentication_data` of its
+/// controller as the `Authorization` header. The body of a `2xx` response
+/// is the serialized response, other statuses fail the call with an error
+/// code by the status, and the body as the error text.
+///
+/// Responses carry no correlation id, so they are taken to answer the
+/// requests in order, and must have a `Content-Length`.
+#[derive(Clone, Debug)]
+pub struct HttpClientProtocol {
+    state: HttpResponseState,
+    max_size: Option<usize>,
+    host: String,
+}
+
+impl HttpClientProtocol {
+    /// Create a new instance sending requests to `host`, e.g.
+    /// `10.1.2.3:8080`.
+    pub fn new(host: &str) -> Self {
+        HttpClientProtocol {
+            state: HttpResponseState::ReadingHeader,
+            max_size: None,
+            host: host.to_string(),
+        }
+    }
+
+    /// Parse the header of a response, returning its length, the length of
+    /// the body and the meta.
+    fn parse_header(&self, buf: &BytesMut) -> Result<(usize, usize, RpcMeta), ProtocolError> {
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut resp = HttpResponse::new(&mut headers);
+        let header_len = match resp.parse(buf) {
+            Ok(Status::Complete(header_len)) => header_len,
+            Ok(Status::Partial) => {
+                if let Some(max) = self.max_size {
+                    if buf.len() > max {
+                        debug!("Http response: header is too large");
+                        return Err(ProtocolError::TooLarge(None));
+                    }
                 }
-                buf.put_slice(b"\r\n");
-                buf.put_slice(&controller.response_body);
+                return Err(ProtocolError::NeedMoreBytes);
             }
-            _ => {
-                let response_len = status_line.as_bytes().len();
-                let free_len = buf.remaining_mut();
-                if free_len < response_len {
-                    buf.reserve(response_len);
+            Err(e) => {
+                debug!("Http response: header parse error: {:?}", e);
+                return Err(ProtocolError::AbsolutelyWrong);
+            }
+        };
+        let mut content_len = None;
+        let mut id = 0;
+        for header in resp.headers.iter() {
+            let val = str::from_utf8(header.value).ok().map(str::trim);
+            if header.name.eq_ignore_ascii_case("Content-Length") {
+                content_len = val.and_then(|val| val.parse().ok());
+                if content_len.is_none() {
+                    debug!("Http response: invalid value of Content-Length");
+                    return Err(ProtocolError::AbsolutelyWrong);
                 }
+            } else if header.name.eq_ignore_ascii_case("Correlation-Id") {
+                id = val.and_then(|val| val.parse().ok()).unwrap_or_default();
+            }
+        }
+        let content_len = content_len.ok_or_else(|| {
+            debug!("Http response: no Content-Length, can not find the end of the body");
+            ProtocolError::AbsolutelyWrong
+        })?;
+        let status = resp.code.unwrap_or_default();
 
-                buf.put_slice(status_line.as_bytes());
+        let mut response_meta = RpcResponseMeta::new();
+        if status / 100 != 2 {
+            response_meta.set_error_code(error_code_of_status(status));
+        }
+        let mut meta = RpcMeta::new();
+        meta.set_response(response_meta);
+        meta.set_correlation_id(id);
+        Ok((header_len, content_len, meta))
+    }
+}
+
+impl RpcProtocol for HttpClientProtocol {
+    fn try_parse(
+        &mut self,
+        buf: &mut BytesMut,
+    ) -> Result<(RequestId, (RpcMeta, Controller, Bytes)), ProtocolError> {
+        loop {
+            match self.state {
+                HttpResponseState::ReadingHeader => {
+                    let (header_len, content_len, meta) = self.parse_header(buf)?;
+                    if let Some(max) = self.max_size {
+                        if header_len + content_len > max {
+                            debug!("Http response: content of {} bytes is too large", content_len);
+                            self.state = HttpResponseState::Discarding(header_len + content_len);
+                            let id = meta.get_correlation_id();
+                            return Err(ProtocolError::TooLarge(Some((id, Box::default()))));
+                        }
+                    }
+                    self.state = HttpResponseState::ReadingContent(header_len, content_len, meta);
+                }
+                HttpResponseState::ReadingContent(header_len, content_len, _) => {
+                    if buf.len() < header_len + content_len {
+                        return Err(ProtocolError::NeedMoreBytes);
+                    }
+                    let state =
+                        ::std::mem::replace(&mut self.state, HttpResponseState::ReadingHeader);
+                    let mut meta = match state {
+                        HttpResponseState::ReadingContent(.., meta) => meta,
+                        _ => unreachable!(),
+                    };
+                    buf.advance(header_len);
+                    let body = buf.split_to(content_len).freeze();
+                    let id = meta.get_correlation_id();
+                    if meta.get_response().get_error_code() == errno::SUCCESS {
+                        return Ok((id, (meta, Controller::default(), body)));
+                    }
+                    let text = String::from_utf8_lossy(&body).trim().to_string();
+                    meta.mut_response().set_error_text(text);
+                    return Ok((id, (meta, Controller::default(), Bytes::new())));
+                }
+                HttpResponseState::Discarding(left) => {
+                    let skipped = left.min(buf.len());
+                    buf.advance(skipped);
+                    if skipped < left {
+                        self.state = HttpResponseState::Discarding(left - skipped);
+                        return Err(ProtocolError::NeedMoreBytes);
+                    }
+                    self.state = HttpResponseState::ReadingHeader;
+                }
             }
         }
+    }
+
+    fn new_boxed(&self) -> Box<RpcProtocol> {
+        Box::new(HttpClientProtocol {
+            state: HttpResponseState::ReadingHeader,
+            max_size: self.max_size,
+            host: self.host.clone(),
+        })
+    }
+
+    fn write_package(
+        &self,
+        meta: (RpcMeta, Controller, Bytes),
+        buf: &mut BytesMut,
+    ) -> io::Result<()> {
+        let (meta, controller, body) = meta;
+        let request = meta.get_request();
+        let mut head = format!(
+            "POST /{}/{} HTTP/1.1\r\nHost: {}\r\n",
+            request.get_service_name(),
+            request.get_method_name(),
+            self.host
+        );
+        let mut headers = controller.headers;
+        headers.insert("Content-Type".to_string(), PROTOBUF_CONTENT_TYPES[0].to_string());
+        headers.insert("Content-Length".to_string(), body.len().to_string());
+        if !meta.get_authentication_data().is_empty() {
+            let credential = str::from_utf8(meta.get_authentication_data()).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "authentication data is not a valid Authorization header",
+                )
+            })?;
+            headers.insert("Authorization".to_string(), credential.to_string());
+        }
+        for (key, val) in headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", key, val));
+        }
+        head.push_str("\r\n");
+
+        buf.reserve(head.len() + body.len());
+        buf.put_slice(head.as_bytes());
+        buf.put_slice(&body);
         Ok(())
     }
 
     fn name(&self) -> &'static str {
         "http"
     }
+
+    fn set_max_package_size(&mut self, max: Option<usize>) {
+        self.max_size = max;
+    }
+
+    fn answers_in_order(&self) -> bool {
+        true
+    }
 }
@@ -4,22 +4,26 @@ use bytes::{Bytes, BytesMut};
 use smallvec::SmallVec;
 use std::fmt;
 use std::io;
+use std::time::{Duration, Instant};
 use tokio_io::codec::{Decoder, Encoder};
 use tokio_proto::multiplex::RequestId;
 
+use compress::CompressType;
 use controller::Controller;
-use message::{RpcMeta, RpcRequestMeta, RpcResponseMeta};
-use message::{RequestPackage, ResponsePackage};
+use errno;
+use message::{RpcMeta, RpcResponseMeta};
+use message::{DecodedRequest, RequestPackage, ResponsePackage};
+use service::MethodError;
 
 pub use self::brpc::BrpcProtocol;
-pub use self::http::HttpProtocol;
+pub use self::http::{HttpClientProtocol, HttpProtocol};
 
 pub mod brpc;
 pub mod http;
 
 // TODO: depracate
 /// Protocol selection enum
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Protocol {
     /// brpc protocol
     Brpc,
@@ -38,6 +42,17 @@ pub enum ProtocolError {
     /// The byte stream has partially matched this protocol, but now there is
     /// a decoding error
     AbsolutelyWrong,
+    /// The package exceeds the size limit
+    ///
+    /// If the request can still be answered, the correlation id and the
+    /// controller of the error response are given. The oversized content is
+    /// skipped by later calls of `try_parse`.
+    TooLarge(Option<(RequestId, Box<Controller>)>),
+    /// The request is well formed, but rejected while decoding, e.g. an
+    /// unknown http path, and answered with the error
+    ///
+    /// The rest of the request is skipped by later calls of `try_parse`.
+    Rejected(RequestId, MethodError),
 }
 
 /// A protocl that can decode and encode RPC messages
@@ -60,6 +75,31 @@ pub trait RpcProtocol: Sync + Send {
 
     /// Protocol name.
     fn name(&self) -> &'static str;
+
+    /// Limit the size of packages accepted by `try_parse`.
+    ///
+    /// Protocols that do not override this method accept packages of any
+    /// size.
+    fn set_max_package_size(&mut self, _max: Option<usize>) {}
+
+    /// Whether packages of this protocol can carry compressed bodies, marked
+    /// by the compress type in the meta.
+    ///
+    /// Bodies are never compressed for protocols that do not override this
+    /// method.
+    fn supports_compression(&self) -> bool {
+        false
+    }
+
+    /// Whether responses carry no correlation id, and answer the requests of
+    /// a connection in the order they are sent.
+    ///
+    /// A client sends a request of such protocols only once the one before
+    /// it is answered. Protocols that do not override this method match
+    /// responses to requests by their ids.
+    fn answers_in_order(&self) -> bool {
+        false
+    }
 }
 
 impl fmt::Debug for RpcProtocol {
@@ -108,17 +148,39 @@ impl ProtoCodec {
             tried_num: 0,
         }
     }
+
+    /// Name of the protocol the last request is decoded with.
+    pub fn protocol_name(&self) -> &'static str {
+        self.schemes[self.cached_scheme].name()
+    }
 }
 
 impl Decoder for ProtoCodec {
-    type Item = (RequestId, RequestPackage);
+    type Item = (RequestId, DecodedRequest);
     type Error = io::Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         loop {
             match self.schemes[self.cached_scheme].try_parse(buf) {
-                Ok((id, (mut meta, controller, body))) => {
+                Ok((id, (mut meta, mut controller, body))) => {
                     self.tried_num = 0;
+                    controller.authentication_data = meta.take_authentication_data();
+                    let body = match decompress(meta.get_compress_type(), body) {
+                        Ok((compress, body)) => {
+                            controller.request_compress_type = compress;
+                            body
+                        }
+                        Err(e) => {
+                            warn!("Failed to decompress the request: {}", e);
+                            return Ok(Some((id, Err(MethodError::CodecError))));
+                        }
+                    };
+                    controller.request_id = meta.get_request().get_log_id() as u64;
+                    let timeout_ms = meta.get_request().get_timeout_ms();
+                    if timeout_ms > 0 {
+                        let timeout = Duration::from_millis(timeout_ms as u64);
+                        controller.deadline = Some(Instant::now() + timeout);
+                    }
                     // if !meta.has_request() {
                     //     warn!("Request package do not have request field");
                     //     return Err(io::Error::new(
@@ -126,9 +188,26 @@ impl Decoder for ProtoCodec {
                     //         "Request package do not have request field",
                     //     ));
                     // }
-                    return Ok(Some((id, (meta.take_request(), controller, body))));
+                    return Ok(Some((id, Ok((meta.take_request(), controller, body)))));
                 }
                 Err(ProtocolError::NeedMoreBytes) => return Ok(None),
+                Err(ProtocolError::TooLarge(Some((id, controller)))) => {
+                    self.tried_num = 0;
+                    warn!("Request package is too large, rejected");
+                    let err = MethodError::RequestTooLarge.with_controller(*controller);
+                    return Ok(Some((id, Err(err))));
+                }
+                Err(ProtocolError::Rejected(id, err)) => {
+                    self.tried_num = 0;
+                    return Ok(Some((id, Err(err))));
+                }
+                Err(ProtocolError::TooLarge(None)) => {
+                    warn!("Request package is too large, closing the connection");
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Request package is too large",
+                    ));
+                }
                 Err(ProtocolError::TryOthers) => {
                     self.cached_scheme = (self.cached_scheme + 1) % self.schemes.len();
                     self.tried_num += 1;
@@ -156,14 +235,28 @@ impl Encoder for ProtoCodec {
 
     fn encode(&mut self, msg: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
         let scheme = &self.schemes[self.cached_scheme];
-        let (id, (resp_meta, controller, body)) = msg;
+        let (id, (resp_meta, controller, mut body)) = msg;
         let mut meta = RpcMeta::new();
         meta.set_response(resp_meta);
         meta.set_correlation_id(id);
+        if let Some(compress) = controller.response_compress_type {
+            if scheme.supports_compression() {
+                body = compress.compress(&body)?.into();
+                meta.set_compress_type(compress.to_meta());
+            }
+        }
         scheme.write_package((meta, controller, body), buf)
     }
 }
 
+/// Decompress a body by the compress type in its meta
+fn decompress(compress_type: i32, body: Bytes) -> io::Result<(Option<CompressType>, Bytes)> {
+    match CompressType::from_meta(compress_type)? {
+        Some(compress) => Ok((Some(compress), compress.decompress(&body)?.into())),
+        None => Ok((None, body)),
+    }
+}
+
 /// Client side codec
 pub struct ProtoCodecClient {
     scheme: Box<RpcProtocol>,
@@ -179,9 +272,24 @@ impl fmt::Debug for ProtoCodecClient {
 
 impl ProtoCodecClient {
     /// Create a new client codec.
+    ///
+    /// Responses are limited to the size set on `proto` by
+    /// [`RpcProtocol::set_max_package_size`]. The calls of larger responses
+    /// fail with `errno::ERESPONSETOOLARGE`, and the rest of the response is
+    /// skipped, unless it can not tell the call, then decoding fails.
+    ///
+    /// [`RpcProtocol::set_max_package_size`]: trait.RpcProtocol.html#method.set_max_package_size
     pub fn new(proto: Box<RpcProtocol>) -> Self {
         ProtoCodecClient { scheme: proto }
     }
+
+    /// Whether the responses answer the requests in order, see
+    /// [`RpcProtocol::answers_in_order`].
+    ///
+    /// [`RpcProtocol::answers_in_order`]: trait.RpcProtocol.html#method.answers_in_order
+    pub fn answers_in_order(&self) -> bool {
+        self.scheme.answers_in_order()
+    }
 }
 
 impl Decoder for ProtoCodecClient {
@@ -197,10 +305,27 @@ impl Decoder for ProtoCodecClient {
                         "Response package do not have response field",
                     ));
                 }
+                let (_, body) = decompress(meta.get_compress_type(), body)?;
                 return Ok(Some((id, (meta.take_response(), body))));
             }
             Err(ProtocolError::NeedMoreBytes) => return Ok(None),
-            Err(ProtocolError::TryOthers) | Err(ProtocolError::AbsolutelyWrong) => {
+            Err(ProtocolError::TooLarge(Some((id, _)))) => {
+                warn!("Response package is too large, failed its call");
+                let mut meta = RpcResponseMeta::new();
+                meta.set_error_code(errno::ERESPONSETOOLARGE);
+                meta.set_error_text("response is too large".to_string());
+                Ok(Some((id, (meta, Bytes::new()))))
+            }
+            Err(ProtocolError::TooLarge(None)) => {
+                warn!("Response package is too large, closing the connection");
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Response package is too large",
+                ))
+            }
+            Err(ProtocolError::TryOthers)
+            | Err(ProtocolError::AbsolutelyWrong)
+            | Err(ProtocolError::Rejected(..)) => {
                 error!("Decode response package failed, invalid package or wrong protocol");
                 return Err(io::Error::new(
                     io::ErrorKind::Other,
@@ -212,16 +337,27 @@ impl Decoder for ProtoCodecClient {
 }
 
 impl Encoder for ProtoCodecClient {
-    type Item = (RequestId, (RpcRequestMeta, Bytes));
+    type Item = (RequestId, RequestPackage);
     type Error = io::Error;
 
     fn encode(&mut self, msg: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
-        let (id, (request_meta, body)) = msg;
+        let (id, (mut request_meta, controller, mut body)) = msg;
+        if controller.request_id != 0 {
+            request_meta.set_log_id(controller.request_id as i64);
+        }
         let mut meta = RpcMeta::new();
         meta.set_request(request_meta);
         meta.set_correlation_id(id);
+        if !controller.authentication_data.is_empty() {
+            meta.set_authentication_data(controller.authentication_data.clone());
+        }
+        if let Some(compress) = controller.request_compress_type {
+            if self.scheme.supports_compression() {
+                body = compress.compress(&body)?.into();
+                meta.set_compress_type(compress.to_meta());
+            }
+        }
 
-        self.scheme
-            .write_package((meta, Controller::default(), body), buf)
+        self.scheme.write_package((meta, controller, body), buf)
     }
 }
@@ -0,0 +1,291 @@
+use bytes::{Buf, BufMut};
+use futures::{Async, Poll, Stream};
+use futures::task::AtomicTask;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{self, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::net::{self as unix_net, UnixListener};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio_core::net::TcpStream;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// What the server does when the connection limit is reached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionLimitPolicy {
+    /// Accept new connections and close them at once.
+    Reject,
+    /// Stop accepting until an existing connection is closed. New connections
+    /// are queued in the listen backlog meanwhile.
+    Pause,
+}
+
+/// Socket options set on every accepted TCP connection, and on every
+/// connection of a channel
+///
+/// Options left as `None` are not touched.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TcpOptions {
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<Option<Duration>>,
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+}
+
+impl TcpOptions {
+    pub fn apply(&self, socket: &TcpStream) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            socket.set_keepalive(keepalive)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
+/// Address of the remote end of an accepted connection
+pub(crate) trait PeerAddr: fmt::Debug {
+    /// The address if the peer is connected over TCP.
+    fn inet(&self) -> Option<SocketAddr>;
+}
+
+impl PeerAddr for SocketAddr {
+    fn inet(&self) -> Option<SocketAddr> {
+        Some(*self)
+    }
+}
+
+#[cfg(unix)]
+impl PeerAddr for unix_net::SocketAddr {
+    fn inet(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+/// Hold one slot of the connection count until dropped
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard {
+    count: Arc<AtomicUsize>,
+    task: Arc<AtomicTask>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+        self.task.notify();
+    }
+}
+
+/// Accepted connections that are counted against a limit
+pub(crate) struct LimitedIncoming<I> {
+    incoming: I,
+    count: Arc<AtomicUsize>,
+    max_connections: Option<usize>,
+    policy: ConnectionLimitPolicy,
+    task: Arc<AtomicTask>,
+    reserved: Option<ConnectionGuard>,
+}
+
+impl<I> LimitedIncoming<I> {
+    pub fn new(
+        incoming: I,
+        count: Arc<AtomicUsize>,
+        max_connections: Option<usize>,
+        policy: ConnectionLimitPolicy,
+    ) -> Self {
+        LimitedIncoming {
+            incoming,
+            count,
+            max_connections,
+            policy,
+            task: Arc::new(AtomicTask::new()),
+            reserved: None,
+        }
+    }
+
+    fn acquire(&self) -> Option<ConnectionGuard> {
+        let mut current = self.count.load(Ordering::SeqCst);
+        loop {
+            if let Some(max) = self.max_connections {
+                if current >= max {
+                    return None;
+                }
+            }
+            match self.count.compare_exchange(
+                current,
+                current + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => {
+                    return Some(ConnectionGuard {
+                        count: self.count.clone(),
+                        task: self.task.clone(),
+                    })
+                }
+                Err(prev) => current = prev,
+            }
+        }
+    }
+}
+
+impl<I, S, A> Stream for LimitedIncoming<I>
+where
+    I: Stream<Item = (S, A), Error = io::Error>,
+    A: fmt::Debug,
+{
+    type Item = (CountedStream<S>, A);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if self.reserved.is_none() && self.policy == ConnectionLimitPolicy::Pause {
+                // register before checking, so that a close in between is
+                // not missed
+                self.task.register();
+                match self.acquire() {
+                    Some(guard) => self.reserved = Some(guard),
+                    None => return Ok(Async::NotReady),
+                }
+            }
+
+            let (socket, addr) = match try_ready!(self.incoming.poll()) {
+                Some(conn) => conn,
+                None => return Ok(Async::Ready(None)),
+            };
+            let guard = match self.reserved.take() {
+                Some(guard) => guard,
+                None => match self.acquire() {
+                    Some(guard) => guard,
+                    None => {
+                        warn!("Connection limit reached, closing connection from {:?}", addr);
+                        continue;
+                    }
+                },
+            };
+
+            return Ok(Async::Ready(Some((CountedStream::new(socket, guard), addr))));
+        }
+    }
+}
+
+/// A stream that is counted as an open connection until dropped
+#[derive(Debug)]
+pub(crate) struct CountedStream<S> {
+    io: S,
+    _guard: ConnectionGuard,
+}
+
+impl<S> CountedStream<S> {
+    fn new(io: S, guard: ConnectionGuard) -> Self {
+        CountedStream { io, _guard: guard }
+    }
+}
+
+impl<S: Read> Read for CountedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl<S: Write> Write for CountedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for CountedStream<S> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+
+    fn read_buf<B: BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        <S as AsyncRead>::read_buf(&mut self.io, buf)
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for CountedStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        <S as AsyncWrite>::shutdown(&mut self.io)
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        self.io.write_buf(buf)
+    }
+}
+
+/// A listening socket, which is turned into a stream of connections by
+/// each event loop
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(net::TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match *self {
+            Listener::Tcp(ref listener) => listener.try_clone().map(Listener::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(ref listener) => listener.try_clone().map(Listener::Unix),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{Future, Stream};
+    use std::net;
+    use tokio_core::net::TcpListener;
+    use tokio_core::reactor::Core;
+
+    use super::*;
+
+    fn accept_with(options: TcpOptions) -> (TcpStream, net::TcpStream) {
+        let mut core = Core::new().unwrap();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listener = TcpListener::bind(&addr, &core.handle()).unwrap();
+        let client = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let accept = listener.incoming().into_future().map_err(|(e, _)| e);
+        let (socket, _) = core.run(accept).unwrap().0.unwrap();
+        options.apply(&socket).unwrap();
+        (socket, client)
+    }
+
+    #[test]
+    fn default_options_untouched() {
+        let (socket, _client) = accept_with(TcpOptions::default());
+        assert!(!socket.nodelay().unwrap());
+        assert_eq!(socket.keepalive().unwrap(), None);
+    }
+
+    #[test]
+    fn options_applied() {
+        let options = TcpOptions {
+            nodelay: Some(true),
+            keepalive: Some(Some(Duration::from_secs(30))),
+            recv_buffer_size: Some(64 * 1024),
+            send_buffer_size: Some(64 * 1024),
+        };
+        let (socket, _client) = accept_with(options);
+        assert!(socket.nodelay().unwrap());
+        assert_eq!(socket.keepalive().unwrap(), Some(Duration::from_secs(30)));
+        // the kernel may round the sizes up
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+    }
+}
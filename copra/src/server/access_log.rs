@@ -0,0 +1,87 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Information about one processed request, passed to the access log
+///
+/// See [`ServerBuilder::access_log`].
+///
+/// [`ServerBuilder::access_log`]: struct.ServerBuilder.html#method.access_log
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessLogEntry {
+    /// Address of the client, `None` for Unix domain sockets
+    pub peer: Option<SocketAddr>,
+    /// Id of the request, 0 if the request is rejected while decoding
+    pub request_id: u64,
+    /// Requested service, empty if the request is rejected while decoding
+    pub service: String,
+    /// Requested method, empty if the request is rejected while decoding
+    pub method: String,
+    /// Error code of the response, `errno::SUCCESS` if the call succeeded
+    pub error_code: i32,
+    /// Size of the request body in bytes
+    pub request_size: usize,
+    /// Size of the response body in bytes
+    pub response_size: usize,
+    /// Time from the request being decoded to the response being produced
+    pub latency: Duration,
+}
+
+impl fmt::Display for AccessLogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.peer {
+            Some(ref peer) => write!(f, "{}", peer)?,
+            None => write!(f, "-")?,
+        }
+        let micros = self.latency.as_secs() * 1_000_000 + u64::from(self.latency.subsec_micros());
+        write!(
+            f,
+            " {} {}::{} {} {}B {}B {}us",
+            self.request_id,
+            self.service,
+            self.method,
+            self.error_code,
+            self.request_size,
+            self.response_size,
+            micros
+        )
+    }
+}
+
+/// Write the entry with `info!`
+///
+/// This function can be passed to [`ServerBuilder::access_log`] directly.
+/// The line looks like
+///
+/// ```text
+/// 127.0.0.1:53124 42 Echo::echo 0 18B 18B 105us
+/// ```
+///
+/// [`ServerBuilder::access_log`]: struct.ServerBuilder.html#method.access_log
+pub fn default_access_log(entry: &AccessLogEntry) {
+    info!("{}", entry);
+}
+
+/// A shared access log callback
+#[derive(Clone)]
+pub(crate) struct AccessLog(Arc<Fn(&AccessLogEntry) + Send + Sync>);
+
+impl AccessLog {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&AccessLogEntry) + Send + Sync + 'static,
+    {
+        AccessLog(Arc::new(f))
+    }
+
+    pub fn write(&self, entry: &AccessLogEntry) {
+        (self.0)(entry)
+    }
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AccessLog")
+    }
+}
@@ -0,0 +1,133 @@
+use bytes::Bytes;
+use log::{LogLevel, LogLevelFilter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use url::form_urlencoded;
+
+use controller::Controller;
+use dispatcher::RegistryHandle;
+use message::{ResponsePackage, RpcResponseMeta};
+use protocol::http::HttpStatus;
+
+/// Service name the http protocol gives to requests of the admin handlers
+pub(crate) const SERVICE_NAME: &str = "copra.admin";
+const REGISTRY: &str = "registry";
+const LOG_LEVEL: &str = "loglevel";
+
+/// Verbosity of the server log sites, a `LogLevelFilter` as `usize`
+static VERBOSITY: AtomicUsize = AtomicUsize::new(LogLevelFilter::Info as usize);
+
+/// Get the method name of the admin handler at `/admin/<path>`.
+pub(crate) fn method_name(path: &str) -> Option<&'static str> {
+    match path {
+        REGISTRY => Some(REGISTRY),
+        LOG_LEVEL => Some(LOG_LEVEL),
+        _ => None,
+    }
+}
+
+fn verbosity() -> LogLevelFilter {
+    match VERBOSITY.load(Ordering::SeqCst) {
+        0 => LogLevelFilter::Off,
+        1 => LogLevelFilter::Error,
+        2 => LogLevelFilter::Warn,
+        3 => LogLevelFilter::Info,
+        4 => LogLevelFilter::Debug,
+        _ => LogLevelFilter::Trace,
+    }
+}
+
+/// Get the level to log a message of `level` at.
+///
+/// Messages below info are raised to info while the verbosity set through
+/// `/admin/loglevel` includes them, so they show up without changing the
+/// configuration of the logger.
+pub(crate) fn log_level(level: LogLevel) -> LogLevel {
+    if level > LogLevel::Info && level <= verbosity() {
+        LogLevel::Info
+    } else {
+        level
+    }
+}
+
+/// The admin handlers, served over http on the listeners they are enabled
+/// for
+#[derive(Debug)]
+pub(crate) struct AdminHandlers {
+    registry: RegistryHandle,
+}
+
+impl AdminHandlers {
+    pub fn new(registry: RegistryHandle) -> Self {
+        AdminHandlers { registry }
+    }
+
+    pub fn response(&self, method: &str, url: &str) -> ResponsePackage {
+        let (status, content_type, body) = match method {
+            REGISTRY => (HttpStatus::Ok, "application/json", self.registry_json()),
+            _ => match set_log_level(url) {
+                Ok(level) => (HttpStatus::Ok, "text/plain", format!("{}\n", level)),
+                Err(e) => (HttpStatus::BadRequest, "text/plain", format!("{}\n", e)),
+            },
+        };
+        let mut controller = Controller {
+            status: Some(status),
+            response_body: body.into_bytes(),
+            ..Default::default()
+        };
+        controller.set_content_type(content_type);
+        (RpcResponseMeta::new(), controller, Bytes::new())
+    }
+
+    /// Describe the registered services as an object of method name arrays.
+    fn registry_json(&self) -> String {
+        let registry = self.registry.snapshot();
+        let services: Vec<_> = registry
+            .services()
+            .iter()
+            .map(|service| {
+                let methods: Vec<_> = registry
+                    .methods(service)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|method| json_string(method))
+                    .collect();
+                format!("{}:[{}]", json_string(service), methods.join(","))
+            })
+            .collect();
+        format!("{{{}}}\n", services.join(","))
+    }
+}
+
+/// Set the verbosity from the `level` query parameter, if any, and get the
+/// verbosity in effect.
+fn set_log_level(url: &str) -> Result<LogLevelFilter, String> {
+    let query = match url.find('?') {
+        Some(pos) => &url[pos + 1..],
+        None => "",
+    };
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        if key == "level" {
+            let level: LogLevelFilter = value
+                .parse()
+                .map_err(|_| format!("invalid log level {:?}", value))?;
+            info!("Server log verbosity set to {}", level);
+            VERBOSITY.store(level as usize, Ordering::SeqCst);
+        }
+    }
+    Ok(verbosity())
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c < ' ' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
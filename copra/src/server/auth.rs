@@ -0,0 +1,100 @@
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use message::RpcRequestMeta;
+
+/// Identity of an authenticated client
+///
+/// Returned by the authenticator given to [`ServerBuilder::authenticator`],
+/// and available to handlers as [`Controller::auth_context`].
+///
+/// [`ServerBuilder::authenticator`]: struct.ServerBuilder.html#method.authenticator
+/// [`Controller::auth_context`]: ../controller/struct.Controller.html#structfield.auth_context
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuthContext {
+    /// Name of the user
+    pub user: String,
+    /// Group the user belongs to
+    pub group: String,
+    /// Whether the client is another service rather than a person
+    pub is_service: bool,
+}
+
+impl AuthContext {
+    /// Create a context for `user`.
+    pub fn new<S: Into<String>>(user: S) -> Self {
+        AuthContext {
+            user: user.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Error returned by an authenticator to reject a request
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthError {
+    reason: String,
+}
+
+impl AuthError {
+    /// Create an error, `reason` is sent back to the client.
+    pub fn new<S: Into<String>>(reason: S) -> Self {
+        AuthError {
+            reason: reason.into(),
+        }
+    }
+
+    /// Why the request is rejected.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "authentication failed: {}", self.reason)
+    }
+}
+
+impl Error for AuthError {
+    fn description(&self) -> &str {
+        "authentication failed"
+    }
+}
+
+type AuthFn = Fn(&RpcRequestMeta, &[u8], Option<SocketAddr>) -> Result<AuthContext, AuthError>
+    + Send
+    + Sync;
+
+/// A shared authenticator callback
+#[derive(Clone)]
+pub(crate) struct Authenticator(Arc<AuthFn>);
+
+impl Authenticator {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&RpcRequestMeta, &[u8], Option<SocketAddr>) -> Result<AuthContext, AuthError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Authenticator(Arc::new(f))
+    }
+
+    pub fn authenticate(
+        &self,
+        meta: &RpcRequestMeta,
+        credential: &[u8],
+        peer: Option<SocketAddr>,
+    ) -> Result<AuthContext, AuthError> {
+        (self.0)(meta, credential, peer)
+    }
+}
+
+impl fmt::Debug for Authenticator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Authenticator")
+    }
+}
@@ -0,0 +1,46 @@
+use futures::IntoFuture;
+use futures_cpupool::{Builder, CpuFuture, CpuPool};
+use std::sync::Arc;
+
+/// Thread pool of a server for handlers that block
+///
+/// Created by [`ServerBuilder::blocking_pool`], and passed to handlers as
+/// [`Controller::blocking_pool`]. Work spawned on the pool does not stall
+/// the event loops, so other requests keep being served meanwhile.
+///
+/// [`ServerBuilder::blocking_pool`]: struct.ServerBuilder.html#method.blocking_pool
+/// [`Controller::blocking_pool`]: ../controller/struct.Controller.html#structfield.blocking_pool
+#[derive(Clone, Debug)]
+pub struct BlockingPool {
+    pool: Arc<CpuPool>,
+}
+
+impl BlockingPool {
+    pub(crate) fn new(size: usize) -> Self {
+        let pool = Builder::new()
+            .pool_size(size)
+            .name_prefix("copra-blocking-")
+            .create();
+        BlockingPool {
+            pool: Arc::new(pool),
+        }
+    }
+
+    /// Run `f` on the pool, the returned future resolves to its result.
+    pub fn spawn_fn<F, R>(&self, f: F) -> CpuFuture<R::Item, R::Error>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoFuture + 'static,
+        R::Future: Send + 'static,
+        R::Item: Send + 'static,
+        R::Error: Send + 'static,
+    {
+        self.pool.spawn_fn(f)
+    }
+}
+
+impl PartialEq for BlockingPool {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.pool, &other.pool)
+    }
+}
@@ -1,41 +1,160 @@
-use bytes::{Buf, BufMut};
-use futures::{Async, Poll};
+use bytes::BytesMut;
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use futures::task;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::io::{self, Read, Write};
-use std::mem;
-use std::time::Duration;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use tokio_io::{AsyncRead, AsyncWrite};
-use tokio_timer::{Sleep, Timer};
+use tokio_io::codec::{Decoder, Encoder};
+use tokio_proto::multiplex::RequestId;
 
+use message::{DecodedRequest, ResponsePackage};
+use protocol::ProtoCodec;
+use service::MethodError;
 use super::Second;
 
-pub struct TcpConnection<T> {
+/// Counters of one connection, listed on the status page
+#[derive(Debug)]
+pub(crate) struct ConnectionStats {
+    id: usize,
+    protocol: Mutex<Option<&'static str>>,
+    requests_received: AtomicUsize,
+    responses_sent: AtomicUsize,
+    /// Oneway requests handled, whose responses are not sent
+    oneway_handled: AtomicUsize,
+    last_read: Mutex<Instant>,
+    last_write: Mutex<Instant>,
+}
+
+impl ConnectionStats {
+    fn new(id: usize) -> Self {
+        let now = Instant::now();
+        ConnectionStats {
+            id,
+            protocol: Mutex::new(None),
+            requests_received: AtomicUsize::new(0),
+            responses_sent: AtomicUsize::new(0),
+            oneway_handled: AtomicUsize::new(0),
+            last_read: Mutex::new(now),
+            last_write: Mutex::new(now),
+        }
+    }
+
+    /// Number of requests received and not answered yet.
+    fn inflight(&self) -> usize {
+        let received = self.requests_received.load(Ordering::SeqCst);
+        let answered = self.responses_sent.load(Ordering::SeqCst)
+            + self.oneway_handled.load(Ordering::SeqCst);
+        received.saturating_sub(answered)
+    }
+
+    /// Time since the last read or write, whichever is later.
+    fn since_active(&self) -> Duration {
+        let last_read = *self.last_read.lock().unwrap();
+        let last_write = *self.last_write.lock().unwrap();
+        last_read.max(last_write).elapsed()
+    }
+}
+
+impl fmt::Display for ConnectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let millis = |t: Duration| t.as_secs() * 1000 + u64::from(t.subsec_millis());
+        write!(
+            f,
+            "#{} {}: {} received, {} sent, last read {}ms ago, last write {}ms ago",
+            self.id,
+            self.protocol.lock().unwrap().unwrap_or("-"),
+            self.requests_received.load(Ordering::SeqCst),
+            self.responses_sent.load(Ordering::SeqCst),
+            millis(self.last_read.lock().unwrap().elapsed()),
+            millis(self.last_write.lock().unwrap().elapsed())
+        )
+    }
+}
+
+/// The open connections of a server
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionTable {
+    next_id: AtomicUsize,
+    connections: Mutex<Vec<Weak<ConnectionStats>>>,
+}
+
+impl ConnectionTable {
+    /// Add a new connection, which is removed once its stats are dropped.
+    pub fn open(&self) -> Arc<ConnectionStats> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let stats = Arc::new(ConnectionStats::new(id));
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|conn| conn.upgrade().is_some());
+        connections.push(Arc::downgrade(&stats));
+        stats
+    }
+
+    /// Get the stats of the open connections, oldest first.
+    pub fn snapshot(&self) -> Vec<Arc<ConnectionStats>> {
+        self.connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect()
+    }
+}
+
+/// A server side connection over any byte stream, optionally closed once
+/// idle
+///
+/// A connection is idle when nothing is read or written for a while, and
+/// no request is waiting for its response.
+pub struct Connection<T> {
     io: T,
-    timer: Timer,
-    idle_timeout: Sleep,
-    idle_secs: Second,
+    idle: Option<Duration>,
+    stats: Arc<ConnectionStats>,
 }
 
-impl<T> TcpConnection<T> {
-    pub fn new(io: T, timer: Timer, idle: Second) -> Self {
-        let init_timeout = timer.sleep(Duration::from_secs(idle));
-        TcpConnection {
+impl<T> Connection<T> {
+    /// Wrap `io`, close it after `idle` seconds without traffic, or never
+    /// if `idle` is `None`. Activity is recorded in `stats`.
+    pub fn new(io: T, idle: Option<Second>, stats: Arc<ConnectionStats>) -> Self {
+        Connection {
             io,
-            timer,
-            idle_timeout: init_timeout,
-            idle_secs: idle,
+            idle: idle.map(Duration::from_secs),
+            stats,
         }
     }
 }
 
-impl<T: Read> Read for TcpConnection<T> {
+// `Framed` reads and writes through `Read` and `Write`, so the activity is
+// recorded there rather than in `read_buf` and `write_buf`
+impl<T: Read> Read for Connection<T> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.io.read(buf)
+        // signal EOF
+        if self.is_idle() {
+            // TODO: log IP
+            server_trace!("Server closed a connection due to idle timeout");
+            return Ok(0);
+        }
+
+        let read = self.io.read(buf)?;
+        *self.stats.last_read.lock().unwrap() = Instant::now();
+
+        Ok(read)
     }
 }
 
-impl<T: Write> Write for TcpConnection<T> {
+impl<T: Write> Write for Connection<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.io.write(buf)
+        let wrote = self.io.write(buf)?;
+        // sending responses also keeps the connection alive, e.g. when
+        // reading is paused by `Throttle`
+        if wrote > 0 {
+            *self.stats.last_write.lock().unwrap() = Instant::now();
+        }
+
+        Ok(wrote)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -43,34 +162,344 @@ impl<T: Write> Write for TcpConnection<T> {
     }
 }
 
-impl<T: AsyncRead> AsyncRead for TcpConnection<T> {
+impl<T: AsyncRead> AsyncRead for Connection<T> {
     unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
         self.io.prepare_uninitialized_buffer(buf)
     }
+}
 
-    fn read_buf<B: BufMut>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
-        // signal EOF
-        if self.idle_timeout.is_expired() {
-            // TODO: log IP
-            trace!("Server closed a connection due to idle timeout");
-            return Ok(Async::Ready(0));
+impl<T: AsyncWrite> AsyncWrite for Connection<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        <AsyncWrite>::shutdown(&mut self.io)
+    }
+}
+
+impl<T> Connection<T> {
+    fn is_idle(&self) -> bool {
+        match self.idle {
+            // a long running request does not make the connection idle
+            Some(idle) => self.stats.inflight() == 0 && self.stats.since_active() >= idle,
+            None => false,
+        }
+    }
+}
+
+/// The codec of a server connection
+///
+/// Besides counting the requests and responses, it keeps the correlation
+/// ids of the requests in flight. Clients pick these ids, and may reuse one
+/// once its response arrives, so every request is given a fresh id before
+/// reaching the multiplexer, and the response is sent back with the id on
+/// the wire. A request reusing the id of one still in flight is answered
+/// with `MethodError::DuplicateRequestId`.
+///
+/// Oneway requests, marked so in their meta, are handled but not answered:
+/// their responses are dropped, and their ids are not kept.
+#[derive(Debug)]
+pub struct ConnectionCodec {
+    codec: ProtoCodec,
+    stats: Arc<ConnectionStats>,
+    next_id: RequestId,
+    // wire id of each request in flight, and how it is answered
+    inflight: HashMap<RequestId, (RequestId, Reply)>,
+    // wire ids taken by the requests in flight, duplicates excluded
+    wire_ids: HashSet<RequestId>,
+}
+
+/// How the response to a request in flight is sent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reply {
+    Normal,
+    /// The request reused the id of one in flight
+    Duplicate,
+    /// The request is oneway, its response is dropped
+    Dropped,
+}
+
+impl ConnectionCodec {
+    pub fn new(codec: ProtoCodec, stats: Arc<ConnectionStats>) -> Self {
+        ConnectionCodec {
+            codec,
+            stats,
+            next_id: 0,
+            inflight: HashMap::new(),
+            wire_ids: HashSet::new(),
         }
+    }
 
-        let read = try_ready!(self.io.read_buf(buf));
-        // reset timeout
-        let new_timer = self.timer.sleep(Duration::from_secs(self.idle_secs));
-        let _ = mem::replace(&mut self.idle_timeout, new_timer);
+    /// Get an id not used by any request in flight.
+    fn fresh_id(&mut self) -> RequestId {
+        loop {
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1);
+            if !self.inflight.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+}
+
+impl Decoder for ConnectionCodec {
+    type Item = (RequestId, DecodedRequest);
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let (wire_id, request) = match self.codec.decode(buf)? {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        self.stats.requests_received.fetch_add(1, Ordering::SeqCst);
+        *self.stats.protocol.lock().unwrap() = Some(self.codec.protocol_name());
 
-        Ok(Async::Ready(read))
+        let id = self.fresh_id();
+        let oneway = match request {
+            Ok((ref meta, _, _)) => meta.get_oneway(),
+            Err(_) => false,
+        };
+        if oneway {
+            self.inflight.insert(id, (wire_id, Reply::Dropped));
+            Ok(Some((id, request)))
+        } else if self.wire_ids.insert(wire_id) {
+            self.inflight.insert(id, (wire_id, Reply::Normal));
+            Ok(Some((id, request)))
+        } else {
+            warn!(
+                "Connection #{} reused correlation id {} of a request in flight",
+                self.stats.id, wire_id
+            );
+            self.inflight.insert(id, (wire_id, Reply::Duplicate));
+            Ok(Some((id, Err(MethodError::DuplicateRequestId))))
+        }
     }
 }
 
-impl<T: AsyncWrite> AsyncWrite for TcpConnection<T> {
-    fn shutdown(&mut self) -> Poll<(), io::Error> {
-        <AsyncWrite>::shutdown(&mut self.io)
+impl Encoder for ConnectionCodec {
+    type Item = (RequestId, ResponsePackage);
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        let (id, response) = msg;
+        let wire_id = match self.inflight.remove(&id) {
+            Some((_, Reply::Dropped)) => {
+                self.stats.oneway_handled.fetch_add(1, Ordering::SeqCst);
+                return Ok(());
+            }
+            Some((wire_id, Reply::Duplicate)) => wire_id,
+            Some((wire_id, Reply::Normal)) => {
+                self.wire_ids.remove(&wire_id);
+                wire_id
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "response to an unknown request",
+                ))
+            }
+        };
+        self.stats.responses_sent.fetch_add(1, Ordering::SeqCst);
+        self.codec.encode((wire_id, response), buf)
+    }
+}
+
+/// A transport middleware that stops reading requests when too many
+/// responses are outstanding
+#[derive(Debug)]
+pub struct Throttle<T> {
+    io: T,
+    max_inflight: Option<usize>,
+    inflight: usize,
+    paused: bool,
+}
+
+impl<T> Throttle<T> {
+    pub fn new(max_inflight: Option<usize>, io: T) -> Self {
+        Throttle {
+            io,
+            max_inflight,
+            inflight: 0,
+            paused: false,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.max_inflight
+            .map(|max| self.inflight >= max)
+            .unwrap_or(false)
+    }
+}
+
+impl<T: Stream> Stream for Throttle<T> {
+    type Item = T::Item;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.is_full() {
+            // the task is notified when a response is sent
+            if !self.paused {
+                server_trace!("Pause reading, {} requests in flight", self.inflight);
+                self.paused = true;
+            }
+            return Ok(Async::NotReady);
+        }
+
+        let item = try_ready!(self.io.poll());
+        if item.is_some() {
+            self.inflight += 1;
+        }
+        Ok(Async::Ready(item))
+    }
+}
+
+impl<T: Sink> Sink for Throttle<T> {
+    type SinkItem = T::SinkItem;
+    type SinkError = T::SinkError;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let res = self.io.start_send(item)?;
+        if let AsyncSink::Ready = res {
+            self.inflight = self.inflight.saturating_sub(1);
+            if self.paused && !self.is_full() {
+                server_trace!("Resume reading");
+                self.paused = false;
+                task::current().notify();
+            }
+        }
+        Ok(res)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.io.poll_complete()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+    use controller::Controller;
+    use message::{RpcMeta, RpcRequestMeta, RpcResponseMeta};
+    use protocol::{BrpcProtocol, ProtoCodecClient, RpcProtocol};
+
+    fn codec() -> ConnectionCodec {
+        let protocols: Vec<Box<RpcProtocol>> = vec![Box::new(BrpcProtocol::new())];
+        let stats = Arc::new(ConnectionStats::new(1));
+        ConnectionCodec::new(ProtoCodec::new(&protocols), stats)
+    }
+
+    fn request_frame(wire_id: RequestId, buf: &mut BytesMut) {
+        oneway_frame(wire_id, false, buf);
+    }
+
+    fn oneway_frame(wire_id: RequestId, oneway: bool, buf: &mut BytesMut) {
+        let mut request = RpcRequestMeta::new();
+        request.set_oneway(oneway);
+        request.set_service_name("Echo".to_string());
+        request.set_method_name("echo".to_string());
+        let mut meta = RpcMeta::new();
+        meta.set_request(request);
+        meta.set_correlation_id(wire_id);
+        BrpcProtocol::new()
+            .write_package((meta, Controller::default(), Bytes::new()), buf)
+            .unwrap();
+    }
+
+    fn respond(codec: &mut ConnectionCodec, id: RequestId) -> (RequestId, RpcResponseMeta) {
+        let mut buf = BytesMut::new();
+        let response = (RpcResponseMeta::new(), Controller::default(), Bytes::new());
+        codec.encode((id, response), &mut buf).unwrap();
+        let mut client = ProtoCodecClient::new(Box::new(BrpcProtocol::new()));
+        let (wire_id, (meta, _)) = client.decode(&mut buf).unwrap().unwrap();
+        (wire_id, meta)
+    }
+
+    #[test]
+    fn duplicate_inflight_id_is_rejected() {
+        let mut codec = codec();
+        let mut buf = BytesMut::new();
+        request_frame(7, &mut buf);
+        request_frame(7, &mut buf);
+        request_frame(8, &mut buf);
+
+        let (first, request) = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(request.is_ok());
+        let (duplicate, request) = codec.decode(&mut buf).unwrap().unwrap();
+        match request {
+            Err(MethodError::DuplicateRequestId) => {}
+            other => panic!("unexpected request {:?}", other.map(|_| ())),
+        }
+        let (other, request) = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(request.is_ok());
+        assert!(first != duplicate && first != other && duplicate != other);
+
+        // every response goes back with the id on the wire, and answering
+        // the duplicate leaves the first request in flight
+        assert_eq!(respond(&mut codec, duplicate).0, 7);
+        assert_eq!(respond(&mut codec, other).0, 8);
+        assert!(codec.wire_ids.contains(&7));
+        assert_eq!(respond(&mut codec, first).0, 7);
+        assert!(codec.wire_ids.is_empty());
+        assert!(codec.inflight.is_empty());
+    }
+
+    #[test]
+    fn id_reused_after_completion() {
+        let mut codec = codec();
+        for _ in 0..3 {
+            let mut buf = BytesMut::new();
+            request_frame(u64::MAX, &mut buf);
+            let (id, request) = codec.decode(&mut buf).unwrap().unwrap();
+            assert!(request.is_ok());
+            assert_eq!(respond(&mut codec, id).0, u64::MAX);
+        }
+    }
+
+    #[test]
+    fn fresh_ids_skip_requests_in_flight() {
+        let mut codec = codec();
+        codec.next_id = u64::MAX;
+        let mut buf = BytesMut::new();
+        request_frame(1, &mut buf);
+        let (long_running, _) = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(long_running, u64::MAX);
+
+        // wrap around to an id still in flight
+        codec.next_id = u64::MAX;
+        request_frame(2, &mut buf);
+        let (id, request) = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(request.is_ok());
+        assert_eq!(id, 0);
+
+        assert_eq!(respond(&mut codec, id).0, 2);
+        assert_eq!(respond(&mut codec, long_running).0, 1);
+    }
+
+    #[test]
+    fn oneway_requests_are_not_answered() {
+        let mut codec = codec();
+        let mut buf = BytesMut::new();
+        oneway_frame(7, true, &mut buf);
+        // a oneway request does not take its id
+        request_frame(7, &mut buf);
+        let (oneway, request) = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(request.is_ok());
+        let (other, request) = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(request.is_ok());
+        assert_eq!(codec.stats.inflight(), 2);
+
+        let response = (RpcResponseMeta::new(), Controller::default(), Bytes::new());
+        codec.encode((oneway, response), &mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(codec.stats.inflight(), 1);
+        assert_eq!(respond(&mut codec, other).0, 7);
+        assert!(codec.inflight.is_empty() && codec.wire_ids.is_empty());
     }
 
-    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
-        self.io.write_buf(buf)
+    #[test]
+    fn response_to_unknown_request_fails() {
+        let mut codec = codec();
+        let mut buf = BytesMut::new();
+        let response = (RpcResponseMeta::new(), Controller::default(), Bytes::new());
+        assert!(codec.encode((3, response), &mut buf).is_err());
     }
 }
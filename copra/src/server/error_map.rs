@@ -0,0 +1,58 @@
+use std::fmt;
+use std::sync::Arc;
+
+use message::RpcResponseMeta;
+use service::MethodError;
+
+/// Build the response meta of a failed request the default way
+///
+/// The error code is [`MethodError::error_code`], and the error text is
+/// [`MethodError::error_text`], except for timeouts, which are reported as
+/// `deadline exceeded on server`. This function can be called by a mapper
+/// passed to [`ServerBuilder::error_mapper`] for the errors it keeps as is.
+///
+/// [`MethodError::error_code`]: ../service/enum.MethodError.html#method.error_code
+/// [`MethodError::error_text`]: ../service/enum.MethodError.html#method.error_text
+/// [`ServerBuilder::error_mapper`]: struct.ServerBuilder.html#method.error_mapper
+pub fn default_error_mapper(err: &MethodError, _service: &str, _method: &str) -> RpcResponseMeta {
+    let mut meta = RpcResponseMeta::new();
+    meta.set_error_code(err.error_code());
+    match *err {
+        MethodError::Timeout => {
+            meta.set_error_text("deadline exceeded on server".to_string());
+        }
+        ref e => meta.set_error_text(e.error_text()),
+    }
+    meta
+}
+
+type MapFn = Fn(&MethodError, &str, &str) -> RpcResponseMeta + Send + Sync;
+
+/// A shared callback building the response meta of failed requests
+#[derive(Clone)]
+pub(crate) struct ErrorMapper(Arc<MapFn>);
+
+impl ErrorMapper {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&MethodError, &str, &str) -> RpcResponseMeta + Send + Sync + 'static,
+    {
+        ErrorMapper(Arc::new(f))
+    }
+
+    pub fn map(&self, err: &MethodError, service: &str, method: &str) -> RpcResponseMeta {
+        (self.0)(err, service, method)
+    }
+}
+
+impl Default for ErrorMapper {
+    fn default() -> Self {
+        ErrorMapper::new(default_error_mapper)
+    }
+}
+
+impl fmt::Debug for ErrorMapper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ErrorMapper")
+    }
+}
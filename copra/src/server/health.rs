@@ -0,0 +1,141 @@
+//! Built-in health check service
+//!
+//! Enabled by [`ServerBuilder::enable_health_check`], the service is
+//! registered as `Health`, with one method `check`. The messages are defined
+//! in `copra/src/message/health.proto`. A request names the service to check,
+//! or leaves it empty to check the server as a whole.
+//!
+//! The overall status is also available over http at `/health`, which
+//! responds with `200 OK` when serving and `503 Service Unavailable`
+//! otherwise.
+//!
+//! Every registered service starts as serving. Use the [`HealthReporter`]
+//! from [`Server::health_reporter`] to change that. Once the server is shut
+//! down, everything is reported as not serving while the in-flight requests
+//! are drained.
+//!
+//! [`ServerBuilder::enable_health_check`]: ../struct.ServerBuilder.html#method.enable_health_check
+//! [`HealthReporter`]: struct.HealthReporter.html
+//! [`Server::health_reporter`]: ../struct.Server.html#method.health_reporter
+
+use futures::future::{self, FutureResult};
+use protobuf::Message;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use codec::ProtobufCodec;
+use controller::Controller;
+use dispatcher::{NamedRegistrant, Registrant};
+use message::health_file_descriptor;
+use protocol::http::HttpStatus;
+use service::{EncapsulatedMethod, MethodError, NewEncapService, NewEncapsulatedMethod, Service};
+
+pub use message::{HealthCheckRequest, HealthCheckResponse, ServingStatus};
+
+/// Name of the health check service
+pub const SERVICE_NAME: &str = "Health";
+
+/// Handle to change the status reported by the health check service
+///
+/// The handle can be cloned and sent to other threads.
+#[derive(Clone, Debug, Default)]
+pub struct HealthReporter {
+    statuses: Arc<RwLock<HashMap<String, ServingStatus>>>,
+}
+
+impl HealthReporter {
+    /// Set the status of `service`, use an empty name for the whole server.
+    pub fn set_status(&self, service: &str, status: ServingStatus) {
+        self.statuses
+            .write()
+            .unwrap()
+            .insert(service.to_string(), status);
+    }
+
+    /// Mark `service` as serving.
+    pub fn set_serving(&self, service: &str) {
+        self.set_status(service, ServingStatus::SERVING);
+    }
+
+    /// Mark `service` as not serving.
+    pub fn set_not_serving(&self, service: &str) {
+        self.set_status(service, ServingStatus::NOT_SERVING);
+    }
+
+    /// Mark the whole server and every reported service as not serving.
+    pub fn set_all_not_serving(&self) {
+        let mut statuses = self.statuses.write().unwrap();
+        for status in statuses.values_mut() {
+            *status = ServingStatus::NOT_SERVING;
+        }
+        statuses.insert(String::new(), ServingStatus::NOT_SERVING);
+    }
+
+    /// Get the status of `service`.
+    ///
+    /// Returns `ServingStatus::SERVICE_UNKNOWN` for services that are never
+    /// reported.
+    pub fn status(&self, service: &str) -> ServingStatus {
+        self.statuses
+            .read()
+            .unwrap()
+            .get(service)
+            .cloned()
+            .unwrap_or(ServingStatus::SERVICE_UNKNOWN)
+    }
+}
+
+#[derive(Clone)]
+struct Check(HealthReporter);
+
+impl Service for Check {
+    type Request = (HealthCheckRequest, Controller);
+    type Response = (HealthCheckResponse, Controller);
+    type Error = MethodError;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn call(&self, (req, ctrl): Self::Request) -> Self::Future {
+        let status = self.0.status(req.get_service());
+        let mut reply = Controller::default();
+        if ctrl.http_url.is_some() {
+            reply.status = Some(match status {
+                ServingStatus::SERVING => HttpStatus::Ok,
+                _ => HttpStatus::ServiceUnavailable,
+            });
+            reply.response_body = format!("{:?}", status).into_bytes();
+        }
+        let mut resp = HealthCheckResponse::new();
+        resp.set_status(status);
+        future::ok((resp, reply))
+    }
+}
+
+/// Registrant of the health check service
+#[derive(Debug)]
+pub(crate) struct HealthRegistrant {
+    reporter: HealthReporter,
+}
+
+impl HealthRegistrant {
+    pub fn new(reporter: HealthReporter) -> Self {
+        HealthRegistrant { reporter }
+    }
+}
+
+impl Registrant for HealthRegistrant {
+    fn methods(&self) -> Vec<(String, NewEncapService)> {
+        let method = EncapsulatedMethod::new(ProtobufCodec::new(), Check(self.reporter.clone()));
+        let method = NewEncapsulatedMethod::new(method);
+        vec![("check".to_string(), Box::new(method) as NewEncapService)]
+    }
+
+    fn file_descriptor(&self) -> Option<Vec<u8>> {
+        health_file_descriptor().write_to_bytes().ok()
+    }
+}
+
+impl NamedRegistrant for HealthRegistrant {
+    fn name() -> &'static str {
+        SERVICE_NAME
+    }
+}
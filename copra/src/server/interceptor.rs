@@ -0,0 +1,117 @@
+use futures::future;
+use std::fmt;
+use std::sync::Arc;
+
+use dispatcher::RegistryHandle;
+use message::RequestPackage;
+use service::{MethodError, MethodFuture, Service};
+
+/// Code that runs around every request, before it reaches the service
+///
+/// Interceptors are added by [`ServerBuilder::add_interceptor`], and run in
+/// the order they are added. Each one receives the request and the rest of
+/// the chain as `next`. It can modify the request, including the
+/// [`Controller`], before passing it to `next`, modify the response that
+/// `next` returns, or answer the request itself without calling `next`.
+///
+/// # Examples
+///
+/// Reject HTTP requests without the right token:
+///
+/// ```
+/// # extern crate copra;
+/// # extern crate futures;
+/// use copra::{Controller, MethodError};
+/// use copra::message::RequestPackage;
+/// use copra::protocol::http::HttpStatus;
+/// use copra::server::{Interceptor, Next};
+/// use copra::service::MethodFuture;
+/// use futures::future;
+///
+/// struct TokenAuth(String);
+///
+/// impl Interceptor for TokenAuth {
+///     fn call(&self, req: RequestPackage, next: &Next) -> MethodFuture {
+///         if req.1.headers.get("Authorization") == Some(&self.0) {
+///             return next.call(req);
+///         }
+///         let mut reply = Controller::default();
+///         reply.status = Some(HttpStatus::Forbidden);
+///         let err = MethodError::Failed("unauthorized".to_string());
+///         Box::new(future::err(err.with_controller(reply)))
+///     }
+/// }
+/// # fn main() {}
+/// ```
+///
+/// [`ServerBuilder::add_interceptor`]: struct.ServerBuilder.html#method.add_interceptor
+/// [`Controller`]: ../controller/struct.Controller.html
+pub trait Interceptor: Send + Sync {
+    /// Process a request, usually by passing it to `next`.
+    fn call(&self, req: RequestPackage, next: &Next) -> MethodFuture;
+}
+
+impl fmt::Debug for Interceptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Interceptor")
+    }
+}
+
+/// The rest of an interceptor chain, ending with the requested method
+#[derive(Clone, Debug)]
+pub struct Next {
+    interceptors: Arc<Vec<Box<Interceptor>>>,
+    registry: RegistryHandle,
+    index: usize,
+}
+
+impl Next {
+    pub(crate) fn new(
+        interceptors: Arc<Vec<Box<Interceptor>>>,
+        registry: RegistryHandle,
+    ) -> Self {
+        Next {
+            interceptors,
+            registry,
+            index: 0,
+        }
+    }
+
+    /// Pass the request to the next interceptor, or to the method if this is
+    /// the end of the chain.
+    pub fn call(&self, req: RequestPackage) -> MethodFuture {
+        match self.interceptors.get(self.index) {
+            Some(interceptor) => {
+                let next = Next {
+                    index: self.index + 1,
+                    ..self.clone()
+                };
+                interceptor.call(req, &next)
+            }
+            None => self.call_method(req),
+        }
+    }
+
+    fn call_method(&self, req: RequestPackage) -> MethodFuture {
+        let (meta, controller, body) = req;
+        let registry = self.registry.snapshot();
+        let service_name = meta.get_service_name();
+        let method_name = meta.get_method_name();
+        if let Some(service) = registry.get_method(service_name, method_name) {
+            return service.call((body, controller));
+        }
+        if let Some(handler) = registry.default_handler() {
+            return handler.call(service_name, method_name, body, controller);
+        }
+        warn!(
+            "Method {}::{} of request {} is not found",
+            service_name, method_name, controller.request_id
+        );
+        let err = if registry.has_service(service_name) {
+            MethodError::MethodNotFound
+        } else {
+            MethodError::ServiceNotFound
+        };
+        Box::new(future::err(err))
+    }
+}
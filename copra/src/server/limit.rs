@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug)]
+struct Limit {
+    max: usize,
+    running: AtomicUsize,
+}
+
+/// Concurrency limits of methods
+#[derive(Debug, Default)]
+pub(crate) struct MethodLimits {
+    limits: HashMap<String, HashMap<String, Arc<Limit>>>,
+}
+
+impl MethodLimits {
+    pub fn set(&mut self, service: &str, method: &str, max: usize) {
+        let limit = Limit {
+            max,
+            running: AtomicUsize::new(0),
+        };
+        self.limits
+            .entry(service.to_string())
+            .or_default()
+            .insert(method.to_string(), Arc::new(limit));
+    }
+
+    /// Count a request to the method as running.
+    ///
+    /// Returns `Err` if the method is running as many requests as allowed,
+    /// `Ok(None)` if the method has no limit.
+    pub fn acquire(&self, service: &str, method: &str) -> Result<Option<Permit>, ()> {
+        let limit = match self.limits.get(service).and_then(|m| m.get(method)) {
+            Some(limit) => limit,
+            None => return Ok(None),
+        };
+        let mut running = limit.running.load(Ordering::SeqCst);
+        loop {
+            if running >= limit.max {
+                return Err(());
+            }
+            match limit.running.compare_exchange(
+                running,
+                running + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => return Ok(Some(Permit(limit.clone()))),
+                Err(current) => running = current,
+            }
+        }
+    }
+}
+
+/// A running request of a limited method, which is done once dropped
+#[derive(Debug)]
+pub(crate) struct Permit(Arc<Limit>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn permits_are_returned_on_drop() {
+        let mut limits = MethodLimits::default();
+        limits.set("Echo", "echo", 2);
+
+        let first = limits.acquire("Echo", "echo").unwrap();
+        let second = limits.acquire("Echo", "echo").unwrap();
+        assert!(first.is_some() && second.is_some());
+        assert!(limits.acquire("Echo", "echo").is_err());
+        assert!(limits.acquire("Echo", "reverse").unwrap().is_none());
+
+        drop(first);
+        assert!(limits.acquire("Echo", "echo").unwrap().is_some());
+    }
+}
@@ -21,82 +21,453 @@
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! To serve in the background, use [`Server::start_background`]. The socket
+//! is already listening when it returns, so clients can connect at once:
+//!
+//! ```no_run
+//! # extern crate copra;
+//! # use std::error::Error;
+//! use copra::{ServiceRegistry, ServerBuilder};
+//! # fn main() {
+//! #     try_main().unwrap();
+//! # }
+//! # fn try_main() -> Result<(), Box<Error>> {
+//!
+//! let registry = ServiceRegistry::new();
+//! let server = ServerBuilder::new("127.0.0.1:8000", registry).build()?;
+//! let handle = server.start_background();
+//!
+//! // connect to handle.local_addrs() and issue some requests
+//!
+//! handle.stop().unwrap();
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A server started by [`Server::start`] can be stopped from another thread
+//! through its [`ShutdownHandle`].
+//!
+//! An application already running a `tokio-core` event loop can serve on it
+//! with [`Server::serve_on`] instead, which returns a future to be spawned on
+//! the loop.
+//!
+//! One server can listen to several addresses, each with its own protocols,
+//! e.g. an HTTP-only admin port:
+//!
+//! ```no_run
+//! # extern crate copra;
+//! # use std::error::Error;
+//! use copra::{ServiceRegistry, ServerBuilder};
+//! use copra::protocol::Protocol;
+//! # fn main() {
+//! #     try_main().unwrap();
+//! # }
+//! # fn try_main() -> Result<(), Box<Error>> {
+//!
+//! let registry = ServiceRegistry::new();
+//! let server = ServerBuilder::new("0.0.0.0:8000", registry)
+//!     .add_listener("127.0.0.1:8001", vec![Protocol::Http])
+//!     .build()?;
+//! server.start();
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Server::start_background`]: struct.Server.html#method.start_background
+//! [`Server::start`]: struct.Server.html#method.start
+//! [`Server::serve_on`]: struct.Server.html#method.serve_on
+//! [`ShutdownHandle`]: struct.ShutdownHandle.html
 
 use bytes::Bytes;
-use tokio_core::reactor::Remote;
-use tokio_proto::TcpServer;
-use tokio_proto::multiplex::Multiplex;
+use net2::TcpBuilder;
+#[cfg(unix)]
+use net2::unix::UnixTcpBuilderExt;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle, Remote};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_proto::BindServer;
 use tokio_service::{NewService, Service};
-use tokio_timer::Timer;
+use tokio_timer::{Sleep, Timer};
 use std::error::Error;
 use std::fmt;
+use std::fs;
 use std::io;
-use std::net::AddrParseError;
-use std::sync::Arc;
-use std::sync::atomic::AtomicUsize;
-use futures::{Future, IntoFuture, Stream};
-use futures::future::Executor;
+use std::any::Any;
+use std::net::{self, AddrParseError, SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use futures::{future, Future, Stream};
+#[cfg(unix)]
+use tokio_uds;
+use futures::future::{Either, Executor};
+#[cfg(feature = "tls")]
+use native_tls;
 
 use controller::Controller;
+use errno;
 use protocol::Protocol;
-use dispatcher::ServiceRegistry;
-use service::MethodError;
-use message::RpcResponseMeta;
-use message::{RequestPackage, ResponsePackage};
-use monitor::ThroughputMaintainer;
+use protocol::http::{HttpProtocol, HttpStatus};
+use dispatcher::{RegistryHandle, ServiceRegistry};
+use service::{MethodError, MethodFuture};
+use message::{RpcRequestMeta, RpcResponseMeta};
+use message::{DecodedRequest, ResponsePackage};
+use monitor::{ExecutingGuard, FinishedCount, ServerStats, Throughput, ThroughputMaintainer};
+use timer;
 
+use self::access_log::AccessLog;
+use self::admin::AdminHandlers;
+use self::error_map::ErrorMapper;
+use self::auth::Authenticator;
+use self::health::{HealthRegistrant, HealthReporter};
+use self::limit::MethodLimits;
+use self::rate_limit::{RateLimitKey, RateLimiter};
+use self::reflection::ReflectionRegistrant;
+use self::status::StatusPage;
+use self::accept::{LimitedIncoming, Listener, PeerAddr};
+pub(crate) use self::accept::TcpOptions;
+use self::connection::ConnectionTable;
 use self::protocol::MetaServerProtocol;
+use self::shutdown::{Drained, InFlight, InFlightGuard};
+#[cfg(feature = "tls")]
+use self::tls::TlsAcceptor;
+
+pub use self::accept::ConnectionLimitPolicy;
+pub use self::access_log::{default_access_log, AccessLogEntry};
+pub use self::auth::{AuthContext, AuthError};
+pub use self::blocking::BlockingPool;
+pub use self::error_map::default_error_mapper;
+pub use self::interceptor::{Interceptor, Next};
+pub use self::shutdown::ShutdownHandle;
+
+/// Log at debug level, raised by the verbosity set through the admin
+/// handlers
+macro_rules! server_debug {
+    ($($arg:tt)*) => (log!(::server::admin::log_level(::log::LogLevel::Debug), $($arg)*))
+}
 
+/// Log at trace level, raised by the verbosity set through the admin
+/// handlers
+macro_rules! server_trace {
+    ($($arg:tt)*) => (log!(::server::admin::log_level(::log::LogLevel::Trace), $($arg)*))
+}
+
+mod accept;
+mod access_log;
+pub(crate) mod admin;
+mod auth;
+mod blocking;
 mod connection;
+mod error_map;
+pub mod health;
+mod interceptor;
+mod limit;
 mod protocol;
+mod rate_limit;
+pub mod reflection;
+mod shutdown;
+#[cfg(all(unix, feature = "signals"))]
+mod signal;
+pub(crate) mod status;
+#[cfg(feature = "tls")]
+mod tls;
 
 type Second = u64;
 
+/// A listening socket with the protocols served on it
+type BoundListener = (Listener, Arc<MetaServerProtocol>);
+
 type MetaServiceFuture = Box<Future<Item = ResponsePackage, Error = io::Error>>;
 
+/// Id of the next request without one from the client
+static NEXT_REQUEST_ID: AtomicUsize = AtomicUsize::new(1);
+
 #[derive(Clone)]
 struct MetaService {
-    registry: Arc<ServiceRegistry>,
+    chain: Next,
+    in_flight: Arc<InFlight>,
+    timer: Timer,
+    request_timeout: Option<Duration>,
+    access_log: Option<AccessLog>,
+    authenticator: Option<Authenticator>,
+    status_page: Option<Arc<StatusPage>>,
+    admin: Option<Arc<AdminHandlers>>,
+    limits: Arc<MethodLimits>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    slow_threshold: Option<Duration>,
+    blocking_pool: Option<BlockingPool>,
+    compress_over: Option<usize>,
+    error_mapper: ErrorMapper,
+    stats: Arc<ServerStats>,
+    peer: Option<SocketAddr>,
 }
 
 impl MetaService {
-    pub fn new(registry: Arc<ServiceRegistry>) -> Self {
-        MetaService { registry }
+    pub fn new(
+        registry: RegistryHandle,
+        interceptors: Arc<Vec<Box<Interceptor>>>,
+        in_flight: Arc<InFlight>,
+        timer: Timer,
+        request_timeout: Option<Duration>,
+        access_log: Option<AccessLog>,
+        authenticator: Option<Authenticator>,
+    ) -> Self {
+        MetaService {
+            chain: Next::new(interceptors, registry),
+            in_flight,
+            timer,
+            request_timeout,
+            access_log,
+            authenticator,
+            status_page: None,
+            admin: None,
+            limits: Arc::default(),
+            rate_limiter: None,
+            slow_threshold: None,
+            blocking_pool: None,
+            compress_over: None,
+            error_mapper: ErrorMapper::default(),
+            stats: Arc::default(),
+            peer: None,
+        }
+    }
+
+    /// A copy of the service that serves the connection from `peer`.
+    pub fn for_peer(&self, peer: Option<SocketAddr>) -> Self {
+        MetaService {
+            peer,
+            ..self.clone()
+        }
+    }
+
+    fn access_entry(&self, req: &DecodedRequest) -> AccessLogEntry {
+        let (request_id, service, method, request_size) = match *req {
+            Ok((ref meta, ref controller, ref body)) => (
+                controller.request_id,
+                meta.get_service_name().to_string(),
+                meta.get_method_name().to_string(),
+                body.len(),
+            ),
+            Err(_) => (0, String::new(), String::new(), 0),
+        };
+        AccessLogEntry {
+            peer: self.peer,
+            request_id,
+            service,
+            method,
+            error_code: errno::SUCCESS,
+            request_size,
+            response_size: 0,
+            latency: Duration::default(),
+        }
+    }
+
+    /// Answer a request with `err`, without calling the method.
+    fn reject(&self, err: MethodError, service: &str, method: &str) -> MetaServiceFuture {
+        let resp = result_to_errno(Err(err), &self.error_mapper, service, method);
+        Box::new(future::result(resp))
+    }
+
+    fn dispatch(&self, req: DecodedRequest) -> MetaServiceFuture {
+        let (meta, mut controller, body) = match req {
+            Ok(req) => req,
+            Err(e) => return self.reject(e, "", ""),
+        };
+        if let Some(ref page) = self.status_page {
+            if controller.http_url.is_some() && meta.get_service_name() == status::SERVICE_NAME
+                && meta.get_method_name() == status::METHOD_NAME
+            {
+                return Box::new(future::ok(page.response()));
+            }
+        }
+        if let Some(ref admin) = self.admin {
+            if let Some(ref url) = controller.http_url {
+                if meta.get_service_name() == admin::SERVICE_NAME {
+                    return Box::new(future::ok(admin.response(meta.get_method_name(), url)));
+                }
+            }
+        }
+        if let Some(ref authenticator) = self.authenticator {
+            let credential = &controller.authentication_data;
+            match authenticator.authenticate(&meta, credential, self.peer) {
+                Ok(context) => controller.auth_context = Some(context),
+                Err(e) => {
+                    warn!(
+                        "Rejected request {} from {:?}: {}",
+                        controller.request_id, self.peer, e
+                    );
+                    let reply = Controller {
+                        status: Some(HttpStatus::Unauthorized),
+                        ..Default::default()
+                    };
+                    let err = MethodError::Unauthenticated(e.reason().to_string());
+                    let err = err.with_controller(reply);
+                    return self.reject(err, meta.get_service_name(), meta.get_method_name());
+                }
+            }
+        }
+        // the client deadline or the request timeout, whichever is earlier
+        let timeout = match (controller.remaining_time(), self.request_timeout) {
+            (Some(remaining), Some(timeout)) => Some(remaining.min(timeout)),
+            (remaining, timeout) => remaining.or(timeout),
+        };
+        if timeout == Some(Duration::from_secs(0)) {
+            server_debug!(
+                "Dropped request {} to {}::{}, the client deadline has passed",
+                controller.request_id,
+                meta.get_service_name(),
+                meta.get_method_name()
+            );
+            let err = MethodError::Timeout;
+            return self.reject(err, meta.get_service_name(), meta.get_method_name());
+        }
+        if let Some(ref limiter) = self.rate_limiter {
+            if !limiter.check(&controller, self.peer) {
+                server_debug!(
+                    "Rejected request {} from {:?}, rate limit reached",
+                    controller.request_id, self.peer
+                );
+                self.stats.record_rate_limited();
+                let reply = Controller {
+                    status: Some(HttpStatus::TooManyRequests),
+                    ..Default::default()
+                };
+                let err = MethodError::RateLimited.with_controller(reply);
+                return self.reject(err, meta.get_service_name(), meta.get_method_name());
+            }
+        }
+        let permit = match self.limits
+            .acquire(meta.get_service_name(), meta.get_method_name())
+        {
+            Ok(permit) => permit,
+            Err(()) => {
+                server_debug!(
+                    "Rejected request {} to {}::{}, concurrency limit reached",
+                    controller.request_id,
+                    meta.get_service_name(),
+                    meta.get_method_name()
+                );
+                self.stats.record_concurrency_rejected();
+                let reply = Controller {
+                    status: Some(HttpStatus::ServiceUnavailable),
+                    ..Default::default()
+                };
+                let err = MethodError::Busy.with_controller(reply);
+                return self.reject(err, meta.get_service_name(), meta.get_method_name());
+            }
+        };
+        controller.blocking_pool = self.blocking_pool.clone();
+        let request_id = controller.request_id;
+        let accepted_compress = controller.request_compress_type;
+        let compress_over = self.compress_over;
+        let error_mapper = self.error_mapper.clone();
+        let service_name = meta.get_service_name().to_string();
+        let method_name = meta.get_method_name().to_string();
+        let req = (meta, controller, body);
+        let guard = InFlightGuard::new(self.in_flight.clone());
+        let executing = ExecutingGuard::new(self.stats.clone());
+        // a panicking handler should not tear down the whole connection
+        let chain = self.chain.clone();
+        let call = future::lazy(move || chain.call(req));
+        let response: MethodFuture = Box::new(AssertUnwindSafe(call).catch_unwind().then(
+            move |result| match result {
+                Ok(resp) => resp,
+                Err(payload) => {
+                    let msg = panic_message(&payload);
+                    error!("Handler of request {} panicked: {}", request_id, msg);
+                    Err(MethodError::Panic(msg))
+                }
+            },
+        ));
+        let response = match timeout {
+            Some(timeout) => with_deadline(response, self.timer.sleep(timeout), request_id),
+            None => response,
+        };
+        let response = response.then(move |resp| {
+            drop(guard);
+            drop(executing);
+            drop(permit);
+            if let Err(ref e) = resp {
+                server_debug!("Request {} failed: {}", request_id, e);
+            }
+            let result = result_to_errno(resp, &error_mapper, &service_name, &method_name);
+            result.map(|(meta, mut controller, body)| {
+                // unless the handler decides, compress large responses the
+                // way the client compresses
+                match compress_over {
+                    Some(over)
+                        if body.len() > over && controller.response_compress_type.is_none() =>
+                    {
+                        controller.response_compress_type = accepted_compress;
+                    }
+                    _ => {}
+                }
+                (meta, controller, body)
+            })
+        });
+        Box::new(response)
     }
 }
 
 impl Service for MetaService {
-    type Request = RequestPackage;
+    type Request = DecodedRequest;
     type Response = ResponsePackage;
     type Error = io::Error;
     type Future = MetaServiceFuture;
 
     fn call(&self, req: Self::Request) -> Self::Future {
-        let (meta, controller, body) = req;
-        let service = {
-            let service_name = meta.get_service_name();
-            let method_name = meta.get_method_name();
-            self.registry
-                .get_method(service_name, method_name)
-                .ok_or(MethodError::UnknownError)
-                .map_err(|e| {
+        let req = req.map(|(meta, mut controller, body)| {
+            if controller.request_id == 0 {
+                controller.request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::SeqCst) as u64;
+            }
+            (meta, controller, body)
+        });
+        if self.access_log.is_none() && self.slow_threshold.is_none() {
+            return self.dispatch(req);
+        }
+        let access_log = self.access_log.clone();
+        let slow_threshold = self.slow_threshold;
+        let stats = self.stats.clone();
+        let mut entry = self.access_entry(&req);
+        let start = Instant::now();
+        let response = self.dispatch(req).map(move |resp| {
+            entry.error_code = resp.0.get_error_code();
+            entry.response_size = resp.2.len();
+            entry.latency = start.elapsed();
+            if let Some(ref log) = access_log {
+                log.write(&entry);
+            }
+            match slow_threshold {
+                Some(threshold) if entry.latency > threshold => {
                     warn!(
-                        "Requested method {}::{} is not found",
-                        service_name, method_name
+                        "Slow request {}: {}::{} from {:?} took {:?}, request size {}B",
+                        entry.request_id,
+                        entry.service,
+                        entry.method,
+                        entry.peer,
+                        entry.latency,
+                        entry.request_size
                     );
-                    e
-                })
-                .into_future()
-        };
-        let response = service
-            .and_then(|service| service.call((body, controller)))
-            .then(|resp| result_to_errno(resp));
+                    stats.record_slow_request();
+                }
+                _ => {}
+            }
+            resp
+        });
         Box::new(response)
     }
 }
 
 impl NewService for MetaService {
-    type Request = RequestPackage;
+    type Request = DecodedRequest;
     type Response = ResponsePackage;
     type Error = io::Error;
     type Instance = Self;
@@ -107,16 +478,32 @@ impl NewService for MetaService {
 }
 
 /// Error raised when building a server
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum ServerBuildError {
     /// Failed to parse socket address from string
     AddrParseError(AddrParseError),
+    /// Failed to bind the listening socket
+    ///
+    /// The message of the inner error contains the address, its kind is kept
+    /// from the original error, e.g. `AddrInUse`.
+    BindError(io::Error),
+    /// Failed to resolve the host name to listen to
+    ///
+    /// The message of the inner error contains the host name.
+    ResolveError(io::Error),
+    /// The option is not supported on this platform
+    UnsupportedOption(&'static str),
 }
 
 impl fmt::Display for ServerBuildError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ServerBuildError::AddrParseError(ref e) => write!(f, "address parse error {}", e),
+            ServerBuildError::BindError(ref e) => write!(f, "bind error {}", e),
+            ServerBuildError::ResolveError(ref e) => write!(f, "resolve error {}", e),
+            ServerBuildError::UnsupportedOption(name) => {
+                write!(f, "option {} is not supported on this platform", name)
+            }
         }
     }
 }
@@ -125,12 +512,17 @@ impl Error for ServerBuildError {
     fn description(&self) -> &str {
         match *self {
             ServerBuildError::AddrParseError(_) => "failed to parse socket address from raw string",
+            ServerBuildError::BindError(_) => "failed to bind the listening socket",
+            ServerBuildError::ResolveError(_) => "failed to resolve the host name",
+            ServerBuildError::UnsupportedOption(_) => "option not supported on this platform",
         }
     }
 
     fn cause(&self) -> Option<&Error> {
         match *self {
             ServerBuildError::AddrParseError(ref e) => Some(e),
+            ServerBuildError::BindError(ref e) | ServerBuildError::ResolveError(ref e) => Some(e),
+            ServerBuildError::UnsupportedOption(_) => None,
         }
     }
 }
@@ -141,37 +533,198 @@ impl From<AddrParseError> for ServerBuildError {
     }
 }
 
+#[derive(Debug)]
+enum Listen<'a> {
+    Addr(&'a str),
+    Listener(net::TcpListener),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Where to listen, the protocols served there if not the default ones, and
+/// whether the admin handlers are served
+type ListenerEntry<'a> = (Listen<'a>, Option<Vec<Protocol>>, bool);
+
+/// Where a server is listening
+#[derive(Clone, Debug)]
+enum LocalAddr {
+    Inet(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl LocalAddr {
+    fn inet(&self) -> Option<SocketAddr> {
+        match *self {
+            LocalAddr::Inet(addr) => Some(addr),
+            LocalAddr::Unix(_) => None,
+        }
+    }
+
+    fn path(&self) -> Option<&Path> {
+        match *self {
+            LocalAddr::Inet(_) => None,
+            LocalAddr::Unix(ref path) => Some(path),
+        }
+    }
+}
+
+impl fmt::Display for LocalAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LocalAddr::Inet(ref addr) => addr.fmt(f),
+            LocalAddr::Unix(ref path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
 /// Server factory, which can be used to setup up a new server
 ///
 /// You can chain up the methods to configure the channel.
 #[derive(Debug)]
 pub struct ServerBuilder<'a> {
     services: ServiceRegistry,
-    addr: &'a str,
+    interceptors: Vec<Box<Interceptor>>,
+    health_check: bool,
+    reflection: bool,
+    status_page: bool,
+    admin: bool,
+    method_limits: MethodLimits,
+    listeners: Vec<ListenerEntry<'a>>,
     threads: Option<usize>,
     protocols: Option<Vec<Protocol>>,
     idle_secs: Option<Second>,
     remote: Option<Remote>,
-    throughput: Option<Arc<AtomicUsize>>,
+    throughput: Option<Throughput>,
+    grace_period: Option<Duration>,
+    max_connections: Option<usize>,
+    limit_policy: Option<ConnectionLimitPolicy>,
+    max_inflight_per_connection: Option<usize>,
+    max_request_size: Option<usize>,
+    http_prefix: String,
+    request_timeout: Option<Duration>,
+    access_log: Option<AccessLog>,
+    authenticator: Option<Authenticator>,
+    rate_limit: Option<(u32, u32)>,
+    rate_limit_key: RateLimitKey,
+    slow_threshold: Option<Duration>,
+    blocking_pool: Option<usize>,
+    compress_over: Option<usize>,
+    error_mapper: ErrorMapper,
+    socket_mode: Option<u32>,
+    bind_options: BindOptions,
+    tcp_options: TcpOptions,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+    #[cfg(all(unix, feature = "signals"))]
+    handle_signals: bool,
 }
 
 impl<'a> ServerBuilder<'a> {
     /// Create a server listening to `addr`.
+    ///
+    /// `addr` is a socket address, e.g. `0.0.0.0:8000` or `[::]:8000`, or a
+    /// host name with a port, e.g. `localhost:8000`, in which case the
+    /// server listens to every address the name resolves to. With port 0,
+    /// these addresses share the same port. See [`only_v6`] for IPv6
+    /// wildcard addresses.
+    ///
+    /// [`only_v6`]: #method.only_v6
     pub fn new(addr: &'a str, services: ServiceRegistry) -> Self {
+        Self::with_listen(Listen::Addr(addr), services)
+    }
+
+    /// Create a server accepting connections from an already bound listener.
+    ///
+    /// This is useful when the socket is handed over by someone else, e.g.
+    /// systemd socket activation. The listener is set to non-blocking mode
+    /// when the server starts. If more than one thread is configured, every
+    /// event loop accepts from a duplicate of the listener.
+    pub fn from_listener(listener: net::TcpListener, services: ServiceRegistry) -> Self {
+        Self::with_listen(Listen::Listener(listener), services)
+    }
+
+    /// Create a server listening to the Unix domain socket at `path`.
+    ///
+    /// If `path` is a socket file left by a server that is no longer
+    /// running, it is removed before binding. The file is removed again when
+    /// the server stops.
+    ///
+    /// This method is only available on Unix.
+    #[cfg(unix)]
+    pub fn new_uds<P: AsRef<Path>>(path: P, services: ServiceRegistry) -> Self {
+        Self::with_listen(Listen::Unix(path.as_ref().to_path_buf()), services)
+    }
+
+    fn with_listen(listen: Listen<'a>, services: ServiceRegistry) -> Self {
         ServerBuilder {
             services,
-            addr,
+            interceptors: Vec::new(),
+            health_check: false,
+            reflection: false,
+            status_page: false,
+            admin: false,
+            method_limits: MethodLimits::default(),
+            listeners: vec![(listen, None, false)],
             threads: None,
             protocols: None,
-            idle_secs: None,
+            idle_secs: Some(60),
             remote: None,
             throughput: None,
+            grace_period: None,
+            max_connections: None,
+            limit_policy: None,
+            max_inflight_per_connection: None,
+            max_request_size: None,
+            http_prefix: String::new(),
+            request_timeout: None,
+            access_log: None,
+            authenticator: None,
+            rate_limit: None,
+            rate_limit_key: RateLimitKey::default(),
+            slow_threshold: None,
+            blocking_pool: None,
+            compress_over: None,
+            error_mapper: ErrorMapper::default(),
+            socket_mode: None,
+            bind_options: BindOptions::default(),
+            tcp_options: TcpOptions::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(all(unix, feature = "signals"))]
+            handle_signals: false,
         }
     }
 
+    /// Also listen to `addr`, and only serve `protocols` on it.
+    ///
+    /// This method can be called multiple times. All listeners share the
+    /// services and the rest of the settings, e.g. the connection limit
+    /// counts connections from every listener. A typical use is serving an
+    /// HTTP-only admin port next to the main one.
+    pub fn add_listener(mut self, addr: &'a str, protocols: Vec<Protocol>) -> Self {
+        self.listeners.push((Listen::Addr(addr), Some(protocols), false));
+        self
+    }
+
+    /// Also listen to `addr`, and serve http with the admin handlers on it.
+    ///
+    /// Unless [`enable_admin`] is called too, the admin handlers are only
+    /// served on the listeners added by this method, which keeps them off
+    /// the public port. See [`enable_admin`] for the handlers.
+    ///
+    /// [`enable_admin`]: #method.enable_admin
+    pub fn add_admin_listener(mut self, addr: &'a str) -> Self {
+        self.listeners
+            .push((Listen::Addr(addr), Some(vec![Protocol::Http]), true));
+        self
+    }
+
     /// Set the number of event loops.
     ///
-    /// The thread number should not exceed the CPU core number.
+    /// The thread number should not exceed the CPU core number. All event
+    /// loops accept connections from the same listening socket, so binding to
+    /// port 0 gives one port shared by all of them.
+    ///
     /// Default to 1.
     pub fn threads(mut self, threads: usize) -> Self {
         self.threads = Some(threads);
@@ -179,55 +732,640 @@ impl<'a> ServerBuilder<'a> {
     }
 
     /// [WIP] Set the protocols that the server willing to support.
+    ///
+    /// Listeners added by [`add_listener`] use their own protocols instead.
+    ///
+    /// [`add_listener`]: #method.add_listener
     pub fn protocols(mut self, protocols: Vec<Protocol>) -> Self {
         self.protocols = Some(protocols);
         self
     }
 
-    // TODO: default to no-op
-    /// Keep an idle connection for `idle` seconds before it iS shuted down
+    /// Keep an idle connection for `idle` seconds before it is shut down
     /// by the server.
     ///
-    /// Default to 60 seconds
-    pub fn idle_secs(mut self, idle: Second) -> Self {
-        self.idle_secs = Some(idle);
+    /// A connection is idle when nothing is read or written, and none of
+    /// its requests is running, so that a slow request does not lose its
+    /// connection.
+    ///
+    /// `None` or `Some(0)` keeps idle connections open until the client
+    /// closes them, which suits long-lived connections that only carry
+    /// traffic now and then. Default to 60 seconds.
+    pub fn idle_secs(mut self, idle: Option<Second>) -> Self {
+        self.idle_secs = idle;
         self
     }
 
+    /// Never close a connection for being idle.
+    ///
+    /// Same as `idle_secs(None)`.
+    pub fn disable_idle_timeout(self) -> Self {
+        self.idle_secs(None)
+    }
+
     /// [WIP] Server monitor, expose throught to the shared variable
     /// `throughput`.
-    pub fn throughput(mut self, throughput: Arc<AtomicUsize>, remote: Remote) -> Self {
+    ///
+    /// Same as [`throughput_monitor`] with a handle storing the total rate
+    /// in `throughput`.
+    ///
+    /// [`throughput_monitor`]: #method.throughput_monitor
+    pub fn throughput(self, throughput: Arc<AtomicUsize>, remote: Remote) -> Self {
+        self.throughput_monitor(Throughput::with_total(throughput), remote)
+    }
+
+    /// Calculate the rates of responses every second on `remote`, and
+    /// store them in `throughput`.
+    ///
+    /// See [`Throughput`] for the rates.
+    ///
+    /// [`Throughput`]: ../monitor/struct.Throughput.html
+    pub fn throughput_monitor(mut self, throughput: Throughput, remote: Remote) -> Self {
         self.throughput = Some(throughput);
         self.remote = Some(remote);
         self
     }
 
+    /// Set how long the server waits for in-flight requests to finish after
+    /// a shutdown is requested.
+    ///
+    /// Requests still running when the grace period ends are dropped. The
+    /// period should be shorter than an hour, which is the longest timeout
+    /// the internal timer supports.
+    ///
+    /// Default to 10 seconds.
+    pub fn grace_period(mut self, grace: Duration) -> Self {
+        self.grace_period = Some(grace);
+        self
+    }
+
+    /// Set the maximum number of open connections.
+    ///
+    /// What happens to new connections beyond the limit is decided by
+    /// [`connection_limit_policy`].
+    ///
+    /// Default to no limit.
+    ///
+    /// [`connection_limit_policy`]: #method.connection_limit_policy
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Set what to do when the connection limit is reached.
+    ///
+    /// Default to `ConnectionLimitPolicy::Reject`.
+    pub fn connection_limit_policy(mut self, policy: ConnectionLimitPolicy) -> Self {
+        self.limit_policy = Some(policy);
+        self
+    }
+
+    /// Set the maximum number of requests being processed on one connection.
+    ///
+    /// Once the limit is reached, the server stops reading from that
+    /// connection until some responses are sent. This prevents a client from
+    /// pipelining an unbounded number of requests.
+    ///
+    /// Default to no limit.
+    pub fn max_inflight_per_connection(mut self, max: usize) -> Self {
+        self.max_inflight_per_connection = Some(max);
+        self
+    }
+
+    /// Serve http calls to methods below the path `prefix`, e.g. `/rpc`
+    /// for `POST /rpc/<service>/<method>`.
+    ///
+    /// The built-in pages stay at their fixed paths. See the [`http`]
+    /// module for how requests are routed.
+    ///
+    /// Default to no prefix.
+    ///
+    /// [`http`]: ../protocol/http/index.html
+    pub fn http_path_prefix(mut self, prefix: &str) -> Self {
+        self.http_prefix = prefix.to_string();
+        self
+    }
+
+    /// Set the maximum size of a request in bytes, headers included.
+    ///
+    /// The size declared by a request is checked before its payload is read,
+    /// so an oversized request is never buffered. The server skips the
+    /// payload and responds with an error, `errno::ETOOLARGE` for brpc and
+    /// `413 Payload Too Large` for HTTP. If the request cannot even be
+    /// identified, the connection is closed.
+    ///
+    /// Default to no limit.
+    pub fn max_request_size(mut self, bytes: usize) -> Self {
+        self.max_request_size = Some(bytes);
+        self
+    }
+
+    /// Set the longest time a request can be processed.
+    ///
+    /// A request still running after `timeout` is cancelled, and an error
+    /// response with code `errno::ERPCTIMEDOUT` is sent back. Like the grace
+    /// period, the timeout should be shorter than an hour.
+    ///
+    /// Requests carrying a client timeout in brpc meta are cancelled the same
+    /// way at the client deadline, if it comes first, and are not processed
+    /// at all if it has passed on arrival. Handlers can check the deadline
+    /// with [`Controller::remaining_time`].
+    ///
+    /// Default to `None`, which means waiting for the handler forever.
+    ///
+    /// [`Controller::remaining_time`]: ../controller/struct.Controller.html#method.remaining_time
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit the number of requests `service::method` processes at the same
+    /// time, across all connections and worker threads.
+    ///
+    /// Requests beyond the limit are not queued, but rejected at once with
+    /// `errno::ELIMIT`, or `503 Service Unavailable` in http, and counted in
+    /// [`ServerStats::concurrency_rejected`]. A request stops counting
+    /// towards the limit when its response is sent, or when it is dropped,
+    /// e.g. by the request timeout.
+    ///
+    /// Default to no limit.
+    ///
+    /// [`ServerStats::concurrency_rejected`]: ../monitor/struct.ServerStats.html#method.concurrency_rejected
+    pub fn method_concurrency(mut self, service: &str, method: &str, max: usize) -> Self {
+        self.method_limits.set(service, method, max);
+        self
+    }
+
+    /// Register the built-in health check service.
+    ///
+    /// See the [`health`] module for the protocol. The status can be changed
+    /// through [`Server::health_reporter`].
+    ///
+    /// [`health`]: health/index.html
+    /// [`Server::health_reporter`]: struct.Server.html#method.health_reporter
+    pub fn enable_health_check(mut self) -> Self {
+        self.health_check = true;
+        self
+    }
+
+    /// Register the built-in reflection service.
+    ///
+    /// See the [`reflection`] module for the protocol.
+    ///
+    /// [`reflection`]: reflection/index.html
+    pub fn enable_reflection(mut self) -> Self {
+        self.reflection = true;
+        self
+    }
+
+    /// Serve a status page over http at `/` and `/status`.
+    ///
+    /// The plain text page shows the uptime, the listening addresses, the
+    /// registered services and methods, the number of requests in flight,
+    /// the throughput, and the open connections with their protocol, the
+    /// requests received, the responses sent, and the time since the last
+    /// read and write. It is answered by the server itself, so the page
+    /// works on every listener serving http without registering anything,
+    /// and bypasses the authenticator and the interceptors.
+    pub fn enable_status_page(mut self) -> Self {
+        self.status_page = true;
+        self
+    }
+
+    /// Serve the admin handlers over http on every listener.
+    ///
+    /// The handlers are:
+    ///
+    /// * `/admin/registry`, a JSON object mapping every registered service,
+    ///   including the ones added while running, to its method names.
+    /// * `/admin/loglevel?level=debug`, which sets the verbosity of the
+    ///   server logs and answers with the verbosity in effect. Messages up
+    ///   to this level are logged at info level, so they show up without
+    ///   changing the configuration of the logger. The default is `info`.
+    ///
+    /// Like the status page, the handlers bypass the authenticator and the
+    /// interceptors, so they should not be exposed publicly. Prefer
+    /// [`add_admin_listener`] to serve them on a separate address.
+    ///
+    /// [`add_admin_listener`]: #method.add_admin_listener
+    pub fn enable_admin(mut self) -> Self {
+        self.admin = true;
+        self
+    }
+
+    /// Run `interceptor` around every request.
+    ///
+    /// Interceptors run in the order they are added, before the request
+    /// reaches the service. See [`Interceptor`] for details.
+    ///
+    /// [`Interceptor`]: trait.Interceptor.html
+    pub fn add_interceptor(mut self, interceptor: Box<Interceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Check every request with `f` before it is processed.
+    ///
+    /// `f` receives the request meta, the credential sent by the client and
+    /// the address of the client, which is `None` for Unix domain sockets.
+    /// The credential is the authentication data in brpc meta, or the
+    /// `Authorization` header in http.
+    ///
+    /// If `f` returns an [`AuthContext`], it is stored in the controller
+    /// passed to the handler. If `f` returns an error, the request is
+    /// answered with `errno::ERPCAUTH`, or `401 Unauthorized` in http,
+    /// without reaching the interceptors and the services.
+    ///
+    /// Default to accepting every request.
+    ///
+    /// [`AuthContext`]: struct.AuthContext.html
+    pub fn authenticator<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&RpcRequestMeta, &[u8], Option<SocketAddr>) -> Result<AuthContext, AuthError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.authenticator = Some(Authenticator::new(f));
+        self
+    }
+
+    /// Limit each client to `requests_per_sec` requests per second on
+    /// average, with bursts of up to `burst` requests.
+    ///
+    /// Every client has a token bucket holding up to `burst` tokens, which
+    /// is refilled at `requests_per_sec`. A request takes a token, or is
+    /// rejected with `errno::ERATELIMIT`, or `429 Too Many Requests` in
+    /// http, if the bucket is empty. Rejections are counted in
+    /// [`ServerStats::rate_limited`]. The limit is checked after the
+    /// authenticator, and shared by all listeners and worker threads.
+    ///
+    /// Clients are told apart by their IP address, see [`rate_limit_key`]
+    /// to change that.
+    ///
+    /// Default to no limit.
+    ///
+    /// [`ServerStats::rate_limited`]: ../monitor/struct.ServerStats.html#method.rate_limited
+    /// [`rate_limit_key`]: #method.rate_limit_key
+    pub fn rate_limit_per_peer(mut self, requests_per_sec: u32, burst: u32) -> Self {
+        self.rate_limit = Some((requests_per_sec, burst));
+        self
+    }
+
+    /// Tell clients apart with `f` for the rate limit.
+    ///
+    /// `f` receives the controller of the request, which holds the
+    /// [`AuthContext`] when an authenticator is set, and the address of the
+    /// client, which is `None` for Unix domain sockets. Requests for which
+    /// `f` returns `None` are not limited.
+    ///
+    /// Default to the IP address of the client.
+    ///
+    /// [`AuthContext`]: struct.AuthContext.html
+    pub fn rate_limit_key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Controller, Option<SocketAddr>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.rate_limit_key = RateLimitKey::new(f);
+        self
+    }
+
+    /// Call `f` after each request is processed.
+    ///
+    /// `f` receives an [`AccessLogEntry`] for every response, including
+    /// error responses, e.g. for unknown methods or oversized requests. It
+    /// is called on the event loop threads, so it should not block. Pass
+    /// [`default_access_log`] to write the entries with `info!`.
+    ///
+    /// Default to no access log.
+    ///
+    /// [`AccessLogEntry`]: struct.AccessLogEntry.html
+    /// [`default_access_log`]: fn.default_access_log.html
+    pub fn access_log<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&AccessLogEntry) + Send + Sync + 'static,
+    {
+        self.access_log = Some(AccessLog::new(f));
+        self
+    }
+
+    /// Log requests taking longer than `threshold` with `warn!`.
+    ///
+    /// The line shows the service, method, client, latency and request
+    /// size, and the request is counted in [`ServerStats::slow_requests`].
+    /// The latency is measured the same way as in the access log. Unlike
+    /// the access log, nothing is written for requests within the threshold.
+    ///
+    /// Default to no slow request log.
+    ///
+    /// [`ServerStats::slow_requests`]: ../monitor/struct.ServerStats.html#method.slow_requests
+    pub fn slow_request_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_threshold = Some(threshold);
+        self
+    }
+
+    /// Compress responses larger than `bytes` when the client supports it.
+    ///
+    /// A brpc client compressing its request is taken to accept responses
+    /// compressed the same way, responses to other clients are not
+    /// compressed. Handlers can decide for themselves by setting
+    /// [`Controller::response_compress_type`], which takes precedence.
+    ///
+    /// Default to only compress as handlers decide.
+    ///
+    /// [`Controller::response_compress_type`]: ../controller/struct.Controller.html#structfield.response_compress_type
+    pub fn compress_responses_over(mut self, bytes: usize) -> Self {
+        self.compress_over = Some(bytes);
+        self
+    }
+
+    /// Build the response meta of failed requests with `f`.
+    ///
+    /// `f` receives the error and the requested service and method names,
+    /// which are empty if the request fails to decode. It covers every
+    /// failed request, including unknown methods, panicking handlers and
+    /// requests rejected by the server, and decides the error code and
+    /// text the client sees, e.g. to hide internal details. With the http
+    /// protocol, the status follows the error code unless the handler sets
+    /// one.
+    ///
+    /// Default to [`default_error_mapper`].
+    ///
+    /// [`default_error_mapper`]: fn.default_error_mapper.html
+    pub fn error_mapper<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&MethodError, &str, &str) -> RpcResponseMeta + Send + Sync + 'static,
+    {
+        self.error_mapper = ErrorMapper::new(f);
+        self
+    }
+
+    /// Create a pool of `size` threads for handlers that block.
+    ///
+    /// Handlers reach the pool through [`Controller::spawn_blocking`], e.g.
+    /// to call a blocking library without stalling the other requests on
+    /// the event loop. The pool is shared by all worker threads.
+    ///
+    /// Default to no pool.
+    ///
+    /// [`Controller::spawn_blocking`]: ../controller/struct.Controller.html#method.spawn_blocking
+    pub fn blocking_pool(mut self, size: usize) -> Self {
+        self.blocking_pool = Some(size);
+        self
+    }
+
+    /// Set the permission bits of the Unix domain socket file, e.g. `0o660`.
+    ///
+    /// Only servers created by [`new_uds`] are affected.
+    ///
+    /// Default to the permissions given by the process umask.
+    ///
+    /// [`new_uds`]: #method.new_uds
+    #[cfg(unix)]
+    pub fn socket_mode(mut self, mode: u32) -> Self {
+        self.socket_mode = Some(mode);
+        self
+    }
+
+    /// Set `SO_REUSEADDR` on the listening sockets before binding.
+    ///
+    /// Only listeners bound by the server are affected, i.e. not the ones
+    /// given to [`from_listener`].
+    ///
+    /// Default to `true`.
+    ///
+    /// [`from_listener`]: #method.from_listener
+    pub fn reuse_addr(mut self, reuse: bool) -> Self {
+        self.bind_options.reuse_addr = reuse;
+        self
+    }
+
+    /// Set `SO_REUSEPORT` on the listening sockets before binding.
+    ///
+    /// With this option, several processes can listen to the same port, and
+    /// the kernel spreads new connections among them. This allows restarting
+    /// without downtime: start the new process, then shut down the old one.
+    ///
+    /// Only listeners bound by the server are affected. The option is only
+    /// supported on Unix, [`build`] fails with
+    /// `ServerBuildError::UnsupportedOption` elsewhere.
+    ///
+    /// Default to `false`.
+    ///
+    /// [`build`]: #method.build
+    pub fn reuse_port(mut self, reuse: bool) -> Self {
+        self.bind_options.reuse_port = reuse;
+        self
+    }
+
+    /// Whether listeners on IPv6 addresses only accept IPv6 connections,
+    /// i.e. the `IPV6_V6ONLY` socket option.
+    ///
+    /// With `false`, a server listening to `[::]` also accepts IPv4
+    /// clients, whatever the default of the platform is. Only listeners
+    /// bound by the server are affected.
+    ///
+    /// Default to `false`.
+    pub fn only_v6(mut self, only_v6: bool) -> Self {
+        self.bind_options.only_v6 = only_v6;
+        self
+    }
+
+    /// Set the length of the queue of pending connections, i.e. the backlog
+    /// argument of `listen()`.
+    ///
+    /// A longer queue helps when many clients connect at once, e.g. after a
+    /// deploy. All event loops accept from the same socket, so they share
+    /// this queue. Only listeners bound by the server are affected, and the
+    /// system may cap the value, e.g. by `net.core.somaxconn` on Linux.
+    ///
+    /// Default to 1024.
+    pub fn backlog(mut self, backlog: i32) -> Self {
+        self.bind_options.backlog = backlog;
+        self
+    }
+
+    /// Set `TCP_NODELAY` on accepted connections.
+    ///
+    /// Disabling Nagle's algorithm saves a round trip for small responses.
+    ///
+    /// Default to the system setting, which is usually `false`.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp_options.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Set TCP keepalive on accepted connections.
+    ///
+    /// `Some(idle)` enables keepalive probes after the connection is idle for
+    /// `idle`, `None` disables them.
+    ///
+    /// Default to the system setting.
+    pub fn tcp_keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.tcp_options.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Set the size of the receive buffer of accepted connections in bytes.
+    ///
+    /// Default to the system setting.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.tcp_options.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size of the send buffer of accepted connections in bytes.
+    ///
+    /// Default to the system setting.
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.tcp_options.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Serve TLS connections only, using `acceptor` for the handshake.
+    ///
+    /// Connections that fail the handshake are logged and closed. The idle
+    /// timeout counts from the end of the handshake.
+    ///
+    /// This method requires the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, acceptor: native_tls::TlsAcceptor) -> Self {
+        self.tls = Some(acceptor.into());
+        self
+    }
+
+    /// Shut down the server gracefully on SIGTERM or SIGINT.
+    ///
+    /// On either signal, the server stops accepting connections, reports not
+    /// serving through the health check if enabled, and waits up to `grace`
+    /// for in-flight requests to finish before [`Server::start`] returns.
+    /// This overrides [`grace_period`].
+    ///
+    /// The signal handlers are installed for the whole process when the
+    /// server starts. This method requires the `signals` feature.
+    ///
+    /// [`Server::start`]: struct.Server.html#method.start
+    /// [`grace_period`]: #method.grace_period
+    #[cfg(all(unix, feature = "signals"))]
+    pub fn handle_signals(mut self, grace: Duration) -> Self {
+        self.handle_signals = true;
+        self.grace_period = Some(grace);
+        self
+    }
+
     /// Consume the builder and build.
-    pub fn build(self) -> Result<Server, ServerBuildError> {
-        let finished = Arc::new(AtomicUsize::new(0));
+    ///
+    /// Unless a listener is given, the listening sockets are bound here, so
+    /// that the real addresses are known before the server starts, e.g. when
+    /// binding to port 0.
+    pub fn build(mut self) -> Result<Server, ServerBuildError> {
+        let health = if self.health_check {
+            let reporter = HealthReporter::default();
+            reporter.set_serving("");
+            for service in self.services.services() {
+                reporter.set_serving(service);
+            }
+            self.services
+                .register_service(HealthRegistrant::new(reporter.clone()));
+            Some(reporter)
+        } else {
+            None
+        };
+        if self.reflection {
+            let registrant = ReflectionRegistrant::new(&self.services);
+            self.services.register_service(registrant);
+        }
+        let finished = Arc::new(FinishedCount::default());
         let threads = self.threads.unwrap_or(1);
         let protocols = self.protocols
             .unwrap_or(vec![Protocol::Brpc, Protocol::Http]);
-        let idle_secs = self.idle_secs.unwrap_or(60);
-        let throughput = self.throughput.unwrap_or(Arc::new(AtomicUsize::new(0)));
+        let idle_secs = self.idle_secs.filter(|&idle| idle > 0);
+        let throughput = self.throughput.unwrap_or_default();
+        let grace_period = self.grace_period.unwrap_or(Duration::from_secs(10));
+        let limit_policy = self.limit_policy.unwrap_or(ConnectionLimitPolicy::Reject);
+        let rate_limit_key = self.rate_limit_key;
+        let rate_limiter = self.rate_limit
+            .map(|(rate, burst)| Arc::new(RateLimiter::new(rate, burst, rate_limit_key)));
+        let bind_options = self.bind_options;
+        if bind_options.reuse_port && !cfg!(unix) {
+            return Err(ServerBuildError::UnsupportedOption("reuse_port"));
+        }
 
-        let timer = Timer::default();
-        let socket_addr = self.addr.parse()?;
+        let timer = timer::new();
+        let open_connections = Arc::new(ConnectionTable::default());
+        let mut listeners = Vec::with_capacity(self.listeners.len());
+        let mut local_addrs = Vec::with_capacity(self.listeners.len());
+        let admin = self.admin || self.listeners.iter().any(|entry| entry.2);
+        for (listen, listen_protocols, listen_admin) in self.listeners {
+            let bound = match listen {
+                Listen::Addr(addr) => inet_listeners(bind_all(addr, &bind_options)?)?,
+                Listen::Listener(listener) => inet_listeners(vec![listener])?,
+                #[cfg(unix)]
+                Listen::Unix(path) => {
+                    let listener = bind_uds(&path, self.socket_mode)
+                        .map_err(|e| bind_error(&path.display(), e))?;
+                    vec![(Listener::Unix(listener), LocalAddr::Unix(path))]
+                }
+            };
+            let http = HttpProtocol::with_prefix(&self.http_prefix);
+            let http = if self.admin || listen_admin {
+                http.with_admin()
+            } else {
+                http
+            };
+            let protocol = MetaServerProtocol::new(
+                listen_protocols.unwrap_or_else(|| protocols.clone()),
+                open_connections.clone(),
+                idle_secs,
+                finished.clone(),
+                self.max_inflight_per_connection,
+                self.max_request_size,
+                &http,
+            );
 
-        let mut listener = TcpServer::new(
-            MetaServerProtocol::new(protocols, timer.clone(), idle_secs, finished.clone()),
-            socket_addr,
-        );
-        listener.threads(threads);
+            let protocol = Arc::new(protocol);
+            for (listener, local_addr) in bound {
+                info!("Server listening: {}", local_addr);
+                listeners.push((listener, protocol.clone()));
+                local_addrs.push(local_addr);
+            }
+        }
 
-        info!("Server listening: {}", socket_addr);
+        let connections = Arc::new(AtomicUsize::new(0));
         let server = Server {
-            services: Arc::new(self.services),
-            listener,
+            services: RegistryHandle::new(self.services),
+            interceptors: Arc::new(self.interceptors),
+            health,
+            status_page: self.status_page,
+            admin,
+            limits: Arc::new(self.method_limits),
+            stats: Arc::new(ServerStats::new(connections.clone())),
+            listeners: Mutex::new(Some(listeners)),
+            local_addrs,
+            threads,
             throughput,
             finished,
             timer,
             remote: self.remote,
+            shutdown: ShutdownHandle::default(),
+            grace_period,
+            connections,
+            open_connections,
+            max_connections: self.max_connections,
+            limit_policy,
+            request_timeout: self.request_timeout,
+            access_log: self.access_log,
+            authenticator: self.authenticator,
+            rate_limiter,
+            slow_threshold: self.slow_threshold,
+            blocking_pool: self.blocking_pool.map(BlockingPool::new),
+            compress_over: self.compress_over,
+            error_mapper: self.error_mapper,
+            tcp_options: self.tcp_options,
+            #[cfg(feature = "tls")]
+            tls: self.tls,
+            #[cfg(all(unix, feature = "signals"))]
+            handle_signals: self.handle_signals,
         };
 
         Ok(server)
@@ -237,19 +1375,206 @@ impl<'a> ServerBuilder<'a> {
 /// A RPC server
 #[derive(Debug)]
 pub struct Server {
-    services: Arc<ServiceRegistry>,
-    listener: TcpServer<Multiplex, MetaServerProtocol>,
-    finished: Arc<AtomicUsize>,
-    throughput: Arc<AtomicUsize>,
+    services: RegistryHandle,
+    interceptors: Arc<Vec<Box<Interceptor>>>,
+    health: Option<HealthReporter>,
+    status_page: bool,
+    admin: bool,
+    limits: Arc<MethodLimits>,
+    stats: Arc<ServerStats>,
+    listeners: Mutex<Option<Vec<BoundListener>>>,
+    local_addrs: Vec<LocalAddr>,
+    threads: usize,
+    finished: Arc<FinishedCount>,
+    throughput: Throughput,
     timer: Timer,
     remote: Option<Remote>,
+    shutdown: ShutdownHandle,
+    grace_period: Duration,
+    connections: Arc<AtomicUsize>,
+    open_connections: Arc<ConnectionTable>,
+    max_connections: Option<usize>,
+    limit_policy: ConnectionLimitPolicy,
+    request_timeout: Option<Duration>,
+    access_log: Option<AccessLog>,
+    authenticator: Option<Authenticator>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    slow_threshold: Option<Duration>,
+    blocking_pool: Option<BlockingPool>,
+    compress_over: Option<usize>,
+    error_mapper: ErrorMapper,
+    tcp_options: TcpOptions,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+    #[cfg(all(unix, feature = "signals"))]
+    handle_signals: bool,
 }
 
 impl Server {
+    /// Get the TCP addresses the server is listening to.
+    ///
+    /// The addresses are in the order the listeners are added, so the first
+    /// one is given to [`ServerBuilder::new`].
+    ///
+    /// [`ServerBuilder::new`]: struct.ServerBuilder.html#method.new
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        inet_addrs(&self.local_addrs)
+    }
+
+    /// Get the path of the Unix domain socket the server is listening to.
+    ///
+    /// Returns `None` for TCP servers.
+    pub fn local_path(&self) -> Option<&Path> {
+        unix_path(&self.local_addrs)
+    }
+
+    /// Get a handle which can stop the server.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Get a handle to add and remove services while the server is running.
+    ///
+    /// The built-in reflection service keeps describing the services
+    /// registered when the server is built.
+    pub fn registry_handle(&self) -> RegistryHandle {
+        self.services.clone()
+    }
+
+    /// Get the number of open connections.
+    pub fn connection_count(&self) -> usize {
+        self.stats.connections()
+    }
+
+    /// Get the number of requests being executed by the handlers.
+    ///
+    /// Requests rejected before reaching a handler are not counted.
+    pub fn inflight_requests(&self) -> usize {
+        self.stats.inflight_requests()
+    }
+
+    /// Get the counters of rejected and unusual requests, and the gauges of
+    /// open connections and executing requests.
+    pub fn stats(&self) -> Arc<ServerStats> {
+        self.stats.clone()
+    }
+
+    /// Get a handle to change the status reported by the health check.
+    ///
+    /// Returns `None` if the health check is not enabled.
+    pub fn health_reporter(&self) -> Option<HealthReporter> {
+        self.health.clone()
+    }
+
+    /// Run the server in a new thread.
+    ///
+    /// The listening socket is bound when the server is built, so clients can
+    /// connect as soon as this method returns.
+    pub fn start_background(self) -> ServerHandle {
+        let local_addrs = self.local_addrs.clone();
+        let shutdown = self.shutdown.clone();
+        let registry = self.services.clone();
+        let stats = self.stats.clone();
+        let thread = thread::Builder::new()
+            .name("copra-server".to_string())
+            .spawn(move || self.start())
+            .expect("failed to spawn server thread");
+
+        ServerHandle {
+            local_addrs,
+            shutdown,
+            registry,
+            stats,
+            thread,
+        }
+    }
+
     /// Run the server.
     ///
-    /// This method will block the current thread forever
+    /// This method will block the current thread until the server is shut
+    /// down through a [`ShutdownHandle`], which stops all listeners. A server
+    /// can only be started once, the listening sockets are closed when this
+    /// method returns.
+    ///
+    /// [`ShutdownHandle`]: struct.ShutdownHandle.html
     pub fn start(&self) {
+        let (listeners, status_page) = match self.prepare() {
+            Some(prepared) => prepared,
+            None => {
+                error!("Server has already been started");
+                return;
+            }
+        };
+
+        let workers = (1..self.threads)
+            .map(|i| {
+                let worker = self.worker(&listeners, status_page.clone());
+                thread::Builder::new()
+                    .name(format!("worker{}", i))
+                    .spawn(move || worker.run())
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        self.worker(&listeners, status_page).run();
+        drop(listeners);
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        stopped(&self.local_addrs);
+    }
+
+    /// Serve on the event loop of `handle`.
+    ///
+    /// Unlike [`Server::start`], no thread or event loop is created. The
+    /// returned future serves the listeners once the caller spawns or runs
+    /// it, so the server can share a `Core` with clients or the rest of an
+    /// application. The number of threads set by [`ServerBuilder::threads`]
+    /// is ignored.
+    ///
+    /// The future resolves after the server is shut down through a
+    /// [`ShutdownHandle`] and the in-flight requests are finished. Like
+    /// [`Server::start`], a server can only be served once, later calls
+    /// return a future resolving to an error.
+    ///
+    /// [`Server::start`]: struct.Server.html#method.start
+    /// [`ServerBuilder::threads`]: struct.ServerBuilder.html#method.threads
+    /// [`ShutdownHandle`]: struct.ShutdownHandle.html
+    pub fn serve_on(&self, handle: &Handle) -> Box<Future<Item = (), Error = io::Error>> {
+        let (listeners, status_page) = match self.prepare() {
+            Some(prepared) => prepared,
+            None => {
+                let e = io::Error::other("server has already been started");
+                return Box::new(future::err(e));
+            }
+        };
+
+        let serve = self.worker(&listeners, status_page).serve_on(handle);
+        let local_addrs = self.local_addrs.clone();
+        match serve {
+            Ok(serve) => Box::new(serve.then(move |res| {
+                stopped(&local_addrs);
+                res
+            })),
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+
+    /// Take the listeners and set up what is shared by all workers, or
+    /// return `None` if the server has already been started.
+    fn prepare(&self) -> Option<(Vec<BoundListener>, Option<Arc<StatusPage>>)> {
+        let listeners = self.listeners.lock().unwrap().take()?;
+
+        #[cfg(all(unix, feature = "signals"))]
+        {
+            if self.handle_signals {
+                if let Err(e) = signal::shutdown_on_signal(self.shutdown.clone()) {
+                    error!("Failed to handle signals: {}", e);
+                }
+            }
+        }
+
         if let Some(ref remote) = self.remote {
             let maintainer = ThroughputMaintainer::new(
                 self.timer.clone(),
@@ -259,23 +1584,527 @@ impl Server {
             remote.execute(maintainer.for_each(|_| Ok(()))).unwrap();
         }
 
-        self.listener.serve(MetaService::new(self.services.clone()))
+        let status_page = if self.status_page {
+            let page = StatusPage::new(
+                self.local_addrs.iter().map(|addr| addr.to_string()).collect(),
+                self.services.clone(),
+                self.stats.clone(),
+                self.open_connections.clone(),
+                self.throughput.clone(),
+            );
+            Some(Arc::new(page))
+        } else {
+            None
+        };
+
+        Some((listeners, status_page))
+    }
+
+    fn worker(&self, listeners: &[BoundListener], status_page: Option<Arc<StatusPage>>) -> Worker {
+        // every worker drains its own requests on shutdown
+        let in_flight = Arc::new(InFlight::default());
+        let mut service = MetaService::new(
+            self.services.clone(),
+            self.interceptors.clone(),
+            in_flight,
+            self.timer.clone(),
+            self.request_timeout,
+            self.access_log.clone(),
+            self.authenticator.clone(),
+        );
+        service.status_page = status_page;
+        if self.admin {
+            service.admin = Some(Arc::new(AdminHandlers::new(self.services.clone())));
+        }
+        service.limits = self.limits.clone();
+        service.rate_limiter = self.rate_limiter.clone();
+        service.slow_threshold = self.slow_threshold;
+        service.blocking_pool = self.blocking_pool.clone();
+        service.compress_over = self.compress_over;
+        service.error_mapper = self.error_mapper.clone();
+        service.stats = self.stats.clone();
+        let acceptor = Acceptor {
+            service,
+            connections: self.connections.clone(),
+            max_connections: self.max_connections,
+            limit_policy: self.limit_policy,
+            #[cfg(feature = "tls")]
+            tls: self.tls.clone(),
+        };
+        let listeners = listeners
+            .iter()
+            .map(|(listener, protocol)| {
+                let listener = listener.try_clone().expect("failed to clone listener");
+                (listener, protocol.clone())
+            })
+            .collect();
+        Worker {
+            listeners,
+            acceptor,
+            health: self.health.clone(),
+            timer: self.timer.clone(),
+            shutdown: self.shutdown.clone(),
+            grace_period: self.grace_period,
+            tcp_options: self.tcp_options,
+        }
+    }
+}
+
+/// Handle to a server running in the background
+///
+/// It is returned by [`Server::start_background`]. Dropping the handle does
+/// not stop the server.
+///
+/// [`Server::start_background`]: struct.Server.html#method.start_background
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addrs: Vec<LocalAddr>,
+    shutdown: ShutdownHandle,
+    registry: RegistryHandle,
+    stats: Arc<ServerStats>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// Get the TCP addresses the server is listening to.
+    ///
+    /// See [`Server::local_addrs`].
+    ///
+    /// [`Server::local_addrs`]: struct.Server.html#method.local_addrs
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        inet_addrs(&self.local_addrs)
+    }
+
+    /// Get the path of the Unix domain socket the server is listening to.
+    ///
+    /// Returns `None` for TCP servers.
+    pub fn local_path(&self) -> Option<&Path> {
+        unix_path(&self.local_addrs)
+    }
+
+    /// Get a handle which can stop the server.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Get a handle to add and remove services while the server is running.
+    ///
+    /// See [`Server::registry_handle`].
+    ///
+    /// [`Server::registry_handle`]: struct.Server.html#method.registry_handle
+    pub fn registry_handle(&self) -> RegistryHandle {
+        self.registry.clone()
+    }
+
+    /// Get the counters of rejected and unusual requests, and the gauges of
+    /// open connections and executing requests.
+    pub fn stats(&self) -> Arc<ServerStats> {
+        self.stats.clone()
+    }
+
+    /// Block until the server is shut down.
+    ///
+    /// An error is returned if the server thread panicked.
+    pub fn join(self) -> thread::Result<()> {
+        self.thread.join()
+    }
+
+    /// Shut down the server and wait for it to stop.
+    ///
+    /// See [`ShutdownHandle::shutdown`] for how in-flight requests are
+    /// handled.
+    ///
+    /// [`ShutdownHandle::shutdown`]: struct.ShutdownHandle.html#method.shutdown
+    pub fn stop(self) -> thread::Result<()> {
+        self.shutdown.shutdown();
+        self.join()
+    }
+}
+
+struct Worker {
+    listeners: Vec<BoundListener>,
+    acceptor: Acceptor,
+    health: Option<HealthReporter>,
+    timer: Timer,
+    shutdown: ShutdownHandle,
+    grace_period: Duration,
+    tcp_options: TcpOptions,
+}
+
+impl Worker {
+    fn run(self) {
+        if let Err(e) = self.serve() {
+            error!("Server worker exited with error: {}", e);
+        }
+    }
+
+    fn serve(self) -> io::Result<()> {
+        let mut core = Core::new()?;
+        let serve = self.serve_on(&core.handle())?;
+        core.run(serve)
+    }
+
+    fn serve_on(self, handle: &Handle) -> io::Result<Box<Future<Item = (), Error = io::Error>>> {
+        let Worker {
+            listeners,
+            acceptor,
+            health,
+            timer,
+            shutdown,
+            grace_period,
+            tcp_options,
+        } = self;
+        let in_flight = acceptor.service.in_flight.clone();
+
+        let mut accepts = Vec::with_capacity(listeners.len());
+        for (listener, protocol) in listeners {
+            let accept = match listener {
+                Listener::Tcp(listener) => {
+                    let addr = listener.local_addr()?;
+                    let listener = TcpListener::from_listener(listener, &addr, handle)?;
+                    let incoming = listener.incoming().map(move |(socket, addr)| {
+                        if let Err(e) = tcp_options.apply(&socket) {
+                            warn!("Failed to set socket options for {}: {}", addr, e);
+                        }
+                        (socket, addr)
+                    });
+                    acceptor.serve(handle, incoming, protocol)
+                }
+                #[cfg(unix)]
+                Listener::Unix(listener) => {
+                    let listener = tokio_uds::UnixListener::from_listener(listener, handle)?;
+                    acceptor.serve(handle, listener.incoming(), protocol)
+                }
+            };
+            accepts.push(accept);
+        }
+        let accept = future::join_all(accepts).map(|_| ());
+        let signal = shutdown
+            .subscribe()
+            .or_else(|_| future::empty::<(), io::Error>());
+
+        // the listeners are dropped along with the unfinished accept loops
+        let serve = accept
+            .select(signal)
+            .map(|_| ())
+            .map_err(|(e, _)| e)
+            .and_then(move |()| {
+                if let Some(health) = health {
+                    health.set_all_not_serving();
+                }
+                server_debug!(
+                    "Stop accepting connections, {} requests in flight",
+                    in_flight.count()
+                );
+                let deadline = timer
+                    .sleep(grace_period)
+                    .map(|_| warn!("Grace period reached, dropping unfinished requests"))
+                    .map_err(|e| warn!("Failed to set up the grace period: {}", e));
+                Drained::new(in_flight).select(deadline).then(|_| Ok(()))
+            });
+        Ok(Box::new(serve))
+    }
+}
+
+/// Serve the connections accepted by one event loop
+struct Acceptor {
+    service: MetaService,
+    connections: Arc<AtomicUsize>,
+    max_connections: Option<usize>,
+    limit_policy: ConnectionLimitPolicy,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+}
+
+impl Acceptor {
+    fn serve<I, S, A>(
+        &self,
+        handle: &Handle,
+        incoming: I,
+        protocol: Arc<MetaServerProtocol>,
+    ) -> Box<Future<Item = (), Error = io::Error>>
+    where
+        I: Stream<Item = (S, A), Error = io::Error> + 'static,
+        S: AsyncRead + AsyncWrite + 'static,
+        A: PeerAddr + 'static,
+    {
+        let handle = handle.clone();
+        let service = self.service.clone();
+        #[cfg(feature = "tls")]
+        let tls = self.tls.clone();
+
+        let incoming = LimitedIncoming::new(
+            incoming,
+            self.connections.clone(),
+            self.max_connections,
+            self.limit_policy,
+        );
+        let accept = incoming
+            .then(|conn| match conn {
+                Ok(conn) => Ok(Some(conn)),
+                Err(e) => {
+                    warn!("Failed to accept a connection: {}", e);
+                    Ok(None)
+                }
+            })
+            .for_each(move |conn| {
+                if let Some((socket, addr)) = conn {
+                    server_trace!("Accepted a connection from {:?}", addr);
+                    let service = service.for_peer(addr.inet());
+                    #[cfg(feature = "tls")]
+                    {
+                        if let Some(ref tls) = tls {
+                            let protocol = protocol.clone();
+                            tls.bind_server(&handle, socket, addr, protocol, service);
+                            return Ok(());
+                        }
+                    }
+                    protocol.bind_server(&handle, socket, service);
+                }
+                Ok(())
+            });
+        Box::new(accept)
+    }
+}
+
+/// Clean up after the listeners on `addrs` are closed
+fn stopped(addrs: &[LocalAddr]) {
+    for local_addr in addrs {
+        if let Some(path) = local_addr.path() {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Failed to remove socket file {}: {}", path.display(), e);
+            }
+        }
+        info!("Server stopped: {}", local_addr);
+    }
+}
+
+fn inet_addrs(addrs: &[LocalAddr]) -> Vec<SocketAddr> {
+    addrs.iter().filter_map(LocalAddr::inet).collect()
+}
+
+fn unix_path(addrs: &[LocalAddr]) -> Option<&Path> {
+    addrs.iter().filter_map(LocalAddr::path).next()
+}
+
+/// Options applied to TCP listeners before binding
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BindOptions {
+    reuse_addr: bool,
+    reuse_port: bool,
+    only_v6: bool,
+    backlog: i32,
+}
+
+impl Default for BindOptions {
+    fn default() -> Self {
+        BindOptions {
+            reuse_addr: true,
+            reuse_port: false,
+            only_v6: false,
+            backlog: 1024,
+        }
+    }
+}
+
+fn bind(addr: &SocketAddr, options: &BindOptions) -> io::Result<net::TcpListener> {
+    let builder = match *addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(_) => {
+            let builder = TcpBuilder::new_v6()?;
+            builder.only_v6(options.only_v6)?;
+            builder
+        }
+    };
+    builder.reuse_address(options.reuse_addr)?;
+    // rejected in `build` on other platforms
+    #[cfg(unix)]
+    {
+        if options.reuse_port {
+            builder.reuse_port(true)?;
+        }
+    }
+    builder.bind(addr)?;
+    builder.listen(options.backlog)
+}
+
+/// Bind `addr`, or every address it resolves to if it is a host name
+fn bind_all(addr: &str, options: &BindOptions) -> Result<Vec<net::TcpListener>, ServerBuildError> {
+    let socket_addrs = match addr.parse() {
+        Ok(socket_addr) => vec![socket_addr],
+        Err(e) => resolve(addr, e)?,
+    };
+    let mut listeners: Vec<net::TcpListener> = Vec::with_capacity(socket_addrs.len());
+    for mut socket_addr in socket_addrs {
+        // the addresses of a host name share the port picked for the first
+        if socket_addr.port() == 0 {
+            if let Some(first) = listeners.first() {
+                let port = first.local_addr().map_err(ServerBuildError::BindError)?.port();
+                socket_addr.set_port(port);
+            }
+        }
+        let listener = bind(&socket_addr, options).map_err(|e| bind_error(&socket_addr, e))?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+/// Resolve a host name with a port, `parse_error` is returned if `addr` is
+/// not in that form either
+fn resolve(addr: &str, parse_error: AddrParseError) -> Result<Vec<SocketAddr>, ServerBuildError> {
+    let resolved = match addr.to_socket_addrs() {
+        Ok(resolved) => resolved,
+        Err(ref e) if e.kind() == io::ErrorKind::InvalidInput => {
+            return Err(ServerBuildError::AddrParseError(parse_error));
+        }
+        Err(e) => {
+            let e = io::Error::new(e.kind(), format!("failed to resolve {}: {}", addr, e));
+            return Err(ServerBuildError::ResolveError(e));
+        }
+    };
+    let mut socket_addrs = Vec::new();
+    for socket_addr in resolved {
+        if !socket_addrs.contains(&socket_addr) {
+            socket_addrs.push(socket_addr);
+        }
+    }
+    if socket_addrs.is_empty() {
+        let e = io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", addr));
+        return Err(ServerBuildError::ResolveError(e));
+    }
+    Ok(socket_addrs)
+}
+
+/// Pair TCP listeners with their addresses
+fn inet_listeners(
+    listeners: Vec<net::TcpListener>,
+) -> Result<Vec<(Listener, LocalAddr)>, ServerBuildError> {
+    listeners
+        .into_iter()
+        .map(|listener| {
+            let local_addr = listener
+                .local_addr()
+                .map_err(ServerBuildError::BindError)?;
+            Ok((Listener::Tcp(listener), LocalAddr::Inet(local_addr)))
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn bind_uds(path: &Path, mode: Option<u32>) -> io::Result<UnixListener> {
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+/// Remove the socket file at `path` unless some server is listening to it
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(ref meta) if meta.file_type().is_socket() => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another server is listening",
+                ));
+            }
+            server_debug!("Removing stale socket file {}", path.display());
+            fs::remove_file(path)
+        }
+        // other kinds of files are reported by `bind`
+        _ => Ok(()),
+    }
+}
+
+fn bind_error(addr: &fmt::Display, e: io::Error) -> ServerBuildError {
+    let e = io::Error::new(e.kind(), format!("failed to bind {}: {}", addr, e));
+    ServerBuildError::BindError(e)
+}
+
+/// Cancel `response` if it is not finished when `deadline` expires
+fn with_deadline(response: MethodFuture, deadline: Sleep, request_id: u64) -> MethodFuture {
+    let response = response
+        .select2(deadline)
+        .then(move |result| -> MethodFuture {
+            match result {
+                Ok(Either::A((resp, _))) => Box::new(future::ok(resp)),
+                Err(Either::A((e, _))) => Box::new(future::err(e)),
+                Ok(Either::B((_, _))) => {
+                    warn!(
+                        "Request {} is not finished before the deadline, cancelled",
+                        request_id
+                    );
+                    Box::new(future::err(MethodError::Timeout))
+                }
+                Err(Either::B((e, response))) => {
+                    warn!("Failed to set up the deadline of request {}: {}", request_id, e);
+                    response
+                }
+            }
+        });
+    Box::new(response)
+}
+
+fn panic_message(payload: &Box<Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
     }
 }
 
 fn result_to_errno(
     result: Result<(Bytes, Controller), MethodError>,
+    error_mapper: &ErrorMapper,
+    service: &str,
+    method: &str,
 ) -> io::Result<ResponsePackage> {
     result
         .and_then(|(body, controller)| {
             let mut meta = RpcResponseMeta::new();
-            meta.set_error_code(0);
+            meta.set_error_code(errno::SUCCESS);
             Ok((meta, controller, body))
         })
-        .or_else(|_| {
-            let mut meta = RpcResponseMeta::new();
-            meta.set_error_code(1);
-            meta.set_error_text("Unknown error".to_string());
-            Ok((meta, Controller::default(), Bytes::new()))
+        .or_else(|e| {
+            let (e, controller) = e.into_parts();
+            let meta = error_mapper.map(&e, service, method);
+            Ok((meta, controller.unwrap_or_default(), Bytes::new()))
         })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bind_options_from_builder() {
+        let builder = ServerBuilder::new("127.0.0.1:0", ServiceRegistry::new());
+        assert_eq!(builder.bind_options, BindOptions::default());
+
+        let builder = builder.backlog(16).reuse_addr(false).reuse_port(true);
+        let expected = BindOptions {
+            reuse_addr: false,
+            reuse_port: true,
+            only_v6: false,
+            backlog: 16,
+        };
+        assert_eq!(builder.bind_options, expected);
+    }
+
+    #[test]
+    fn bind_with_backlog() {
+        let options = BindOptions {
+            backlog: 16,
+            ..BindOptions::default()
+        };
+        let listener = bind(&"127.0.0.1:0".parse().unwrap(), &options).unwrap();
+        let addr = listener.local_addr().unwrap();
+        net::TcpStream::connect(addr).unwrap();
+        listener.accept().unwrap();
+    }
+}
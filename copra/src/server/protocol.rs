@@ -1,46 +1,53 @@
 use std::io;
 use std::sync::Arc;
-use std::sync::atomic::AtomicUsize;
 use tokio_io::{AsyncRead, AsyncWrite};
 use tokio_io::codec::Framed;
 use tokio_proto::multiplex::ServerProto;
-use tokio_timer::Timer;
 
-use monitor::TrafficCounting;
+use monitor::{FinishedCount, TrafficCounting};
 use protocol::{BrpcProtocol, HttpProtocol, ProtoCodec, Protocol, RpcProtocol};
-use message::{RequestPackage, ResponsePackage};
+use message::{DecodedRequest, ResponsePackage};
 
-use super::connection::TcpConnection;
+use super::connection::{Connection, ConnectionTable, ConnectionCodec, Throttle};
 use super::Second;
 
 #[derive(Debug)]
 pub struct MetaServerProtocol {
     protocols: Vec<Box<RpcProtocol>>,
-    timer: Timer,
-    idle_secs: Second,
-    finished: Arc<AtomicUsize>,
+    connections: Arc<ConnectionTable>,
+    idle_secs: Option<Second>,
+    finished: Arc<FinishedCount>,
+    max_inflight: Option<usize>,
 }
 
 impl MetaServerProtocol {
     pub fn new(
         protocols: Vec<Protocol>,
-        timer: Timer,
-        idle_secs: Second,
-        finished: Arc<AtomicUsize>,
+        connections: Arc<ConnectionTable>,
+        idle_secs: Option<Second>,
+        finished: Arc<FinishedCount>,
+        max_inflight: Option<usize>,
+        max_request_size: Option<usize>,
+        http: &HttpProtocol,
     ) -> Self {
         let protocols: Vec<_> = protocols
             .iter()
-            .map(|proto| match proto {
-                &Protocol::Brpc => Box::new(BrpcProtocol::new()) as Box<RpcProtocol>,
-                &Protocol::Http => Box::new(HttpProtocol::new()) as Box<RpcProtocol>,
+            .map(|proto| {
+                let mut proto = match proto {
+                    &Protocol::Brpc => Box::new(BrpcProtocol::new()) as Box<RpcProtocol>,
+                    &Protocol::Http => http.new_boxed(),
+                };
+                proto.set_max_package_size(max_request_size);
+                proto
             })
             .collect();
 
         MetaServerProtocol {
             protocols,
-            timer,
+            connections,
             idle_secs,
             finished,
+            max_inflight,
         }
     }
 }
@@ -49,16 +56,18 @@ impl<T> ServerProto<T> for MetaServerProtocol
 where
     T: AsyncRead + AsyncWrite + 'static,
 {
-    type Request = RequestPackage;
+    type Request = DecodedRequest;
     type Response = ResponsePackage;
-    type Transport = TrafficCounting<Framed<TcpConnection<T>, ProtoCodec>>;
+    type Transport = Throttle<TrafficCounting<Framed<Connection<T>, ConnectionCodec>>>;
     type BindTransport = io::Result<Self::Transport>;
 
     fn bind_transport(&self, io: T) -> Self::BindTransport {
-        trace!("New connection established");
-        let connection = TcpConnection::new(io, self.timer.clone(), self.idle_secs);
-        let codec = ProtoCodec::new(self.protocols.as_slice());
+        server_trace!("New connection established");
+        let stats = self.connections.open();
+        let connection = Connection::new(io, self.idle_secs, stats.clone());
+        let codec = ConnectionCodec::new(ProtoCodec::new(self.protocols.as_slice()), stats);
         let transport = TrafficCounting::new(self.finished.clone(), connection.framed(codec));
+        let transport = Throttle::new(self.max_inflight, transport);
 
         Ok(transport)
     }
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use controller::Controller;
+
+/// Buckets are forgotten once there are more than this many, and they are
+/// full again
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+type KeyFn = Fn(&Controller, Option<SocketAddr>) -> Option<String> + Send + Sync;
+
+/// Key requests by the IP address of the client
+fn peer_ip(_: &Controller, peer: Option<SocketAddr>) -> Option<String> {
+    peer.map(|addr| addr.ip().to_string())
+}
+
+/// A shared callback telling which client a request is from
+#[derive(Clone)]
+pub(crate) struct RateLimitKey(Arc<KeyFn>);
+
+impl RateLimitKey {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Controller, Option<SocketAddr>) -> Option<String> + Send + Sync + 'static,
+    {
+        RateLimitKey(Arc::new(f))
+    }
+}
+
+impl Default for RateLimitKey {
+    fn default() -> Self {
+        RateLimitKey::new(peer_ip)
+    }
+}
+
+impl fmt::Debug for RateLimitKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RateLimitKey")
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets limiting the request rate of each client
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    key: RateLimitKey,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Allow `rate` requests per second on average, and `burst` requests at
+    /// once, for every client told apart by `key`.
+    pub fn new(rate: u32, burst: u32, key: RateLimitKey) -> Self {
+        RateLimiter {
+            rate: f64::from(rate),
+            burst: f64::from(burst.max(1)),
+            key,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token from the bucket of the client, return `false` if the
+    /// bucket is empty.
+    ///
+    /// Requests without a key are not limited.
+    pub fn check(&self, controller: &Controller, peer: Option<SocketAddr>) -> bool {
+        match (self.key.0)(controller, peer) {
+            Some(key) => self.check_at(key, Instant::now()),
+            None => true,
+        }
+    }
+
+    fn check_at(&self, key: String, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            let refill = self.refill_time();
+            buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated) < refill);
+        }
+
+        let burst = self.burst;
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Time for an empty bucket to become full
+    fn refill_time(&self) -> Duration {
+        if self.rate > 0.0 {
+            let secs = self.burst / self.rate;
+            Duration::new(secs as u64, (secs.fract() * 1e9) as u32)
+        } else {
+            Duration::from_secs(u64::MAX)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bucket_refills_at_rate() {
+        let limiter = RateLimiter::new(2, 3, RateLimitKey::default());
+        let start = Instant::now();
+        let check = |millis| limiter.check_at("peer".to_string(), start + Duration::from_millis(millis));
+
+        assert!(check(0));
+        assert!(check(0));
+        assert!(check(0));
+        assert!(!check(0));
+        // one token every 500ms
+        assert!(!check(400));
+        assert!(check(600));
+        assert!(!check(600));
+        // never more than the burst
+        assert!(check(10_000));
+        assert!(check(10_000));
+        assert!(check(10_000));
+        assert!(!check(10_000));
+        // other clients have their own buckets
+        assert!(limiter.check_at("other".to_string(), start));
+    }
+}
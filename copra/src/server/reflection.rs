@@ -0,0 +1,177 @@
+//! Built-in reflection service
+//!
+//! Enabled by [`ServerBuilder::enable_reflection`], the service is registered
+//! as `Reflection`, and tells clients what the server provides. The messages
+//! are defined in `copra/src/message/reflection.proto`. The methods are
+//!
+//! * `list_services`, which returns the names of all services;
+//! * `list_methods`, which returns the method names of a service;
+//! * `file_descriptor`, which returns the serialized `FileDescriptorProto`
+//!   of the file defining a service. Registrants generated by
+//!   `protoc-rust-copra` provide the descriptor, others may not, in which
+//!   case an empty message is returned.
+//!
+//! The reflection service reports the services registered when the server
+//! is built, including itself and the health check service.
+//!
+//! [`ServerBuilder::enable_reflection`]: ../struct.ServerBuilder.html#method.enable_reflection
+
+use futures::future::{self, FutureResult};
+use protobuf::{Message, MessageStatic, RepeatedField};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use codec::ProtobufCodec;
+use controller::Controller;
+use dispatcher::{NamedRegistrant, Registrant, ServiceRegistry};
+use message::reflection_file_descriptor;
+use service::{EncapsulatedMethod, MethodError, NewEncapService, NewEncapsulatedMethod, Service};
+
+pub use message::{FileDescriptorRequest, FileDescriptorResponse, ListMethodsRequest,
+                  ListMethodsResponse, ListServicesRequest, ListServicesResponse};
+
+/// Name of the reflection service
+pub const SERVICE_NAME: &str = "Reflection";
+
+const METHODS: &[&str] = &["file_descriptor", "list_methods", "list_services"];
+
+/// Services and methods of a server, with the file descriptors
+#[derive(Debug, Default)]
+struct Surface {
+    services: BTreeMap<String, (Vec<String>, Option<Vec<u8>>)>,
+}
+
+impl Surface {
+    fn get(&self, service: &str) -> Result<&(Vec<String>, Option<Vec<u8>>), MethodError> {
+        self.services
+            .get(service)
+            .ok_or(MethodError::ServiceNotFound)
+    }
+}
+
+fn list_services(
+    surface: &Surface,
+    _: ListServicesRequest,
+) -> Result<ListServicesResponse, MethodError> {
+    let mut resp = ListServicesResponse::new();
+    resp.set_services(surface.services.keys().cloned().collect());
+    Ok(resp)
+}
+
+fn list_methods(
+    surface: &Surface,
+    req: ListMethodsRequest,
+) -> Result<ListMethodsResponse, MethodError> {
+    let (ref methods, _) = *surface.get(req.get_service())?;
+    let mut resp = ListMethodsResponse::new();
+    resp.set_methods(RepeatedField::from_vec(methods.clone()));
+    Ok(resp)
+}
+
+fn file_descriptor(
+    surface: &Surface,
+    req: FileDescriptorRequest,
+) -> Result<FileDescriptorResponse, MethodError> {
+    let (_, ref descriptor) = *surface.get(req.get_service())?;
+    let mut resp = FileDescriptorResponse::new();
+    if let Some(ref descriptor) = *descriptor {
+        resp.set_file_descriptor(descriptor.clone());
+    }
+    Ok(resp)
+}
+
+struct Method<Req, Resp> {
+    surface: Arc<Surface>,
+    handler: fn(&Surface, Req) -> Result<Resp, MethodError>,
+}
+
+impl<Req, Resp> Clone for Method<Req, Resp> {
+    fn clone(&self) -> Self {
+        Method {
+            surface: self.surface.clone(),
+            handler: self.handler,
+        }
+    }
+}
+
+impl<Req, Resp> Service for Method<Req, Resp> {
+    type Request = (Req, Controller);
+    type Response = (Resp, Controller);
+    type Error = MethodError;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn call(&self, (req, ctrl): Self::Request) -> Self::Future {
+        future::result((self.handler)(&self.surface, req).map(|resp| (resp, ctrl)))
+    }
+}
+
+/// Registrant of the reflection service
+#[derive(Debug)]
+pub(crate) struct ReflectionRegistrant {
+    surface: Arc<Surface>,
+}
+
+impl ReflectionRegistrant {
+    /// Describe the services in `registry`, and the reflection service
+    /// itself.
+    pub fn new(registry: &ServiceRegistry) -> Self {
+        let mut surface = Surface::default();
+        for service in registry.services() {
+            let methods = registry
+                .methods(service)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|m| m.to_string())
+                .collect();
+            let descriptor = registry.file_descriptor(service).map(|d| d.to_vec());
+            surface
+                .services
+                .insert(service.to_string(), (methods, descriptor));
+        }
+        let methods = METHODS.iter().map(|m| m.to_string()).collect();
+        let descriptor = reflection_file_descriptor().write_to_bytes().ok();
+        surface
+            .services
+            .insert(SERVICE_NAME.to_string(), (methods, descriptor));
+
+        ReflectionRegistrant {
+            surface: Arc::new(surface),
+        }
+    }
+
+    fn method<Req, Resp>(
+        &self,
+        handler: fn(&Surface, Req) -> Result<Resp, MethodError>,
+    ) -> NewEncapService
+    where
+        Req: Message + MessageStatic + Clone,
+        Resp: Message + Clone,
+    {
+        let method = Method {
+            surface: self.surface.clone(),
+            handler,
+        };
+        let method = EncapsulatedMethod::new(ProtobufCodec::new(), method);
+        Box::new(NewEncapsulatedMethod::new(method))
+    }
+}
+
+impl Registrant for ReflectionRegistrant {
+    fn methods(&self) -> Vec<(String, NewEncapService)> {
+        vec![
+            ("file_descriptor".to_string(), self.method(file_descriptor)),
+            ("list_methods".to_string(), self.method(list_methods)),
+            ("list_services".to_string(), self.method(list_services)),
+        ]
+    }
+
+    fn file_descriptor(&self) -> Option<Vec<u8>> {
+        reflection_file_descriptor().write_to_bytes().ok()
+    }
+}
+
+impl NamedRegistrant for ReflectionRegistrant {
+    fn name() -> &'static str {
+        SERVICE_NAME
+    }
+}
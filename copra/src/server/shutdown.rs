@@ -0,0 +1,119 @@
+use futures::{Async, Future, Poll};
+use futures::sync::oneshot;
+use futures::task::AtomicTask;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Default)]
+struct ShutdownState {
+    requested: bool,
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+/// Handle to stop a running [`Server`]
+///
+/// The handle can be cloned and sent to other threads. It is obtained by
+/// calling [`Server::shutdown_handle`].
+///
+/// [`Server`]: struct.Server.html
+/// [`Server::shutdown_handle`]: struct.Server.html#method.shutdown_handle
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle {
+    state: Arc<Mutex<ShutdownState>>,
+}
+
+impl ShutdownHandle {
+    /// Ask the server to shut down.
+    ///
+    /// The server stops accepting new connections at once, then waits for
+    /// in-flight requests to finish, until the grace period is reached.
+    /// After that, [`Server::start`] returns.
+    ///
+    /// This method does not block. Calling it more than once has no further
+    /// effect.
+    ///
+    /// [`Server::start`]: struct.Server.html#method.start
+    pub fn shutdown(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.requested = true;
+        for waiter in state.waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+    }
+
+    /// Whether a shutdown is requested.
+    pub fn is_shutdown(&self) -> bool {
+        self.state.lock().unwrap().requested
+    }
+
+    pub(crate) fn subscribe(&self) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        let mut state = self.state.lock().unwrap();
+        if state.requested {
+            let _ = tx.send(());
+        } else {
+            state.waiters.push(tx);
+        }
+        rx
+    }
+}
+
+/// Number of requests being processed by a worker
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    count: AtomicUsize,
+    task: AtomicTask,
+}
+
+impl InFlight {
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+}
+
+/// Count a request as in-flight until dropped
+#[derive(Debug)]
+pub(crate) struct InFlightGuard {
+    in_flight: Arc<InFlight>,
+}
+
+impl InFlightGuard {
+    pub fn new(in_flight: Arc<InFlight>) -> Self {
+        in_flight.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard { in_flight }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.in_flight.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.in_flight.task.notify();
+        }
+    }
+}
+
+/// A future that resolves when no request is in flight
+#[derive(Debug)]
+pub(crate) struct Drained {
+    in_flight: Arc<InFlight>,
+}
+
+impl Drained {
+    pub fn new(in_flight: Arc<InFlight>) -> Self {
+        Drained { in_flight }
+    }
+}
+
+impl Future for Drained {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.in_flight.task.register();
+        if self.in_flight.count() == 0 {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Shut down servers when the process receives SIGTERM or SIGINT
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+
+use super::ShutdownHandle;
+
+/// Servers to shut down, `None` before the handlers are installed
+static SERVERS: Mutex<Option<Vec<ShutdownHandle>>> = Mutex::new(None);
+
+/// Shut down through `shutdown` on the next SIGTERM or SIGINT.
+///
+/// The handlers are installed on the first call, and kept for the lifetime
+/// of the process.
+pub(crate) fn shutdown_on_signal(shutdown: ShutdownHandle) -> io::Result<()> {
+    let mut servers = SERVERS.lock().unwrap();
+    if servers.is_none() {
+        install()?;
+        *servers = Some(Vec::new());
+    }
+    if let Some(ref mut servers) = *servers {
+        servers.push(shutdown);
+    }
+    Ok(())
+}
+
+fn install() -> io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    thread::Builder::new()
+        .name("copra-signal".to_string())
+        .spawn(move || {
+            for signal in signals.forever() {
+                info!("Received signal {}, shutting down", signal);
+                if let Some(ref mut servers) = *SERVERS.lock().unwrap() {
+                    for server in servers.drain(..) {
+                        server.shutdown();
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
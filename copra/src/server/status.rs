@@ -0,0 +1,82 @@
+use bytes::Bytes;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Instant;
+
+use controller::Controller;
+use dispatcher::RegistryHandle;
+use message::{ResponsePackage, RpcResponseMeta};
+use monitor::{ServerStats, Throughput};
+use protocol::http::HttpStatus;
+use super::connection::ConnectionTable;
+
+/// Service name the http protocol gives to requests of `/` and `/status`
+pub(crate) const SERVICE_NAME: &str = "copra.builtin";
+/// Method name the http protocol gives to requests of `/` and `/status`
+pub(crate) const METHOD_NAME: &str = "status";
+
+/// A plain text page describing the running server
+#[derive(Debug)]
+pub(crate) struct StatusPage {
+    started: Instant,
+    local_addrs: Vec<String>,
+    registry: RegistryHandle,
+    stats: Arc<ServerStats>,
+    open_connections: Arc<ConnectionTable>,
+    throughput: Throughput,
+}
+
+impl StatusPage {
+    pub fn new(
+        local_addrs: Vec<String>,
+        registry: RegistryHandle,
+        stats: Arc<ServerStats>,
+        open_connections: Arc<ConnectionTable>,
+        throughput: Throughput,
+    ) -> Self {
+        StatusPage {
+            started: Instant::now(),
+            local_addrs,
+            registry,
+            stats,
+            open_connections,
+            throughput,
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut page = String::new();
+        let uptime = self.started.elapsed().as_secs();
+        // writing to a string never fails
+        let _ = writeln!(page, "uptime: {}s", uptime);
+        let _ = writeln!(page, "listening: {}", self.local_addrs.join(", "));
+        let _ = writeln!(page, "connections: {}", self.stats.connections());
+        for conn in self.open_connections.snapshot() {
+            let _ = writeln!(page, "  {}", conn);
+        }
+        let _ = writeln!(page, "requests in flight: {}", self.stats.inflight_requests());
+        let _ = writeln!(
+            page,
+            "throughput: {}/s, failed {}/s",
+            self.throughput.total(),
+            self.throughput.failed()
+        );
+        let _ = writeln!(page, "services:");
+        let registry = self.registry.snapshot();
+        for service in registry.services() {
+            let methods = registry.methods(service).unwrap_or_default();
+            let _ = writeln!(page, "  {}: {}", service, methods.join(", "));
+        }
+        page
+    }
+
+    pub fn response(&self) -> ResponsePackage {
+        let mut controller = Controller {
+            status: Some(HttpStatus::Ok),
+            response_body: self.render().into_bytes(),
+            ..Default::default()
+        };
+        controller.set_content_type("text/plain; charset=utf-8");
+        (RpcResponseMeta::new(), controller, Bytes::new())
+    }
+}
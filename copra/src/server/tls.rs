@@ -0,0 +1,61 @@
+use futures::Future;
+use native_tls;
+use std::fmt;
+use std::sync::Arc;
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_proto::BindServer;
+use tokio_tls;
+
+use super::MetaService;
+use super::accept::CountedStream;
+use super::protocol::MetaServerProtocol;
+
+/// Perform the TLS handshake on accepted connections
+#[derive(Clone)]
+pub(crate) struct TlsAcceptor {
+    inner: tokio_tls::TlsAcceptor,
+}
+
+impl fmt::Debug for TlsAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TlsAcceptor")
+    }
+}
+
+impl From<native_tls::TlsAcceptor> for TlsAcceptor {
+    fn from(acceptor: native_tls::TlsAcceptor) -> Self {
+        TlsAcceptor {
+            inner: acceptor.into(),
+        }
+    }
+}
+
+impl TlsAcceptor {
+    /// Serve the connection once the handshake is done.
+    ///
+    /// The handshake runs in its own task, so a slow or broken client does
+    /// not hold up the accept loop.
+    pub fn bind_server<S, A>(
+        &self,
+        handle: &Handle,
+        socket: CountedStream<S>,
+        addr: A,
+        protocol: Arc<MetaServerProtocol>,
+        service: MetaService,
+    ) where
+        S: AsyncRead + AsyncWrite + 'static,
+        A: fmt::Debug + 'static,
+    {
+        let bind_handle = handle.clone();
+        let handshake = self.inner.accept(socket).then(move |result| {
+            match result {
+                // the idle timer starts from here
+                Ok(stream) => protocol.bind_server(&bind_handle, stream, service),
+                Err(e) => warn!("TLS handshake with {:?} failed: {}", addr, e),
+            }
+            Ok(())
+        });
+        handle.spawn(handshake);
+    }
+}
@@ -9,6 +9,7 @@ use tokio_service::NewService;
 
 use controller::Controller;
 use codec::{MethodCodec, ProtobufError};
+use errno;
 
 pub use tokio_service::Service;
 
@@ -90,14 +91,148 @@ where
     }
 }
 
-// TODO: seperate this into server error and stub error
 /// [WIP] Error return by service providers
+///
+/// The calls made through stubs fail with [`RpcError`] instead, which
+/// converts into this error to be passed on by handlers.
+///
+/// [`RpcError`]: ../stub/enum.RpcError.html
 #[derive(Clone, Debug, PartialEq)]
 pub enum MethodError {
     /// [WIP] Other errors that might be worth discussion
     UnknownError,
     /// Failed to decode message
     CodecError,
+    /// The request did not finish before its deadline
+    Timeout,
+    /// The service handler panicked, with the panic message
+    Panic(String),
+    /// The requested service is not registered on the server
+    ServiceNotFound,
+    /// The requested method is not found in the service
+    MethodNotFound,
+    /// The service handler failed, with a message for the client
+    Failed(String),
+    /// The request exceeds the size limit of the server
+    RequestTooLarge,
+    /// The request is rejected by the authenticator, with the reason
+    Unauthenticated(String),
+    /// The method is running as many requests as it is allowed to
+    Busy,
+    /// The client sent more requests than its rate limit allows
+    RateLimited,
+    /// The request reuses the correlation id of a request still in flight
+    /// on the same connection
+    DuplicateRequestId,
+    /// The call failed on the client side because the connection to the
+    /// server is broken, or no server is connected, with the reason
+    ConnectionFailed(String),
+    /// The call failed on the client side because the channel is shut down
+    ChannelClosed,
+    /// The call failed on the client side because as many calls as the
+    /// channel allows are pending
+    ChannelFull,
+    /// The call failed on the client side because its response exceeds the
+    /// size limit of the channel
+    ResponseTooLarge,
+    /// The call failed on the client side because as many calls as the
+    /// concurrency limit of the channel allows are in flight
+    ConcurrencyLimited,
+    /// Another error, with the controller used to build the response
+    ///
+    /// Created by [`with_controller`](#method.with_controller).
+    WithController(Box<MethodError>, Box<Controller>),
+}
+
+impl MethodError {
+    /// Attach a controller to the error.
+    ///
+    /// The server uses this controller, instead of a default one, to build
+    /// the response of the failed request. With the http protocol, this is
+    /// the way to respond with an error status, headers and body.
+    pub fn with_controller(self, controller: Controller) -> Self {
+        let inner = match self {
+            MethodError::WithController(inner, _) => inner,
+            e => Box::new(e),
+        };
+        MethodError::WithController(inner, Box::new(controller))
+    }
+
+    /// Get the controller attached to the error, if any.
+    pub fn controller(&self) -> Option<&Controller> {
+        match *self {
+            MethodError::WithController(_, ref controller) => Some(controller),
+            _ => None,
+        }
+    }
+
+    /// Split the error from the attached controller.
+    pub fn into_parts(self) -> (MethodError, Option<Controller>) {
+        match self {
+            MethodError::WithController(inner, controller) => (*inner, Some(*controller)),
+            e => (e, None),
+        }
+    }
+
+    /// Get the error code sent in the response.
+    ///
+    /// See [`errno`] for the values.
+    ///
+    /// [`errno`]: ../errno/index.html
+    pub fn error_code(&self) -> i32 {
+        match *self {
+            MethodError::UnknownError => errno::EUNKNOWN,
+            MethodError::CodecError => errno::EREQUEST,
+            MethodError::Timeout => errno::ERPCTIMEDOUT,
+            MethodError::Panic(_) => errno::EPANIC,
+            MethodError::ServiceNotFound => errno::ENOSERVICE,
+            MethodError::MethodNotFound => errno::ENOMETHOD,
+            MethodError::Failed(_) => errno::EINTERNAL,
+            MethodError::RequestTooLarge => errno::ETOOLARGE,
+            MethodError::Unauthenticated(_) => errno::ERPCAUTH,
+            MethodError::Busy => errno::ELIMIT,
+            MethodError::RateLimited => errno::ERATELIMIT,
+            MethodError::DuplicateRequestId => errno::EREQUEST,
+            MethodError::ConnectionFailed(_) => errno::EFAILEDSOCKET,
+            MethodError::ChannelClosed => errno::ECLOSE,
+            MethodError::ChannelFull => errno::EOVERCROWDED,
+            MethodError::ResponseTooLarge => errno::ERESPONSETOOLARGE,
+            MethodError::ConcurrencyLimited => errno::ECONCURRENCYLIMIT,
+            MethodError::WithController(ref inner, _) => inner.error_code(),
+        }
+    }
+
+    /// Get the error text sent in the response.
+    pub fn error_text(&self) -> String {
+        match *self {
+            MethodError::Panic(ref msg)
+            | MethodError::Failed(ref msg)
+            | MethodError::Unauthenticated(ref msg) => msg.clone(),
+            MethodError::WithController(ref inner, _) => inner.error_text(),
+            ref e => e.to_string(),
+        }
+    }
+
+    /// Recover the error from the error code and text in a response.
+    ///
+    /// Unrecognized codes become `MethodError::UnknownError`.
+    pub fn from_error_code(code: i32, text: &str) -> Self {
+        match code {
+            errno::EREQUEST => MethodError::CodecError,
+            errno::ERPCTIMEDOUT => MethodError::Timeout,
+            errno::EPANIC => MethodError::Panic(text.to_string()),
+            errno::ENOSERVICE => MethodError::ServiceNotFound,
+            errno::ENOMETHOD => MethodError::MethodNotFound,
+            errno::EINTERNAL => MethodError::Failed(text.to_string()),
+            errno::ETOOLARGE => MethodError::RequestTooLarge,
+            errno::ERPCAUTH => MethodError::Unauthenticated(text.to_string()),
+            errno::ELIMIT => MethodError::Busy,
+            errno::ERATELIMIT => MethodError::RateLimited,
+            errno::ERESPONSETOOLARGE => MethodError::ResponseTooLarge,
+            errno::ECONCURRENCYLIMIT => MethodError::ConcurrencyLimited,
+            _ => MethodError::UnknownError,
+        }
+    }
 }
 
 impl fmt::Display for MethodError {
@@ -105,6 +240,26 @@ impl fmt::Display for MethodError {
         match *self {
             MethodError::UnknownError => write!(f, "unknown error produced by server"),
             MethodError::CodecError => write!(f, "failed to decode message"),
+            MethodError::Timeout => write!(f, "request timed out"),
+            MethodError::Panic(ref msg) => write!(f, "service handler panicked: {}", msg),
+            MethodError::ServiceNotFound => write!(f, "service not found"),
+            MethodError::MethodNotFound => write!(f, "method not found"),
+            MethodError::Failed(ref msg) => write!(f, "service handler failed: {}", msg),
+            MethodError::RequestTooLarge => write!(f, "request is too large"),
+            MethodError::Unauthenticated(ref msg) => write!(f, "authentication failed: {}", msg),
+            MethodError::Busy => write!(f, "server is busy"),
+            MethodError::RateLimited => write!(f, "rejected, too many requests"),
+            MethodError::DuplicateRequestId => {
+                write!(f, "correlation id is used by another request in flight")
+            }
+            MethodError::ConnectionFailed(ref msg) => write!(f, "connection failed: {}", msg),
+            MethodError::ChannelClosed => write!(f, "channel closed"),
+            MethodError::ChannelFull => write!(f, "too many pending calls on the channel"),
+            MethodError::ResponseTooLarge => write!(f, "response is too large"),
+            MethodError::ConcurrencyLimited => {
+                write!(f, "concurrency limit of the channel reached")
+            }
+            MethodError::WithController(ref inner, _) => inner.fmt(f),
         }
     }
 }
@@ -114,6 +269,23 @@ impl Error for MethodError {
         match *self {
             MethodError::UnknownError => "unknown error",
             MethodError::CodecError => "codec error",
+            MethodError::Timeout => "timeout",
+            MethodError::Panic(_) => "service handler panicked",
+            MethodError::ServiceNotFound => "service not found",
+            MethodError::MethodNotFound => "method not found",
+            MethodError::Failed(_) => "service handler failed",
+            MethodError::RequestTooLarge => "request too large",
+            MethodError::Unauthenticated(_) => "authentication failed",
+            MethodError::Busy => "server busy",
+            MethodError::RateLimited => "too many requests",
+            MethodError::DuplicateRequestId => "duplicate correlation id",
+            MethodError::ConnectionFailed(_) => "connection failed",
+            MethodError::ChannelClosed => "channel closed",
+            MethodError::ChannelFull => "channel full",
+            MethodError::ResponseTooLarge => "response too large",
+            MethodError::ConcurrencyLimited => "concurrency limit reached",
+            #[allow(deprecated)]
+            MethodError::WithController(ref inner, _) => inner.description(),
         }
     }
 }
@@ -172,7 +344,6 @@ where
             .and_then(move |body| {
                 method
                     .call((body, controller))
-                    .map_err(|_| MethodError::UnknownError)
                     .and_then(move |(body, controller)| {
                         codec
                             .encode(body)
@@ -0,0 +1,200 @@
+//! Calls gathered under a shared deadline
+//!
+//! Fanning a request out to several methods or servers, and gathering what
+//! they answer in time, is done with [`join`]:
+//!
+//! ```no_run
+//! # extern crate bytes;
+//! # extern crate copra;
+//! # extern crate futures;
+//! # extern crate tokio_timer;
+//! use bytes::Bytes;
+//! use copra::stub::batch;
+//! use copra::stub::{CallOptions, DynamicStub};
+//! use futures::Future;
+//! use std::time::Duration;
+//! use tokio_timer::Timer;
+//!
+//! # fn fan_out(stubs: &[DynamicStub], body: Bytes) {
+//! let timer = Timer::default();
+//! let calls = stubs
+//!     .iter()
+//!     .map(|stub| stub.call_raw("Search", "query", body.clone(), CallOptions::new()))
+//!     .collect();
+//! let results = batch::join(calls, Duration::from_millis(200), &timer)
+//!     .wait()
+//!     .unwrap();
+//! for result in results {
+//!     match result {
+//!         Ok((resp, _)) => println!("{} bytes", resp.len()),
+//!         Err(e) => println!("failed: {}", e),
+//!     }
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+//!
+//! [`join`]: fn.join.html
+
+use futures::{Async, Future, Poll};
+use std::fmt;
+use std::time::Duration;
+use tokio_timer::{Sleep, Timer};
+
+use super::RpcError;
+
+/// Wait for all of `calls`, giving up those not done within `deadline` of
+/// now, as measured by `timer`.
+///
+/// The returned future never fails. It resolves to the outcome of every
+/// call, in the order of `calls`, once all of them are done or the deadline
+/// passes. A call still in flight at the deadline is dropped, which gives
+/// it up, and fails with `RpcError::Timeout`.
+///
+/// The calls are polled in place, any future failing with `RpcError` will
+/// do, e.g. [`StubFuture`] or [`DynamicFuture`].
+///
+/// [`StubFuture`]: ../struct.StubFuture.html
+/// [`DynamicFuture`]: ../struct.DynamicFuture.html
+pub fn join<F>(calls: Vec<F>, deadline: Duration, timer: &Timer) -> JoinCalls<F>
+where
+    F: Future<Error = RpcError>,
+{
+    JoinCalls {
+        slots: calls.into_iter().map(Slot::Pending).collect(),
+        deadline: Some(timer.sleep(deadline)),
+    }
+}
+
+/// The outcome of a call gathered by [`join`], or the call itself while it
+/// is in flight
+///
+/// [`join`]: fn.join.html
+enum Slot<F: Future> {
+    Pending(F),
+    Done(Result<F::Item, RpcError>),
+}
+
+/// A future that will resolve to the outcomes of the calls gathered by
+/// [`join`]
+///
+/// [`join`]: fn.join.html
+pub struct JoinCalls<F: Future> {
+    slots: Vec<Slot<F>>,
+    deadline: Option<Sleep>,
+}
+
+impl<F: Future> fmt::Debug for JoinCalls<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pending = self.slots
+            .iter()
+            .filter(|slot| match **slot {
+                Slot::Pending(_) => true,
+                Slot::Done(_) => false,
+            })
+            .count();
+        f.debug_struct("JoinCalls")
+            .field("calls", &self.slots.len())
+            .field("pending", &pending)
+            .finish()
+    }
+}
+
+impl<F: Future> JoinCalls<F> {
+    /// Check if the deadline passed. A deadline the timer can not keep is
+    /// given up, and the calls are waited for.
+    fn expired(&mut self) -> bool {
+        let expired = match self.deadline {
+            Some(ref mut sleep) => sleep.poll(),
+            None => return false,
+        };
+        match expired {
+            Ok(Async::Ready(())) => true,
+            Ok(Async::NotReady) => false,
+            Err(e) => {
+                warn!("Failed to set up the deadline of a batch of calls: {}", e);
+                self.deadline = None;
+                false
+            }
+        }
+    }
+}
+
+impl<F> Future for JoinCalls<F>
+where
+    F: Future<Error = RpcError>,
+{
+    type Item = Vec<Result<F::Item, RpcError>>;
+
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut pending = false;
+        for slot in &mut self.slots {
+            let result = match *slot {
+                Slot::Pending(ref mut call) => match call.poll() {
+                    Ok(Async::Ready(item)) => Ok(item),
+                    Ok(Async::NotReady) => {
+                        pending = true;
+                        continue;
+                    }
+                    Err(e) => Err(e),
+                },
+                Slot::Done(_) => continue,
+            };
+            *slot = Slot::Done(result);
+        }
+        if pending && !self.expired() {
+            return Ok(Async::NotReady);
+        }
+
+        self.deadline = None;
+        let results = self.slots
+            .drain(..)
+            .map(|slot| match slot {
+                Slot::Done(result) => result,
+                // dropping the call gives it up
+                Slot::Pending(_) => Err(RpcError::Timeout),
+            })
+            .collect();
+        Ok(Async::Ready(results))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::future::{self, Empty, FutureResult};
+    use futures::Future;
+    use std::time::{Duration, Instant};
+    use tokio_timer::Timer;
+
+    use super::*;
+
+    #[test]
+    fn outcomes_kept_in_order() {
+        let timer = Timer::default();
+        let calls: Vec<FutureResult<i32, RpcError>> = vec![
+            future::ok(1),
+            future::err(RpcError::ChannelFull),
+            future::ok(3),
+        ];
+        let results = join(calls, Duration::from_secs(5), &timer).wait().unwrap();
+        assert_eq!(results, vec![Ok(1), Err(RpcError::ChannelFull), Ok(3)]);
+    }
+
+    #[test]
+    fn stragglers_time_out() {
+        let timer = Timer::default();
+        let calls: Vec<future::Either<FutureResult<i32, RpcError>, Empty<i32, RpcError>>> = vec![
+            future::Either::A(future::ok(1)),
+            future::Either::B(future::empty()),
+        ];
+        let start = Instant::now();
+        let results = join(calls, Duration::from_millis(300), &timer).wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(results, vec![Ok(1), Err(RpcError::Timeout)]);
+
+        let none: Vec<FutureResult<i32, RpcError>> = Vec::new();
+        assert!(join(none, Duration::from_secs(5), &timer).wait().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1024 @@
+//! Types that help to generate RPC stubs
+
+use bytes::Bytes;
+use futures::{Async, Future, Poll};
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_timer::{Sleep, Timer};
+
+use codec::{BytesCodec, MethodCodec};
+use compress::CompressType;
+use channel::{Channel, ChannelError, ChannelFuture, Interceptors, OnewayFuture, RequestPackage,
+              RetryPolicy, SendOptions};
+use channel::interceptor::before_call;
+use controller::Controller;
+use errno;
+use load_balancer::{CallInfo, LbHint};
+use message::{RpcRequestMeta, RpcResponseMeta};
+use monitor::{CallOutcome, ChannelMetrics};
+use service::MethodError;
+
+pub mod batch;
+
+type ResponsePackage = (RpcResponseMeta, Bytes);
+
+/// Options that apply to a single RPC call
+///
+/// Generated stubs provide a `*_opts` variant for every method, which takes
+/// this struct as an extra argument. The plain methods use the default value.
+#[derive(Clone, Debug, Default)]
+pub struct CallOptions {
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    max_retry: Option<u32>,
+    backup_request_after: Option<Duration>,
+    request_id: Option<u64>,
+    lb_hint: Option<LbHint>,
+    compress: Option<CompressType>,
+    controller: Controller,
+}
+
+impl CallOptions {
+    /// Create a new set of options with default values.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set the timeout of this call.
+    ///
+    /// The call will fail with `RpcError::Timeout` if no response is
+    /// received within `timeout`. The timeout is also sent to the server in
+    /// the request meta, so that the server can give up at the same time.
+    ///
+    /// Default to `None`, which means the timeout of the channel set by
+    /// `ChannelBuilder::rpc_timeout` applies.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the deadline of this call.
+    ///
+    /// The call fails with `RpcError::Timeout` if no response is
+    /// received by `deadline`, or within its timeout if it comes first.
+    /// The time left is sent to the server in the request meta of every
+    /// attempt, retries and backup requests included.
+    ///
+    /// Default to `None`, the timeout alone applies.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Give up this call at the deadline of the request handled by
+    /// `controller`, if it has one.
+    ///
+    /// Handlers making calls to other servers pass them the deadline of
+    /// their own client this way, so that every server down the chain gives
+    /// up as soon as the client does:
+    ///
+    /// ```ignore
+    /// fn echo(&self, (msg, ctrl): (Simple, Controller)) -> Self::EchoFuture {
+    ///     let opts = CallOptions::new().propagate_deadline(&ctrl);
+    ///     Box::new(self.stub.echo_opts(msg, opts).map(|(msg, _)| (msg, ctrl)))
+    /// }
+    /// ```
+    pub fn propagate_deadline(self, controller: &Controller) -> Self {
+        match controller.deadline {
+            Some(deadline) => self.deadline(deadline),
+            None => self,
+        }
+    }
+
+    /// Set the maximum number of retries of this call.
+    ///
+    /// This overrides the number of attempts of the [`RetryPolicy`] of the
+    /// channel, which still decides which failures are retried. Calls of
+    /// methods which are not idempotent should opt out with
+    /// `max_retry(0)`. Without a retry policy on the channel, calls are
+    /// never retried.
+    ///
+    /// [`RetryPolicy`]: ../channel/struct.RetryPolicy.html
+    pub fn max_retry(mut self, max_retry: u32) -> Self {
+        self.max_retry = Some(max_retry);
+        self
+    }
+
+    /// Send a backup request to another server if this call is not answered
+    /// within `delay`.
+    ///
+    /// See [`ChannelBuilder::backup_request_after`], default to the delay of
+    /// the channel.
+    ///
+    /// [`ChannelBuilder::backup_request_after`]: ../channel/struct.ChannelBuilder.html#method.backup_request_after
+    pub fn backup_request_after(mut self, delay: Duration) -> Self {
+        self.backup_request_after = Some(delay);
+        self
+    }
+
+    /// Set the id of this call, sent as the log id in the request meta.
+    ///
+    /// The server logs the request with this id, and passes it to the
+    /// handler in `Controller::request_id`. Default to `None`, which lets
+    /// the server generate one.
+    pub fn request_id(mut self, request_id: u64) -> Self {
+        self.request_id = Some(request_id);
+        self
+    }
+
+    /// Pass a hint to the load balancer of the channel, e.g. the hash of a
+    /// key for consistent hashing.
+    ///
+    /// See [`LoadBalance`]. Default to `None`.
+    ///
+    /// [`LoadBalance`]: ../load_balancer/trait.LoadBalance.html
+    pub fn lb_hint(mut self, hint: LbHint) -> Self {
+        self.lb_hint = Some(hint);
+        self
+    }
+
+    /// Set the key of this call for consistent hashing, calls with equal
+    /// keys are sent to the same server.
+    ///
+    /// This is a shorthand of `lb_hint(LbHint::from_key(key))`, see
+    /// [`ConsistentHash`].
+    ///
+    /// [`ConsistentHash`]: ../load_balancer/consistent_hash/struct.ConsistentHash.html
+    pub fn hash_key<K: Hash + ?Sized>(self, key: &K) -> Self {
+        self.lb_hint(LbHint::from_key(key))
+    }
+
+    /// Compress the request body of this call with `compress`.
+    ///
+    /// The body is compressed after it is encoded, and the compression is
+    /// set in the request meta for the server to decompress it. Only the
+    /// brpc protocol compresses. Default to `None`, which compresses as
+    /// [`ChannelBuilder::compress_requests_over`] tells.
+    ///
+    /// [`ChannelBuilder::compress_requests_over`]: ../channel/struct.ChannelBuilder.html#method.compress_requests_over
+    pub fn compress(mut self, compress: CompressType) -> Self {
+        self.compress = Some(compress);
+        self
+    }
+
+    /// Attach a pre-populated controller to this call.
+    ///
+    /// Its `request_id` is sent as the log id unless [`request_id`] is set,
+    /// its `deadline` applies unless [`deadline`] is set, and its
+    /// `authentication_data`, `request_compress_type` and, over http,
+    /// `headers` are sent along with the request. The server finds them in
+    /// the controller passed to the handler. Its other fields are ignored.
+    ///
+    /// [`request_id`]: #method.request_id
+    /// [`deadline`]: #method.deadline
+    pub fn controller(mut self, controller: Controller) -> Self {
+        self.controller = controller;
+        self
+    }
+
+    /// Get the timeout of this call.
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Get the deadline of this call, the one of its controller if none is
+    /// set.
+    pub fn get_deadline(&self) -> Option<Instant> {
+        self.deadline.or(self.controller.deadline)
+    }
+
+    /// Get the maximum number of retries of this call.
+    pub fn get_max_retry(&self) -> Option<u32> {
+        self.max_retry
+    }
+
+    /// Get the delay after which this call sends a backup request.
+    pub fn get_backup_request_after(&self) -> Option<Duration> {
+        self.backup_request_after
+    }
+
+    /// Get the id of this call.
+    pub fn get_request_id(&self) -> Option<u64> {
+        self.request_id
+    }
+
+    /// Get the compression of the request body of this call.
+    pub fn get_compress(&self) -> Option<CompressType> {
+        self.compress
+    }
+
+    /// Get the load balancer hint of this call.
+    pub fn get_lb_hint(&self) -> Option<LbHint> {
+        self.lb_hint
+    }
+
+    /// Get the controller attached to this call.
+    pub fn get_controller(&self) -> &Controller {
+        &self.controller
+    }
+}
+
+/// Bind a stub to a [`Channel`]
+///
+/// The wrapper holds a clone of the channel, so that it and the futures of
+/// its calls may outlive the channel they are created from, e.g. be moved
+/// into a future spawned on a `Handle`.
+///
+/// [`Channel`]: ../channel/struct.Channel.html
+#[allow(missing_debug_implementations)]
+#[derive(Clone)]
+pub struct RpcWrapper<C: Clone> {
+    codec: C,
+    channel: Channel,
+}
+
+impl<C: Clone> RpcWrapper<C> {
+    /// Create a binding from a codec and a reference to channel, which is
+    /// cloned.
+    pub fn new(codec: C, channel: &Channel) -> Self {
+        RpcWrapper::from_channel(codec, channel.clone())
+    }
+
+    /// Create a binding from a codec and a channel.
+    pub fn from_channel(codec: C, channel: Channel) -> Self {
+        RpcWrapper { codec, channel }
+    }
+}
+
+impl<C> RpcWrapper<C>
+where
+    C: MethodCodec + Clone,
+{
+    /// Issue a request and obtain a future.
+    pub fn call(&self, bundle: (C::Response, String, String)) -> StubFuture<C> {
+        self.call_with_options(bundle, CallOptions::default())
+    }
+
+    /// Issue a request with per-call options and obtain a future.
+    pub fn call_with_options(
+        &self,
+        bundle: (C::Response, String, String),
+        options: CallOptions,
+    ) -> StubFuture<C> {
+        self.start_call(bundle, options, false).0
+    }
+
+    /// Issue a request with per-call options, and get the controller it is
+    /// sent with too if `keep_controller` is set and it is sent.
+    fn start_call(
+        &self,
+        bundle: (C::Response, String, String),
+        options: CallOptions,
+        keep_controller: bool,
+    ) -> (StubFuture<C>, Option<Controller>) {
+        let timeout = options.get_timeout().or_else(|| self.channel.rpc_timeout());
+        let now = Instant::now();
+        let deadline = match (timeout.map(|timeout| now + timeout), options.get_deadline()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let timeout = deadline.map(|deadline| {
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        });
+        let send_options = SendOptions {
+            hint: options.get_lb_hint(),
+            retry: false,
+            backup_after: options
+                .get_backup_request_after()
+                .or_else(|| self.channel.backup_request_after()),
+            deadline,
+        };
+        let mut retry = None;
+        let mut sent = 0;
+        let mut intercepted = None;
+        let mut controller = None;
+        let (channel_fut, rejected) = match self.prepare(bundle, &options, timeout) {
+            Ok(req) => {
+                sent = req.2.len();
+                if keep_controller {
+                    controller = Some(req.1.clone());
+                }
+                if !self.channel.interceptors().is_empty() {
+                    intercepted = Some((self.channel.interceptors().clone(), req.0.clone()));
+                }
+                if let Some(policy) = self.channel.retry_policy() {
+                    let left = options
+                        .get_max_retry()
+                        .unwrap_or_else(|| policy.get_max_attempts() - 1);
+                    if left > 0 {
+                        retry = Some(Retry {
+                            channel: self.channel.clone(),
+                            req: req.clone(),
+                            options: SendOptions {
+                                retry: true,
+                                ..send_options
+                            },
+                            policy,
+                            left,
+                            retries: 0,
+                            backoff: None,
+                        });
+                    }
+                }
+                (Some(self.channel.send_call(req, send_options)), None)
+            }
+            Err(e) => (None, Some(e)),
+        };
+
+        let timeout = timeout.map(|timeout| self.channel.timer().sleep(timeout));
+
+        let measure = self.channel.metrics().map(|metrics| Measure {
+            metrics: metrics.clone(),
+            start: Instant::now(),
+            sent,
+        });
+
+        let fut = StubFuture::new(channel_fut, self.codec.clone())
+            .with_timeout(timeout)
+            .with_retry(retry)
+            .with_measure(measure)
+            .with_interceptors(intercepted)
+            .with_rejected(rejected);
+        (fut, controller)
+    }
+
+    /// Issue a request along with a pre-populated controller and obtain a
+    /// future.
+    ///
+    /// See [`CallOptions::controller`] for the fields of the controller
+    /// sent to the server.
+    ///
+    /// [`CallOptions::controller`]: struct.CallOptions.html#method.controller
+    pub fn call_with_controller(
+        &self,
+        bundle: ((C::Response, Controller), String, String),
+    ) -> StubFuture<C> {
+        let ((msg, controller), service_name, method_name) = bundle;
+        let options = CallOptions::new().controller(controller);
+        self.call_with_options((msg, service_name, method_name), options)
+    }
+
+    /// Issue a request without waiting for the response.
+    ///
+    /// The server is told not to answer the request. The returned future
+    /// resolves once the request is written to the connection, so that a
+    /// slow handler does not hold it, while a congested connection does. A
+    /// oneway call is never retried, whatever the retry policy of the
+    /// channel, and is counted apart in the metrics of the channel.
+    pub fn call_oneway(&self, bundle: (C::Response, String, String)) -> OnewayCallFuture {
+        let metrics = self.channel.metrics().cloned();
+        match self.prepare(bundle, &CallOptions::default(), None) {
+            Ok(req) => OnewayCallFuture {
+                inner: Some(self.channel.call_oneway(req)),
+                rejected: None,
+                metrics,
+            },
+            Err(e) => OnewayCallFuture {
+                inner: None,
+                rejected: Some(e),
+                metrics,
+            },
+        }
+    }
+
+    /// Encode a request and build its meta, then pass them to the
+    /// interceptors of the channel.
+    fn prepare(
+        &self,
+        bundle: (C::Response, String, String),
+        options: &CallOptions,
+        timeout: Option<Duration>,
+    ) -> Result<RequestPackage, RpcError> {
+        let (req, service_name, method_name) = bundle;
+        let body = self.codec
+            .encode(req)
+            .map_err(|_| encode_error())?;
+        let mut meta = RpcRequestMeta::new();
+        meta.set_service_name(service_name);
+        meta.set_method_name(method_name);
+        if let Some(timeout) = timeout {
+            meta.set_timeout_ms(timeout_ms(timeout));
+        }
+        let mut controller = options.get_controller().clone();
+        if let Some(request_id) = options.get_request_id() {
+            controller.request_id = request_id;
+        }
+        if options.get_compress().is_some() {
+            controller.request_compress_type = options.get_compress();
+        } else if controller.request_compress_type.is_none() {
+            match self.channel.compress_requests_over() {
+                Some((over, compress)) if body.len() > over => {
+                    controller.request_compress_type = Some(compress);
+                }
+                _ => {}
+            }
+        }
+        before_call(self.channel.interceptors(), &mut meta, &mut controller)
+            .map_err(RpcError::Rejected)?;
+        Ok((meta, controller, body))
+    }
+}
+
+/// A stub calling methods by their names, with the bodies as raw bytes
+///
+/// Unlike generated stubs, it needs no message types, so that requests
+/// whose types are not known at compile time can be forwarded, e.g. by a
+/// gateway. The bodies are sent and returned as they are, without being
+/// encoded or decoded. It holds a clone of its channel, as [`RpcWrapper`]
+/// does.
+///
+/// [`RpcWrapper`]: struct.RpcWrapper.html
+#[allow(missing_debug_implementations)]
+#[derive(Clone)]
+pub struct DynamicStub {
+    wrapper: RpcWrapper<BytesCodec>,
+}
+
+impl DynamicStub {
+    /// Create a stub calling through a clone of `channel`.
+    pub fn new(channel: &Channel) -> Self {
+        DynamicStub::from_channel(channel.clone())
+    }
+
+    /// Create a stub calling through `channel`.
+    pub fn from_channel(channel: Channel) -> Self {
+        DynamicStub {
+            wrapper: RpcWrapper::from_channel(BytesCodec, channel),
+        }
+    }
+
+    /// Call `method` of `service` with `body` as the request body.
+    ///
+    /// The future resolves to the response body, along with the controller
+    /// the request was sent with, once the interceptors of the channel ran.
+    pub fn call_raw(
+        &self,
+        service: &str,
+        method: &str,
+        body: Bytes,
+        options: CallOptions,
+    ) -> DynamicFuture {
+        let bundle = (body, service.to_string(), method.to_string());
+        let (inner, controller) = self.wrapper.start_call(bundle, options, true);
+        DynamicFuture { inner, controller }
+    }
+}
+
+/// A future that will resolve to the response body of a call made by a
+/// [`DynamicStub`], and the controller the request was sent with
+///
+/// [`DynamicStub`]: struct.DynamicStub.html
+#[derive(Debug)]
+pub struct DynamicFuture {
+    inner: StubFuture<BytesCodec>,
+    controller: Option<Controller>,
+}
+
+impl DynamicFuture {
+    /// Fail the call with `RpcError::Timeout` if it is not answered
+    /// within `timeout`, see [`StubFuture::timeout`].
+    ///
+    /// [`StubFuture::timeout`]: struct.StubFuture.html#method.timeout
+    pub fn timeout(self, timeout: Duration, timer: &Timer) -> Self {
+        DynamicFuture {
+            inner: self.inner.timeout(timeout, timer),
+            controller: self.controller,
+        }
+    }
+}
+
+impl Future for DynamicFuture {
+    type Item = (Bytes, Controller);
+
+    type Error = RpcError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (body, _) = try_ready!(self.inner.poll());
+        let controller = self.controller.take().unwrap_or_default();
+        Ok(Async::Ready((body, controller)))
+    }
+}
+
+/// Error of a call made through a stub
+#[derive(Debug)]
+pub enum RpcError {
+    /// The server answered with an error code, see [`errno`], and the error
+    /// text it set, unless the error is told by another variant
+    ///
+    /// [`errno`]: ../errno/index.html
+    RemoteError {
+        /// The error code in the response meta
+        code: i32,
+        /// The error text in the response meta
+        message: String,
+    },
+    /// The connection to the server failed, or no server is connected
+    TransportError(io::Error),
+    /// The request could not be encoded, or the response decoded, with the
+    /// reason
+    CodecError(String),
+    /// No response was received before the deadline of the call, or the
+    /// server gave up at the deadline sent along
+    Timeout,
+    /// The channel is shut down
+    ChannelClosed,
+    /// As many calls as the channel allows are pending
+    ChannelFull,
+    /// As many calls as the concurrency limit of the channel allows are in
+    /// flight
+    ConcurrencyLimited,
+    /// The response exceeds the size limit of the channel
+    ResponseTooLarge,
+    /// A client interceptor rejected the call, with its error
+    Rejected(MethodError),
+}
+
+impl RpcError {
+    /// Get the error code of the error, the one sent by the server if it
+    /// answered with an error.
+    ///
+    /// See [`errno`] for the values.
+    ///
+    /// [`errno`]: ../errno/index.html
+    pub fn error_code(&self) -> i32 {
+        match *self {
+            RpcError::RemoteError { code, .. } => code,
+            RpcError::TransportError(_) => errno::EFAILEDSOCKET,
+            RpcError::CodecError(_) => errno::ERESPONSE,
+            RpcError::Timeout => errno::ERPCTIMEDOUT,
+            RpcError::ChannelClosed => errno::ECLOSE,
+            RpcError::ChannelFull => errno::EOVERCROWDED,
+            RpcError::ConcurrencyLimited => errno::ECONCURRENCYLIMIT,
+            RpcError::ResponseTooLarge => errno::ERESPONSETOOLARGE,
+            RpcError::Rejected(ref e) => e.error_code(),
+        }
+    }
+}
+
+/// Transport errors are equal if their kind and message are.
+impl PartialEq for RpcError {
+    fn eq(&self, other: &RpcError) -> bool {
+        match (self, other) {
+            (
+                RpcError::RemoteError { code, message },
+                RpcError::RemoteError {
+                    code: other_code,
+                    message: other_message,
+                },
+            ) => code == other_code && message == other_message,
+            (RpcError::TransportError(a), RpcError::TransportError(b)) => {
+                a.kind() == b.kind() && a.to_string() == b.to_string()
+            }
+            (RpcError::CodecError(a), RpcError::CodecError(b)) => a == b,
+            (RpcError::Timeout, RpcError::Timeout)
+            | (RpcError::ChannelClosed, RpcError::ChannelClosed)
+            | (RpcError::ChannelFull, RpcError::ChannelFull)
+            | (RpcError::ConcurrencyLimited, RpcError::ConcurrencyLimited)
+            | (RpcError::ResponseTooLarge, RpcError::ResponseTooLarge) => true,
+            (RpcError::Rejected(a), RpcError::Rejected(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RpcError::RemoteError { code, ref message } => {
+                write!(f, "server error {}: {}", code, message)
+            }
+            RpcError::TransportError(ref e) => write!(f, "connection failed: {}", e),
+            RpcError::CodecError(ref msg) => write!(f, "codec error: {}", msg),
+            RpcError::Timeout => write!(f, "call timed out"),
+            RpcError::ChannelClosed => write!(f, "channel closed"),
+            RpcError::ChannelFull => write!(f, "too many pending calls on the channel"),
+            RpcError::ConcurrencyLimited => {
+                write!(f, "concurrency limit of the channel reached")
+            }
+            RpcError::ResponseTooLarge => write!(f, "response is too large"),
+            RpcError::Rejected(ref e) => write!(f, "rejected by an interceptor: {}", e),
+        }
+    }
+}
+
+impl Error for RpcError {
+    fn description(&self) -> &str {
+        match *self {
+            RpcError::RemoteError { .. } => "server error",
+            RpcError::TransportError(_) => "connection failed",
+            RpcError::CodecError(_) => "codec error",
+            RpcError::Timeout => "timeout",
+            RpcError::ChannelClosed => "channel closed",
+            RpcError::ChannelFull => "channel full",
+            RpcError::ConcurrencyLimited => "concurrency limit reached",
+            RpcError::ResponseTooLarge => "response too large",
+            RpcError::Rejected(_) => "rejected by an interceptor",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            RpcError::TransportError(ref e) => Some(e),
+            RpcError::Rejected(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Let handlers calling other servers pass the errors of their calls on,
+/// keeping the code and text of the errors of the server.
+impl From<RpcError> for MethodError {
+    fn from(e: RpcError) -> Self {
+        match e {
+            RpcError::RemoteError { code, message } => {
+                MethodError::from_error_code(code, &message)
+            }
+            RpcError::TransportError(e) => MethodError::ConnectionFailed(e.to_string()),
+            RpcError::CodecError(_) => MethodError::CodecError,
+            RpcError::Timeout => MethodError::Timeout,
+            RpcError::ChannelClosed => MethodError::ChannelClosed,
+            RpcError::ChannelFull => MethodError::ChannelFull,
+            RpcError::ConcurrencyLimited => MethodError::ConcurrencyLimited,
+            RpcError::ResponseTooLarge => MethodError::ResponseTooLarge,
+            RpcError::Rejected(e) => e,
+        }
+    }
+}
+
+/// Convert a timeout to the milliseconds in request meta, rounding up so
+/// that a short or passed timeout is not sent as no timeout
+pub(crate) fn timeout_ms(timeout: Duration) -> i32 {
+    let millis = timeout.as_secs() * 1000 + u64::from(timeout.subsec_nanos().div_ceil(1_000_000));
+    millis.max(1).min(i32::MAX as u64) as i32
+}
+
+fn errno_to_result(result: ResponsePackage) -> Result<Bytes, RpcError> {
+    let (meta, body) = result;
+    match meta.get_error_code() {
+        errno::SUCCESS => Ok(body),
+        code => {
+            let text = meta.get_error_text();
+            match code {
+                // set by the connection, the response is not read
+                errno::ERESPONSETOOLARGE => return Err(RpcError::ResponseTooLarge),
+                // the server gave up at the deadline sent along
+                errno::ERPCTIMEDOUT => return Err(RpcError::Timeout),
+                _ => {}
+            }
+            error!("Server mark rpc to failed, error code {}: {}", code, text);
+            Err(RpcError::RemoteError {
+                code,
+                message: text.to_string(),
+            })
+        }
+    }
+}
+
+fn encode_error() -> RpcError {
+    RpcError::CodecError("failed to encode the request".to_string())
+}
+
+/// Check if a call which got no response may be retried, i.e. it did not
+/// fail to decode the response.
+fn is_connection_failure(e: &ChannelError) -> bool {
+    match *e {
+        ChannelError::IoError(ref e) => e.kind() != io::ErrorKind::InvalidData,
+        _ => false,
+    }
+}
+
+/// Convert the error of a call which got no response.
+fn channel_error(e: ChannelError) -> RpcError {
+    match e {
+        ChannelError::IoError(e) => RpcError::TransportError(e),
+        ChannelError::Closed => RpcError::ChannelClosed,
+        ChannelError::ChannelFull => RpcError::ChannelFull,
+        ChannelError::ConcurrencyLimitReached => RpcError::ConcurrencyLimited,
+        ChannelError::UnknownError => {
+            RpcError::TransportError(io::Error::other("unknown error"))
+        }
+    }
+}
+
+/// Why an attempt of a call failed
+#[derive(Debug, Clone, Copy)]
+enum Failure {
+    /// The connection failed, or no server is connected
+    Connection,
+    /// The server answered with an error code
+    ErrorCode,
+    /// The call can not succeed by retrying, e.g. the response can not be
+    /// decoded
+    Fatal,
+}
+
+/// How a failed call is sent again
+#[derive(Debug)]
+struct Retry {
+    channel: Channel,
+    req: RequestPackage,
+    options: SendOptions,
+    policy: Arc<RetryPolicy>,
+    /// Retries left
+    left: u32,
+    /// Retries made
+    retries: u32,
+    /// The wait before the next retry
+    backoff: Option<Sleep>,
+}
+
+impl Retry {
+    /// Wait before sending the call again if the failure is retried.
+    fn failed(&mut self, failure: Failure, e: &RpcError) -> bool {
+        let retried = match failure {
+            Failure::Connection => self.policy.retries_connection_failures(),
+            Failure::ErrorCode => self.policy.retries_code(e.error_code()),
+            Failure::Fatal => false,
+        };
+        if !retried || self.left == 0 {
+            return false;
+        }
+        self.left -= 1;
+        self.retries += 1;
+        let delay = self.policy.delay(self.retries);
+        debug!(
+            "[{}] Call failed with \"{}\", retrying in {:?}",
+            self.channel.name(),
+            e,
+            delay
+        );
+        self.backoff = Some(self.channel.timer().sleep(delay));
+        true
+    }
+
+    /// Send the call again once the wait is over.
+    fn poll_backoff(&mut self) -> Async<ChannelFuture> {
+        if let Some(ref mut sleep) = self.backoff {
+            match sleep.poll() {
+                Ok(Async::NotReady) => return Async::NotReady,
+                // a failed timer retries at once
+                Ok(Async::Ready(())) | Err(_) => {}
+            }
+        }
+        self.backoff = None;
+        Async::Ready(self.channel.send_call(self.req.clone(), self.options))
+    }
+}
+
+/// How a call is recorded in the metrics of its channel
+#[derive(Debug)]
+struct Measure {
+    metrics: ChannelMetrics,
+    start: Instant,
+    /// Size of the request body
+    sent: usize,
+}
+
+/// A future that will resolve to a pair of response and RPC info
+///
+/// Dropping it gives up the call: it is neither retried nor backed up, and
+/// its response is dropped when it arrives. The server is not told.
+#[derive(Debug)]
+pub struct StubFuture<C> {
+    start_usec: u64,
+    inner: Option<ChannelFuture>,
+    codec: C,
+    timeout: Option<Sleep>,
+    retry: Option<Retry>,
+    measure: Option<Measure>,
+    /// The interceptors passed the response meta, with the request meta
+    interceptors: Option<(Interceptors, RpcRequestMeta)>,
+    /// Why the call is not sent, if it is not
+    rejected: Option<RpcError>,
+}
+
+impl<C> StubFuture<C> {
+    /// Create a new future.
+    pub fn new(inner: Option<ChannelFuture>, codec: C) -> Self {
+        StubFuture {
+            start_usec: 0,
+            inner,
+            codec,
+            timeout: None,
+            retry: None,
+            measure: None,
+            interceptors: None,
+            rejected: None,
+        }
+    }
+
+    /// Fail the call with `RpcError::Timeout` if it is not answered
+    /// within `timeout`, as measured by `timer`.
+    ///
+    /// Unlike `Timer::timeout`, this keeps the error type of the call, and
+    /// gives up the call as soon as the timeout fires, releasing its place
+    /// in the limits of the channel even if the future is kept. The earlier
+    /// of this timeout and the one the call is issued with applies. The
+    /// server is not told of this timeout.
+    pub fn timeout(mut self, timeout: Duration, timer: &Timer) -> Self {
+        let sleep = timer.sleep(timeout);
+        let sleep = match self.timeout.take() {
+            Some(current) if current.remaining() <= sleep.remaining() => current,
+            _ => sleep,
+        };
+        self.with_timeout(Some(sleep))
+    }
+
+    fn with_timeout(mut self, timeout: Option<Sleep>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn with_retry(mut self, retry: Option<Retry>) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn with_measure(mut self, measure: Option<Measure>) -> Self {
+        self.measure = measure;
+        self
+    }
+
+    fn with_interceptors(mut self, interceptors: Option<(Interceptors, RpcRequestMeta)>) -> Self {
+        self.interceptors = interceptors;
+        self
+    }
+
+    fn with_rejected(mut self, rejected: Option<RpcError>) -> Self {
+        self.rejected = rejected;
+        self
+    }
+
+    fn poll_timeout(&mut self) -> Poll<(), RpcError> {
+        let expired = match self.timeout {
+            Some(ref mut sleep) => sleep.poll().map_err(|e| {
+                warn!("Failed to set up the timer of a call: {}", e);
+            }),
+            None => return Ok(Async::NotReady),
+        };
+        match expired {
+            Ok(Async::Ready(())) => Err(RpcError::Timeout),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(()) => {
+                self.timeout = None;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl<C> StubFuture<C>
+where
+    C: MethodCodec,
+{
+    /// Poll the call, telling how it ended on failure, and the size of the
+    /// response body on success.
+    fn poll_call(&mut self) -> Poll<(C::Request, usize), (RpcError, CallOutcome)> {
+        loop {
+            if let Some(ref mut retry) = self.retry {
+                if retry.backoff.is_some() {
+                    match retry.poll_backoff() {
+                        Async::Ready(fut) => self.inner = Some(fut),
+                        Async::NotReady => return self.poll_timed_out(),
+                    }
+                }
+            }
+
+            let (e, failure) = if let Some(ref mut channel) = self.inner {
+                match channel.poll() {
+                    Ok(Async::Ready((resp, fb_handle))) => {
+                        if let Some((ref interceptors, ref meta)) = self.interceptors {
+                            for interceptor in interceptors.iter() {
+                                interceptor.after_call(meta, &resp.0);
+                            }
+                        }
+                        match errno_to_result(resp) {
+                            Ok(body) => {
+                                let received = body.len();
+                                let resp = self.codec.decode(body).map_err(|_| {
+                                    let msg = "failed to decode the response".to_string();
+                                    (RpcError::CodecError(msg), CallOutcome::Other)
+                                })?;
+                                let fb = CallInfo::new(self.start_usec, None);
+                                fb_handle.call(fb);
+
+                                return Ok(Async::Ready((resp, received)));
+                            }
+                            // the response of a retry would be as large
+                            Err(RpcError::ResponseTooLarge) => {
+                                (RpcError::ResponseTooLarge, Failure::Fatal)
+                            }
+                            Err(e) => (e, Failure::ErrorCode),
+                        }
+                    }
+                    Ok(Async::NotReady) => return self.poll_timed_out(),
+                    Err(e) => {
+                        let failure = if is_connection_failure(&e) {
+                            Failure::Connection
+                        } else {
+                            Failure::Fatal
+                        };
+                        (channel_error(e), failure)
+                    }
+                }
+            } else {
+                let e = self.rejected.take().unwrap_or_else(encode_error);
+                return Err((e, CallOutcome::Other));
+            };
+
+            let retried = match self.retry {
+                Some(ref mut retry) => retry.failed(failure, &e),
+                None => false,
+            };
+            if !retried {
+                let outcome = match (failure, &e) {
+                    (Failure::Connection, _) => CallOutcome::TransportError,
+                    // the server gave up at the deadline sent along
+                    (Failure::ErrorCode, &RpcError::Timeout) => CallOutcome::TimedOut,
+                    (Failure::ErrorCode, _) => CallOutcome::ServerError,
+                    (Failure::Fatal, _) => CallOutcome::Other,
+                };
+                return Err((e, outcome));
+            }
+        }
+    }
+
+    fn poll_timed_out<T>(&mut self) -> Poll<T, (RpcError, CallOutcome)> {
+        match self.poll_timeout() {
+            Ok(_) => Ok(Async::NotReady),
+            Err(e) => Err((e, CallOutcome::TimedOut)),
+        }
+    }
+}
+
+impl<C> Future for StubFuture<C>
+where
+    C: MethodCodec,
+{
+    type Item = (C::Request, RpcInfo);
+
+    type Error = RpcError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (result, outcome, received) = match self.poll_call() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready((resp, received))) => {
+                (Ok((resp, RpcInfo)), CallOutcome::Succeeded, received)
+            }
+            Err((e, outcome)) => (Err(e), outcome, 0),
+        };
+        // give up the call at once, rather than once this future is dropped
+        self.inner = None;
+        self.retry = None;
+        self.timeout = None;
+        if let Some(measure) = self.measure.take() {
+            measure
+                .metrics
+                .record(outcome, measure.start, measure.sent, received);
+        }
+        result.map(Async::Ready)
+    }
+}
+
+/// A future that will resolve when a oneway request is written to the
+/// connection
+#[derive(Debug)]
+pub struct OnewayCallFuture {
+    inner: Option<OnewayFuture>,
+    /// Why the call is not sent, if it is not
+    rejected: Option<RpcError>,
+    /// Where the call is counted once it ends
+    metrics: Option<ChannelMetrics>,
+}
+
+impl Future for OnewayCallFuture {
+    type Item = ();
+
+    type Error = RpcError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.inner {
+            Some(ref mut channel) => channel.poll().map_err(channel_error),
+            None => Err(self.rejected.take().unwrap_or_else(encode_error)),
+        };
+        if let Ok(Async::NotReady) = result {
+            return result;
+        }
+        if let Some(metrics) = self.metrics.take() {
+            metrics.record_oneway(result.is_ok());
+        }
+        result
+    }
+}
+
+/// [WIP] Information about how the RPC request has been processed
+#[derive(Clone, Debug, PartialEq)]
+pub struct RpcInfo;
@@ -0,0 +1,179 @@
+//! Blocking client for synchronous programs
+//!
+//! A [`SyncChannel`] runs its event loop and the backend of its channel in a
+//! thread of its own, so calls can be made without a `Core`, and block until
+//! they are answered.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # extern crate copra;
+//! # extern crate protobuf;
+//! # use std::error::Error;
+//! use copra::sync::SyncChannel;
+//! use protobuf::well_known_types::StringValue;
+//! use std::time::Duration;
+//!
+//! # fn main() {
+//! #     try_main().unwrap();
+//! # }
+//! # fn try_main() -> Result<(), Box<Error>> {
+//! let channel = SyncChannel::connect("127.0.0.1:8000", |builder| {
+//!     builder.rpc_timeout(Duration::from_secs(1))
+//! })?;
+//! let mut request = StringValue::new();
+//! request.set_value("hello".to_string());
+//! let response: StringValue = channel.call("Echo", "echo", request, None)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`SyncChannel`]: struct.SyncChannel.html
+
+use futures::Future;
+use protobuf::{Message, MessageStatic};
+use std::fmt;
+use std::io;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio_core::reactor::Core;
+
+use channel::{Channel, ChannelBuildError, ChannelBuilder};
+use codec::ProtobufCodec;
+use stub::{CallOptions, RpcError, RpcWrapper};
+
+/// A channel whose calls block the calling thread
+///
+/// The event loop of the channel runs in a background thread, started by
+/// [`connect`]. Dropping the channel shuts it down, waiting for the calls in
+/// flight up to its drain timeout, and joins the thread.
+///
+/// Generated stubs are created on it by `new_sync`, and their futures are
+/// waited for with `Future::wait`:
+///
+/// ```ignore
+/// let stub = EchoStub::new_sync(&channel);
+/// let (response, _info) = stub.echo(request).wait()?;
+/// ```
+///
+/// [`connect`]: #method.connect
+pub struct SyncChannel {
+    channel: Channel,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl fmt::Debug for SyncChannel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SyncChannel")
+            .field("closed", &self.channel.is_closed())
+            .finish()
+    }
+}
+
+impl SyncChannel {
+    /// Connect to a server by its address, or its host name and port, as
+    /// [`ChannelBuilder::single_server`].
+    ///
+    /// The builder is set up by `configure` in the background thread, e.g.
+    /// `|builder| builder.rpc_timeout(timeout)`, and `|builder| builder`
+    /// keeps the defaults. This method returns once the channel is
+    /// built.
+    ///
+    /// # Errors
+    /// Same as [`ChannelBuilder::build`], and an `IoError` if the event loop
+    /// can not be created.
+    ///
+    /// [`ChannelBuilder::single_server`]: ../channel/struct.ChannelBuilder.html#method.single_server
+    /// [`ChannelBuilder::build`]: ../channel/struct.ChannelBuilder.html#method.build
+    pub fn connect<F>(addr: &str, configure: F) -> Result<Self, ChannelBuildError>
+    where
+        F: for<'a> FnOnce(ChannelBuilder<'a>) -> ChannelBuilder<'a> + Send + 'static,
+    {
+        let addr = addr.to_string();
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("copra-channel".to_string())
+            .spawn(move || {
+                let mut core = match Core::new() {
+                    Ok(core) => core,
+                    Err(e) => {
+                        let _ = tx.send(Err(ChannelBuildError::IoError(e)));
+                        return;
+                    }
+                };
+                let builder = configure(ChannelBuilder::single_server(&*addr, core.handle()));
+                match core.run(builder.build_split()) {
+                    Ok((channel, backend)) => {
+                        let _ = tx.send(Ok(channel));
+                        // ends once the channel is shut down
+                        let _ = core.run(backend);
+                    }
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                    }
+                }
+            })
+            .map_err(ChannelBuildError::IoError)?;
+
+        match rx.recv() {
+            Ok(Ok(channel)) => Ok(SyncChannel {
+                channel,
+                thread: Some(thread),
+            }),
+            Ok(Err(e)) => {
+                let _ = thread.join();
+                Err(e)
+            }
+            Err(_) => {
+                let _ = thread.join();
+                let e = io::Error::other("channel thread panicked");
+                Err(ChannelBuildError::IoError(e))
+            }
+        }
+    }
+
+    /// Call `method` of `service` with `request`, blocking until the
+    /// response arrives.
+    ///
+    /// The call fails with `RpcError::Timeout` if no response is received
+    /// within `timeout`, or the rpc timeout of the channel if `None`.
+    pub fn call<Req, Resp>(
+        &self,
+        service: &str,
+        method: &str,
+        request: Req,
+        timeout: Option<Duration>,
+    ) -> Result<Resp, RpcError>
+    where
+        Req: Message + Clone,
+        Resp: Message + MessageStatic + Clone,
+    {
+        let wrapper = RpcWrapper::new(ProtobufCodec::<Resp, Req>::new(), &self.channel);
+        let mut options = CallOptions::new();
+        if let Some(timeout) = timeout {
+            options = options.timeout(timeout);
+        }
+        let bundle = (request, service.to_string(), method.to_string());
+        let (response, _info) = wrapper.call_with_options(bundle, options).wait()?;
+        Ok(response)
+    }
+
+    /// Get the channel, e.g. to create stubs on it.
+    ///
+    /// Its futures are resolved by the background thread, and can be waited
+    /// for in any thread.
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+}
+
+impl Drop for SyncChannel {
+    fn drop(&mut self) {
+        // the backend ends once the channel is shut down
+        let _ = self.channel.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! The timer of the timeouts of calls and requests
+
+use std::time::Duration;
+use tokio_timer::{self, Timer};
+
+/// Create a timer ticking every millisecond.
+///
+/// `Timer::default()` ticks every 100 milliseconds, firing any timeout
+/// within a tick at once, so that a timeout of 80 milliseconds would fire
+/// at the first tick. Timeouts up to an hour are supported.
+pub(crate) fn new() -> Timer {
+    tokio_timer::wheel()
+        .tick_duration(Duration::from_millis(1))
+        .max_timeout(Duration::from_secs(3600))
+        .build()
+}
@@ -1,13 +1,24 @@
-use bytes::{Bytes, BytesMut, BigEndian, BufMut};
-use copra::{ChannelBuilder, MethodError};
+use bytes::{Buf, Bytes, BytesMut, BigEndian, BufMut, IntoBuf};
+use copra::ChannelBuilder;
+use copra::channel::{BackendInfo, ChannelBuildError, ConcurrencyLimit, LbHint, LoadBalance,
+                     RetryPolicy};
+use copra::errno;
 use copra::message::{ResponsePackage, RpcResponseMeta, RpcMeta};
 use copra::controller::Controller;
-use futures::Future;
+use copra::stub::{CallOptions, RpcError};
+use futures::{future, Future};
+use futures::future::Either;
 use mock::MockServerBuilder;
+use net2::TcpBuilder;
 use protobuf::{CodedOutputStream, Message};
-use std::time::Duration;
-use std::thread::spawn;
-use tokio_core::reactor::{Core, Handle};
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, spawn, JoinHandle};
+use tokio_core::reactor::{Core, Handle, Timeout};
 
 use generated::simple::Simple;
 use generated::simple_copra::EchoStub;
@@ -102,7 +113,7 @@ fn bad_body_message() {
     let stub = EchoStub::new(&channel);
 
     let result = core.run(stub.echo(msg));
-    assert_eq!(result, Err(MethodError::CodecError));
+    assert_eq!(result.unwrap_err().error_code(), errno::ERESPONSE);
 
     join.join().unwrap();
 }
@@ -150,8 +161,900 @@ fn bad_rpc_response_meta() {
     let channel = core.run(builder.build()).unwrap();
     let stub = EchoStub::new(&channel);
 
-    let result= core.run(stub.echo(msg));
-    assert_eq!(result, Err(MethodError::UnknownError));
+    // a response which can not be decoded breaks the connection
+    match core.run(stub.echo(msg)) {
+        Err(RpcError::TransportError(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    join.join().unwrap();
+}
+
+#[test]
+fn per_call_timeout() {
+    let addr = "127.0.0.1:9004";
+    let mut core = Core::new().unwrap();
+
+    let mut builder = MockServerBuilder::new(addr, core.handle());
+
+    let msg = simple(10, true, "HelloWorld");
+
+    // operations are popped in reverse order
+    for &delay in [0, 1, 1].iter() {
+        let send_msg = msg.clone();
+        builder.respond_package(
+            move || {
+                let meta = RpcResponseMeta::new();
+                let ctrl = Controller::default();
+                (meta, ctrl, encode_message(&send_msg).freeze())
+            },
+            Duration::from_secs(delay),
+        );
+    }
+
+    let join = spawn(move || {
+        builder.build().start().unwrap();
+    });
+
+    let builder = ChannelBuilder::single_server(addr, core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let opts = CallOptions::new().timeout(Duration::from_millis(200));
+    let result = core.run(stub.echo_opts(msg.clone(), opts));
+    assert_eq!(result, Err(RpcError::Timeout));
+
+    // a timeout shorter than a tick of the default timer fires on time
+    let start = Instant::now();
+    let opts = CallOptions::new().timeout(Duration::from_millis(50));
+    let result = core.run(stub.echo_opts(msg.clone(), opts));
+    assert_eq!(result, Err(RpcError::Timeout));
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(45), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(90), "{:?}", elapsed);
+
+    // the late response of the timed out call must be discarded
+    let (resp, _info) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+
+    join.join().unwrap();
+}
+
+/// Record the calls in flight when a call is sent, and the failed calls
+#[derive(Clone, Debug, Default)]
+struct InflightProbe {
+    inflight: Arc<AtomicUsize>,
+    errors: Arc<AtomicUsize>,
+}
+
+impl LoadBalance for InflightProbe {
+    fn select(&self, backends: &[BackendInfo], _: Option<&LbHint>) -> Option<usize> {
+        self.inflight.store(backends[0].inflight(), Ordering::SeqCst);
+        Some(0)
+    }
+
+    fn on_error(&self, _: usize, _: Duration) {
+        self.errors.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn channel_rpc_timeout() {
+    let addr = "127.0.0.1:9006";
+    let mut core = Core::new().unwrap();
+
+    let mut builder = MockServerBuilder::new(addr, core.handle());
+
+    let late = simple(1, true, "late");
+    let fresh = simple(2, true, "fresh");
+    // operations are popped in reverse order, the second request is read
+    // once the late response is sent
+    for &(msg, delay) in &[(&fresh, 0), (&late, 1)] {
+        let send_msg = msg.clone();
+        builder.respond_package(
+            move || {
+                let meta = RpcResponseMeta::new();
+                let ctrl = Controller::default();
+                (meta, ctrl, encode_message(&send_msg).freeze())
+            },
+            Duration::from_secs(delay),
+        );
+    }
+
+    let join = spawn(move || {
+        builder.build().start().unwrap();
+    });
+
+    let probe = InflightProbe::default();
+    let builder = ChannelBuilder::single_server(addr, core.handle())
+        .rpc_timeout(Duration::from_millis(200))
+        .load_balancer(Box::new(probe.clone()));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let start = Instant::now();
+    let result = core.run(stub.echo(late.clone()));
+    assert_eq!(result, Err(RpcError::Timeout));
+    assert!(start.elapsed() < Duration::from_millis(900));
+
+    // the timed out call is given up and no longer in flight
+    let wait = Timeout::new(Duration::from_millis(50), &core.handle()).unwrap();
+    core.run(wait).unwrap();
+    assert_eq!(probe.errors.load(Ordering::SeqCst), 1);
+
+    // the late response arrives while this call waits, and is not taken
+    // for its response
+    let opts = CallOptions::new().timeout(Duration::from_secs(5));
+    let (resp, _info) = core.run(stub.echo_opts(fresh.clone(), opts)).unwrap();
+    assert_eq!(probe.inflight.load(Ordering::SeqCst), 0);
+    assert_eq!(resp, fresh);
+    assert!(start.elapsed() >= Duration::from_secs(1));
+
+    join.join().unwrap();
+}
+
+/// Start a mock server which answers with each of `codes` in turn, `msg`
+/// when the code is 0.
+fn flaky_server(addr: &str, codes: &[i32], msg: &Simple, handle: Handle) -> JoinHandle<()> {
+    let mut builder = MockServerBuilder::new(addr, handle);
+    // operations are popped in reverse order
+    for &code in codes.iter().rev() {
+        let send_msg = msg.clone();
+        builder.respond_package(
+            move || {
+                let mut meta = RpcResponseMeta::new();
+                meta.set_error_code(code);
+                let ctrl = Controller::default();
+                (meta, ctrl, encode_message(&send_msg).freeze())
+            },
+            Duration::from_secs(0),
+        );
+    }
+    spawn(move || {
+        builder.build().start().unwrap();
+    })
+}
+
+#[test]
+fn retry_policy_retries_busy_server() {
+    let msg = simple(3, true, "retried");
+    let busy = errno::ELIMIT;
+    let mut core = Core::new().unwrap();
+
+    // fails the first two calls
+    let addr = "127.0.0.1:9007";
+    let join = flaky_server(addr, &[busy, busy, 0, busy], &msg, core.handle());
+    let builder = ChannelBuilder::single_server(addr, core.handle())
+        .retry_policy(RetryPolicy::new().max_attempts(3));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let (resp, _info) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+    // a call which is not idempotent opts out
+    let opts = CallOptions::new().max_retry(0);
+    let result = core.run(stub.echo_opts(msg.clone(), opts));
+    assert_eq!(result.unwrap_err().error_code(), errno::ELIMIT);
+    let addr = addr.parse().unwrap();
+    assert_eq!(channel.server_calls(), vec![(addr, 2)]);
+    assert_eq!(channel.server_retries(), vec![(addr, 2)]);
+    join.join().unwrap();
+
+    // without a retry policy the first failure is returned
+    let addr = "127.0.0.1:9008";
+    let join = flaky_server(addr, &[busy], &msg, core.handle());
+    let builder = ChannelBuilder::single_server(addr, core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let result = core.run(stub.echo(msg.clone()));
+    assert_eq!(result.unwrap_err().error_code(), errno::ELIMIT);
+    join.join().unwrap();
+}
+
+#[test]
+fn oneway_call_does_not_wait_for_response() {
+    let addr = "127.0.0.1:9005";
+    let mut core = Core::new().unwrap();
+
+    let mut builder = MockServerBuilder::new(addr, core.handle());
+
+    let msg = simple(10, true, "HelloWorld");
+
+    // operations are popped in reverse order
+    for &delay in [0, 1].iter() {
+        let send_msg = msg.clone();
+        builder.respond_package(
+            move || {
+                let meta = RpcResponseMeta::new();
+                let ctrl = Controller::default();
+                (meta, ctrl, encode_message(&send_msg).freeze())
+            },
+            Duration::from_secs(delay),
+        );
+    }
+
+    let join = spawn(move || {
+        builder.build().start().unwrap();
+    });
+
+    let builder = ChannelBuilder::single_server(addr, core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let start = Instant::now();
+    core.run(stub.notify_oneway(msg.clone())).unwrap();
+    assert!(start.elapsed() < Duration::from_millis(500));
+
+    // the response of the oneway call arrives later and is discarded
+    let (resp, _info) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+
+    join.join().unwrap();
+}
+
+#[test]
+fn oneway_call_fails_when_not_written() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    // close the connection at once, and refuse the next ones
+    let join = spawn(move || {
+        let _ = listener.accept().unwrap();
+    });
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    join.join().unwrap();
+
+    // let the channel see the connection closed
+    assert!(core.run(stub.echo(simple(1, true, "closed"))).is_err());
+
+    // nothing is written, so the oneway call is not sent
+    assert!(core.run(stub.notify_oneway(simple(2, true, "down"))).is_err());
+}
+
+#[test]
+fn pending_call_fails_when_connection_closed() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    // close the connection once a request arrives, and refuse the next ones
+    let join = spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 64];
+        let _ = stream.read(&mut buf).unwrap();
+    });
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let start = Instant::now();
+    match core.run(stub.echo(simple(1, true, "closed"))) {
+        Err(RpcError::TransportError(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+    join.join().unwrap();
+
+    // no server is connected, new calls fail at once
+    let start = Instant::now();
+    match core.run(stub.echo(simple(2, true, "down"))) {
+        Err(RpcError::TransportError(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
+
+#[test]
+fn pending_calls_are_limited() {
+    // a server which never reads
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle())
+        .rpc_timeout(Duration::from_millis(200))
+        .max_pending_calls(2);
+    let channel = core.run(builder.build()).unwrap();
+    let _conn = listener.accept().unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let first = stub.echo(simple(1, true, "pending"));
+    let second = stub.echo(simple(2, true, "pending"));
+    let start = Instant::now();
+    let full = core.run(stub.echo(simple(3, true, "full")));
+    assert_eq!(full.unwrap_err(), RpcError::ChannelFull);
+    let full = core.run(stub.notify_oneway(simple(3, true, "full")));
+    assert_eq!(full.unwrap_err(), RpcError::ChannelFull);
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    // a dropped call leaves room for another
+    drop(first);
+    let third = stub.echo(simple(3, true, "pending"));
+    let full = core.run(stub.echo(simple(4, true, "full")));
+    assert_eq!(full.unwrap_err(), RpcError::ChannelFull);
+
+    // so do the calls which time out
+    assert_eq!(core.run(second).unwrap_err(), RpcError::Timeout);
+    assert_eq!(core.run(third).unwrap_err(), RpcError::Timeout);
+    let fourth = stub.echo(simple(4, true, "pending"));
+    let fifth = stub.echo(simple(5, true, "pending"));
+    assert_eq!(core.run(fourth).unwrap_err(), RpcError::Timeout);
+    assert_eq!(core.run(fifth).unwrap_err(), RpcError::Timeout);
+}
+
+#[test]
+fn build_error_names_unreachable_server() {
+    let down = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let addr = down.to_string();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle());
+    let e = match core.run(builder.build()) {
+        Err(e) => e,
+        Ok(_) => panic!("connected to a closed port"),
+    };
+    match e {
+        ChannelBuildError::ConnectError { addr, ref source } => {
+            assert_eq!(addr, down);
+            assert_eq!(source.kind(), io::ErrorKind::ConnectionRefused);
+        }
+        ref other => panic!("unexpected error {:?}", other),
+    }
+    assert!(e.source().is_some());
+    assert!(e.to_string().contains(&addr), "{}", e);
+}
+
+#[test]
+fn connect_timeout_bounds_build() {
+    // a listener which never accepts, with its queue full, leaves new
+    // connections unanswered as a firewall dropping them would
+    let listener = TcpBuilder::new_v4()
+        .unwrap()
+        .bind("127.0.0.1:0")
+        .unwrap()
+        .listen(0)
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    let queued: Vec<_> = (0..4)
+        .filter_map(|_| TcpStream::connect_timeout(&addr, Duration::from_millis(100)).ok())
+        .collect();
+    assert!(!queued.is_empty());
+
+    let mut core = Core::new().unwrap();
+    let addr = addr.to_string();
+    let builder = ChannelBuilder::single_server(&addr, core.handle())
+        .connect_timeout(Duration::from_millis(300));
+    let start = Instant::now();
+    match core.run(builder.build()) {
+        Err(ChannelBuildError::ConnectTimeout) => {}
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(250), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+}
+
+#[test]
+fn lazy_channel_connects_on_first_call() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle()).lazy_connect(true);
+    let channel = core.run(builder.build()).unwrap();
+    assert!(listener.accept().is_err());
+
+    // the calls sent while connecting wait for the connection
+    let stub = EchoStub::new(&channel);
+    let calls: Vec<_> = (0..3)
+        .map(|i| {
+            let opts = CallOptions::new().timeout(Duration::from_millis(300));
+            stub.echo_opts(simple(i, true, "lazy"), opts)
+                .then(Ok::<_, ()>)
+        })
+        .collect();
+    for result in core.run(future::join_all(calls)).unwrap() {
+        assert_eq!(result, Err(RpcError::Timeout));
+    }
+    let (mut conn, _) = listener.accept().unwrap();
+    assert!(listener.accept().is_err());
+    conn.set_nonblocking(false).unwrap();
+    conn.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    let mut buf = [0; 256];
+    assert!(conn.read(&mut buf).unwrap() > 0);
+
+    // an answering server
+    let msg = simple(7, false, "lazy");
+    let addr = "127.0.0.1:9009";
+    let join = flaky_server(addr, &[0], &msg, core.handle());
+    let builder = ChannelBuilder::single_server(addr, core.handle()).lazy_connect(true);
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let (resp, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+    join.join().unwrap();
+
+    // nothing listens here, building still succeeds
+    let down = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let down = down.to_string();
+    let builder = ChannelBuilder::single_server(&down, core.handle()).lazy_connect(true);
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    match core.run(stub.echo(msg)) {
+        Err(RpcError::TransportError(_)) => {}
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn warm_up_waits_for_min_ready_servers() {
+    // answers every request with its own body, pings included
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let up = listener.local_addr().unwrap().to_string();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let join = spawn(move || {
+        for conn in listener.incoming().take(5) {
+            let mut conn = conn.unwrap();
+            let counter = counter.clone();
+            spawn(move || {
+                while let Ok((id, body)) = read_request(&mut conn) {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    conn.write_all(&response_bytes(id, &body, None)).unwrap();
+                }
+            });
+        }
+    });
+    let down = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let down = down.to_string();
+
+    let mut core = Core::new().unwrap();
+    let build = |core: &mut Core, f: &Fn(ChannelBuilder) -> ChannelBuilder| {
+        let builder = ChannelBuilder::multi_server(vec![&up, &down], core.handle());
+        core.run(f(builder).build())
+    };
+    // every server by default
+    match build(&mut core, &|builder| builder.warm_up(true)) {
+        Err(ChannelBuildError::ConnectError { ref source, .. }) => {
+            assert_eq!(source.kind(), io::ErrorKind::ConnectionRefused);
+        }
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+    match build(&mut core, &|builder| builder.warm_up(true).min_ready(3)) {
+        Err(ChannelBuildError::ConnectError { .. }) => {}
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+
+    let channel = build(&mut core, &|builder| builder.warm_up(true).min_ready(1)).unwrap();
+    let stub = EchoStub::new(&channel);
+    let msg = simple(1, true, "warm");
+    let (resp, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+    assert_eq!(requests.swap(0, Ordering::SeqCst), 1);
+
+    // pinged before the channel is built, connected despite lazy_connect
+    let channel = build(&mut core, &|builder| {
+        builder
+            .lazy_connect(true)
+            .warm_up(true)
+            .min_ready(1)
+            .warm_up_ping(true)
+            .connect_timeout(Duration::from_secs(1))
+    }).unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    let stub = EchoStub::new(&channel);
+    let (resp, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    // a connection which is accepted but never answered is not ready
+    let mute = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mute_addr = mute.local_addr().unwrap().to_string();
+    let builder = ChannelBuilder::single_server(&mute_addr, core.handle())
+        .warm_up(true)
+        .warm_up_ping(true)
+        .connect_timeout(Duration::from_millis(300));
+    match core.run(builder.build()) {
+        Err(ChannelBuildError::ConnectTimeout) => {}
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+
+    drop(stub);
+    drop(channel);
+    drop(core);
+    // one more connection ends the server
+    drop(TcpStream::connect(&up[..]).unwrap());
+    join.join().unwrap();
+}
+
+/// Read a brpc request from `conn`, returning its correlation id and body.
+fn read_request(conn: &mut TcpStream) -> io::Result<(u64, Vec<u8>)> {
+    let mut header = [0; 12];
+    conn.read_exact(&mut header)?;
+    let pkg_len = (&header[4..8]).into_buf().get_u32::<BigEndian>() as usize;
+    let meta_len = (&header[8..12]).into_buf().get_u32::<BigEndian>() as usize;
+    let mut pkg = vec![0; pkg_len];
+    conn.read_exact(&mut pkg)?;
+    let meta: RpcMeta = protobuf::parse_from_bytes(&pkg[..meta_len]).unwrap();
+    Ok((meta.get_correlation_id(), pkg.split_off(meta_len)))
+}
+
+/// Encode a brpc response to the request `id`, declaring a package of
+/// `declared` bytes if given.
+fn response_bytes(id: u64, body: &[u8], declared: Option<u32>) -> BytesMut {
+    let mut meta = RpcMeta::new();
+    meta.set_correlation_id(id);
+    meta.set_response(RpcResponseMeta::new());
+    let meta = meta.write_to_bytes().unwrap();
+    let mut buf = BytesMut::with_capacity(12 + meta.len() + body.len());
+    buf.put_slice(b"PRPC");
+    match declared {
+        Some(len) => {
+            buf.put_u32::<BigEndian>(len);
+            buf.put_u32::<BigEndian>(len);
+        }
+        None => {
+            buf.put_u32::<BigEndian>((meta.len() + body.len()) as u32);
+            buf.put_u32::<BigEndian>(meta.len() as u32);
+            buf.put_slice(&meta);
+            buf.put_slice(body);
+        }
+    }
+    buf
+}
+
+#[test]
+fn oversized_responses_are_rejected() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let join = spawn(move || {
+        let (mut conn, _) = listener.accept().unwrap();
+        // a response over the limit, then one within it
+        for _ in 0..2 {
+            let (id, body) = read_request(&mut conn).unwrap();
+            conn.write_all(&response_bytes(id, &body, None)).unwrap();
+        }
+        // a length prefix of 4 GiB, the meta can not even be read
+        let (id, _) = read_request(&mut conn).unwrap();
+        conn.write_all(&response_bytes(id, b"", Some(u32::MAX))).unwrap();
+
+        let (mut conn, _) = listener.accept().unwrap();
+        let (id, body) = read_request(&mut conn).unwrap();
+        conn.write_all(&response_bytes(id, &body, None)).unwrap();
+    });
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle())
+        .max_response_size(1024)
+        .read_buffer_size(64 * 1024);
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let large = simple(1, true, &"large".repeat(1000));
+    assert_eq!(core.run(stub.echo(large)), Err(RpcError::ResponseTooLarge));
+    // the rest of the response is skipped, the connection is still usable
+    let msg = simple(2, true, "small");
+    let (resp, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+
+    match core.run(stub.echo(msg.clone())) {
+        Err(RpcError::TransportError(_)) => {}
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+    // connected again
+    core.run(Timeout::new(Duration::from_millis(300), &core.handle()).unwrap()).unwrap();
+    let (resp, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+    join.join().unwrap();
+}
+
+#[test]
+fn concurrency_limit_stays_below_collapse() {
+    // the server slows down five times above 8 calls in flight
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let join = spawn(move || {
+        let (mut conn, _) = listener.accept().unwrap();
+        // the responses are written one by one
+        conn.set_nodelay(true).unwrap();
+        let writer = Arc::new(Mutex::new(conn.try_clone().unwrap()));
+        let inflight = Arc::new(AtomicUsize::new(0));
+        while let Ok((id, body)) = read_request(&mut conn) {
+            let writer = writer.clone();
+            let inflight = inflight.clone();
+            spawn(move || {
+                let latency = if inflight.fetch_add(1, Ordering::SeqCst) < 8 { 20 } else { 100 };
+                thread::sleep(Duration::from_millis(latency));
+                inflight.fetch_sub(1, Ordering::SeqCst);
+                let response = response_bytes(id, &body, None);
+                let _ = writer.lock().unwrap().write_all(&response);
+            });
+        }
+    });
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle())
+        .concurrency_limit(ConcurrencyLimit::new().initial(20));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    assert_eq!(channel.concurrency_limit(), Some((20, 0)));
+
+    let msg = simple(1, true, "load");
+    let mut answered = 0;
+    for _ in 0..40 {
+        let calls: Vec<_> = (0..30)
+            .map(|_| stub.echo(msg.clone()).then(Ok::<_, ()>))
+            .collect();
+        for result in core.run(future::join_all(calls)).unwrap() {
+            match result {
+                Ok(_) => answered += 1,
+                // shed at once, never sent
+                Err(RpcError::ConcurrencyLimited) => {}
+                Err(e) => panic!("unexpected error {}", e),
+            }
+        }
+    }
+    let (limit, inflight) = channel.concurrency_limit().unwrap();
+    assert_eq!(inflight, 0);
+    // at most one call above the collapse point, probing for more
+    assert!((6..=9).contains(&limit), "limit {}", limit);
+    assert!(answered >= 40 * 6, "answered {}", answered);
+
+    drop(stub);
+    drop(channel);
+    drop(core);
+    join.join().unwrap();
+}
+
+/// Accept a connection on `listener`, answering every request with its
+/// own body after `delay`, and return the bodies read once it is closed.
+fn delayed_echo_server(listener: TcpListener, delay: Duration) -> JoinHandle<Vec<Vec<u8>>> {
+    spawn(move || {
+        let (mut conn, _) = listener.accept().unwrap();
+        let writer = Arc::new(Mutex::new(conn.try_clone().unwrap()));
+        let mut bodies = Vec::new();
+        while let Ok((id, body)) = read_request(&mut conn) {
+            let response = response_bytes(id, &body, None);
+            bodies.push(body);
+            let writer = writer.clone();
+            spawn(move || {
+                thread::sleep(delay);
+                let _ = writer.lock().unwrap().write_all(&response);
+            });
+        }
+        bodies
+    })
+}
+
+/// Drop a call `after` it is issued.
+fn drop_call(core: &mut Core, stub: &EchoStub, msg: Simple, after: Duration) {
+    let call = stub.echo(msg);
+    let timeout = Timeout::new(after, &core.handle()).unwrap();
+    match core.run(call.select2(timeout)) {
+        Ok(Either::B(((), call))) => drop(call),
+        _ => panic!("call finished before it is dropped"),
+    }
+}
+
+fn count(bodies: &[Vec<u8>], msg: &Simple) -> usize {
+    let encoded = encode_message(msg);
+    bodies.iter().filter(|body| body[..] == encoded[..]).count()
+}
+
+#[test]
+fn dropped_call_is_not_retried() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let join = delayed_echo_server(listener, Duration::from_millis(300));
+
+    let mut core = Core::new().unwrap();
+    let policy = RetryPolicy::new()
+        .max_attempts(3)
+        .retry_connection_failures(true);
+    let builder = ChannelBuilder::single_server(&addr, core.handle()).retry_policy(policy);
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let dropped = simple(1, true, "dropped");
+    drop_call(&mut core, &stub, dropped.clone(), Duration::from_millis(100));
+    // its response arrives meanwhile, and is dropped
+    core.run(Timeout::new(Duration::from_millis(400), &core.handle()).unwrap()).unwrap();
+    let msg = simple(2, true, "after");
+    let (resp, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+    let addr = addr.parse().unwrap();
+    assert_eq!(channel.server_retries(), vec![(addr, 0)]);
+
+    drop(stub);
+    drop(channel);
+    drop(core);
+    let bodies = join.join().unwrap();
+    assert_eq!(bodies.len(), 2);
+    assert_eq!((count(&bodies, &dropped), count(&bodies, &msg)), (1, 1));
+}
+
+#[test]
+fn dropped_call_gives_up_backup_request() {
+    let delay = Duration::from_millis(300);
+    let mut addrs = Vec::new();
+    let mut joins = Vec::new();
+    for _ in 0..2 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        addrs.push(listener.local_addr().unwrap().to_string());
+        joins.push(delayed_echo_server(listener, delay));
+    }
+
+    let mut core = Core::new().unwrap();
+    let addrs = addrs.iter().map(|a| &a[..]).collect();
+    let builder = ChannelBuilder::multi_server(addrs, core.handle())
+        .retry_policy(RetryPolicy::new().max_attempts(3))
+        .backup_request_after(Duration::from_millis(50));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let dropped = simple(1, true, "dropped");
+    drop_call(&mut core, &stub, dropped.clone(), Duration::from_millis(150));
+    assert_eq!(channel.backup_requests(), 1);
+    core.run(Timeout::new(Duration::from_millis(400), &core.handle()).unwrap()).unwrap();
+    // both connections are still usable
+    let msg = simple(2, true, "after");
+    let (resp, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+    assert_eq!(channel.backup_requests(), 2);
+    assert!(channel.server_retries().iter().all(|&(_, retries)| retries == 0));
+
+    drop(stub);
+    drop(channel);
+    drop(core);
+    let bodies: Vec<_> = joins
+        .into_iter()
+        .flat_map(|join| join.join().unwrap())
+        .collect();
+    // the call and its backup request, never sent again
+    assert_eq!((count(&bodies, &dropped), count(&bodies, &msg)), (2, 2));
+}
+
+#[test]
+fn call_fails_over_when_request_is_not_sent() {
+    // accept a connection, and close it when told to
+    let dead = TcpListener::bind("127.0.0.1:0").unwrap();
+    let dead_addr = dead.local_addr().unwrap().to_string();
+    let (close, closing) = std::sync::mpsc::channel::<()>();
+    let dead_join = spawn(move || {
+        let (conn, _) = dead.accept().unwrap();
+        let _ = closing.recv();
+        drop(conn);
+    });
+    let live = TcpListener::bind("127.0.0.1:0").unwrap();
+    let live_addr = live.local_addr().unwrap().to_string();
+    let live_join = delayed_echo_server(live, Duration::from_millis(0));
+
+    let mut core = Core::new().unwrap();
+    let addrs = vec![&dead_addr[..], &live_addr[..]];
+    let builder = ChannelBuilder::multi_server(addrs, core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    // the channel finds the connection closed only once the calls are sent
+    close.send(()).unwrap();
+    dead_join.join().unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let msgs: Vec<_> = (0..4).map(|i| simple(i, true, "failover")).collect();
+    let calls: Vec<_> = msgs.iter().map(|msg| stub.echo(msg.clone())).collect();
+    let resps = core.run(future::join_all(calls)).unwrap();
+    for ((resp, _), msg) in resps.into_iter().zip(&msgs) {
+        assert_eq!(&resp, msg);
+    }
+
+    drop(stub);
+    drop(channel);
+    drop(core);
+    let bodies = live_join.join().unwrap();
+    assert_eq!(bodies.len(), 4);
+}
+
+/// Send every call to the first connected server
+#[derive(Debug)]
+struct FirstConnected;
+
+impl LoadBalance for FirstConnected {
+    fn select(&self, backends: &[BackendInfo], _: Option<&LbHint>) -> Option<usize> {
+        backends.iter().position(BackendInfo::is_connected)
+    }
+}
+
+#[test]
+fn queued_requests_fail_over() {
+    // a server which never reads, and closes the connection when told to
+    let dead = TcpListener::bind("127.0.0.1:0").unwrap();
+    let dead_addr = dead.local_addr().unwrap().to_string();
+    let (close, closing) = std::sync::mpsc::channel::<()>();
+    let dead_join = spawn(move || {
+        let (conn, _) = dead.accept().unwrap();
+        let _ = closing.recv();
+        drop(conn);
+    });
+    let live = TcpListener::bind("127.0.0.1:0").unwrap();
+    let live_addr = live.local_addr().unwrap().to_string();
+    let live_join = delayed_echo_server(live, Duration::from_millis(0));
+
+    let mut core = Core::new().unwrap();
+    let addrs = vec![&dead_addr[..], &live_addr[..]];
+    let builder = ChannelBuilder::multi_server(addrs, core.handle())
+        .load_balancer(Box::new(FirstConnected));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    // the first request fills the socket buffers, the next ones wait for it
+    let big = simple(0, true, &"x".repeat(32 << 20));
+    let big_call = stub.echo(big);
+    let msgs: Vec<_> = (1..4).map(|i| simple(i, true, "queued")).collect();
+    let calls: Vec<_> = msgs.iter().map(|msg| stub.echo(msg.clone())).collect();
+    let wait = Timeout::new(Duration::from_millis(200), &core.handle()).unwrap();
+    let big_call = match core.run(big_call.select2(wait)) {
+        Ok(Either::B(((), call))) => call,
+        _ => panic!("call to a server which does not read finished"),
+    };
+    assert_eq!((channel.inflight(), channel.queued()), (4, 3));
+
+    close.send(()).unwrap();
+    dead_join.join().unwrap();
+    match core.run(big_call) {
+        Err(RpcError::TransportError(_)) => {}
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+    // the requests never sent are sent to the other server
+    let resps = core.run(future::join_all(calls)).unwrap();
+    for ((resp, _), msg) in resps.into_iter().zip(&msgs) {
+        assert_eq!(&resp, msg);
+    }
+    let live_addr = live_addr.parse().unwrap();
+    assert!(channel.server_retries().contains(&(live_addr, 3)));
+
+    drop(stub);
+    drop(channel);
+    drop(core);
+    assert_eq!(live_join.join().unwrap().len(), 3);
+}
+
+#[test]
+fn gauges_count_calls_in_flight() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let join = delayed_echo_server(listener, Duration::from_millis(300));
+
+    let mut core = Core::new().unwrap();
+    let inflight = Arc::new(AtomicUsize::new(0));
+    let builder = ChannelBuilder::single_server(&addr, core.handle())
+        .inflight_gauge(inflight.clone());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let msgs: Vec<_> = (0..5).map(|i| simple(i, true, "slow")).collect();
+    let calls: Vec<_> = msgs.iter().map(|msg| stub.echo(msg.clone())).collect();
+    // not written until the backend runs
+    assert_eq!((channel.inflight(), channel.queued()), (5, 5));
+    let wait = Timeout::new(Duration::from_millis(100), &core.handle()).unwrap();
+    let calls = match core.run(future::join_all(calls).select2(wait)) {
+        Ok(Either::B(((), calls))) => calls,
+        _ => panic!("slow calls finished"),
+    };
+    assert_eq!((channel.inflight(), channel.queued()), (5, 0));
+    assert_eq!(inflight.load(Ordering::SeqCst), 5);
+
+    let resps = core.run(calls).unwrap();
+    assert_eq!(resps.len(), 5);
+    assert_eq!((channel.inflight(), channel.queued()), (0, 0));
+    assert_eq!(inflight.load(Ordering::SeqCst), 0);
 
+    drop(stub);
+    drop(channel);
+    drop(core);
     join.join().unwrap();
 }
@@ -11,7 +11,14 @@ pub trait EchoService {
         Error = ::copra::service::MethodError,
     > + 'static;
 
+    type NotifyFuture: ::futures::Future<
+        Item = (super::simple::Empty, ::copra::controller::Controller), 
+        Error = ::copra::service::MethodError,
+    > + 'static;
+
     fn echo(&self, msg: (super::simple::Simple, ::copra::controller::Controller)) -> Self::EchoFuture;
+
+    fn notify(&self, msg: (super::simple::Simple, ::copra::controller::Controller)) -> Self::NotifyFuture;
 }
 
 pub struct EchoRegistrant<S> {
@@ -61,8 +68,41 @@ where
             ));
         }
         
+        {
+            #[derive(Clone)]
+            struct Wrapper<S: Clone>(S);
+
+            impl<S> ::copra::service::Service for Wrapper<S>
+            where
+                S: EchoService + Clone,
+            {
+                type Request = (super::simple::Simple, ::copra::controller::Controller);
+                type Response = (super::simple::Empty, ::copra::controller::Controller);
+                type Error = ::copra::service::MethodError;
+                type Future = <S as EchoService>::NotifyFuture;
+
+                fn call(&self, req: Self::Request) -> Self::Future {
+                    self.0.notify(req)
+                }
+            }
+
+            let wrap = Wrapper(provider.clone());
+            let method = ::copra::service::EncapsulatedMethod::new(
+                ::copra::codec::ProtobufCodec::new(), wrap
+            );
+            let new_method = ::copra::service::NewEncapsulatedMethod::new(method);
+            entries.push((
+                "notify".to_string(), 
+                Box::new(new_method) as ::copra::service::NewEncapService,
+            ));
+        }
+        
         entries
     }
+
+    fn file_descriptor(&self) -> Option<Vec<u8>> {
+        ::protobuf::Message::write_to_bytes(super::simple::file_descriptor_proto()).ok()
+    }
 }
 
 impl<S> ::copra::dispatcher::NamedRegistrant for EchoRegistrant<S> 
@@ -75,23 +115,47 @@ where
 }
 
 #[derive(Clone)]
-pub struct EchoStub<'a> {
-    echo_wrapper: ::copra::stub::RpcWrapper<'a,
+pub struct EchoStub {
+    echo_wrapper: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<super::simple::Simple, super::simple::Simple>>,
+
+    notify_wrapper: ::copra::stub::RpcWrapper<
+        ::copra::codec::ProtobufCodec<super::simple::Empty, super::simple::Simple>>,
 }
 
-impl<'a> EchoStub<'a> {
-    pub fn new(channel: &'a ::copra::channel::Channel) -> Self {
+impl EchoStub {
+    pub fn new(channel: &::copra::channel::Channel) -> Self {
         EchoStub {
             echo_wrapper: ::copra::stub::RpcWrapper::new(
                 ::copra::codec::ProtobufCodec::new(), channel
             ),
+
+            notify_wrapper: ::copra::stub::RpcWrapper::new(
+                ::copra::codec::ProtobufCodec::new(), channel
+            ),
         }
     }
 
+    pub fn new_sync(channel: &::copra::sync::SyncChannel) -> Self {
+        EchoStub::new(channel.channel())
+    }
+
     pub fn echo(
-        &'a self, 
+        &self,
+        msg: super::simple::Simple,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::simple::Simple,
+            super::simple::Simple,
+        >,
+    > {
+        self.echo_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn echo_opts(
+        &self,
         msg: super::simple::Simple,
+        opts: ::copra::stub::CallOptions,
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             super::simple::Simple,
@@ -99,6 +163,40 @@ impl<'a> EchoStub<'a> {
         >,
     > {
         self.echo_wrapper
-            .call((msg, "Echo".to_string(), "echo".to_string()))
+            .call_with_options((msg, "Echo".to_string(), "echo".to_string()), opts)
+    }
+
+    pub fn notify(
+        &self,
+        msg: super::simple::Simple,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::simple::Empty,
+            super::simple::Simple,
+        >,
+    > {
+        self.notify_opts(msg, ::copra::stub::CallOptions::default())
+    }
+
+    pub fn notify_opts(
+        &self,
+        msg: super::simple::Simple,
+        opts: ::copra::stub::CallOptions,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            super::simple::Empty,
+            super::simple::Simple,
+        >,
+    > {
+        self.notify_wrapper
+            .call_with_options((msg, "Echo".to_string(), "notify".to_string()), opts)
+    }
+
+    pub fn notify_oneway(
+        &self,
+        msg: super::simple::Simple,
+    ) -> ::copra::stub::OnewayCallFuture {
+        self.notify_wrapper
+            .call_oneway((msg, "Echo".to_string(), "notify".to_string()))
     }
 }
@@ -1,6 +1,7 @@
 extern crate bytes;
 extern crate copra;
 extern crate futures;
+extern crate net2;
 extern crate protobuf;
 extern crate tokio_core;
 extern crate tokio_io;
@@ -10,6 +11,7 @@ extern crate tokio_timer;
 pub mod generated;
 pub mod mock;
 pub mod channel_tests;
+pub mod server_tests;
 
 #[test]
 fn it_works() {
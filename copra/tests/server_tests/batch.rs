@@ -0,0 +1,49 @@
+use copra::{errno, ChannelBuilder, ServerBuilder};
+use copra::stub::RpcError;
+use copra::stub::batch;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Core;
+use tokio_timer::Timer;
+
+use generated::simple_copra::EchoStub;
+use super::{delayed, registry};
+
+#[test]
+fn batch_keeps_each_outcome() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(server.local_addrs()[0], core.handle()).build();
+    let channel = core.run(channel).unwrap();
+    let stub = EchoStub::new(&channel);
+    let timer = Timer::default();
+
+    let mut failing = delayed(0);
+    failing.set_str_val("fail".to_string());
+    // two fast calls, one failing on the server, one answered too late
+    let requests = [delayed(0), failing, delayed(1000), delayed(50)];
+    let calls = requests.iter().map(|msg| stub.echo(msg.clone())).collect();
+    let start = Instant::now();
+    let results = core.run(batch::join(calls, Duration::from_millis(500), &timer))
+        .unwrap();
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_millis(900));
+
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0].as_ref().unwrap().0, delayed(0));
+    let expected = RpcError::RemoteError {
+        code: errno::EINTERNAL,
+        message: "asked to fail".to_string(),
+    };
+    assert_eq!(results[1].as_ref().unwrap_err(), &expected);
+    assert_eq!(results[2].as_ref().unwrap_err(), &RpcError::Timeout);
+    assert_eq!(results[3].as_ref().unwrap().0, delayed(50));
+    // the call left at the deadline is given up
+    assert_eq!(channel.inflight(), 0);
+
+    drop(stub);
+    drop(channel);
+    server.stop().unwrap();
+}
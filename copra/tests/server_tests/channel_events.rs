@@ -0,0 +1,164 @@
+use copra::{ChannelBuilder, ServerBuilder};
+use copra::channel::ChannelEvent;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Core, Timeout};
+
+use generated::simple_copra::EchoStub;
+
+use super::{delayed, registry};
+
+#[derive(Debug, PartialEq)]
+enum Seen {
+    Connected(SocketAddr),
+    Disconnected(SocketAddr, io::ErrorKind),
+    ReconnectScheduled(SocketAddr, bool),
+    Closed,
+}
+
+impl From<&ChannelEvent> for Seen {
+    fn from(event: &ChannelEvent) -> Self {
+        match *event {
+            ChannelEvent::Connected(addr) => Seen::Connected(addr),
+            ChannelEvent::Disconnected { addr, ref cause } => {
+                Seen::Disconnected(addr, cause.kind())
+            }
+            ChannelEvent::ReconnectScheduled { addr, delay } => {
+                Seen::ReconnectScheduled(addr, delay > Duration::from_secs(0))
+            }
+            ChannelEvent::Closed => Seen::Closed,
+        }
+    }
+}
+
+fn idle(core: &mut Core, millis: u64) {
+    let timeout = Timeout::new(Duration::from_millis(millis), &core.handle()).unwrap();
+    core.run(timeout).unwrap();
+}
+
+#[test]
+fn channel_reports_lost_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let events = seen.clone();
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(addr, core.handle())
+        .reconnect_backoff(Duration::from_secs(1), Duration::from_secs(1))
+        .on_channel_event(move |_, event| events.lock().unwrap().push(Seen::from(event)))
+        .build();
+    let channel = core.run(channel).unwrap();
+    let (conn, _) = listener.accept().unwrap();
+    assert_eq!(*seen.lock().unwrap(), vec![Seen::Connected(addr)]);
+
+    // the server goes away
+    drop(conn);
+    drop(listener);
+    idle(&mut core, 200);
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            Seen::Connected(addr),
+            Seen::Disconnected(addr, io::ErrorKind::ConnectionAborted),
+            // connected to again at once, then after the backoff
+            Seen::ReconnectScheduled(addr, false),
+            Seen::ReconnectScheduled(addr, true),
+        ]
+    );
+
+    core.run(channel.shutdown()).unwrap();
+    assert_eq!(seen.lock().unwrap().last(), Some(&Seen::Closed));
+}
+
+#[test]
+fn keepalive_finds_dead_connection() {
+    // a server which stops answering, the connection stays open
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let events = seen.clone();
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(addr, core.handle())
+        .keepalive_interval(Duration::from_millis(200))
+        .reconnect_backoff(Duration::from_secs(5), Duration::from_secs(5))
+        .on_channel_event(move |_, event| events.lock().unwrap().push(Seen::from(event)))
+        .build();
+    let _channel = core.run(channel).unwrap();
+    let _conn = listener.accept().unwrap();
+
+    let start = Instant::now();
+    while !seen.lock().unwrap().iter().any(|event| matches!(*event, Seen::Disconnected(..))) {
+        assert!(start.elapsed() < Duration::from_secs(2), "{:?}", seen.lock().unwrap());
+        idle(&mut core, 20);
+    }
+    // found within about two intervals, with the slack of the timers
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(800), "{:?}", elapsed);
+    assert_eq!(
+        seen.lock().unwrap()[1],
+        Seen::Disconnected(addr, io::ErrorKind::TimedOut)
+    );
+}
+
+#[test]
+fn keepalive_keeps_live_connection() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let events = seen.clone();
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(addr, core.handle())
+        .keepalive_interval(Duration::from_millis(100))
+        .tcp_keepalive(Some(Duration::from_secs(30)))
+        .on_channel_event(move |_, event| events.lock().unwrap().push(Seen::from(event)))
+        .build();
+    let channel = core.run(channel).unwrap();
+
+    // the server answers the pings without the health check service
+    idle(&mut core, 600);
+    assert_eq!(*seen.lock().unwrap(), vec![Seen::Connected(addr)]);
+    let stub = EchoStub::new(&channel);
+    assert_eq!(core.run(stub.echo(delayed(0))).unwrap().0, delayed(0));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn events_tell_channel_name() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+
+    let names = Arc::new(Mutex::new(Vec::new()));
+    let seen = names.clone();
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(addr, core.handle())
+        .name("orders")
+        .on_channel_event(move |name, _| seen.lock().unwrap().push(name.to_string()))
+        .build();
+    let channel = core.run(channel).unwrap();
+    assert_eq!(channel.name(), "orders");
+    core.run(channel.shutdown()).unwrap();
+    assert_eq!(*names.lock().unwrap(), vec!["orders", "orders"]);
+
+    // named after the servers by default
+    let channel = core.run(ChannelBuilder::single_server(addr, core.handle()).build()).unwrap();
+    assert_eq!(channel.name(), addr.to_string());
+    let uri = format!("brpc://{}?timeout_ms=500", addr);
+    let builder = ChannelBuilder::from_uri(&uri, core.handle()).unwrap();
+    let channel = core.run(builder.build()).unwrap();
+    assert_eq!(channel.name(), uri);
+    drop(channel);
+    server.stop().unwrap();
+}
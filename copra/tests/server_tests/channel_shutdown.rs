@@ -0,0 +1,136 @@
+use copra::{ChannelBuilder, ServerBuilder};
+use copra::stub::RpcError;
+use futures::Future;
+use futures::future::join_all;
+use std::io::Read;
+use std::net::TcpListener;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Core, Timeout};
+
+use generated::simple_copra::EchoStub;
+
+use super::{delayed, registry_with, DelayedEcho};
+
+// turn the reactor for a while, so that the closed connections are dropped
+fn idle(core: &mut Core, millis: u64) {
+    let timeout = Timeout::new(Duration::from_millis(millis), &core.handle()).unwrap();
+    core.run(timeout).unwrap();
+}
+
+#[test]
+fn shutdown_idle_channel_closes_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(&addr, core.handle()).build();
+    let channel = core.run(channel).unwrap();
+    let (mut conn, _) = listener.accept().unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+    let start = Instant::now();
+    core.run(channel.shutdown()).unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert!(channel.is_closed());
+    idle(&mut core, 50);
+    // the connection ends without any request
+    let mut buf = [0; 16];
+    assert_eq!(conn.read(&mut buf).unwrap(), 0);
+
+    let stub = EchoStub::new(&channel);
+    let result = core.run(stub.echo(delayed(0)));
+    assert_eq!(result.unwrap_err(), RpcError::ChannelClosed);
+    // shutting down again is done at once
+    core.run(channel.clone().shutdown()).unwrap();
+}
+
+#[test]
+fn shutdown_waits_for_calls_in_flight() {
+    let echo = DelayedEcho::new();
+    let calls = echo.calls.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo))
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(&addr, core.handle())
+        .drain_timeout(Duration::from_secs(2))
+        .build();
+    let channel = core.run(channel).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let replies: Vec<_> = (0..3).map(|i| stub.echo(delayed(100 * i))).collect();
+    let shutdown = channel.shutdown();
+    // new calls fail at once, the ones in flight are answered
+    let rejected = core.run(stub.echo(delayed(0)));
+    assert_eq!(rejected.unwrap_err(), RpcError::ChannelClosed);
+    let (replies, ()) = core.run(join_all(replies).join(shutdown.map_err(|()| {
+        RpcError::ChannelClosed
+    }))).unwrap();
+    let replies: Vec<_> = replies.into_iter().map(|(msg, _)| msg.get_int_val()).collect();
+    assert_eq!(replies, vec![0, 100, 200]);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn shutdown_gives_up_calls_after_drain_timeout() {
+    // a server which never answers
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(&addr, core.handle())
+        .drain_timeout(Duration::from_millis(200))
+        .build();
+    let channel = core.run(channel).unwrap();
+    let (mut conn, _) = listener.accept().unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let pending = stub.echo(delayed(0));
+    let start = Instant::now();
+    core.run(channel.shutdown()).unwrap();
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    assert_eq!(core.run(pending).unwrap_err(), RpcError::ChannelClosed);
+
+    // the connection is closed with the call in flight
+    idle(&mut core, 50);
+    let mut received = Vec::new();
+    conn.read_to_end(&mut received).unwrap();
+    assert!(!received.is_empty());
+}
+
+#[test]
+fn spawned_channel_serves_and_shuts_down() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(DelayedEcho::new()))
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let channel = ChannelBuilder::single_server_spawned(&addr[..], &handle, |builder| {
+        builder.drain_timeout(Duration::from_secs(2))
+    });
+    let channel = core.run(channel).unwrap();
+    let stub = EchoStub::new(&channel);
+    let (reply, _) = core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(reply.get_int_val(), 0);
+
+    // the backend spawned on the handle drains the call in flight
+    let pending = stub.echo(delayed(100));
+    let shutdown = channel.shutdown().map_err(|()| RpcError::ChannelClosed);
+    let ((reply, _), ()) = core.run(pending.join(shutdown)).unwrap();
+    assert_eq!(reply.get_int_val(), 100);
+    assert!(channel.is_closed());
+
+    server.stop().unwrap();
+}
@@ -0,0 +1,91 @@
+use copra::{Controller, MethodError, ServerBuilder, ServiceRegistry};
+use copra::stub::CallOptions;
+use copra::sync::SyncChannel;
+use futures::{future, Future};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_timer::Timer;
+
+use generated::simple::{Empty, Simple};
+use generated::simple_copra::{EchoRegistrant, EchoService, EchoStub};
+use super::registry;
+
+// wait 200 milliseconds, then ask the next server for the milliseconds left
+// before the deadline, replying with them and the milliseconds left before
+// its own deadline in `str_val`
+#[derive(Clone)]
+struct Relay {
+    next: Arc<SyncChannel>,
+    timer: Timer,
+}
+
+impl EchoService for Relay {
+    type EchoFuture = Box<Future<Item = (Simple, Controller), Error = MethodError>>;
+
+    type NotifyFuture = future::FutureResult<(Empty, Controller), MethodError>;
+
+    fn echo(&self, (msg, ctrl): (Simple, Controller)) -> Self::EchoFuture {
+        let left = ctrl.remaining_time()
+            .map(|t| t.as_secs() as i32 * 1000 + t.subsec_millis() as i32);
+        let next = self.next.clone();
+        let wait = self.timer
+            .sleep(Duration::from_millis(200))
+            .map_err(|_| MethodError::UnknownError);
+        Box::new(wait.and_then(move |()| {
+            let stub = EchoStub::new(next.channel());
+            let opts = CallOptions::new().propagate_deadline(&ctrl);
+            stub.echo_opts(msg, opts)
+                .map(move |(mut reply, _)| {
+                    reply.set_str_val(left.unwrap_or(-1).to_string());
+                    (reply, ctrl)
+                })
+                .map_err(MethodError::from)
+        }))
+    }
+
+    fn notify(&self, (_, ctrl): (Simple, Controller)) -> Self::NotifyFuture {
+        future::ok((Empty::new(), ctrl))
+    }
+}
+
+#[test]
+fn deadline_shrinks_across_hops() {
+    let last = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let last_addr = last.local_addrs()[0].to_string();
+    let relay = Relay {
+        next: Arc::new(SyncChannel::connect(&last_addr, |builder| builder).unwrap()),
+        timer: Timer::default(),
+    };
+    let mut registry = ServiceRegistry::new();
+    registry.register_service(EchoRegistrant::new(relay));
+    let first = ServerBuilder::new("127.0.0.1:0", registry)
+        .build()
+        .unwrap()
+        .start_background();
+    let first_addr = first.local_addrs()[0].to_string();
+
+    let channel = SyncChannel::connect(&first_addr, |builder| builder).unwrap();
+    let stub = EchoStub::new_sync(&channel);
+    let mut msg = Simple::new();
+    msg.set_str_val("deadline".to_string());
+
+    let opts = CallOptions::new().timeout(Duration::from_secs(2));
+    let (reply, _) = stub.echo_opts(msg.clone(), opts).wait().unwrap();
+    let relay_left: i32 = reply.get_str_val().parse().unwrap();
+    let last_left = reply.get_int_val();
+    assert!(relay_left > 1500 && relay_left <= 2000, "relay left {}", relay_left);
+    // the relay spent about 200 milliseconds before calling on
+    assert!(last_left > 0 && last_left <= relay_left - 100, "last left {}", last_left);
+
+    // no deadline to propagate
+    let (reply, _) = stub.echo(msg).wait().unwrap();
+    assert_eq!((reply.get_str_val(), reply.get_int_val()), ("-1", -1));
+
+    drop(stub);
+    drop(channel);
+    first.stop().unwrap();
+    last.stop().unwrap();
+}
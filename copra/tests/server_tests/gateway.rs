@@ -0,0 +1,87 @@
+use bytes::Bytes;
+use copra::{errno, Controller, MethodError, ServerBuilder, ServiceRegistry};
+use copra::dispatcher::DefaultHandler;
+use copra::service::MethodFuture;
+use copra::stub::{CallOptions, DynamicStub, RpcError};
+use copra::sync::SyncChannel;
+use futures::Future;
+use protobuf::{self, Message};
+use std::sync::Arc;
+use std::time::Duration;
+
+use generated::simple::Simple;
+use super::{delayed, registry};
+
+// forward every request as is to the next server
+struct Gateway {
+    next: Arc<SyncChannel>,
+}
+
+impl DefaultHandler for Gateway {
+    fn call(&self, service: &str, method: &str, body: Bytes, ctrl: Controller) -> MethodFuture {
+        let stub = DynamicStub::new(self.next.channel());
+        let opts = CallOptions::new().propagate_deadline(&ctrl);
+        let fut = stub.call_raw(service, method, body, opts)
+            .map(move |(body, _)| (body, ctrl))
+            .map_err(MethodError::from);
+        Box::new(fut)
+    }
+}
+
+#[test]
+fn gateway_forwards_raw_bytes() {
+    let backend = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let backend_addr = backend.local_addrs()[0].to_string();
+    let next = SyncChannel::connect(&backend_addr, |builder| builder).unwrap();
+    let mut registry = ServiceRegistry::new();
+    registry.set_default_handler(Gateway {
+        next: Arc::new(next),
+    });
+    let gateway = ServerBuilder::new("127.0.0.1:0", registry)
+        .build()
+        .unwrap()
+        .start_background();
+    let gateway_addr = gateway.local_addrs()[0].to_string();
+
+    let channel = SyncChannel::connect(&gateway_addr, |builder| builder).unwrap();
+    let stub = DynamicStub::new(channel.channel());
+    let mut msg = delayed(0);
+    msg.set_str_val("through the gateway".to_string());
+    let body = Bytes::from(msg.write_to_bytes().unwrap());
+    let opts = CallOptions::new().request_id(7);
+    let (resp, ctrl) = stub.call_raw("Echo", "echo", body.clone(), opts).wait().unwrap();
+    assert_eq!(resp, body);
+    assert_eq!(protobuf::parse_from_bytes::<Simple>(&resp).unwrap(), msg);
+    assert_eq!(ctrl.request_id, 7);
+
+    // the errors of the backend are passed along
+    let mut msg = delayed(0);
+    msg.set_str_val("fail".to_string());
+    let body = Bytes::from(msg.write_to_bytes().unwrap());
+    let err = stub.call_raw("Echo", "echo", body, CallOptions::new())
+        .wait()
+        .unwrap_err();
+    let expected = RpcError::RemoteError {
+        code: errno::EINTERNAL,
+        message: "asked to fail".to_string(),
+    };
+    assert_eq!(err, expected);
+    let err = stub.call_raw("Nope", "echo", Bytes::new(), CallOptions::new())
+        .wait()
+        .unwrap_err();
+    assert_eq!(err.error_code(), errno::ENOSERVICE);
+
+    // and so is the deadline
+    let body = Bytes::from(delayed(1000).write_to_bytes().unwrap());
+    let opts = CallOptions::new().timeout(Duration::from_millis(300));
+    let err = stub.call_raw("Echo", "echo", body, opts).wait().unwrap_err();
+    assert_eq!(err, RpcError::Timeout);
+
+    drop(stub);
+    drop(channel);
+    gateway.stop().unwrap();
+    backend.stop().unwrap();
+}
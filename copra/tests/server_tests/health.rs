@@ -0,0 +1,162 @@
+use copra::{ChannelBuilder, ServerBuilder};
+use copra::channel::HealthCheckMode;
+use copra::codec::ProtobufCodec;
+use copra::server::health::{HealthCheckRequest, HealthCheckResponse, ServingStatus};
+use copra::stub::RpcWrapper;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio_core::reactor::{Core, Timeout};
+
+use generated::simple_copra::EchoStub;
+
+use super::{delayed, registry, registry_with, DelayedEcho};
+
+// GET /health, return the status line
+fn http_health(addr: SocketAddr) -> String {
+    let mut conn = TcpStream::connect(addr).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    conn.write_all(b"GET /health HTTP/1.1\r\n\r\n").unwrap();
+
+    let mut resp = Vec::new();
+    let mut buf = [0; 256];
+    while !resp.contains(&b'\n') {
+        let n = conn.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed early");
+        resp.extend_from_slice(&buf[..n]);
+    }
+    let resp = String::from_utf8(resp).unwrap();
+    resp.lines().next().unwrap().to_string()
+}
+
+#[test]
+fn health_check_follows_reporter() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .enable_health_check()
+        .build()
+        .unwrap();
+    let reporter = server.health_reporter().unwrap();
+    let server = server.start_background();
+    let addr = server.local_addrs()[0];
+
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(addr, core.handle()).build();
+    let channel = core.run(channel).unwrap();
+    let codec: ProtobufCodec<HealthCheckResponse, HealthCheckRequest> = ProtobufCodec::new();
+    let health = RpcWrapper::new(codec, &channel);
+    let mut check = |service: &str| {
+        let mut req = HealthCheckRequest::new();
+        req.set_service(service.to_string());
+        let call = health.call((req, "Health".to_string(), "check".to_string()));
+        core.run(call).unwrap().0.get_status()
+    };
+
+    assert_eq!(check(""), ServingStatus::SERVING);
+    assert_eq!(check("Echo"), ServingStatus::SERVING);
+    assert_eq!(check("Nope"), ServingStatus::SERVICE_UNKNOWN);
+    assert_eq!(http_health(addr), "HTTP/1.1 200 OK");
+
+    reporter.set_not_serving("");
+    reporter.set_not_serving("Echo");
+    assert_eq!(check(""), ServingStatus::NOT_SERVING);
+    assert_eq!(check("Echo"), ServingStatus::NOT_SERVING);
+    assert_eq!(http_health(addr), "HTTP/1.1 503 Service Unavailable");
+
+    reporter.set_serving("");
+    assert_eq!(http_health(addr), "HTTP/1.1 200 OK");
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn channel_skips_servers_failing_health_check() {
+    let echoes: Vec<_> = (0..3).map(|_| DelayedEcho::new()).collect();
+    let mut reporters = Vec::new();
+    let mut servers = Vec::new();
+    for (i, echo) in echoes.iter().enumerate() {
+        let mut builder = ServerBuilder::new("127.0.0.1:0", registry_with(echo.clone()));
+        // the last server does not register the health check service
+        if i < 2 {
+            builder = builder.enable_health_check();
+        }
+        let server = builder.build().unwrap();
+        reporters.push(server.health_reporter());
+        servers.push(server.start_background());
+    }
+    let addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0].to_string())
+        .collect();
+
+    let mut core = Core::new().unwrap();
+    let addrs = addrs.iter().map(String::as_str).collect();
+    let builder = ChannelBuilder::multi_server(addrs, core.handle())
+        .health_check(Duration::from_millis(100), HealthCheckMode::Rpc);
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let calls = |core: &mut Core, n| {
+        for _ in 0..n {
+            let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+            assert_eq!(resp, delayed(0));
+        }
+        echoes
+            .iter()
+            .map(|echo| echo.calls.load(Ordering::SeqCst))
+            .collect::<Vec<_>>()
+    };
+    let wait = |core: &mut Core| {
+        let wait = Timeout::new(Duration::from_millis(350), &core.handle()).unwrap();
+        core.run(wait).unwrap();
+    };
+    let health = |channel: &::copra::channel::Channel| {
+        channel
+            .server_health()
+            .into_iter()
+            .map(|(_, up)| up)
+            .collect::<Vec<_>>()
+    };
+
+    reporters[1].as_ref().unwrap().set_not_serving("");
+    wait(&mut core);
+    assert_eq!(health(&channel), vec![true, false, true]);
+    assert_eq!(calls(&mut core, 30), vec![15, 0, 15]);
+
+    reporters[1].as_ref().unwrap().set_serving("");
+    wait(&mut core);
+    assert_eq!(health(&channel), vec![true, true, true]);
+    let after = calls(&mut core, 30);
+    assert!(after[1] > 0);
+    assert_eq!(after.iter().sum::<usize>(), 60);
+    // probes are not counted as calls
+    let sent: usize = channel.server_calls().into_iter().map(|(_, n)| n).sum();
+    assert_eq!(sent, 60);
+
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+#[test]
+fn connect_probe_marks_stopped_server_down() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle())
+        .health_check(Duration::from_millis(100), HealthCheckMode::Connect);
+    let channel = core.run(builder.build()).unwrap();
+    let wait = |core: &mut Core| {
+        let wait = Timeout::new(Duration::from_millis(350), &core.handle()).unwrap();
+        core.run(wait).unwrap();
+    };
+
+    wait(&mut core);
+    assert!(channel.server_health()[0].1);
+    server.stop().unwrap();
+    wait(&mut core);
+    assert!(!channel.server_health()[0].1);
+}
@@ -0,0 +1,189 @@
+use copra::{errno, ChannelBuilder, ServerBuilder, ServiceRegistry};
+use copra::stub::RpcError;
+use copra::protocol::Protocol;
+use futures::future::join_all;
+use protobuf::{self, Message};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio_core::reactor::Core;
+
+use generated::simple::Simple;
+use generated::simple_copra::{EchoRegistrant, EchoStub};
+use super::{delayed, registry, registry_with, DelayedEcho};
+
+// send an http request, return the status line, the headers and the body
+fn http_call(
+    conn: &mut TcpStream,
+    method: &str,
+    path: &str,
+    content_type: &str,
+    body: &[u8],
+) -> (String, String, Vec<u8>) {
+    write!(
+        conn,
+        "{} {} HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        method,
+        path,
+        content_type,
+        body.len()
+    ).unwrap();
+    conn.write_all(body).unwrap();
+
+    let mut resp = Vec::new();
+    let mut buf = [0; 256];
+    let header_len = loop {
+        if let Some(pos) = resp.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let n = conn.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed early");
+        resp.extend_from_slice(&buf[..n]);
+    };
+    let header = String::from_utf8(resp[..header_len].to_vec()).unwrap();
+    let content_len: usize = header
+        .lines()
+        .filter_map(|line| line.split("Content-Length: ").nth(1))
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap();
+    while resp.len() < header_len + content_len {
+        let n = conn.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed early");
+        resp.extend_from_slice(&buf[..n]);
+    }
+    let status = header.lines().next().unwrap().to_string();
+    (status, header, resp[header_len..].to_vec())
+}
+
+#[test]
+fn http_calls_routed_by_path() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .protocols(vec![Protocol::Http])
+        .http_path_prefix("/rpc")
+        .enable_health_check()
+        .build()
+        .unwrap()
+        .start_background();
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let protobuf = "application/x-protobuf";
+    let mut msg = delayed(0);
+    msg.set_str_val("over http".to_string());
+    let body = msg.write_to_bytes().unwrap();
+
+    let (status, header, resp) = http_call(&mut conn, "POST", "/rpc/Echo/echo", protobuf, &body);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(header.contains("Content-Type: application/x-protobuf\r\n"));
+    assert_eq!(protobuf::parse_from_bytes::<Simple>(&resp).unwrap(), msg);
+
+    // errors are answered on the same connection
+    for path in &["/Echo/echo", "/rpc/Nope/echo", "/rpc/Echo/nope", "/rpc/Echo", "/rpc/a/b/c"] {
+        let (status, _, resp) = http_call(&mut conn, "POST", path, protobuf, &body);
+        assert_eq!(status, "HTTP/1.1 404 Not Found", "{}", path);
+        assert!(!resp.is_empty());
+    }
+    let (status, header, _) = http_call(&mut conn, "GET", "/rpc/Echo/echo", protobuf, b"");
+    assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+    assert!(header.contains("Allow: POST\r\n"));
+    let (status, _, resp) = http_call(&mut conn, "POST", "/rpc/Echo/echo", protobuf, b"\xff\xff");
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    assert_eq!(resp, b"failed to decode message\n");
+
+    // the built-in pages are not below the prefix
+    let (status, _, _) = http_call(&mut conn, "GET", "/health", "text/plain", b"");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+
+    let (status, _, resp) = http_call(&mut conn, "POST", "/rpc/Echo/echo?x=1", protobuf, &body);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(protobuf::parse_from_bytes::<Simple>(&resp).unwrap(), msg);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn echo_stub_over_http_channel() {
+    let echo = DelayedEcho::new();
+    let failing = echo.failing.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo))
+        .protocols(vec![Protocol::Http])
+        .build()
+        .unwrap()
+        .start_background();
+    let stats = server.stats();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle()).protocol(Protocol::Http);
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let mut msg = delayed(0);
+    msg.set_str_val("over http".to_string());
+    let (resp, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+
+    // overlapping calls are sent one after another, over the same connection
+    let calls = (0..4).map(|i| stub.echo(delayed(300 - 100 * i))).collect::<Vec<_>>();
+    let resps = core.run(join_all(calls)).unwrap();
+    for (i, (resp, _)) in resps.into_iter().enumerate() {
+        assert_eq!(resp, delayed(300 - 100 * i as i32));
+    }
+    assert_eq!(stats.connections(), 1);
+
+    // the body of an error response is the error text
+    failing.store(true, Ordering::SeqCst);
+    match core.run(stub.echo(delayed(0))) {
+        Err(RpcError::RemoteError { code, message }) => {
+            assert_eq!((code, message.as_str()), (errno::EINTERNAL, "scripted to fail"))
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    failing.store(false, Ordering::SeqCst);
+    let (resp, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+    assert_eq!(stats.connections(), 1);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn admin_handlers_on_their_own_listener() {
+    let server = ServerBuilder::new("127.0.0.1:0", ServiceRegistry::new())
+        .add_admin_listener("127.0.0.1:0")
+        .build()
+        .unwrap()
+        .start_background();
+    // services added while running are listed too
+    let registry = server.registry_handle();
+    assert!(registry.register_service(EchoRegistrant::new(DelayedEcho::new())));
+    let addrs = server.local_addrs();
+    let mut public = TcpStream::connect(addrs[0]).unwrap();
+    public.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let mut admin = TcpStream::connect(addrs[1]).unwrap();
+    admin.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+    let (status, header, resp) = http_call(&mut admin, "GET", "/admin/registry", "text/plain", b"");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(header.contains("Content-Type: application/json\r\n"));
+    let resp = String::from_utf8(resp).unwrap();
+    assert!(resp.starts_with("{\"Echo\":["), "{}", resp);
+    assert!(resp.contains("\"echo\""), "{}", resp);
+
+    let (status, _, _) = http_call(&mut public, "GET", "/admin/registry", "text/plain", b"");
+    assert!(status != "HTTP/1.1 200 OK", "{}", status);
+
+    let path = "/admin/loglevel?level=trace";
+    let (status, _, resp) = http_call(&mut admin, "GET", path, "text/plain", b"");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(resp, b"TRACE\n");
+    let path = "/admin/loglevel?level=loud";
+    let (status, _, _) = http_call(&mut admin, "GET", path, "text/plain", b"");
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    let path = "/admin/loglevel?level=info";
+    let (_, _, resp) = http_call(&mut admin, "GET", path, "text/plain", b"");
+    assert_eq!(resp, b"INFO\n");
+
+    server.stop().unwrap();
+}
@@ -0,0 +1,782 @@
+use copra::{ChannelBuilder, ServerBuilder};
+use copra::channel::{BackendInfo, ChannelBuildError, CircuitBreaker, CircuitState, LbHint,
+                     LoadBalance, NamingOptions, NamingService};
+use copra::load_balancer::{ConsistentHash, Random};
+use copra::naming::{Resolve, ResolveFuture, ServerEndpoint, ServerStream};
+use copra::server::ServerHandle;
+use copra::stub::CallOptions;
+use futures::{future, Future, Stream};
+use futures::sync::mpsc;
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Core, Handle, Timeout};
+
+use generated::simple_copra::EchoStub;
+
+use super::{delayed, registry_with, DelayedEcho};
+
+fn start_servers(echoes: &[DelayedEcho]) -> Vec<ServerHandle> {
+    echoes
+        .iter()
+        .map(|echo| {
+            ServerBuilder::new("127.0.0.1:0", registry_with(echo.clone()))
+                .build()
+                .unwrap()
+                .start_background()
+        })
+        .collect()
+}
+
+#[test]
+fn calls_distributed_round_robin() {
+    let echoes: Vec<_> = (0..3).map(|_| DelayedEcho::new()).collect();
+    let mut servers = start_servers(&echoes);
+    let mut addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0].to_string())
+        .collect();
+    // nothing listens here, the server is skipped
+    let down = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    addrs.push(down.to_string());
+
+    let mut core = Core::new().unwrap();
+    let addrs = addrs.iter().map(String::as_str).collect();
+    let builder = ChannelBuilder::multi_server(addrs, core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let calls = |core: &mut Core, n| {
+        for _ in 0..n {
+            let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+            assert_eq!(resp, delayed(0));
+        }
+        channel
+            .server_calls()
+            .into_iter()
+            .map(|(_, calls)| calls)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(calls(&mut core, 30), vec![10, 10, 10, 0]);
+    for echo in &echoes {
+        assert_eq!(echo.calls.load(Ordering::SeqCst), 10);
+    }
+
+    // calls skip a server once its connection is closed
+    servers.remove(1).stop().unwrap();
+    // let the client notice
+    let wait = Timeout::new(Duration::from_millis(100), &core.handle()).unwrap();
+    core.run(wait).unwrap();
+    assert_eq!(calls(&mut core, 20), vec![20, 10, 20, 0]);
+    assert_eq!(echoes[1].calls.load(Ordering::SeqCst), 10);
+
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+// send every call to the server given by the hint, and count the outcomes
+#[derive(Default)]
+struct Pinned {
+    successes: Arc<AtomicUsize>,
+    errors: Arc<AtomicUsize>,
+}
+
+impl LoadBalance for Pinned {
+    fn select(&self, backends: &[BackendInfo], hint: Option<&LbHint>) -> Option<usize> {
+        assert_eq!(backends.len(), 3);
+        hint.map(|hint| hint.key() as usize)
+    }
+
+    fn on_success(&self, _: usize, _: Duration) {
+        self.successes.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_error(&self, _: usize, _: Duration) {
+        self.errors.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn custom_load_balancer_gets_hints() {
+    let echoes: Vec<_> = (0..3).map(|_| DelayedEcho::new()).collect();
+    let servers = start_servers(&echoes);
+    let addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0].to_string())
+        .collect();
+    let lb = Pinned::default();
+    let successes = lb.successes.clone();
+    let errors = lb.errors.clone();
+
+    let mut core = Core::new().unwrap();
+    let addrs = addrs.iter().map(String::as_str).collect();
+    let builder = ChannelBuilder::multi_server(addrs, core.handle())
+        .load_balancer(Box::new(lb));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    for _ in 0..5 {
+        let opts = CallOptions::new().lb_hint(LbHint::new(2));
+        core.run(stub.echo_opts(delayed(0), opts)).unwrap();
+    }
+    let mut fail = delayed(0);
+    fail.set_str_val("fail".to_string());
+    let opts = CallOptions::new().lb_hint(LbHint::new(1));
+    assert!(core.run(stub.echo_opts(fail, opts)).is_err());
+    // no server is selected without a hint
+    assert!(core.run(stub.echo(delayed(0))).is_err());
+
+    let calls: Vec<_> = echoes.iter().map(|e| e.calls.load(Ordering::SeqCst)).collect();
+    assert_eq!(calls, vec![0, 1, 5]);
+    assert_eq!(successes.load(Ordering::SeqCst), 5);
+    assert_eq!(errors.load(Ordering::SeqCst), 1);
+
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+#[test]
+fn random_load_balancer_uses_every_server() {
+    let echoes: Vec<_> = (0..3).map(|_| DelayedEcho::new()).collect();
+    let servers = start_servers(&echoes);
+    let addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0].to_string())
+        .collect();
+
+    let mut core = Core::new().unwrap();
+    let addrs = addrs.iter().map(String::as_str).collect();
+    let builder = ChannelBuilder::multi_server(addrs, core.handle())
+        .load_balancer(Box::new(Random::with_seed(7)));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    for _ in 0..60 {
+        core.run(stub.echo(delayed(0))).unwrap();
+    }
+    for echo in &echoes {
+        assert!(echo.calls.load(Ordering::SeqCst) > 0);
+    }
+    let total: usize = channel.server_calls().iter().map(|&(_, calls)| calls).sum();
+    assert_eq!(total, 60);
+
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+#[test]
+fn calls_follow_weights() {
+    let echoes: Vec<_> = (0..3).map(|_| DelayedEcho::new()).collect();
+    let servers = start_servers(&echoes);
+    let addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0].to_string())
+        .collect();
+    let weighted: Vec<_> = addrs.iter().map(String::as_str).zip(vec![3, 2, 1]).collect();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::multi_server_weighted(&weighted, core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    for _ in 0..60 {
+        core.run(stub.echo(delayed(0))).unwrap();
+    }
+    let calls: Vec<_> = echoes.iter().map(|e| e.calls.load(Ordering::SeqCst)).collect();
+    assert_eq!(calls, vec![30, 20, 10]);
+
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+#[test]
+fn same_hash_key_same_server() {
+    let echoes: Vec<_> = (0..4).map(|_| DelayedEcho::new()).collect();
+    let servers = start_servers(&echoes);
+    let addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0].to_string())
+        .collect();
+
+    let mut core = Core::new().unwrap();
+    let addrs = addrs.iter().map(String::as_str).collect();
+    let builder = ChannelBuilder::multi_server(addrs, core.handle())
+        .load_balancer(Box::new(ConsistentHash::new()));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    for user in 0..20 {
+        let before: Vec<_> = echoes.iter().map(|e| e.calls.load(Ordering::SeqCst)).collect();
+        for _ in 0..3 {
+            let opts = CallOptions::new().hash_key(&format!("user-{}", user));
+            core.run(stub.echo_opts(delayed(0), opts)).unwrap();
+        }
+        let after: Vec<_> = echoes.iter().map(|e| e.calls.load(Ordering::SeqCst)).collect();
+        let served: Vec<_> = before.iter().zip(&after).map(|(b, a)| a - b).collect();
+        assert!(served.contains(&3), "calls of one key spread: {:?}", served);
+    }
+
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+// resolve every name to the addresses it holds
+#[derive(Clone, Default)]
+struct FixedResolver {
+    addrs: Arc<Mutex<Vec<SocketAddr>>>,
+    lookups: Arc<AtomicUsize>,
+}
+
+impl FixedResolver {
+    fn set(&self, addrs: &[SocketAddr]) {
+        *self.addrs.lock().unwrap() = addrs.to_vec();
+    }
+}
+
+impl Resolve for FixedResolver {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        assert_eq!((host, port), ("echo.service", 8000));
+        self.lookups.fetch_add(1, Ordering::SeqCst);
+        let addrs = self.addrs.lock().unwrap().clone();
+        if addrs.is_empty() {
+            Box::new(future::err(io::Error::other("no such host")))
+        } else {
+            Box::new(future::ok(addrs))
+        }
+    }
+}
+
+#[test]
+fn dns_servers_follow_resolution() {
+    let echoes: Vec<_> = (0..3).map(|_| DelayedEcho::new()).collect();
+    let servers = start_servers(&echoes);
+    let addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0])
+        .collect();
+    let resolver = FixedResolver::default();
+    resolver.set(&addrs[..2]);
+
+    let mut core = Core::new().unwrap();
+    let refresh = Duration::from_millis(50);
+    let builder = ChannelBuilder::dns("echo.service", 8000, refresh, core.handle())
+        .resolver(Box::new(resolver.clone()));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    for _ in 0..4 {
+        core.run(stub.echo(delayed(0))).unwrap();
+    }
+    let calls: Vec<_> = echoes.iter().map(|e| e.calls.load(Ordering::SeqCst)).collect();
+    assert_eq!(calls, vec![2, 2, 0]);
+
+    // the first server is replaced while a call to it is in flight
+    let slow = stub.echo(delayed(300));
+    resolver.set(&addrs[1..]);
+    let wait = Timeout::new(Duration::from_millis(150), &core.handle()).unwrap();
+    core.run(wait).unwrap();
+    let served: Vec<_> = channel.server_calls().iter().map(|&(addr, _)| addr).collect();
+    assert_eq!(served, &addrs[1..]);
+    for _ in 0..4 {
+        core.run(stub.echo(delayed(0))).unwrap();
+    }
+    assert_eq!(core.run(slow).unwrap().0.get_int_val(), 300);
+    let calls: Vec<_> = echoes.iter().map(|e| e.calls.load(Ordering::SeqCst)).collect();
+    assert_eq!(calls, vec![3, 4, 2]);
+
+    // a failed resolution keeps the servers
+    let lookups = resolver.lookups.load(Ordering::SeqCst);
+    resolver.set(&[]);
+    let wait = Timeout::new(Duration::from_millis(150), &core.handle()).unwrap();
+    core.run(wait).unwrap();
+    assert!(resolver.lookups.load(Ordering::SeqCst) > lookups);
+    core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(channel.server_calls().len(), 2);
+
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+#[test]
+fn dns_build_fails_without_address() {
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::dns("echo.service", 8000, Duration::from_secs(1), core.handle())
+        .resolver(Box::new(FixedResolver::default()));
+    assert!(core.run(builder.build()).is_err());
+}
+
+#[test]
+fn single_server_accepts_host_names_and_addresses() {
+    let echo = DelayedEcho::new();
+    let v4 = ServerBuilder::new("127.0.0.1:0", registry_with(echo.clone()))
+        .build()
+        .unwrap()
+        .start_background();
+    let v6 = ServerBuilder::new("[::1]:0", registry_with(echo.clone()))
+        .build()
+        .unwrap()
+        .start_background();
+    let (v4_addr, v6_addr) = (v4.local_addrs()[0], v6.local_addrs()[0]);
+
+    let mut core = Core::new().unwrap();
+    let host = format!("localhost:{}", v4_addr.port());
+    let (v4_str, v6_str) = (v4_addr.to_string(), v6_addr.to_string());
+    let builders = vec![
+        (ChannelBuilder::single_server(&host, core.handle()), v4_addr),
+        (ChannelBuilder::single_server(("localhost", v4_addr.port()), core.handle()), v4_addr),
+        (ChannelBuilder::single_server(v4_str.as_str(), core.handle()), v4_addr),
+        (ChannelBuilder::single_server(&v6_str, core.handle()), v6_addr),
+        (ChannelBuilder::single_server(("::1", v6_addr.port()), core.handle()), v6_addr),
+        (ChannelBuilder::single_server(v4_addr, core.handle()), v4_addr),
+    ];
+    for (builder, addr) in builders {
+        let channel = core.run(builder.build()).unwrap();
+        let stub = EchoStub::new(&channel);
+        core.run(stub.echo(delayed(0))).unwrap();
+        assert_eq!(channel.server_calls(), vec![(addr, 1)]);
+    }
+    assert_eq!(echo.calls.load(Ordering::SeqCst), 6);
+
+    let builder = ChannelBuilder::single_server("localhost", core.handle());
+    match core.run(builder.build()) {
+        Err(ChannelBuildError::AddrParseError(_)) => {}
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+
+    v4.stop().unwrap();
+    v6.stop().unwrap();
+}
+
+#[test]
+fn single_server_tries_resolved_addresses_in_order() {
+    let echo = DelayedEcho::new();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo.clone()))
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+    let down = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let resolver = FixedResolver::default();
+    resolver.set(&[down, addr]);
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server("echo.service:8000", core.handle())
+        .resolver(Box::new(resolver.clone()));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(channel.server_calls(), vec![(addr, 1)]);
+
+    // the error names the host which can not be resolved
+    resolver.set(&[]);
+    let builder = ChannelBuilder::single_server(("echo.service", 8000), core.handle())
+        .resolver(Box::new(resolver.clone()));
+    match core.run(builder.build()) {
+        Err(ChannelBuildError::ResolveHostError(ref host, _)) if host == "echo.service" => {}
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn file_naming_follows_file() {
+    let echoes: Vec<_> = (0..3).map(|_| DelayedEcho::new()).collect();
+    let servers = start_servers(&echoes);
+    let addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0])
+        .collect();
+    let path = env::temp_dir().join(format!("copra-backends-{}.txt", std::process::id()));
+    let content = format!(
+        "# echo servers\n{} 3\n\nnot an address\n{}  # weight 1\n",
+        addrs[0], addrs[1]
+    );
+    fs::write(&path, content).unwrap();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::file_naming(&path, Duration::from_millis(50), core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    for _ in 0..8 {
+        core.run(stub.echo(delayed(0))).unwrap();
+    }
+    let calls: Vec<_> = echoes.iter().map(|e| e.calls.load(Ordering::SeqCst)).collect();
+    assert_eq!(calls, vec![6, 2, 0]);
+
+    // the first server is removed, the second is kept with a new weight
+    fs::write(&path, format!("{} 1\n{} 1\n", addrs[2], addrs[1])).unwrap();
+    let wait = Timeout::new(Duration::from_millis(150), &core.handle()).unwrap();
+    core.run(wait).unwrap();
+    let listed: Vec<_> = channel.server_calls().iter().map(|&(addr, _)| addr).collect();
+    assert_eq!(listed, vec![addrs[1], addrs[2]]);
+    // the second server stays connected
+    assert_eq!(channel.server_calls()[0].1, 2);
+    for _ in 0..4 {
+        core.run(stub.echo(delayed(0))).unwrap();
+    }
+    let calls: Vec<_> = echoes.iter().map(|e| e.calls.load(Ordering::SeqCst)).collect();
+    assert_eq!(calls, vec![6, 4, 2]);
+
+    // an empty file keeps the servers
+    fs::write(&path, "# nothing here\n").unwrap();
+    let wait = Timeout::new(Duration::from_millis(150), &core.handle()).unwrap();
+    core.run(wait).unwrap();
+    assert_eq!(channel.server_calls().len(), 2);
+    core.run(stub.echo(delayed(0))).unwrap();
+
+    fs::remove_file(&path).unwrap();
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+// give the servers sent through the channel
+struct PushedNaming {
+    updates: Mutex<Option<mpsc::UnboundedReceiver<Vec<ServerEndpoint>>>>,
+}
+
+impl NamingService for PushedNaming {
+    fn subscribe(&self, _: &Handle) -> ServerStream {
+        let updates = self.updates.lock().unwrap().take().expect("subscribed twice");
+        Box::new(updates.map_err(|()| io::Error::other("closed")))
+    }
+}
+
+// prefer the connected servers tagged with the local zone
+#[derive(Default)]
+struct LocalZone {
+    next: AtomicUsize,
+}
+
+impl LoadBalance for LocalZone {
+    fn select(&self, backends: &[BackendInfo], _: Option<&LbHint>) -> Option<usize> {
+        let up: Vec<_> = (0..backends.len())
+            .filter(|&i| backends[i].is_connected())
+            .collect();
+        let local: Vec<_> = up.iter()
+            .cloned()
+            .filter(|&i| backends[i].tag("zone") == Some("local"))
+            .collect();
+        let candidates = if local.is_empty() { up } else { local };
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(candidates[self.next.fetch_add(1, Ordering::SeqCst) % candidates.len()])
+    }
+}
+
+// issue `n` calls one after another, return the calls each server got
+fn run_calls(core: &mut Core, stub: &EchoStub, echoes: &[DelayedEcho], n: usize) -> Vec<usize> {
+    for _ in 0..n {
+        core.run(stub.echo(delayed(0))).unwrap();
+    }
+    echoes
+        .iter()
+        .map(|e| e.calls.load(Ordering::SeqCst))
+        .collect()
+}
+
+#[test]
+fn naming_service_updates_servers() {
+    let echoes: Vec<_> = (0..3).map(|_| DelayedEcho::new()).collect();
+    let servers = start_servers(&echoes);
+    let addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0])
+        .collect();
+    let endpoint = |i: usize, zone: &str| ServerEndpoint::new(addrs[i]).with_tag("zone", zone);
+    let (tx, rx) = mpsc::unbounded();
+    let naming = PushedNaming {
+        updates: Mutex::new(Some(rx)),
+    };
+    tx.unbounded_send(vec![endpoint(0, "local"), endpoint(1, "remote")])
+        .unwrap();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::with_naming(Box::new(naming), NamingOptions::new(), core.handle())
+        .load_balancer(Box::new(LocalZone::default()));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let wait = |core: &mut Core| {
+        let timeout = Timeout::new(Duration::from_millis(100), &core.handle()).unwrap();
+        core.run(timeout).unwrap();
+    };
+    assert_eq!(run_calls(&mut core, &stub, &echoes, 4), vec![4, 0, 0]);
+
+    // the local server goes away, then comes back with a new one
+    tx.unbounded_send(vec![endpoint(1, "remote")]).unwrap();
+    wait(&mut core);
+    assert_eq!(run_calls(&mut core, &stub, &echoes, 2), vec![4, 2, 0]);
+    tx.unbounded_send(vec![endpoint(1, "remote"), endpoint(0, "local"), endpoint(2, "local")])
+        .unwrap();
+    wait(&mut core);
+    assert_eq!(run_calls(&mut core, &stub, &echoes, 4), vec![6, 2, 2]);
+
+    // the second server moves to the local zone
+    tx.unbounded_send(vec![endpoint(1, "local"), endpoint(2, "remote")])
+        .unwrap();
+    wait(&mut core);
+    assert_eq!(run_calls(&mut core, &stub, &echoes, 2), vec![6, 4, 2]);
+
+    // an empty update and the end of the stream keep the servers
+    tx.unbounded_send(Vec::new()).unwrap();
+    drop(tx);
+    wait(&mut core);
+    assert_eq!(run_calls(&mut core, &stub, &echoes, 1), vec![6, 5, 2]);
+    assert_eq!(channel.server_calls().len(), 2);
+
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+#[test]
+fn update_endpoints_moves_calls() {
+    let echoes: Vec<_> = (0..2).map(|_| DelayedEcho::new()).collect();
+    let servers = start_servers(&echoes);
+    let addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0])
+        .collect();
+
+    let a = addrs[0].to_string();
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&*a, core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    assert_eq!(run_calls(&mut core, &stub, &echoes, 2), vec![2, 0]);
+
+    // updated from another thread
+    let other = channel.clone();
+    let b = addrs[1];
+    thread::spawn(move || other.update_endpoints(vec![b]).unwrap())
+        .join()
+        .unwrap();
+    let timeout = Timeout::new(Duration::from_millis(100), &core.handle()).unwrap();
+    core.run(timeout).unwrap();
+    assert_eq!(run_calls(&mut core, &stub, &echoes, 3), vec![2, 3]);
+    assert_eq!(channel.server_calls(), vec![(b, 3)]);
+
+    // an empty list keeps the servers, a closed channel can not be updated
+    channel.update_endpoints(Vec::new()).unwrap();
+    assert_eq!(run_calls(&mut core, &stub, &echoes, 1), vec![2, 4]);
+    core.run(channel.shutdown()).unwrap();
+    assert!(channel.update_endpoints(vec![addrs[0]]).is_err());
+
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+#[test]
+fn naming_service_without_server() {
+    let (tx, rx) = mpsc::unbounded();
+    tx.unbounded_send(Vec::new()).unwrap();
+    let naming = PushedNaming {
+        updates: Mutex::new(Some(rx)),
+    };
+    let mut core = Core::new().unwrap();
+    let options = NamingOptions::new();
+    let builder = ChannelBuilder::with_naming(Box::new(naming), options, core.handle());
+    assert!(core.run(builder.build()).is_err());
+
+    let (tx, rx) = mpsc::unbounded();
+    tx.unbounded_send(Vec::new()).unwrap();
+    let naming = PushedNaming {
+        updates: Mutex::new(Some(rx)),
+    };
+    let options = NamingOptions::new().succeed_without_server(true);
+    let builder = ChannelBuilder::with_naming(Box::new(naming), options, core.handle());
+    let channel = core.run(builder.build()).unwrap();
+    // calls fail until a server is given
+    assert!(core.run(EchoStub::new(&channel).echo(delayed(0))).is_err());
+    drop(tx);
+}
+
+#[test]
+fn channel_reconnects_after_server_restart() {
+    let start = |addr: &str, echo: &DelayedEcho| {
+        ServerBuilder::new(addr, registry_with(echo.clone()))
+            .grace_period(Duration::from_millis(50))
+            .build()
+            .unwrap()
+            .start_background()
+    };
+    let first = DelayedEcho::new();
+    let server = start("127.0.0.1:0", &first);
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle())
+        .reconnect_backoff(Duration::from_millis(20), Duration::from_millis(100));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    core.run(stub.echo(delayed(0))).unwrap();
+
+    // a call in flight fails when the server goes away, and so do the calls
+    // made while it is down
+    let pending = stub.echo(delayed(-1));
+    let wait = Timeout::new(Duration::from_millis(50), &core.handle()).unwrap();
+    core.run(wait).unwrap();
+    server.stop().unwrap();
+    let started = Instant::now();
+    assert!(core.run(pending).is_err());
+    assert!(started.elapsed() < Duration::from_secs(1));
+    let wait = Timeout::new(Duration::from_millis(200), &core.handle()).unwrap();
+    core.run(wait).unwrap();
+    assert!(core.run(stub.echo(delayed(0))).is_err());
+
+    let second = DelayedEcho::new();
+    let server = start(&addr, &second);
+    let started = Instant::now();
+    while core.run(stub.echo(delayed(0))).is_err() {
+        assert!(started.elapsed() < Duration::from_secs(2), "not reconnected");
+        let wait = Timeout::new(Duration::from_millis(20), &core.handle()).unwrap();
+        core.run(wait).unwrap();
+    }
+    for _ in 0..3 {
+        core.run(stub.echo(delayed(0))).unwrap();
+    }
+    assert_eq!(first.calls.load(Ordering::SeqCst), 2);
+    assert_eq!(second.calls.load(Ordering::SeqCst), 4);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn backup_request_cuts_slow_server() {
+    let echo = DelayedEcho::new();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo.clone()))
+        .build()
+        .unwrap()
+        .start_background();
+    let fast = server.local_addrs()[0].to_string();
+    // connections are established by the system, but requests are never
+    // read nor answered
+    let stuck = TcpListener::bind("127.0.0.1:0").unwrap();
+    let slow = stuck.local_addr().unwrap().to_string();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::multi_server(vec![&fast, &slow], core.handle())
+        .backup_request_after(Duration::from_millis(50));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    for _ in 0..10 {
+        let start = Instant::now();
+        let opts = CallOptions::new().timeout(Duration::from_secs(2));
+        let (resp, _) = core.run(stub.echo_opts(delayed(0), opts)).unwrap();
+        assert_eq!(resp, delayed(0));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+    // every call sent to the slow server is answered by its backup, and
+    // only those
+    let calls = channel.server_calls();
+    assert_eq!(calls[0].1 + calls[1].1, 10);
+    assert!(calls[1].1 > 0);
+    assert_eq!(channel.backup_requests(), calls[1].1);
+    assert_eq!(channel.backup_wins(), calls[1].1);
+    assert_eq!(echo.calls.load(Ordering::SeqCst), 10);
+
+    // no backup request without another server
+    let builder = ChannelBuilder::single_server(&fast, core.handle())
+        .backup_request_after(Duration::from_millis(10));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let (resp, _) = core.run(stub.echo(delayed(100))).unwrap();
+    assert_eq!(resp, delayed(100));
+    assert_eq!(channel.backup_requests(), 0);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn circuit_breaker_ejects_failing_server() {
+    let echoes: Vec<_> = (0..3).map(|_| DelayedEcho::new()).collect();
+    let servers = start_servers(&echoes);
+    let addrs: Vec<_> = servers
+        .iter()
+        .map(|server| server.local_addrs()[0].to_string())
+        .collect();
+    echoes[1].failing.store(true, Ordering::SeqCst);
+
+    let mut core = Core::new().unwrap();
+    let breaker = CircuitBreaker::new()
+        .window(10, 4)
+        .max_error_rate(0.5)
+        .cooldown(Duration::from_millis(200))
+        .probes(2);
+    let addrs = addrs.iter().map(String::as_str).collect();
+    let builder = ChannelBuilder::multi_server(addrs, core.handle()).circuit_breaker(breaker);
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let calls = |core: &mut Core, n| {
+        (0..n)
+            .filter(|_| core.run(stub.echo(delayed(0))).is_err())
+            .count()
+    };
+
+    // the failing server is ejected after its first 4 calls
+    assert_eq!(calls(&mut core, 30), 4);
+    assert_eq!(echoes[1].calls.load(Ordering::SeqCst), 4);
+    let states: Vec<_> = channel.circuit_states().into_iter().map(|(_, s)| s).collect();
+    assert_eq!(
+        states,
+        vec![CircuitState::Closed, CircuitState::Open, CircuitState::Closed]
+    );
+    assert_eq!(channel.server_ejections()[1].1, 1);
+
+    // once recovered, the probe calls succeed and the server is restored
+    echoes[1].failing.store(false, Ordering::SeqCst);
+    let wait = Timeout::new(Duration::from_millis(300), &core.handle()).unwrap();
+    core.run(wait).unwrap();
+    assert_eq!(calls(&mut core, 30), 0);
+    assert!(echoes[1].calls.load(Ordering::SeqCst) >= 4 + 5);
+    assert_eq!(channel.circuit_states()[1].1, CircuitState::Closed);
+    assert_eq!(channel.server_ejections()[1].1, 1);
+
+    for server in servers {
+        server.stop().unwrap();
+    }
+}
+
+#[test]
+fn connection_pool_spreads_calls() {
+    // connections are established by the system, then accepted here
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::single_server(&addr, core.handle()).connections_per_backend(3);
+    let channel = core.run(builder.build()).unwrap();
+    let mut conns: Vec<_> = (0..3).map(|_| listener.accept().unwrap().0).collect();
+    listener.set_nonblocking(true).unwrap();
+    assert_eq!(
+        listener.accept().unwrap_err().kind(),
+        io::ErrorKind::WouldBlock
+    );
+
+    let stub = EchoStub::new(&channel);
+    let calls: Vec<_> = (0..3)
+        .map(|_| {
+            let opts = CallOptions::new().timeout(Duration::from_millis(200));
+            stub.echo_opts(delayed(0), opts).then(|result| Ok::<_, ()>(result.is_err()))
+        })
+        .collect();
+    // never answered
+    assert_eq!(core.run(future::join_all(calls)).unwrap(), vec![true; 3]);
+    // one request on every connection
+    for conn in &mut conns {
+        conn.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        let mut buf = [0; 256];
+        assert!(conn.read(&mut buf).unwrap() > 0);
+    }
+    assert_eq!(channel.server_calls()[0].1, 3);
+}
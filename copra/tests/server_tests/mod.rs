@@ -0,0 +1,1998 @@
+use copra::{errno, ChannelBuilder, Controller, MethodError, ServerBuilder, ServiceRegistry};
+use copra::channel::{Channel, ClientInterceptor};
+use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
+use copra::codec::{MethodCodec, ProtobufCodec};
+use copra::dispatcher::DefaultHandler;
+use copra::monitor::{ChannelMetrics, Throughput};
+use copra::compress::CompressType;
+use copra::message::{RequestPackage, RpcMeta, RpcRequestMeta, RpcResponseMeta};
+use copra::protocol::Protocol;
+use copra::protocol::http::HttpStatus;
+use copra::server::{default_error_mapper, AccessLogEntry, AuthContext, AuthError,
+                    ConnectionLimitPolicy, Interceptor, Next, Server, ServerBuildError};
+use copra::service::MethodFuture;
+use copra::stub::{CallOptions, OnewayCallFuture, RpcError, RpcWrapper, StubFuture};
+use futures::{Async, Future};
+use futures::future::{self, join_all};
+use futures::sync::oneshot;
+use protobuf::{self, Message};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Core;
+use tokio_timer::Timer;
+
+use generated::simple::{Empty, Simple};
+use generated::simple_copra::{EchoRegistrant, EchoService, EchoStub};
+
+mod batch;
+mod channel_events;
+mod channel_shutdown;
+mod deadline;
+mod gateway;
+mod health;
+mod http;
+mod load_balance;
+mod reflection;
+mod registry;
+#[cfg(all(unix, feature = "signals"))]
+mod signal;
+mod status;
+mod sync;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(unix)]
+mod uds;
+
+// echo the message back after `int_val` milliseconds, never respond if
+// `int_val` is negative, fail or panic if `str_val` asks to, reject http
+// requests whose body is "bad request", reply with the authenticated user
+// to "whoami" and the milliseconds left before the deadline to "deadline",
+// and sleep `int_val` milliseconds on the blocking pool if `str_val` is
+// "block", compress the reply with snappy if `str_val` is "compress", and
+// reply with the request id to "request id", and fail every call while
+// `failing` is set
+#[derive(Clone)]
+struct DelayedEcho {
+    timer: Timer,
+    calls: Arc<AtomicUsize>,
+    cancelled: Arc<AtomicUsize>,
+    failing: Arc<AtomicBool>,
+}
+
+impl DelayedEcho {
+    fn new() -> Self {
+        DelayedEcho {
+            timer: Timer::default(),
+            calls: Arc::new(AtomicUsize::new(0)),
+            cancelled: Arc::new(AtomicUsize::new(0)),
+            failing: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+struct CountOnDrop(Arc<AtomicUsize>);
+
+impl Drop for CountOnDrop {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl EchoService for DelayedEcho {
+    type EchoFuture = Box<Future<Item = (Simple, Controller), Error = MethodError>>;
+
+    type NotifyFuture = future::FutureResult<(Empty, Controller), MethodError>;
+
+    fn echo(&self, (msg, ctrl): (Simple, Controller)) -> Self::EchoFuture {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if self.failing.load(Ordering::SeqCst) {
+            let err = MethodError::Failed("scripted to fail".to_string());
+            return Box::new(future::err(err));
+        }
+        if ctrl.request_body == b"bad request" {
+            let mut reply = Controller {
+                status: Some(HttpStatus::BadRequest),
+                response_body: br#"{"error":"bad request"}"#.to_vec(),
+                ..Default::default()
+            };
+            reply.set_content_type("application/json");
+            let err = MethodError::Failed("bad request".to_string()).with_controller(reply);
+            return Box::new(future::err(err));
+        }
+        match msg.get_str_val() {
+            "fail" => {
+                let err = MethodError::Failed("asked to fail".to_string());
+                return Box::new(future::err(err));
+            }
+            "panic" => panic!("asked to panic"),
+            "panic later" => return Box::new(future::lazy(|| -> Result<_, _> {
+                panic!("asked to panic later")
+            })),
+            "whoami" => {
+                let mut reply = msg.clone();
+                let user = ctrl.auth_context.as_ref().map(|ctx| ctx.user.clone());
+                reply.set_str_val(user.unwrap_or_default());
+                return Box::new(future::ok((reply, ctrl)));
+            }
+            "deadline" => {
+                let mut reply = msg.clone();
+                let remaining = ctrl.remaining_time()
+                    .map(|t| t.as_secs() as i32 * 1000 + t.subsec_millis() as i32);
+                reply.set_int_val(remaining.unwrap_or(-1));
+                return Box::new(future::ok((reply, ctrl)));
+            }
+            "request id" => {
+                let mut reply = msg.clone();
+                reply.set_str_val(ctrl.request_id.to_string());
+                return Box::new(future::ok((reply, ctrl)));
+            }
+            "compress" => {
+                let mut ctrl = ctrl;
+                ctrl.response_compress_type = Some(CompressType::Snappy);
+                return Box::new(future::ok((msg, ctrl)));
+            }
+            "block" => {
+                let delay = Duration::from_millis(msg.get_int_val() as u64);
+                let fut = ctrl.spawn_blocking(move || {
+                    sleep(delay);
+                    Ok(msg)
+                });
+                return Box::new(fut.map(move |msg| (msg, ctrl)));
+            }
+            _ => {}
+        }
+        if msg.get_int_val() < 0 {
+            let guard = CountOnDrop(self.cancelled.clone());
+            return Box::new(future::empty().map(move |()| {
+                drop(guard);
+                (msg, ctrl)
+            }));
+        }
+        let delay = Duration::from_millis(msg.get_int_val() as u64);
+        let fut = self.timer
+            .sleep(delay)
+            .map_err(|_| MethodError::UnknownError)
+            .map(move |_| (msg, ctrl));
+        Box::new(fut)
+    }
+
+    fn notify(&self, (_, ctrl): (Simple, Controller)) -> Self::NotifyFuture {
+        future::ok((Empty::new(), ctrl))
+    }
+}
+
+fn registry() -> ServiceRegistry {
+    registry_with(DelayedEcho::new())
+}
+
+fn registry_with(echo: DelayedEcho) -> ServiceRegistry {
+    let mut registry = ServiceRegistry::new();
+    registry.register_service(EchoRegistrant::new(echo));
+    registry
+}
+
+// send raw bytes as the request body
+#[derive(Clone)]
+struct RawCodec;
+
+impl MethodCodec for RawCodec {
+    type Request = Bytes;
+    type Response = Bytes;
+    type Error = ();
+
+    fn decode(&self, buf: Bytes) -> Result<Bytes, ()> {
+        Ok(buf)
+    }
+
+    fn encode(&self, msg: Bytes) -> Result<Bytes, ()> {
+        Ok(msg)
+    }
+}
+
+fn delayed(millis: i32) -> Simple {
+    let mut msg = Simple::new();
+    msg.set_int_val(millis);
+    msg
+}
+
+// call Echo::echo over a blocking connection, speaking brpc
+fn raw_echo<S: Read + Write>(conn: &mut S, msg: &Simple) -> Simple {
+    let (meta, body) = raw_call(conn, msg, b"");
+    assert_eq!(meta.get_response().get_error_code(), 0);
+    protobuf::parse_from_bytes(&body).unwrap()
+}
+
+// send a brpc request with the authentication data, return the response meta
+// and body
+fn raw_call<S: Read + Write>(conn: &mut S, msg: &Simple, auth: &[u8]) -> (RpcMeta, Vec<u8>) {
+    let mut meta = echo_meta();
+    meta.set_authentication_data(auth.to_vec());
+    raw_call_with(conn, &meta, &msg.write_to_bytes().unwrap())
+}
+
+// the meta of a brpc request to Echo::echo
+fn echo_meta() -> RpcMeta {
+    let mut request = RpcRequestMeta::new();
+    request.set_service_name("Echo".to_string());
+    request.set_method_name("echo".to_string());
+    let mut meta = RpcMeta::new();
+    meta.set_request(request);
+    meta.set_correlation_id(1);
+    meta
+}
+
+// send a brpc request with the meta and body as is, return the response
+// meta and body
+fn raw_call_with<S: Read + Write>(conn: &mut S, meta: &RpcMeta, body: &[u8]) -> (RpcMeta, Vec<u8>) {
+    conn.write_all(&raw_frame(meta, body)).unwrap();
+
+    let mut header = [0; 12];
+    conn.read_exact(&mut header).unwrap();
+    assert_eq!(&header[..4], b"PRPC");
+    let mut lens = (&header[4..]).into_buf();
+    let pkg_len = lens.get_u32_be() as usize;
+    let meta_len = lens.get_u32_be() as usize;
+    let mut content = vec![0; pkg_len];
+    conn.read_exact(&mut content).unwrap();
+
+    let meta: RpcMeta = protobuf::parse_from_bytes(&content[..meta_len]).unwrap();
+    assert_eq!(meta.get_correlation_id(), 1);
+    (meta, content.split_off(meta_len))
+}
+
+// a brpc package with the meta and body as is
+fn raw_frame(meta: &RpcMeta, body: &[u8]) -> BytesMut {
+    let meta = meta.write_to_bytes().unwrap();
+    let mut frame = BytesMut::with_capacity(12 + meta.len() + body.len());
+    frame.put_slice(b"PRPC");
+    frame.put_u32_be((meta.len() + body.len()) as u32);
+    frame.put_u32_be(meta.len() as u32);
+    frame.put_slice(&meta);
+    frame.put_slice(body);
+    frame
+}
+
+fn wait_until<F: Fn() -> bool>(cond: F) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(2) {
+        if cond() {
+            return true;
+        }
+        sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn shutdown_stops_server() {
+    let grace = Duration::from_secs(2);
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .grace_period(grace)
+        .build()
+        .unwrap();
+    let addr = server.local_addrs()[0].to_string();
+    let handle = server.shutdown_handle();
+    let join = spawn(move || server.start());
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(resp, delayed(0));
+
+    let start = Instant::now();
+    handle.shutdown();
+    join.join().unwrap();
+    assert!(start.elapsed() < grace);
+}
+
+#[test]
+fn shutdown_waits_for_in_flight_requests() {
+    let grace = Duration::from_secs(3);
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .grace_period(grace)
+        .build()
+        .unwrap();
+    let addr = server.local_addrs()[0].to_string();
+    let handle = server.shutdown_handle();
+    let join = spawn(move || server.start());
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+
+    // the timer ticks every 100ms, shorter sleeps may fire at once
+    let timer = Timer::default();
+    let start = Instant::now();
+    let shutdown = timer
+        .sleep(Duration::from_millis(300))
+        .map(move |_| handle.shutdown())
+        .map_err(|_| RpcError::Timeout);
+    let (resp, ()) = core.run(stub.echo(delayed(1000)).join(shutdown)).unwrap();
+    assert_eq!(resp.0, delayed(1000));
+
+    join.join().unwrap();
+    assert!(start.elapsed() < grace);
+}
+
+#[test]
+fn shutdown_before_start() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap();
+    server.shutdown_handle().shutdown();
+    server.start();
+}
+
+#[test]
+fn start_in_background() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap();
+    let server = server.start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(resp, delayed(0));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn serve_on_callers_core() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap();
+    let addr = server.local_addrs()[0].to_string();
+    let shutdown = server.shutdown_handle();
+
+    let mut core = Core::new().unwrap();
+    let (tx, rx) = oneshot::channel();
+    let serve = server.serve_on(&core.handle()).then(|res| tx.send(res.is_ok()));
+    core.handle().spawn(serve.map_err(|_| ()));
+
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(resp, delayed(0));
+
+    // a server is only served once
+    let result = core.run(server.serve_on(&core.handle()));
+    assert!(result.is_err());
+
+    shutdown.shutdown();
+    assert!(core.run(rx).unwrap());
+}
+
+#[test]
+fn servers_on_port_zero() {
+    let first = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap();
+    let second = ServerBuilder::new("127.0.0.1:0", registry())
+        .threads(2)
+        .build()
+        .unwrap();
+    assert_ne!(first.local_addrs()[0].port(), 0);
+    assert_ne!(first.local_addrs()[0], second.local_addrs()[0]);
+
+    let first = first.start_background();
+    let second = second.start_background();
+
+    let mut core = Core::new().unwrap();
+    for server in &[&first, &second] {
+        let addr = server.local_addrs()[0].to_string();
+        let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+            .unwrap();
+        let stub = EchoStub::new(&channel);
+        let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+        assert_eq!(resp, delayed(0));
+    }
+
+    first.stop().unwrap();
+    second.stop().unwrap();
+}
+
+#[test]
+fn serve_on_bound_listener() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = ServerBuilder::from_listener(listener, registry())
+        .threads(2)
+        .build()
+        .unwrap();
+    assert_eq!(server.local_addrs()[0], addr);
+    let server = server.start_background();
+
+    let mut core = Core::new().unwrap();
+    let addr = addr.to_string();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(resp, delayed(0));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn bind_address_in_use() {
+    let first = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap();
+    let addr = first.local_addrs()[0].to_string();
+
+    match ServerBuilder::new(&addr, registry()).build() {
+        Err(ServerBuildError::BindError(e)) => {
+            assert_eq!(e.kind(), io::ErrorKind::AddrInUse);
+            assert!(e.to_string().contains(&addr));
+        }
+        other => panic!("expect a bind error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn dual_stack_serves_both_families() {
+    let server = ServerBuilder::new("[::]:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let port = server.local_addrs()[0].port();
+
+    for addr in &["127.0.0.1", "[::1]"] {
+        let mut conn = TcpStream::connect(format!("{}:{}", addr, port)).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        assert_eq!(raw_echo(&mut conn, &delayed(0)), delayed(0));
+    }
+    server.stop().unwrap();
+
+    let server = ServerBuilder::new("[::]:0", registry())
+        .only_v6(true)
+        .build()
+        .unwrap()
+        .start_background();
+    let port = server.local_addrs()[0].port();
+    assert!(TcpStream::connect(("::1", port)).is_ok());
+    assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    server.stop().unwrap();
+}
+
+#[test]
+fn host_name_binds_every_address() {
+    let server = ServerBuilder::new("localhost:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addrs = server.local_addrs();
+    assert!(!addrs.is_empty());
+    for addr in &addrs {
+        assert!(addr.ip().is_loopback());
+        assert_eq!(addr.port(), addrs[0].port());
+        let mut conn = TcpStream::connect(addr).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        assert_eq!(raw_echo(&mut conn, &delayed(0)), delayed(0));
+    }
+    server.stop().unwrap();
+
+    match ServerBuilder::new("no-such-host.invalid:0", registry()).build() {
+        Err(ServerBuildError::ResolveError(e)) => {
+            assert!(e.to_string().contains("no-such-host.invalid"));
+        }
+        other => panic!("expect a resolve error, got {:?}", other.map(|_| ())),
+    }
+    match ServerBuilder::new("localhost", registry()).build() {
+        Err(ServerBuildError::AddrParseError(_)) => {}
+        other => panic!("expect a parse error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn connection_limit_rejects_extra_connection() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .max_connections(2)
+        .connection_limit_policy(ConnectionLimitPolicy::Reject)
+        .build()
+        .unwrap();
+    let stats = server.stats();
+    let server = server.start_background();
+    let addr = server.local_addrs()[0];
+
+    let first = TcpStream::connect(addr).unwrap();
+    let _second = TcpStream::connect(addr).unwrap();
+    assert!(wait_until(|| stats.connections() == 2));
+
+    let mut extra = TcpStream::connect(addr).unwrap();
+    extra.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    match extra.read(&mut [0; 1]) {
+        Ok(0) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+        other => panic!("expect the connection to be closed, got {:?}", other),
+    }
+    assert_eq!(stats.connections(), 2);
+
+    drop(first);
+    assert!(wait_until(|| stats.connections() == 1));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn connection_limit_pauses_accepting() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .max_connections(1)
+        .connection_limit_policy(ConnectionLimitPolicy::Pause)
+        .build()
+        .unwrap();
+    let stats = server.stats();
+    let server = server.start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let first = TcpStream::connect(&addr).unwrap();
+    assert!(wait_until(|| stats.connections() == 1));
+
+    // queued in the backlog until the first connection is closed
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let timer = Timer::default();
+    let close_first = timer
+        .sleep(Duration::from_millis(300))
+        .map(move |_| drop(first))
+        .map_err(|_| RpcError::Timeout);
+
+    let start = Instant::now();
+    let ((resp, _), ()) = core.run(stub.echo(delayed(0)).join(close_first)).unwrap();
+    assert_eq!(resp, delayed(0));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn inflight_limit_pauses_reading() {
+    let echo = DelayedEcho::new();
+    let calls = echo.calls.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo))
+        .max_inflight_per_connection(2)
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let requests = join_all((0..5).map(|_| stub.echo(delayed(600))).collect::<Vec<_>>());
+    let timer = Timer::default();
+    let observed = Arc::new(AtomicUsize::new(0));
+    let observe = {
+        let calls = calls.clone();
+        let observed = observed.clone();
+        timer
+            .sleep(Duration::from_millis(300))
+            .map(move |_| observed.store(calls.load(Ordering::SeqCst), Ordering::SeqCst))
+            .map_err(|_| RpcError::Timeout)
+    };
+
+    let (responses, ()) = core.run(requests.join(observe)).unwrap();
+    assert_eq!(responses.len(), 5);
+    assert_eq!(observed.load(Ordering::SeqCst), 2);
+    assert_eq!(calls.load(Ordering::SeqCst), 5);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn request_timeout_cancels_handler() {
+    let echo = DelayedEcho::new();
+    let cancelled = echo.cancelled.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo))
+        .request_timeout(Duration::from_millis(300))
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let start = Instant::now();
+    let result = core.run(stub.echo(delayed(-1)));
+    assert_eq!(result.unwrap_err(), RpcError::Timeout);
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(cancelled.load(Ordering::SeqCst), 1);
+
+    // the connection is still usable
+    let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(resp, delayed(0));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn client_deadline_cancels_handler() {
+    let echo = DelayedEcho::new();
+    let cancelled = echo.cancelled.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo))
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let mut msg = delayed(0);
+    msg.set_str_val("deadline".to_string());
+
+    let opts = CallOptions::new().timeout(Duration::from_secs(1));
+    let (resp, _) = core.run(stub.echo_opts(msg.clone(), opts)).unwrap();
+    assert!(resp.get_int_val() > 0 && resp.get_int_val() <= 1000);
+    let (resp, _) = core.run(stub.echo(msg)).unwrap();
+    assert_eq!(resp.get_int_val(), -1);
+
+    // the server gives up together with the client
+    let opts = CallOptions::new().timeout(Duration::from_millis(300));
+    let result = core.run(stub.echo_opts(delayed(-1), opts));
+    assert_eq!(result.unwrap_err(), RpcError::Timeout);
+    assert!(wait_until(|| cancelled.load(Ordering::SeqCst) == 1));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn call_timeout_overrides_channel_timeout() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(&addr, core.handle())
+        .rpc_timeout(Duration::from_secs(5))
+        .build();
+    let channel = core.run(channel).unwrap();
+    let raw = RpcWrapper::new(RawCodec, &channel);
+    let call = |msg: &Simple| {
+        let body = Bytes::from(msg.write_to_bytes().unwrap());
+        (body, "Echo".to_string(), "echo".to_string())
+    };
+
+    let start = Instant::now();
+    let opts = CallOptions::new().timeout(Duration::from_millis(300));
+    let result = core.run(raw.call_with_options(call(&delayed(600)), opts));
+    assert_eq!(result.unwrap_err(), RpcError::Timeout);
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // the response to the timed out call arrives while this one waits, and
+    // is not taken for its own
+    let slow = delayed(500);
+    let (body, _) = core.run(raw.call(call(&slow))).unwrap();
+    assert_eq!(protobuf::parse_from_bytes::<Simple>(&body).unwrap(), slow);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn stub_future_timeout() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server_spawned(&addr, &core.handle(), |b| b))
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let timer = tokio_timer::wheel()
+        .tick_duration(Duration::from_millis(5))
+        .build();
+
+    // the call is given up when the timeout fires, though its future is kept
+    let start = Instant::now();
+    let mut call = stub.echo(delayed(500)).timeout(Duration::from_millis(200), &timer);
+    let result = core.run(future::poll_fn(|| match call.poll() {
+        Ok(Async::NotReady) => Ok(Async::NotReady),
+        result => Ok::<_, ()>(Async::Ready(result)),
+    })).unwrap();
+    assert_eq!(result.unwrap_err(), RpcError::Timeout);
+    assert!(start.elapsed() < Duration::from_millis(450), "{:?}", start.elapsed());
+    assert_eq!(channel.inflight(), 0);
+    drop(call);
+
+    // the response wins, the timer of the server firing within 200
+    // milliseconds
+    let call = stub.echo(delayed(100)).timeout(Duration::from_millis(230), &timer);
+    assert_eq!(core.run(call).unwrap().0, delayed(100));
+
+    // the timeout of the call is earlier
+    let start = Instant::now();
+    let opts = CallOptions::new().timeout(Duration::from_millis(200));
+    let call = stub.echo_opts(delayed(500), opts).timeout(Duration::from_secs(5), &timer);
+    assert_eq!(core.run(call).unwrap_err(), RpcError::Timeout);
+    assert!(start.elapsed() < Duration::from_millis(450), "{:?}", start.elapsed());
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn blocking_handler_does_not_stall_others() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .threads(1)
+        .blocking_pool(1)
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let mut msg = delayed(500);
+    msg.set_str_val("block".to_string());
+    let start = Instant::now();
+    let timer = Timer::default();
+    let slow = stub.echo(msg.clone()).map(|resp| (resp, start.elapsed()));
+    let fast = timer
+        .sleep(Duration::from_millis(50))
+        .then(|_| stub.echo(delayed(0)))
+        .map(|resp| (resp, start.elapsed()));
+    let ((slow, slow_elapsed), (fast, fast_elapsed)) = core.run(slow.join(fast)).unwrap();
+    assert_eq!(slow.0, msg);
+    assert_eq!(fast.0, delayed(0));
+    assert!(fast_elapsed < slow_elapsed);
+    assert!(slow_elapsed >= Duration::from_millis(500));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn gauges_follow_connections_and_requests() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let stats = server.stats();
+    let addr = server.local_addrs()[0];
+
+    let idle = (0..3)
+        .map(|_| TcpStream::connect(addr).unwrap())
+        .collect::<Vec<_>>();
+    assert!(wait_until(|| stats.connections() == 3));
+    drop(idle);
+    assert!(wait_until(|| stats.connections() == 0));
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(addr, core.handle()).build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let timer = Timer::default();
+    let requests = join_all(vec![stub.echo(delayed(500)), stub.echo(delayed(500))]);
+    let probe = timer
+        .sleep(Duration::from_millis(200))
+        .map(|_| stats.inflight_requests())
+        .map_err(|_| RpcError::Timeout);
+    let (_, inflight) = core.run(requests.join(probe)).unwrap();
+    assert_eq!(inflight, 2);
+    assert_eq!(stats.inflight_requests(), 0);
+    assert_eq!(stats.connections(), 1);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn method_concurrency_rejects_excess() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .method_concurrency("Echo", "echo", 1)
+        .request_timeout(Duration::from_millis(300))
+        .build()
+        .unwrap()
+        .start_background();
+    let stats = server.stats();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let timer = Timer::default();
+    let slow = stub.echo(delayed(200)).then(Ok::<_, ()>);
+    let fast = timer
+        .sleep(Duration::from_millis(50))
+        .then(|_| stub.echo(delayed(0)))
+        .then(Ok);
+    let (slow, fast) = core.run(slow.join(fast)).unwrap();
+    assert_eq!(slow.unwrap().0, delayed(200));
+    assert_eq!(fast.unwrap_err().error_code(), errno::ELIMIT);
+    assert_eq!(stats.concurrency_rejected(), 1);
+
+    // a request cancelled by the timeout gives up its slot
+    let result = core.run(stub.echo(delayed(-1)));
+    assert_eq!(result.unwrap_err(), RpcError::Timeout);
+    let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(resp, delayed(0));
+    assert_eq!(stats.concurrency_rejected(), 1);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn panicking_handler_keeps_connection() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let cases = [
+        ("panic", "asked to panic"),
+        ("panic later", "asked to panic later"),
+    ];
+    for &(text, panic_msg) in &cases {
+        let mut msg = delayed(0);
+        msg.set_str_val(text.to_string());
+        let err = core.run(stub.echo(msg)).unwrap_err();
+        let expected = RpcError::RemoteError {
+            code: errno::EPANIC,
+            message: panic_msg.to_string(),
+        };
+        assert_eq!(err, expected);
+
+        let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+        assert_eq!(resp, delayed(0));
+    }
+
+    server.stop().unwrap();
+}
+
+// answer failed calls to Echo with error code 42, the others as usual
+fn hide_echo_errors(err: &MethodError, service: &str, method: &str) -> RpcResponseMeta {
+    if service != "Echo" {
+        return default_error_mapper(err, service, method);
+    }
+    let mut meta = RpcResponseMeta::new();
+    meta.set_error_code(42);
+    meta.set_error_text(format!("{} failed", method));
+    meta
+}
+
+#[test]
+fn error_mapper_builds_error_meta() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .error_mapper(hide_echo_errors)
+        .build()
+        .unwrap()
+        .start_background();
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+    for text in &["fail", "panic", "panic later"] {
+        let mut msg = delayed(0);
+        msg.set_str_val(text.to_string());
+        let (meta, body) = raw_call(&mut conn, &msg, b"");
+        assert_eq!(meta.get_response().get_error_code(), 42, "{}", text);
+        assert_eq!(meta.get_response().get_error_text(), "echo failed");
+        assert!(body.is_empty());
+    }
+
+    let mut meta = echo_meta();
+    meta.mut_request().set_method_name("nope".to_string());
+    let (meta, _) = raw_call_with(&mut conn, &meta, b"");
+    assert_eq!(meta.get_response().get_error_code(), 42);
+    assert_eq!(meta.get_response().get_error_text(), "nope failed");
+
+    let mut meta = echo_meta();
+    meta.mut_request().set_service_name("Nope".to_string());
+    let (meta, _) = raw_call_with(&mut conn, &meta, b"");
+    assert_eq!(meta.get_response().get_error_code(), errno::ENOSERVICE);
+
+    // successful calls are not affected
+    assert_eq!(raw_echo(&mut conn, &delayed(0)), delayed(0));
+
+    let addr = server.local_addrs()[0].to_string();
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let mut msg = delayed(0);
+    msg.set_str_val("fail".to_string());
+    // the code and text set by the mapper reach the client
+    let err = core.run(stub.echo(msg)).unwrap_err();
+    let expected = RpcError::RemoteError {
+        code: 42,
+        message: "echo failed".to_string(),
+    };
+    assert_eq!(err, expected);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn error_codes_reach_client() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let raw = RpcWrapper::new(RawCodec, &channel);
+    let call = |service: &str, method: &str, body: &'static [u8]| {
+        (Bytes::from_static(body), service.to_string(), method.to_string())
+    };
+
+    let err = core.run(raw.call(call("Nope", "echo", b""))).unwrap_err();
+    assert_eq!(err.error_code(), errno::ENOSERVICE);
+
+    let err = core.run(raw.call(call("Echo", "nope", b""))).unwrap_err();
+    assert_eq!(err.error_code(), errno::ENOMETHOD);
+
+    // truncated length-delimited field
+    let err = core.run(raw.call(call("Echo", "echo", b"\x1a\x05ab"))).unwrap_err();
+    assert_eq!(err.error_code(), errno::EREQUEST);
+
+    let stub = EchoStub::new(&channel);
+    let mut msg = delayed(0);
+    msg.set_str_val("fail".to_string());
+    let err = core.run(stub.echo(msg)).unwrap_err();
+    let expected = RpcError::RemoteError {
+        code: errno::EINTERNAL,
+        message: "asked to fail".to_string(),
+    };
+    assert_eq!(err, expected);
+    // handlers calling on pass the error of the server along
+    assert_eq!(MethodError::from(err), MethodError::Failed("asked to fail".to_string()));
+
+    server.stop().unwrap();
+}
+
+struct Forward;
+
+impl DefaultHandler for Forward {
+    fn call(&self, service: &str, method: &str, body: Bytes, ctrl: Controller) -> MethodFuture {
+        if method == "fail" {
+            return Box::new(future::err(MethodError::Failed("forward failed".to_string())));
+        }
+        let mut resp = format!("{}::{} ", service, method).into_bytes();
+        resp.extend_from_slice(&body);
+        Box::new(future::ok((Bytes::from(resp), ctrl)))
+    }
+}
+
+#[test]
+fn default_handler_serves_unknown_methods() {
+    let mut registry = registry();
+    registry.set_default_handler(Forward);
+    let server = ServerBuilder::new("127.0.0.1:0", registry)
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let raw = RpcWrapper::new(RawCodec, &channel);
+    let call = |service: &str, method: &str, body: &'static [u8]| {
+        (Bytes::from_static(body), service.to_string(), method.to_string())
+    };
+
+    let (resp, _) = core.run(raw.call(call("Nope", "echo", b"body"))).unwrap();
+    assert_eq!(&resp[..], b"Nope::echo body");
+    let (resp, _) = core.run(raw.call(call("Echo", "nope", b""))).unwrap();
+    assert_eq!(&resp[..], b"Echo::nope ");
+    let err = core.run(raw.call(call("Nope", "fail", b""))).unwrap_err();
+    let expected = RpcError::RemoteError {
+        code: errno::EINTERNAL,
+        message: "forward failed".to_string(),
+    };
+    assert_eq!(err, expected);
+
+    // registered methods are not affected
+    let stub = EchoStub::new(&channel);
+    let msg = delayed(0);
+    assert_eq!(core.run(stub.echo(msg.clone())).unwrap().0, msg);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn access_log_records_requests() {
+    let entries = Arc::new(Mutex::new(Vec::new()));
+    let log = entries.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .access_log(move |entry: &AccessLogEntry| log.lock().unwrap().push(entry.clone()))
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let msg = delayed(0);
+    core.run(stub.echo(msg.clone())).unwrap();
+    let raw = RpcWrapper::new(RawCodec, &channel);
+    let call = (Bytes::from_static(b"abc"), "Nope".to_string(), "echo".to_string());
+    core.run(raw.call(call)).unwrap_err();
+
+    let entries = entries.lock().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].service, "Echo");
+    assert_eq!(entries[0].method, "echo");
+    assert_eq!(entries[0].error_code, errno::SUCCESS);
+    assert_eq!(entries[0].request_size, msg.compute_size() as usize);
+    assert_eq!(entries[0].response_size, msg.compute_size() as usize);
+    assert!(entries[0].peer.unwrap().ip().is_loopback());
+    assert_eq!(entries[1].service, "Nope");
+    assert_eq!(entries[1].error_code, errno::ENOSERVICE);
+    assert_eq!(entries[1].request_size, 3);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn request_ids_reach_handler_and_log() {
+    let entries = Arc::new(Mutex::new(Vec::new()));
+    let log = entries.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .access_log(move |entry: &AccessLogEntry| log.lock().unwrap().push(entry.clone()))
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let mut msg = delayed(0);
+    msg.set_str_val("request id".to_string());
+    let opts = CallOptions::new().request_id(42);
+    let (reply, _) = core.run(stub.echo_opts(msg.clone(), opts)).unwrap();
+    assert_eq!(reply.get_str_val(), "42");
+
+    // the server generates distinct ids for requests without one
+    let (first, _) = core.run(stub.echo(msg.clone())).unwrap();
+    let (second, _) = core.run(stub.echo(msg)).unwrap();
+    assert_ne!(first.get_str_val(), "0");
+    assert_ne!(first.get_str_val(), second.get_str_val());
+
+    let entries = entries.lock().unwrap();
+    let ids: Vec<_> = entries.iter().map(|e| e.request_id.to_string()).collect();
+    assert_eq!(ids, vec!["42", first.get_str_val(), second.get_str_val()]);
+    assert!(entries[0].to_string().contains(" 42 Echo::echo 0 "));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn controller_fields_reach_handler() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server_spawned(&addr, &core.handle(), |b| b))
+        .unwrap();
+    let wrapper = RpcWrapper::new(ProtobufCodec::<Simple, Simple>::new(), &channel);
+    let call = |str_val: &str, controller: Controller| {
+        let mut msg = delayed(0);
+        msg.set_str_val(str_val.to_string());
+        ((msg, controller), "Echo".to_string(), "echo".to_string())
+    };
+
+    let controller = Controller {
+        request_id: 42,
+        ..Controller::default()
+    };
+    let (reply, _) = core.run(wrapper.call_with_controller(call("request id", controller)))
+        .unwrap();
+    assert_eq!(reply.get_str_val(), "42");
+
+    let controller = Controller {
+        deadline: Some(Instant::now() + Duration::from_secs(2)),
+        ..Controller::default()
+    };
+    let (reply, _) = core.run(wrapper.call_with_controller(call("deadline", controller)))
+        .unwrap();
+    let left = reply.get_int_val();
+    assert!(left > 1500 && left <= 2000, "left {}", left);
+
+    // the id set in the options wins
+    let controller = Controller {
+        request_id: 42,
+        ..Controller::default()
+    };
+    let opts = CallOptions::new().controller(controller).request_id(7);
+    let stub = EchoStub::new(&channel);
+    let mut msg = delayed(0);
+    msg.set_str_val("request id".to_string());
+    let (reply, _) = core.run(stub.echo_opts(msg, opts)).unwrap();
+    assert_eq!(reply.get_str_val(), "7");
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn throughput_counts_failures() {
+    let (remote_tx, remote_rx) = oneshot::channel();
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let monitor = spawn(move || {
+        let mut core = Core::new().unwrap();
+        remote_tx.send(core.remote()).unwrap();
+        let _ = core.run(stop_rx);
+    });
+    let throughput = Throughput::new();
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .throughput_monitor(throughput.clone(), remote_rx.wait().unwrap())
+        .build()
+        .unwrap()
+        .start_background();
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+    // keep sending until a whole interval is counted
+    let mut fail = delayed(0);
+    fail.set_str_val("fail".to_string());
+    let start = Instant::now();
+    let by_code = loop {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        raw_echo(&mut conn, &delayed(0));
+        raw_call(&mut conn, &fail, b"");
+        sleep(Duration::from_millis(10));
+        let by_code = throughput.failed_by_error_code();
+        if throughput.succeeded() > 0 && !by_code.is_empty() {
+            break by_code;
+        }
+    };
+    assert_eq!(by_code.keys().collect::<Vec<_>>(), vec![&errno::EINTERNAL]);
+
+    server.stop().unwrap();
+    stop_tx.send(()).unwrap();
+    monitor.join().unwrap();
+}
+
+#[test]
+fn slow_requests_are_counted() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .slow_request_threshold(Duration::from_millis(200))
+        .build()
+        .unwrap()
+        .start_background();
+    let stats = server.stats();
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+    assert_eq!(raw_echo(&mut conn, &delayed(0)), delayed(0));
+    assert_eq!(stats.slow_requests(), 0);
+    assert_eq!(raw_echo(&mut conn, &delayed(400)), delayed(400));
+    assert_eq!(stats.slow_requests(), 1);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn channel_metrics_count_outcomes() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let metrics = ChannelMetrics::new();
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(server.local_addrs()[0], core.handle())
+        .metrics(metrics.clone())
+        .build();
+    let channel = core.run(channel).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    for _ in 0..3 {
+        assert_eq!(core.run(stub.echo(delayed(0))).unwrap().0, delayed(0));
+    }
+    let mut fail = delayed(0);
+    fail.set_str_val("fail".to_string());
+    assert!(core.run(stub.echo(fail)).is_err());
+    let opts = CallOptions::new().timeout(Duration::from_millis(300));
+    let result = core.run(stub.echo_opts(delayed(600), opts));
+    assert_eq!(result.unwrap_err(), RpcError::Timeout);
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.calls(), 5);
+    assert_eq!(snapshot.succeeded, 3);
+    assert_eq!(snapshot.server_errors, 1);
+    assert_eq!(snapshot.timed_out, 1);
+    let sent = delayed(0).compute_size() as usize;
+    assert_eq!(snapshot.bytes_received, 3 * sent);
+    assert!(snapshot.bytes_sent > 3 * sent);
+    assert!(snapshot.latency_quantile(0.5).unwrap() < Duration::from_millis(100));
+    assert!(snapshot.mean_latency().unwrap() >= Duration::from_millis(40));
+
+    // the connection goes down with the server
+    server.stop().unwrap();
+    match core.run(stub.echo(delayed(0))) {
+        Err(RpcError::TransportError(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.transport_errors, 1);
+    assert_eq!(snapshot.failed(), 3);
+    assert_eq!(snapshot.other_errors, 0);
+}
+
+#[test]
+fn responses_compressed_like_requests() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .compress_responses_over(64)
+        .build()
+        .unwrap()
+        .start_background();
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    let mut large = delayed(0);
+    large.set_str_val("large".repeat(20));
+    let gzip = CompressType::Gzip;
+    let mut gzip_meta = echo_meta();
+    gzip_meta.set_compress_type(gzip.to_meta());
+
+    // large responses are compressed the way the client compresses
+    let body = gzip.compress(&large.write_to_bytes().unwrap()).unwrap();
+    let (meta, body) = raw_call_with(&mut conn, &gzip_meta, &body);
+    assert_eq!(meta.get_response().get_error_code(), 0);
+    assert_eq!(meta.get_compress_type(), gzip.to_meta());
+    let resp: Simple = protobuf::parse_from_bytes(&gzip.decompress(&body).unwrap()).unwrap();
+    assert_eq!(resp, large);
+
+    // but not small ones
+    let body = gzip.compress(&delayed(0).write_to_bytes().unwrap()).unwrap();
+    let (meta, body) = raw_call_with(&mut conn, &gzip_meta, &body);
+    assert_eq!(meta.get_compress_type(), 0);
+    assert_eq!(protobuf::parse_from_bytes::<Simple>(&body).unwrap(), delayed(0));
+
+    // nor responses to clients not compressing
+    let (meta, body) = raw_call(&mut conn, &large, b"");
+    assert_eq!(meta.get_compress_type(), 0);
+    assert_eq!(protobuf::parse_from_bytes::<Simple>(&body).unwrap(), large);
+
+    // undecodable bodies are rejected
+    let (meta, _) = raw_call_with(&mut conn, &gzip_meta, b"not gzip");
+    assert_eq!(meta.get_response().get_error_code(), errno::EREQUEST);
+
+    server.stop().unwrap();
+}
+
+// keep the compression of the requests
+struct SeenCompress(Arc<Mutex<Vec<Option<CompressType>>>>);
+
+impl Interceptor for SeenCompress {
+    fn call(&self, req: RequestPackage, next: &Next) -> MethodFuture {
+        self.0.lock().unwrap().push(req.1.request_compress_type);
+        next.call(req)
+    }
+}
+
+#[test]
+fn client_compresses_requests() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .add_interceptor(Box::new(SeenCompress(seen.clone())))
+        .compress_responses_over(64)
+        .build()
+        .unwrap()
+        .start_background();
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(server.local_addrs()[0], core.handle())
+        .compress_requests_over(64, CompressType::Gzip)
+        .build();
+    let channel = core.run(channel).unwrap();
+    let stub = EchoStub::new(&channel);
+    let mut large = delayed(0);
+    large.set_str_val("large".repeat(20));
+
+    // large requests are compressed, and the responses come back the same
+    // way
+    assert_eq!(core.run(stub.echo(large.clone())).unwrap().0, large);
+    assert_eq!(core.run(stub.echo(delayed(0))).unwrap().0, delayed(0));
+    // the compression of the call wins over the channel
+    for &compress in &[CompressType::Snappy, CompressType::Zlib] {
+        let opts = CallOptions::new().compress(compress);
+        assert_eq!(core.run(stub.echo_opts(delayed(0), opts)).unwrap().0, delayed(0));
+    }
+    let opts = CallOptions::new().compress(CompressType::Snappy);
+    assert_eq!(core.run(stub.echo_opts(large.clone(), opts)).unwrap().0, large);
+
+    let seen = seen.lock().unwrap().clone();
+    assert_eq!(
+        seen,
+        vec![
+            Some(CompressType::Gzip),
+            None,
+            Some(CompressType::Snappy),
+            Some(CompressType::Zlib),
+            Some(CompressType::Snappy),
+        ]
+    );
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn handler_compresses_response() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+    let mut msg = delayed(0);
+    msg.set_str_val("compress".to_string());
+
+    let mut conn = TcpStream::connect(addr).unwrap();
+    let (meta, body) = raw_call(&mut conn, &msg, b"");
+    assert_eq!(meta.get_compress_type(), CompressType::Snappy.to_meta());
+    let body = CompressType::Snappy.decompress(&body).unwrap();
+    assert_eq!(protobuf::parse_from_bytes::<Simple>(&body).unwrap(), msg);
+
+    // the client decompresses
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(addr, core.handle()).build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let (resp, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(resp, msg);
+
+    server.stop().unwrap();
+}
+
+// send an http request that the handler rejects, return the response
+fn bad_http_request(addr: SocketAddr) -> String {
+    let mut conn = TcpStream::connect(addr).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    conn.write_all(b"POST /Echo/echo HTTP/1.1\r\nContent-Length: 11\r\n\r\nbad request")
+        .unwrap();
+
+    let mut resp = Vec::new();
+    let mut buf = [0; 256];
+    while !resp.ends_with(BAD_REQUEST_BODY) {
+        let n = conn.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed early");
+        resp.extend_from_slice(&buf[..n]);
+    }
+    String::from_utf8(resp).unwrap()
+}
+
+const BAD_REQUEST_BODY: &[u8] = br#"{"error":"bad request"}"#;
+
+#[test]
+fn failed_http_request_keeps_controller() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .protocols(vec![Protocol::Http])
+        .build()
+        .unwrap()
+        .start_background();
+
+    let resp = bad_http_request(server.local_addrs()[0]);
+    assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(resp.contains("Content-Type: application/json\r\n"));
+    assert!(resp.contains(&format!("Content-Length: {}\r\n", BAD_REQUEST_BODY.len())));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn oversized_request_rejected() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .max_request_size(64 * 1024)
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let raw = RpcWrapper::new(RawCodec, &channel);
+    let body = Bytes::from(vec![0; 128 * 1024]);
+    let err = core.run(raw.call((body, "Echo".to_string(), "echo".to_string())))
+        .unwrap_err();
+    assert_eq!(err.error_code(), errno::ETOOLARGE);
+
+    // the oversized payload is skipped, the connection is still usable
+    let stub = EchoStub::new(&channel);
+    let (resp, _) = core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(resp, delayed(0));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn oversized_http_request_rejected() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .protocols(vec![Protocol::Http])
+        .max_request_size(1024)
+        .build()
+        .unwrap()
+        .start_background();
+
+    // only the header is sent, the server must answer without the body
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    conn.write_all(b"POST /Echo/echo HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n")
+        .unwrap();
+    let mut buf = [0; 256];
+    let n = conn.read(&mut buf).unwrap();
+    let resp = String::from_utf8_lossy(&buf[..n]);
+    assert!(resp.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+    server.stop().unwrap();
+}
+
+// let http requests carrying the token through, and tag them with a
+// request id
+struct TokenAuth {
+    token: String,
+    next_id: AtomicUsize,
+}
+
+impl Interceptor for TokenAuth {
+    fn call(&self, (meta, mut ctrl, body): RequestPackage, next: &Next) -> MethodFuture {
+        if ctrl.headers.get("Authorization") != Some(&self.token) {
+            let reply = Controller {
+                status: Some(HttpStatus::Forbidden),
+                ..Default::default()
+            };
+            let err = MethodError::Failed("unauthorized".to_string());
+            return Box::new(future::err(err.with_controller(reply)));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        ctrl.headers.clear();
+        ctrl.headers.insert("X-Request-Id".to_string(), id.to_string());
+        next.call((meta, ctrl, body))
+    }
+}
+
+// send an http request with extra header lines, return the response header
+fn http_request(addr: SocketAddr, headers: &str) -> String {
+    let mut conn = TcpStream::connect(addr).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    write!(conn, "POST /Echo/echo HTTP/1.1\r\n{}Content-Length: 0\r\n\r\n", headers).unwrap();
+
+    let mut resp = Vec::new();
+    let mut buf = [0; 256];
+    // error responses come with a body, which is not returned
+    while !resp.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = conn.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed early");
+        resp.extend_from_slice(&buf[..n]);
+    }
+    let resp = String::from_utf8(resp).unwrap();
+    resp.split("\r\n\r\n").next().unwrap().to_string() + "\r\n\r\n"
+}
+
+#[test]
+fn interceptor_checks_token() {
+    let auth = TokenAuth {
+        token: "Bearer secret".to_string(),
+        next_id: AtomicUsize::new(7),
+    };
+    let echo = DelayedEcho::new();
+    let calls = echo.calls.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo))
+        .protocols(vec![Protocol::Http])
+        .add_interceptor(Box::new(auth))
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+
+    let resp = http_request(addr, "");
+    assert!(resp.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    let resp = http_request(addr, "Authorization: Bearer wrong\r\n");
+    assert!(resp.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    let resp = http_request(addr, "Authorization: Bearer secret\r\n");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(resp.contains("X-Request-Id: 7\r\n"));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    server.stop().unwrap();
+}
+
+// let brpc requests carrying the credential through
+struct CredentialCheck(&'static [u8]);
+
+impl Interceptor for CredentialCheck {
+    fn call(&self, req: RequestPackage, next: &Next) -> MethodFuture {
+        if req.1.authentication_data != self.0 {
+            let err = MethodError::Failed("unauthorized".to_string());
+            return Box::new(future::err(err));
+        }
+        next.call(req)
+    }
+}
+
+// attach a token, or the tenant to the token attached before, and keep the
+// error codes of the responses
+struct Credential {
+    part: &'static [u8],
+    reject: Arc<AtomicBool>,
+    codes: Arc<Mutex<Vec<i32>>>,
+}
+
+impl ClientInterceptor for Credential {
+    fn before_call(
+        &self,
+        meta: &mut RpcRequestMeta,
+        ctrl: &mut Controller,
+    ) -> Result<(), MethodError> {
+        assert_eq!(meta.get_service_name(), "Echo");
+        if self.reject.load(Ordering::SeqCst) {
+            return Err(MethodError::Failed("no token".to_string()));
+        }
+        ctrl.authentication_data.extend_from_slice(self.part);
+        Ok(())
+    }
+
+    fn after_call(&self, _: &RpcRequestMeta, response: &RpcResponseMeta) {
+        self.codes.lock().unwrap().push(response.get_error_code());
+    }
+}
+
+#[test]
+fn client_interceptors_attach_credential() {
+    let echo = DelayedEcho::new();
+    let calls = echo.calls.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo))
+        .add_interceptor(Box::new(CredentialCheck(b"secret;tenant=7")))
+        .build()
+        .unwrap()
+        .start_background();
+
+    let reject = Arc::new(AtomicBool::new(false));
+    let codes = Arc::new(Mutex::new(Vec::new()));
+    let token = Credential {
+        part: b"secret",
+        reject: reject.clone(),
+        codes: codes.clone(),
+    };
+    let tenant = Credential {
+        part: b";tenant=7",
+        reject: Arc::new(AtomicBool::new(false)),
+        codes: Arc::new(Mutex::new(Vec::new())),
+    };
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(server.local_addrs()[0], core.handle())
+        .add_interceptor(Box::new(token))
+        .add_interceptor(Box::new(tenant))
+        .build();
+    let channel = core.run(channel).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    assert_eq!(core.run(stub.echo(delayed(0))).unwrap().0, delayed(0));
+    core.run(stub.notify_oneway(delayed(0))).unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    // the credential set in the call options comes first
+    let ctrl = Controller {
+        authentication_data: b"stale;".to_vec(),
+        ..Default::default()
+    };
+    let opts = CallOptions::new().controller(ctrl);
+    let err = core.run(stub.echo_opts(delayed(0), opts)).unwrap_err();
+    let expected = RpcError::RemoteError {
+        code: errno::EINTERNAL,
+        message: "unauthorized".to_string(),
+    };
+    assert_eq!(err, expected);
+    assert_eq!(*codes.lock().unwrap(), vec![errno::SUCCESS, errno::EINTERNAL]);
+
+    // a failed interceptor fails the call before it is sent
+    reject.store(true, Ordering::SeqCst);
+    let err = core.run(stub.echo(delayed(0))).unwrap_err();
+    assert_eq!(err, RpcError::Rejected(MethodError::Failed("no token".to_string())));
+    assert_eq!(codes.lock().unwrap().len(), 2);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    server.stop().unwrap();
+}
+
+fn check_token(
+    _: &RpcRequestMeta,
+    credential: &[u8],
+    _: Option<SocketAddr>,
+) -> Result<AuthContext, AuthError> {
+    match credential {
+        b"alice-token" => Ok(AuthContext::new("alice")),
+        b"bob-token" => Ok(AuthContext::new("bob")),
+        _ => Err(AuthError::new("unknown token")),
+    }
+}
+
+#[test]
+fn authenticator_checks_credential() {
+    let echo = DelayedEcho::new();
+    let calls = echo.calls.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo))
+        .authenticator(check_token)
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+    let mut conn = TcpStream::connect(addr).unwrap();
+    let mut msg = delayed(0);
+    msg.set_str_val("whoami".to_string());
+
+    let (meta, _) = raw_call(&mut conn, &msg, b"mallory-token");
+    assert_eq!(meta.get_response().get_error_code(), errno::ERPCAUTH);
+    assert_eq!(meta.get_response().get_error_text(), "unknown token");
+    let (meta, _) = raw_call(&mut conn, &msg, b"");
+    assert_eq!(meta.get_response().get_error_code(), errno::ERPCAUTH);
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    let (meta, body) = raw_call(&mut conn, &msg, b"alice-token");
+    assert_eq!(meta.get_response().get_error_code(), errno::SUCCESS);
+    let resp: Simple = protobuf::parse_from_bytes(&body).unwrap();
+    assert_eq!(resp.get_str_val(), "alice");
+
+    let resp = http_request(addr, "");
+    assert!(resp.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    let resp = http_request(addr, "Authorization: alice-token\r\n");
+    assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn rate_limit_rejects_excess() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .rate_limit_per_peer(1, 3)
+        .build()
+        .unwrap()
+        .start_background();
+    let stats = server.stats();
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+    let codes: Vec<_> = (0..6)
+        .map(|_| {
+            let (meta, _) = raw_call(&mut conn, &delayed(0), b"");
+            meta.get_response().get_error_code()
+        })
+        .collect();
+    let ok = codes.iter().filter(|&&code| code == errno::SUCCESS).count();
+    let limited = codes.iter().filter(|&&code| code == errno::ERATELIMIT).count();
+    assert_eq!((ok, limited), (3, 3));
+    assert_eq!(stats.rate_limited(), 3);
+
+    // the bucket refills over time
+    sleep(Duration::from_millis(1100));
+    assert_eq!(raw_echo(&mut conn, &delayed(0)), delayed(0));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn rate_limit_keyed_by_user() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .authenticator(check_token)
+        .rate_limit_per_peer(1, 2)
+        .rate_limit_key(|ctrl, _| ctrl.auth_context.as_ref().map(|ctx| ctx.user.clone()))
+        .build()
+        .unwrap()
+        .start_background();
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let mut call = |token: &[u8]| {
+        let (meta, _) = raw_call(&mut conn, &delayed(0), token);
+        meta.get_response().get_error_code()
+    };
+
+    assert_eq!(call(b"alice-token"), errno::SUCCESS);
+    assert_eq!(call(b"alice-token"), errno::SUCCESS);
+    assert_eq!(call(b"alice-token"), errno::ERATELIMIT);
+    // bob comes from the same address, but has a bucket of its own
+    assert_eq!(call(b"bob-token"), errno::SUCCESS);
+
+    server.stop().unwrap();
+}
+
+// connect and stay idle for a while, return whether the server still reads
+// from the connection
+fn alive_after_idle(server: Server) -> bool {
+    let server = server.start_background();
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    assert_eq!(raw_echo(&mut conn, &delayed(0)), delayed(0));
+    sleep(Duration::from_millis(1500));
+
+    // the server closes an expired connection on the next read, and waits
+    // for the rest of the frame otherwise
+    conn.write_all(b"PRPC").unwrap();
+    conn.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    let alive = match conn.read(&mut [0; 1]) {
+        Ok(n) => n > 0,
+        Err(e) => e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut,
+    };
+    server.stop().unwrap();
+    alive
+}
+
+#[test]
+fn long_request_keeps_idle_connection() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .idle_secs(Some(1))
+        .build()
+        .unwrap()
+        .start_background();
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(4))).unwrap();
+
+    // nothing is read or written while the request runs
+    assert_eq!(raw_echo(&mut conn, &delayed(2000)), delayed(2000));
+    assert_eq!(raw_echo(&mut conn, &delayed(0)), delayed(0));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn idle_timeout_can_be_disabled() {
+    let builder = || ServerBuilder::new("127.0.0.1:0", registry());
+    assert!(!alive_after_idle(builder().idle_secs(Some(1)).build().unwrap()));
+    assert!(alive_after_idle(builder().disable_idle_timeout().build().unwrap()));
+    assert!(alive_after_idle(builder().idle_secs(Some(0)).build().unwrap()));
+}
+
+#[test]
+fn listeners_with_own_protocols() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .add_listener("127.0.0.1:0", vec![Protocol::Http])
+        .build()
+        .unwrap()
+        .start_background();
+    let addrs = server.local_addrs();
+    assert_eq!(addrs.len(), 2);
+    assert_ne!(addrs[0], addrs[1]);
+
+    // the first listener serves both protocols
+    let mut conn = TcpStream::connect(addrs[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    assert_eq!(raw_echo(&mut conn, &delayed(0)), delayed(0));
+    assert!(bad_http_request(addrs[0]).starts_with("HTTP/1.1 400"));
+
+    // the second one only speaks http
+    assert!(bad_http_request(addrs[1]).starts_with("HTTP/1.1 400"));
+    let mut conn = TcpStream::connect(addrs[1]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    conn.write_all(b"PRPC\0\0\0\0\0\0\0\0").unwrap();
+    let mut buf = Vec::new();
+    conn.read_to_end(&mut buf).unwrap();
+    assert!(buf.is_empty());
+
+    server.stop().unwrap();
+    for addr in &addrs {
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn servers_share_port_with_reuse_port() {
+    let first = ServerBuilder::new("127.0.0.1:0", registry())
+        .reuse_port(true)
+        .build()
+        .unwrap();
+    let addr = first.local_addrs()[0];
+    let second = ServerBuilder::new(&addr.to_string(), registry())
+        .reuse_port(true)
+        .build()
+        .unwrap();
+    assert_eq!(second.local_addrs()[0], addr);
+
+    let stats = [first.stats(), second.stats()];
+    let first = first.start_background();
+    let second = second.start_background();
+
+    // the kernel spreads connections among the servers by their source port
+    let conns: Vec<_> = (0..20)
+        .map(|_| TcpStream::connect(addr).unwrap())
+        .collect();
+    assert!(wait_until(|| {
+        stats.iter().map(|s| s.connections()).sum::<usize>() == conns.len()
+    }));
+    assert!(stats.iter().all(|s| s.connections() > 0));
+
+    first.stop().unwrap();
+    second.stop().unwrap();
+}
+
+#[test]
+fn channel_is_shared_between_threads() {
+    let echo = DelayedEcho::new();
+    let calls = echo.calls.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo))
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    // the backend runs on an event loop of its own thread, which ends once
+    // the channel is shut down
+    let (tx, rx) = oneshot::channel();
+    let io = spawn(move || {
+        let mut core = Core::new().unwrap();
+        let split = ChannelBuilder::single_server(&addr, core.handle()).build_split();
+        let (channel, backend) = core.run(split).unwrap();
+        tx.send(channel).unwrap();
+        core.run(backend).unwrap();
+    });
+    let channel = rx.wait().unwrap();
+
+    let workers: Vec<_> = (0..2)
+        .map(|i| {
+            let channel = channel.clone();
+            spawn(move || {
+                let stub = EchoStub::new(&channel);
+                // in flight together with the call of the other thread
+                let (resp, _) = stub.echo(delayed(300 + i)).wait().unwrap();
+                resp.get_int_val()
+            })
+        })
+        .collect();
+    let start = Instant::now();
+    let replies: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+    assert_eq!(replies, vec![300, 301]);
+    assert!(start.elapsed() < Duration::from_millis(550), "{:?}", start.elapsed());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    channel.shutdown().wait().unwrap();
+    io.join().unwrap();
+    server.stop().unwrap();
+}
+
+#[test]
+fn stubs_move_into_spawned_calls() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let channel = core.run(ChannelBuilder::single_server_spawned(&addr, &handle, |b| b))
+        .unwrap();
+    let replies: Vec<_> = (0..10)
+        .map(|i| {
+            // the stub holds a clone of the channel, and goes along the call
+            let stub = EchoStub::new(&channel);
+            let (tx, rx) = oneshot::channel();
+            handle.spawn(future::lazy(move || {
+                stub.echo(delayed(200 + i)).then(move |result| {
+                    let _ = tx.send(result.map(|(resp, _)| resp.get_int_val()));
+                    Ok(())
+                })
+            }));
+            rx
+        })
+        .collect();
+    drop(channel);
+
+    let start = Instant::now();
+    let replies = core.run(join_all(replies)).unwrap();
+    let replies: Vec<_> = replies.into_iter().map(|r| r.unwrap()).collect();
+    assert_eq!(replies, (200..210).collect::<Vec<_>>());
+    // in flight together
+    assert!(start.elapsed() < Duration::from_millis(800), "{:?}", start.elapsed());
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn calls_are_sent_to_other_threads() {
+    fn assert_send<T: Send>() {}
+    assert_send::<Channel>();
+    assert_send::<RpcWrapper<RawCodec>>();
+    assert_send::<EchoStub>();
+    assert_send::<StubFuture<ProtobufCodec<Simple, Simple>>>();
+    assert_send::<OnewayCallFuture>();
+
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server_spawned(&addr, &core.handle(), |b| b))
+        .unwrap();
+    // issued here and driven on another thread, while the backend runs here
+    let call = EchoStub::new(&channel).echo(delayed(300));
+    let (tx, rx) = oneshot::channel();
+    let waiter = spawn(move || {
+        let _ = tx.send(call.wait().map(|(resp, _)| resp));
+    });
+    assert_eq!(core.run(rx).unwrap().unwrap(), delayed(300));
+    waiter.join().unwrap();
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn oneway_calls_do_not_wait_for_handlers() {
+    let echo = DelayedEcho::new();
+    let calls = echo.calls.clone();
+    let server = ServerBuilder::new("127.0.0.1:0", registry_with(echo))
+        .build()
+        .unwrap()
+        .start_background();
+    let metrics = ChannelMetrics::new();
+    let mut core = Core::new().unwrap();
+    let channel = ChannelBuilder::single_server(server.local_addrs()[0], core.handle())
+        .metrics(metrics.clone())
+        .build();
+    let channel = core.run(channel).unwrap();
+    let raw = RpcWrapper::new(RawCodec, &channel);
+
+    // each handler takes 600 milliseconds, the calls are done once written
+    let body = Bytes::from(delayed(600).write_to_bytes().unwrap());
+    let start = Instant::now();
+    for _ in 0..5 {
+        let bundle = (body.clone(), "Echo".to_string(), "echo".to_string());
+        core.run(raw.call_oneway(bundle)).unwrap();
+    }
+    assert!(start.elapsed() < Duration::from_millis(300), "{:?}", start.elapsed());
+    assert!(wait_until(|| calls.load(Ordering::SeqCst) == 5));
+    let snapshot = metrics.snapshot();
+    assert_eq!((snapshot.oneway_sent, snapshot.oneway_failed), (5, 0));
+    assert_eq!(snapshot.calls(), 0);
+
+    // the handlers end while a call is in flight, which still gets its own
+    // response
+    let stub = EchoStub::new(&channel);
+    assert_eq!(core.run(stub.echo(delayed(900))).unwrap().0, delayed(900));
+    assert_eq!(metrics.snapshot().calls(), 1);
+
+    // the server sends no response to a oneway request, nor takes its id
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    let mut oneway = echo_meta();
+    oneway.mut_request().set_oneway(true);
+    let frame = raw_frame(&oneway, &delayed(0).write_to_bytes().unwrap());
+    conn.write_all(&frame).unwrap();
+    assert_eq!(raw_echo(&mut conn, &delayed(300)), delayed(300));
+    assert!(wait_until(|| calls.load(Ordering::SeqCst) == 8));
+    conn.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    let mut buf = [0; 1];
+    assert!(conn.read(&mut buf).is_err());
+
+    drop(stub);
+    drop(raw);
+    drop(channel);
+    server.stop().unwrap();
+}
@@ -0,0 +1,77 @@
+use copra::{errno, ChannelBuilder, ServerBuilder};
+use copra::codec::ProtobufCodec;
+use copra::server::reflection::{FileDescriptorRequest, FileDescriptorResponse,
+                                ListMethodsRequest, ListMethodsResponse, ListServicesRequest,
+                                ListServicesResponse};
+use copra::stub::{RpcError, RpcWrapper};
+use protobuf::{self, Message, MessageStatic};
+use protobuf::descriptor::FileDescriptorProto;
+use tokio_core::reactor::Core;
+
+use super::registry;
+
+// call a reflection method without generated stubs, like a client that
+// knows nothing about the server
+fn reflect<Req, Resp>(
+    core: &mut Core,
+    addr: &str,
+    method: &str,
+    req: Req,
+) -> Result<Resp, RpcError>
+where
+    Req: Message + Clone,
+    Resp: Message + MessageStatic + Clone,
+{
+    let channel = ChannelBuilder::single_server(addr, core.handle()).build();
+    let channel = core.run(channel).unwrap();
+    let codec: ProtobufCodec<Resp, Req> = ProtobufCodec::new();
+    let wrapper = RpcWrapper::new(codec, &channel);
+    let call = wrapper.call((req, "Reflection".to_string(), method.to_string()));
+    core.run(call).map(|(resp, _)| resp)
+}
+
+#[test]
+fn reflection_describes_server() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .enable_health_check()
+        .enable_reflection()
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+    let mut core = Core::new().unwrap();
+
+    let resp: ListServicesResponse =
+        reflect(&mut core, &addr, "list_services", ListServicesRequest::new()).unwrap();
+    assert_eq!(resp.get_services(), ["Echo", "Health", "Reflection"]);
+
+    let mut surface = Vec::new();
+    for service in resp.get_services() {
+        let mut req = ListMethodsRequest::new();
+        req.set_service(service.clone());
+        let resp: ListMethodsResponse = reflect(&mut core, &addr, "list_methods", req).unwrap();
+        surface.push(format!("{}: {}", service, resp.get_methods().join(", ")));
+    }
+    assert_eq!(
+        surface,
+        [
+            "Echo: echo, notify",
+            "Health: check",
+            "Reflection: file_descriptor, list_methods, list_services",
+        ]
+    );
+
+    let mut req = FileDescriptorRequest::new();
+    req.set_service("Echo".to_string());
+    let resp: FileDescriptorResponse = reflect(&mut core, &addr, "file_descriptor", req).unwrap();
+    let file: FileDescriptorProto = protobuf::parse_from_bytes(resp.get_file_descriptor()).unwrap();
+    assert_eq!(file.get_name(), "copra/tests/protos/simple.proto");
+    assert_eq!(file.get_service()[0].get_name(), "Echo");
+
+    let mut req = ListMethodsRequest::new();
+    req.set_service("Nope".to_string());
+    let err = reflect::<_, ListMethodsResponse>(&mut core, &addr, "list_methods", req).unwrap_err();
+    assert_eq!(err.error_code(), errno::ENOSERVICE);
+
+    server.stop().unwrap();
+}
@@ -0,0 +1,41 @@
+use copra::{errno, ServerBuilder, ServiceRegistry};
+use std::net::TcpStream;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use generated::simple_copra::EchoRegistrant;
+
+use super::{delayed, raw_call, DelayedEcho};
+
+#[test]
+fn services_change_while_serving() {
+    let server = ServerBuilder::new("127.0.0.1:0", ServiceRegistry::new())
+        .build()
+        .unwrap()
+        .start_background();
+    let registry = server.registry_handle();
+    let mut conn = TcpStream::connect(server.local_addrs()[0]).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let mut echo = || {
+        let (meta, _) = raw_call(&mut conn, &delayed(0), b"");
+        meta.get_response().get_error_code()
+    };
+
+    assert_eq!(echo(), errno::ENOSERVICE);
+
+    assert!(registry.register_service(EchoRegistrant::new(DelayedEcho::new())));
+    assert!(!registry.register_service(EchoRegistrant::new(DelayedEcho::new())));
+    assert_eq!(echo(), errno::SUCCESS);
+
+    let replacement = DelayedEcho::new();
+    let calls = replacement.calls.clone();
+    assert!(registry.replace_service(EchoRegistrant::new(replacement)));
+    assert_eq!(echo(), errno::SUCCESS);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    assert!(registry.unregister_service("Echo"));
+    assert!(!registry.unregister_service("Echo"));
+    assert_eq!(echo(), errno::ENOSERVICE);
+
+    server.stop().unwrap();
+}
@@ -0,0 +1,92 @@
+use copra::{ChannelBuilder, ServerBuilder};
+use copra::codec::ProtobufCodec;
+use copra::server::health::{HealthCheckRequest, HealthCheckResponse, ServingStatus};
+use copra::stub::RpcWrapper;
+use futures::Future;
+use futures::sync::oneshot;
+use std::env;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Core;
+
+use generated::simple_copra::EchoStub;
+use super::{delayed, registry};
+
+const CHILD_ENV: &str = "COPRA_SIGNAL_CHILD";
+
+// the server run by `drain_on_sigterm` in a child process, does nothing in
+// a normal test run
+#[test]
+fn serve_until_signal() {
+    if env::var(CHILD_ENV).is_err() {
+        return;
+    }
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .enable_health_check()
+        .handle_signals(Duration::from_secs(5))
+        .build()
+        .unwrap();
+    println!("listening on {}", server.local_addrs()[0]);
+    server.start();
+}
+
+#[test]
+fn drain_on_sigterm() {
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["server_tests::signal::serve_until_signal", "--exact", "--nocapture"])
+        .env(CHILD_ENV, "1")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut output = BufReader::new(child.stdout.take().unwrap())
+        .lines()
+        .map(|line| line.unwrap());
+    let addr = output
+        .by_ref()
+        .filter_map(|line| line.split("listening on ").nth(1).map(str::to_string))
+        .next()
+        .unwrap();
+
+    let mut core = Core::new().unwrap();
+    let channel = core.run(ChannelBuilder::single_server(&addr, core.handle()).build())
+        .unwrap();
+    let stub = EchoStub::new(&channel);
+    let codec: ProtobufCodec<HealthCheckResponse, HealthCheckRequest> = ProtobufCodec::new();
+    let health = RpcWrapper::new(codec, &channel);
+    let handle = core.handle();
+    let mut check = || {
+        let call = health.call((
+            HealthCheckRequest::new(),
+            "Health".to_string(),
+            "check".to_string(),
+        ));
+        core.run(call).unwrap().0.get_status()
+    };
+    assert_eq!(check(), ServingStatus::SERVING);
+
+    // a request in flight when the signal arrives
+    let (tx, rx) = oneshot::channel();
+    let slow = stub.echo(delayed(1000)).then(|resp| tx.send(resp).map_err(|_| ()));
+    handle.spawn(slow);
+    assert_eq!(check(), ServingStatus::SERVING);
+
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let start = Instant::now();
+    while check() != ServingStatus::NOT_SERVING {
+        assert!(start.elapsed() < Duration::from_secs(2), "still serving");
+        sleep(Duration::from_millis(10));
+    }
+
+    let (resp, _) = core.run(rx).unwrap().unwrap();
+    assert_eq!(resp, delayed(1000));
+    // the child test passes once the server returns
+    assert!(output.any(|line| line.contains("1 passed")));
+    assert!(child.wait().unwrap().success());
+}
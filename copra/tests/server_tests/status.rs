@@ -0,0 +1,102 @@
+use copra::ServerBuilder;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use super::{delayed, raw_echo, registry};
+
+// GET `path`, return the status line and the body
+fn http_get(addr: SocketAddr, path: &str) -> (String, String) {
+    let mut conn = TcpStream::connect(addr).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    write!(conn, "GET {} HTTP/1.1\r\n\r\n", path).unwrap();
+
+    let mut resp = Vec::new();
+    let mut buf = [0; 256];
+    let header_len = loop {
+        if let Some(pos) = resp.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let n = conn.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed early");
+        resp.extend_from_slice(&buf[..n]);
+    };
+    let header = String::from_utf8(resp[..header_len].to_vec()).unwrap();
+    let content_len: usize = header
+        .lines()
+        .find(|line| line.to_lowercase().starts_with("content-length:"))
+        .map(|line| line["content-length:".len()..].trim().parse().unwrap())
+        .unwrap();
+    while resp.len() < header_len + content_len {
+        let n = conn.read(&mut buf).unwrap();
+        assert!(n > 0, "connection closed early");
+        resp.extend_from_slice(&buf[..n]);
+    }
+    let status = header.lines().next().unwrap().to_string();
+    let body = String::from_utf8(resp[header_len..].to_vec()).unwrap();
+    (status, body)
+}
+
+#[test]
+fn status_page_lists_services() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .enable_status_page()
+        .enable_health_check()
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+
+    for path in &["/status", "/"] {
+        let (status, body) = http_get(addr, path);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(body.contains("uptime: "), "{}", body);
+        assert!(body.contains(&format!("listening: {}", addr)), "{}", body);
+        assert!(body.contains("connections: 1"), "{}", body);
+        assert!(body.contains("requests in flight: 0"), "{}", body);
+        assert!(body.contains("  Echo: echo"), "{}", body);
+        assert!(body.contains("  Health: check"), "{}", body);
+    }
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn status_page_lists_connections() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .enable_status_page()
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+    let mut conn = TcpStream::connect(addr).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    raw_echo(&mut conn, &delayed(0));
+    raw_echo(&mut conn, &delayed(0));
+
+    let (_, body) = http_get(addr, "/status");
+    assert!(body.contains("connections: 2"), "{}", body);
+    assert!(body.contains(" brpc: 2 received, 2 sent, "), "{}", body);
+    // the status request is answered after the page is rendered
+    assert!(body.contains(" http: 1 received, 0 sent, "), "{}", body);
+
+    drop(conn);
+    let (_, body) = http_get(addr, "/status");
+    assert!(!body.contains(" brpc: "), "{}", body);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn status_page_is_opt_in() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+
+    let (_, body) = http_get(addr, "/status");
+    assert!(!body.contains("uptime"), "{}", body);
+
+    server.stop().unwrap();
+}
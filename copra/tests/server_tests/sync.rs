@@ -0,0 +1,71 @@
+use copra::{errno, ServerBuilder};
+use copra::channel::ChannelBuildError;
+use copra::stub::RpcError;
+use copra::sync::SyncChannel;
+use futures::Future;
+use std::net::TcpListener;
+use std::time::Duration;
+
+use generated::simple::Simple;
+use generated::simple_copra::EchoStub;
+use super::{delayed, registry};
+
+#[test]
+fn sync_channel_calls_block() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let channel = SyncChannel::connect(&addr, |builder| builder).unwrap();
+    let mut msg = delayed(0);
+    msg.set_str_val("blocking".to_string());
+    let resp: Simple = channel.call("Echo", "echo", msg.clone(), None).unwrap();
+    assert_eq!(resp, msg);
+
+    let late: Result<Simple, _> =
+        channel.call("Echo", "echo", delayed(1000), Some(Duration::from_millis(300)));
+    assert_eq!(late, Err(RpcError::Timeout));
+    let missing: Result<Simple, _> = channel.call("Echo", "nope", msg.clone(), None);
+    assert_eq!(missing.unwrap_err().error_code(), errno::ENOMETHOD);
+
+    // generated stubs wrap it too
+    let stub = EchoStub::new_sync(&channel);
+    let (resp, _) = stub.echo(msg.clone()).wait().unwrap();
+    assert_eq!(resp, msg);
+
+    // dropping joins the thread of the channel
+    drop(stub);
+    drop(channel);
+    server.stop().unwrap();
+}
+
+#[test]
+fn sync_channel_configured_by_builder() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0].to_string();
+
+    let channel = SyncChannel::connect(&addr, |builder| {
+        builder.rpc_timeout(Duration::from_millis(300))
+    }).unwrap();
+    let late: Result<Simple, _> = channel.call("Echo", "echo", delayed(1000), None);
+    assert_eq!(late, Err(RpcError::Timeout));
+    let (resp, _) = EchoStub::new(channel.channel()).echo(delayed(0)).wait().unwrap();
+    assert_eq!(resp, delayed(0));
+
+    drop(channel);
+    server.stop().unwrap();
+}
+
+#[test]
+fn sync_channel_connect_fails() {
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    match SyncChannel::connect(&addr.to_string(), |builder| builder) {
+        Err(ChannelBuildError::ConnectError { .. }) => {}
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,122 @@
+use copra::{ChannelBuilder, ServerBuilder};
+use copra::channel::{ChannelBuildError, ClientTlsConfig};
+use copra::native_tls::{Certificate, Identity, TlsAcceptor, TlsConnector, TlsStream};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use tokio_core::reactor::Core;
+
+use generated::simple_copra::EchoStub;
+
+use super::{delayed, raw_echo, registry};
+
+static CERT: &[u8] = include_bytes!("../certs/localhost.crt");
+static KEY: &[u8] = include_bytes!("../certs/localhost.key");
+
+fn acceptor() -> TlsAcceptor {
+    let identity = Identity::from_pkcs8(CERT, KEY).unwrap();
+    TlsAcceptor::new(identity).unwrap()
+}
+
+fn connect(addr: SocketAddr) -> TlsStream<TcpStream> {
+    let connector = TlsConnector::builder()
+        .add_root_certificate(Certificate::from_pem(CERT).unwrap())
+        .build()
+        .unwrap();
+    let tcp = TcpStream::connect(addr).unwrap();
+    tcp.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    connector.connect("localhost", tcp).unwrap()
+}
+
+#[test]
+fn echo_over_tls_connection() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .tls(acceptor())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+
+    let mut msg = delayed(0);
+    msg.set_str_val("over tls".to_string());
+    assert_eq!(raw_echo(&mut connect(addr), &msg), msg);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn failed_handshake_keeps_accepting() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .tls(acceptor())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+
+    // a plaintext client fails the handshake and is disconnected
+    let mut plain = TcpStream::connect(addr).unwrap();
+    plain.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    plain.write_all(b"PRPC not a client hello").unwrap();
+    let mut buf = Vec::new();
+    let _ = plain.read_to_end(&mut buf);
+
+    assert_eq!(raw_echo(&mut connect(addr), &delayed(0)), delayed(0));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn channel_calls_over_tls() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .tls(acceptor())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+
+    let mut core = Core::new().unwrap();
+    let config =
+        ClientTlsConfig::new("localhost").root_certificate(Certificate::from_pem(CERT).unwrap());
+    let channel = ChannelBuilder::single_server(addr, core.handle()).tls(config);
+    let channel = core.run(channel.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+
+    let mut msg = delayed(0);
+    msg.set_str_val("over tls".to_string());
+    let (reply, _) = core.run(stub.echo(msg.clone())).unwrap();
+    assert_eq!(reply, msg);
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn channel_rejects_untrusted_server() {
+    let server = ServerBuilder::new("127.0.0.1:0", registry())
+        .tls(acceptor())
+        .build()
+        .unwrap()
+        .start_background();
+    let addr = server.local_addrs()[0];
+
+    let mut core = Core::new().unwrap();
+    // the self-signed certificate is not trusted
+    let channel = ChannelBuilder::single_server(addr, core.handle())
+        .tls(ClientTlsConfig::new("localhost"));
+    match core.run(channel.build()) {
+        Err(ChannelBuildError::ConnectError { ref source, .. }) => {
+            assert!(source.to_string().contains("TLS handshake failed"), "{}", source)
+        }
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("connected to an untrusted server"),
+    }
+
+    // unless verification is turned off
+    let config = ClientTlsConfig::new("localhost").accept_invalid_certs(true);
+    let channel = ChannelBuilder::single_server(addr, core.handle()).tls(config);
+    let channel = core.run(channel.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let (reply, _) = core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(reply, delayed(0));
+
+    server.stop().unwrap();
+}
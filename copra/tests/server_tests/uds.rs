@@ -0,0 +1,146 @@
+use copra::{ChannelBuilder, ServerBuilder};
+use copra::channel::ChannelBuildError;
+use copra::server::ServerBuildError;
+use copra::stub::RpcError;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Core, Timeout};
+
+use generated::simple_copra::EchoStub;
+
+use super::{delayed, raw_echo, registry};
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("copra-{}-{}.sock", process::id(), name));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn connect(path: &PathBuf) -> UnixStream {
+    let conn = UnixStream::connect(path).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    conn
+}
+
+#[test]
+fn echo_over_unix_socket() {
+    let path = socket_path("echo");
+    let server = ServerBuilder::new_uds(&path, registry())
+        .socket_mode(0o600)
+        .build()
+        .unwrap()
+        .start_background();
+    assert_eq!(server.local_path(), Some(path.as_path()));
+
+    let meta = fs::metadata(&path).unwrap();
+    assert!(meta.file_type().is_socket());
+    assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+
+    assert_eq!(raw_echo(&mut connect(&path), &delayed(0)), delayed(0));
+
+    server.stop().unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn stale_socket_file_is_replaced() {
+    let path = socket_path("stale");
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let server = ServerBuilder::new_uds(&path, registry())
+        .build()
+        .unwrap()
+        .start_background();
+    assert_eq!(raw_echo(&mut connect(&path), &delayed(0)), delayed(0));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn socket_in_use() {
+    let path = socket_path("in-use");
+    let server = ServerBuilder::new_uds(&path, registry())
+        .build()
+        .unwrap()
+        .start_background();
+
+    match ServerBuilder::new_uds(&path, registry()).build() {
+        Err(ServerBuildError::BindError(e)) => assert_eq!(e.kind(), io::ErrorKind::AddrInUse),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    // the running server is not affected
+    assert_eq!(raw_echo(&mut connect(&path), &delayed(0)), delayed(0));
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn channel_over_unix_socket() {
+    let path = socket_path("channel");
+    let server = ServerBuilder::new_uds(&path, registry())
+        .build()
+        .unwrap()
+        .start_background();
+
+    let mut core = Core::new().unwrap();
+    let builder = ChannelBuilder::unix_socket(&path, core.handle())
+        .reconnect_backoff(Duration::from_millis(50), Duration::from_millis(50));
+    let channel = core.run(builder.build()).unwrap();
+    let stub = EchoStub::new(&channel);
+    let (reply, _) = core.run(stub.echo(delayed(0))).unwrap();
+    assert_eq!(reply, delayed(0));
+
+    // a restarted server is connected to again
+    server.stop().unwrap();
+    let server = ServerBuilder::new_uds(&path, registry())
+        .build()
+        .unwrap()
+        .start_background();
+    let start = Instant::now();
+    loop {
+        match core.run(stub.echo(delayed(1))) {
+            Ok((reply, _)) => {
+                assert_eq!(reply, delayed(1));
+                break;
+            }
+            Err(RpcError::TransportError(_)) => {}
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+        let timeout = Timeout::new(Duration::from_millis(20), &core.handle()).unwrap();
+        core.run(timeout).unwrap();
+    }
+
+    server.stop().unwrap();
+}
+
+#[test]
+fn channel_tells_missing_socket_from_stale_one() {
+    let path = socket_path("channel-missing");
+    let mut core = Core::new().unwrap();
+    match core.run(ChannelBuilder::unix_socket(&path, core.handle()).build()) {
+        Err(ChannelBuildError::ConnectError { source: e, .. }) => {
+            assert_eq!(e.kind(), io::ErrorKind::NotFound);
+            assert!(e.to_string().contains("no socket file"), "{}", e);
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    // nothing listens on the file left by a listener
+    drop(UnixListener::bind(&path).unwrap());
+    match core.run(ChannelBuilder::unix_socket(&path, core.handle()).build()) {
+        Err(ChannelBuildError::ConnectError { source: e, .. }) => {
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+            assert!(e.to_string().contains(&*path.to_string_lossy()), "{}", e);
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+    fs::remove_file(&path).unwrap();
+}
@@ -40,7 +40,7 @@ fn gen_file(file: &FileDescriptorProto, root: &descriptorx::RootScope) -> io::Re
     for service in file.get_service() {
         snippets.push(generate_service_trait(service, root)?);
         snippets.push(generate_registrant_basic(service)?);
-        snippets.push(generate_registrant_service(service, root)?);
+        snippets.push(generate_registrant_service(service, root, &base_name)?);
         snippets.push(generate_client_stub(service, root)?);
     }
 
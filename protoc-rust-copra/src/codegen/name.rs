@@ -1,8 +1,12 @@
 use inflector::Inflector;
+use protobuf::Message;
 use protobuf::descriptor::ServiceDescriptorProto;
 use protobuf::descriptorx::{RootScope, WithScope};
 use std::io;
 
+/// Field number of the `copra.oneway` method option
+const ONEWAY_OPTION: u32 = 50001;
+
 pub fn full_message_name(root: &RootScope, input: &str) -> String {
     format!("super::{}", root.find_message(input).rust_fq_name())
 }
@@ -95,3 +99,20 @@ pub fn response_types(proto: &ServiceDescriptorProto, root: &RootScope) -> io::R
         .collect();
     Ok(types)
 }
+
+pub fn oneway_flags(proto: &ServiceDescriptorProto) -> io::Result<Vec<bool>> {
+    let flags = proto
+        .get_method()
+        .iter()
+        .map(|method| {
+            // custom options are not known to rust-protobuf
+            method
+                .get_options()
+                .get_unknown_fields()
+                .get(ONEWAY_OPTION)
+                .and_then(|values| values.varint.last().cloned())
+                .unwrap_or(0) != 0
+        })
+        .collect();
+    Ok(flags)
+}
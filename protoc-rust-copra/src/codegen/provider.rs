@@ -75,6 +75,7 @@ impl<S> {}<S> {{
 pub fn generate_registrant_service(
     proto: &ServiceDescriptorProto,
     root: &RootScope,
+    file_mod: &str,
 ) -> io::Result<String> {
     let service_name = service_name(proto)?;
     let trait_name = trait_name(proto)?;
@@ -143,13 +144,19 @@ where
             );
     }
 
+    // the descriptor is generated by rust-protobuf next to this file
     gen = gen
         + &format!(
             r"
         entries
     }}
+
+    fn file_descriptor(&self) -> Option<Vec<u8>> {{
+        ::protobuf::Message::write_to_bytes(super::{}::file_descriptor_proto()).ok()
+    }}
 }}
-"
+",
+            file_mod
         );
 
     // generate NamedRegistrant implementation
@@ -14,6 +14,7 @@ pub fn generate_client_stub(
     let wrapper_names = wrapper_names(proto)?;
     let request_types = request_types(proto, root)?;
     let response_types = response_types(proto, root)?;
+    let oneway_flags = oneway_flags(proto)?;
 
     let mut gen = String::new();
 
@@ -21,7 +22,7 @@ pub fn generate_client_stub(
         + &format!(
             r"
 #[derive(Clone)]
-pub struct {}<'a> {{",
+pub struct {} {{",
             stub_name
         );
 
@@ -33,7 +34,7 @@ pub struct {}<'a> {{",
         gen = gen
             + &format!(
                 r"
-    {}: ::copra::stub::RpcWrapper<'a,
+    {}: ::copra::stub::RpcWrapper<
         ::copra::codec::ProtobufCodec<{}, {}>>,
 ",
                 wrap, resp, req
@@ -44,8 +45,8 @@ pub struct {}<'a> {{",
         + &format!(
             r"}}
 
-impl<'a> {}<'a> {{
-    pub fn new(channel: &'a ::copra::channel::Channel) -> Self {{
+impl {} {{
+    pub fn new(channel: &::copra::channel::Channel) -> Self {{
         {} {{",
             stub_name, stub_name
         );
@@ -63,9 +64,16 @@ impl<'a> {}<'a> {{
     }
 
     gen = gen
-        + r"        }
-    }
-";
+        + &format!(
+            r"        }}
+    }}
+
+    pub fn new_sync(channel: &::copra::sync::SyncChannel) -> Self {{
+        {}::new(channel.channel())
+    }}
+",
+            stub_name
+        );
 
     for (((method, req), resp), wrap) in method_names
         .iter()
@@ -77,22 +85,58 @@ impl<'a> {}<'a> {{
             + &format!(
                 r#"
     pub fn {}(
-        &'a self, 
+        &self,
         msg: {},
     ) -> ::copra::stub::StubFuture<
         ::copra::codec::ProtobufCodec<
             {},
             {},
         >,
+    > {{
+        self.{}_opts(msg, ::copra::stub::CallOptions::default())
+    }}
+
+    pub fn {}_opts(
+        &self,
+        msg: {},
+        opts: ::copra::stub::CallOptions,
+    ) -> ::copra::stub::StubFuture<
+        ::copra::codec::ProtobufCodec<
+            {},
+            {},
+        >,
     > {{
         self.{}
-            .call((msg, "{}".to_string(), "{}".to_string()))
+            .call_with_options((msg, "{}".to_string(), "{}".to_string()), opts)
     }}
 "#,
+                method, req, resp, req, method,
                 method, req, resp, req, wrap, service_name, method
             );
     }
 
+    for (((method, req), wrap), _) in method_names
+        .iter()
+        .zip(request_types.iter())
+        .zip(wrapper_names.iter())
+        .zip(oneway_flags.iter())
+        .filter(|&(_, &oneway)| oneway)
+    {
+        gen = gen
+            + &format!(
+                r#"
+    pub fn {}_oneway(
+        &self,
+        msg: {},
+    ) -> ::copra::stub::OnewayCallFuture {{
+        self.{}
+            .call_oneway((msg, "{}".to_string(), "{}".to_string()))
+    }}
+"#,
+                method, req, wrap, service_name, method
+            );
+    }
+
     gen = gen + "}\n";
 
     Ok(gen)
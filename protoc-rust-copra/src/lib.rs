@@ -27,6 +27,38 @@
 //! }
 //! ```
 //!
+//! # Locating `protoc`
+//!
+//! `protoc` (version 3) is looked up in the following order, the first one that
+//! works is used:
+//!
+//! 1. the path in the `PROTOC` environment variable;
+//! 2. the path passed to [`run_with_protoc`];
+//! 3. `protoc` in `PATH`;
+//! 4. the binary bundled with this crate, if the `vendored-protoc` feature is
+//!    enabled.
+//!
+//! [`run_with_protoc`]: fn.run_with_protoc.html
+//!
+//! # Method options
+//!
+//! The code generator understands a few custom method options, which are
+//! defined in `copra/options.proto`. This file is always available to the
+//! compiler, so you can import it without adding anything to `includes`:
+//!
+//! ```protobuf
+//! syntax = "proto3";
+//!
+//! import "copra/options.proto";
+//!
+//! service Log {
+//!     // generate `push_oneway` in `LogStub` besides `push`
+//!     rpc push(Entry) returns (Empty) {
+//!         option (copra.oneway) = true;
+//!     }
+//! }
+//! ```
+//!
 //! # Acknowledgment
 //!
 //! The crate is a mirror of [protoc-rust-grpc].
@@ -40,15 +72,20 @@ extern crate inflector;
 extern crate log;
 extern crate protobuf;
 extern crate protoc;
-extern crate protoc_rust;
+#[cfg(feature = "vendored-protoc")]
+extern crate protoc_bin_vendored;
 extern crate tempdir;
 
 mod codegen;
 
+use std::env;
 use std::io;
 use std::io::Read;
 use std::io::Write;
 use std::fs;
+use std::path::Path;
+
+static OPTIONS_PROTO: &str = include_str!("../proto/copra/options.proto");
 
 #[derive(Debug, Default)]
 /// Argument passed to `run`
@@ -68,27 +105,40 @@ pub struct Args<'a> {
 
 /// Generate rust code
 pub fn run(args: Args) -> io::Result<()> {
-    let protoc = protoc::Protoc::from_env_path();
-    let version = protoc.version().expect("protoc version");
-    if !version.is_3() {
-        panic!("protobuf must have version 3");
-    }
+    let protoc = find_protoc(None)?;
+    generate(args, &protoc)
+}
 
-    if args.rust_protobuf {
-        protoc_rust::run(protoc_rust::Args {
-            out_dir: args.out_dir,
-            includes: args.includes,
-            input: args.input,
-        })?;
-    }
+/// Generate rust code, using the `protoc` executable at `protoc_path`
+///
+/// The `PROTOC` environment variable still takes precedence over
+/// `protoc_path`. If `protoc_path` does not work, the lookup continues as
+/// described in the [crate documentation](index.html#locating-protoc).
+pub fn run_with_protoc(args: Args, protoc_path: &str) -> io::Result<()> {
+    let protoc = find_protoc(Some(protoc_path))?;
+    generate(args, &protoc)
+}
 
+fn generate(args: Args, protoc: &protoc::Protoc) -> io::Result<()> {
     let temp_dir = tempdir::TempDir::new("protoc-rust")?;
+    let option_dir = temp_dir.path().join("include");
+    write_options_proto(&option_dir)?;
+    let option_dir = option_dir.to_str().expect("utf-8 file name");
+
+    // protoc only searches the current directory when no include path is given
+    let mut protoc_includes = if args.includes.is_empty() {
+        vec!["."]
+    } else {
+        args.includes.to_vec()
+    };
+    protoc_includes.push(option_dir);
+
     let temp_file = temp_dir.path().join("descriptor.pbbin");
     let temp_file = temp_file.to_str().expect("utf-8 file name");
 
     protoc.write_descriptor_set(protoc::DescriptorSetOutArgs {
         out: temp_file,
-        includes: args.includes,
+        includes: &protoc_includes,
         input: args.input,
         include_imports: true,
     })?;
@@ -127,7 +177,10 @@ pub fn run(args: Args) -> io::Result<()> {
         ));
     }
 
-    let gen_result = codegen::gen(fds.get_file(), &files_to_generate)?;
+    let mut gen_result = codegen::gen(fds.get_file(), &files_to_generate)?;
+    if args.rust_protobuf {
+        gen_result.extend(protobuf::codegen::gen(fds.get_file(), &files_to_generate));
+    }
 
     for r in gen_result {
         let r: protobuf::compiler_plugin::GenResult = r;
@@ -140,6 +193,65 @@ pub fn run(args: Args) -> io::Result<()> {
     Ok(())
 }
 
+fn find_protoc(explicit: Option<&str>) -> io::Result<protoc::Protoc> {
+    let mut candidates = Vec::new();
+    if let Ok(path) = env::var("PROTOC") {
+        candidates.push(("PROTOC environment variable", path));
+    }
+    if let Some(path) = explicit {
+        candidates.push(("explicit path", path.to_string()));
+    }
+    candidates.push(("PATH", "protoc".to_string()));
+    if let Some(path) = vendored_protoc() {
+        candidates.push(("vendored binary", path));
+    }
+
+    let mut failures = Vec::new();
+    for (source, path) in candidates {
+        let protoc = protoc::Protoc::from_path(&path);
+        match protoc.version() {
+            Ok(ref version) if version.is_3() => {
+                debug!("Using protoc from {}: {}", source, path);
+                return Ok(protoc);
+            }
+            Ok(_) => failures.push(format!("{} ({}): protoc must have version 3", source, path)),
+            Err(e) => failures.push(format!("{} ({}): {}", source, path, e)),
+        }
+    }
+    if !cfg!(feature = "vendored-protoc") {
+        failures.push("vendored binary: the `vendored-protoc` feature is disabled".to_string());
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "failed to find a working protoc, tried in order: {}",
+            failures.join("; ")
+        ),
+    ))
+}
+
+#[cfg(feature = "vendored-protoc")]
+fn vendored_protoc() -> Option<String> {
+    protoc_bin_vendored::protoc_bin_path()
+        .map_err(|e| warn!("No vendored protoc for this host: {}", e))
+        .ok()
+        .and_then(|path| path.to_str().map(|s| s.to_string()))
+}
+
+#[cfg(not(feature = "vendored-protoc"))]
+fn vendored_protoc() -> Option<String> {
+    None
+}
+
+fn write_options_proto(include_dir: &Path) -> io::Result<()> {
+    let dir = include_dir.join("copra");
+    fs::create_dir_all(&dir)?;
+    let mut file = fs::File::create(dir.join("options.proto"))?;
+    file.write_all(OPTIONS_PROTO.as_bytes())?;
+    file.flush()
+}
+
 fn remove_dot_slash(path: &str) -> &str {
     if path == "." {
         ""